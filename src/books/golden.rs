@@ -0,0 +1,204 @@
+// Golden-image regression tests: small, seeded renders of the book scenes compared against
+// reference images in tests/golden. Run with UPDATE_GOLDEN=1 to write new references after
+// an intended change in the look of a scene. When a render drifts too far, the render and
+// an amplified difference image land in target/golden-diff for inspection.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub(super) const SEED: u64 = 0x5eed;
+
+// Renders are small and noisy, so they are compared after averaging blocks of pixels. The
+// tolerances sit a few times above the difference between renders with different seeds,
+// so a change in the random sequence passes while a missing object or a shift in
+// brightness does not.
+const BLOCK: usize = 4;
+
+#[derive(Clone, Copy)]
+pub(super) struct Tolerance {
+    // Root mean square difference over all blocks and channels, on a 0 to 1 scale.
+    pub rmse: f64,
+    // The largest difference allowed in any one block and channel.
+    pub max: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self { rmse: 0.02, max: 0.15 }
+    }
+}
+
+struct Image {
+    width: usize,
+    height: usize,
+    // Channel values between 0 and 1, row by row.
+    pixels: Vec<[f64; 3]>,
+}
+
+impl Image {
+    // Reads a P3 or P6 image with 8-bit channels.
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut pos = 0;
+        let mut token = || -> Result<String, String> {
+            loop {
+                while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                if pos < bytes.len() && bytes[pos] == b'#' {
+                    while pos < bytes.len() && bytes[pos] != b'\n' {
+                        pos += 1;
+                    }
+                    continue;
+                }
+                break;
+            }
+            let start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err("unexpected end of image".to_string());
+            }
+            Ok(String::from_utf8_lossy(&bytes[start..pos]).into_owned())
+        };
+        let number = |s: String| s.parse::<usize>().map_err(|_| format!("bad number '{}'", s));
+
+        let magic = token()?;
+        let width = number(token()?)?;
+        let height = number(token()?)?;
+        let max_value = number(token()?)?;
+        if max_value != 255 {
+            return Err(format!("unsupported maximum value {}", max_value));
+        }
+
+        let channels: Vec<u8> = match magic.as_str() {
+            "P3" => (0..width * height * 3)
+                .map(|_| token().and_then(number).map(|c| c.min(255) as u8))
+                .collect::<Result<_, _>>()?,
+            "P6" => {
+                // A single whitespace byte separates the header from the samples.
+                let start = pos + 1;
+                bytes
+                    .get(start..start + width * height * 3)
+                    .ok_or_else(|| "truncated image".to_string())?
+                    .to_vec()
+            }
+            _ => return Err(format!("unsupported format '{}'", magic)),
+        };
+
+        let pixels = channels
+            .chunks(3)
+            .map(|c| [c[0] as f64 / 255.0, c[1] as f64 / 255.0, c[2] as f64 / 255.0])
+            .collect();
+        Ok(Self { width, height, pixels })
+    }
+
+    fn write(&self, path: &Path) {
+        let mut bytes = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        for pixel in &self.pixels {
+            bytes.extend(pixel.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8));
+        }
+        fs::write(path, bytes).unwrap_or_else(|e| panic!("failed to write {}: {}", path.display(), e));
+    }
+
+    // The mean of each BLOCK by BLOCK square, with partial blocks at the right and bottom.
+    fn block_averages(&self) -> Vec<[f64; 3]> {
+        let (columns, rows) = (self.width.div_ceil(BLOCK), self.height.div_ceil(BLOCK));
+        let mut sums = vec![([0.0; 3], 0); columns * rows];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (sum, count) = &mut sums[(y / BLOCK) * columns + x / BLOCK];
+                for (s, c) in sum.iter_mut().zip(self.pixels[y * self.width + x]) {
+                    *s += c;
+                }
+                *count += 1;
+            }
+        }
+        sums.into_iter().map(|(sum, count)| sum.map(|s| s / count as f64)).collect()
+    }
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(format!("{}.ppm", name))
+}
+
+fn diff_dir() -> PathBuf {
+    let target = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("target"));
+    target.join("golden-diff")
+}
+
+// Compares a rendered PPM against the reference image called `name`, panicking with the
+// error and the paths of the diff images when it is out of tolerance.
+pub(super) fn assert_matches_reference(name: &str, ppm: &[u8], tolerance: Tolerance) {
+    let actual = Image::parse(ppm).unwrap_or_else(|e| panic!("{}: bad render: {}", name, e));
+    let path = reference_path(name);
+
+    if env::var("UPDATE_GOLDEN").is_ok_and(|v| v == "1") {
+        fs::create_dir_all(path.parent().unwrap()).expect("failed to create the reference directory");
+        actual.write(&path);
+        return;
+    }
+
+    let bytes = fs::read(&path).unwrap_or_else(|e| {
+        panic!("{}: cannot read {} ({}); run with UPDATE_GOLDEN=1 to create it", name, path.display(), e)
+    });
+    let expected = Image::parse(&bytes).unwrap_or_else(|e| panic!("{}: bad reference: {}", name, e));
+    assert!(
+        (actual.width, actual.height) == (expected.width, expected.height),
+        "{}: rendered {}x{} but the reference is {}x{}",
+        name,
+        actual.width,
+        actual.height,
+        expected.width,
+        expected.height
+    );
+
+    let (mut sum_squares, mut max) = (0.0, 0.0_f64);
+    let (actual_blocks, expected_blocks) = (actual.block_averages(), expected.block_averages());
+    for (a, e) in actual_blocks.iter().zip(&expected_blocks) {
+        for channel in 0..3 {
+            let difference = (a[channel] - e[channel]).abs();
+            sum_squares += difference * difference;
+            max = max.max(difference);
+        }
+    }
+    let rmse = (sum_squares / (3 * actual_blocks.len()) as f64).sqrt();
+
+    if rmse <= tolerance.rmse && max <= tolerance.max {
+        return;
+    }
+
+    // Save the render and its difference from the reference, amplified to show up.
+    let dir = diff_dir();
+    fs::create_dir_all(&dir).expect("failed to create the diff directory");
+    let actual_path = dir.join(format!("{}.actual.ppm", name));
+    let diff_path = dir.join(format!("{}.diff.ppm", name));
+    actual.write(&actual_path);
+    let diff = Image {
+        width: actual.width,
+        height: actual.height,
+        pixels: actual
+            .pixels
+            .iter()
+            .zip(&expected.pixels)
+            .map(|(a, e)| [0, 1, 2].map(|c| 4.0 * (a[c] - e[c]).abs()))
+            .collect(),
+    };
+    diff.write(&diff_path);
+
+    panic!(
+        "{}: render differs from {} (rmse {:.4}, allowed {:.4}; largest block difference {:.4}, allowed {:.4}); \
+         see {} and {}",
+        name,
+        path.display(),
+        rmse,
+        tolerance.rmse,
+        max,
+        tolerance.max,
+        actual_path.display(),
+        diff_path.display()
+    );
+}
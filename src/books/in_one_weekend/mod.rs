@@ -52,9 +52,21 @@ fn apply_overrides(cam: &mut Camera) {
     if let Some(value) = o.focus_dist {
         cam.focus_dist = value;
     }
+    if let Some(value) = crate::config::projection_override() {
+        cam.projection = value;
+    }
 }
 
 pub fn run(_scene: Option<i32>) {
+    let (mut cam, world) = final_scene();
+
+    apply_overrides(&mut cam);
+
+    let world = BvhNode::new(world);
+    cam.render(&world);
+}
+
+fn final_scene() -> (Camera, HittableList) {
     let mut world = HittableList::new();
 
     let ground_material = make_mat(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
@@ -119,8 +131,25 @@ pub fn run(_scene: Option<i32>) {
     cam.defocus_angle = 0.6;
     cam.focus_dist = 10.0;
 
-    apply_overrides(&mut cam);
+    (cam, world)
+}
 
-    let world = BvhNode::new(world);
-    cam.render(&world);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden::{self, Tolerance};
+
+    #[test]
+    fn final_scene_matches_reference() {
+        rtweekend::seed_random(golden::SEED);
+        let (mut cam, world) = final_scene();
+        cam.image_width = 64;
+        cam.samples_per_pixel = 64;
+        cam.max_depth = 8;
+        cam.seed = Some(golden::SEED);
+
+        let mut image = Vec::new();
+        cam.render_to(&BvhNode::new(world), &mut image);
+        golden::assert_matches_reference("in_one_weekend_final_scene", &image, Tolerance::default());
+    }
 }
@@ -1,3 +1,6 @@
 pub mod in_one_weekend;
 pub mod the_next_week;
 pub mod the_rest_of_your_life;
+
+#[cfg(test)]
+mod golden;
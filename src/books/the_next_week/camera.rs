@@ -3,11 +3,13 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 
 use rayon::prelude::*;
 
+use crate::config::Projection;
+
 use super::color::write_color;
 use super::hittable::Hittable;
 use super::interval::Interval;
 use super::ray::Ray;
-use super::rtweekend::{degrees_to_radians, random_double, INFINITY};
+use super::rtweekend::{degrees_to_radians, random_double, seed_random, INFINITY, PI};
 use super::vec3::{
     cross, random_in_unit_disk, unit_vector, Color, Point3, Vec3,
 };
@@ -26,6 +28,10 @@ pub struct Camera {
 
     pub defocus_angle: f64,
     pub focus_dist: f64,
+
+    pub projection: Projection,
+    // Makes renders repeatable: each row restarts the random sequence from this seed.
+    pub seed: Option<u64>,
 }
 
 impl Default for Camera {
@@ -42,6 +48,8 @@ impl Default for Camera {
             vup: Vec3::new(0.0, 1.0, 0.0),
             defocus_angle: 0.0,
             focus_dist: 10.0,
+            projection: Projection::Perspective,
+            seed: None,
         }
     }
 }
@@ -53,12 +61,21 @@ struct CameraInternals {
     pixel00_loc: Point3,
     pixel_delta_u: Vec3,
     pixel_delta_v: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
 }
 
 impl Camera {
     pub fn render<H: Hittable>(&self, world: &H) {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        self.render_to(world, &mut out);
+    }
+
+    pub fn render_to<H: Hittable, W: Write>(&self, world: &H, out: &mut W) {
         let data = self.initialize();
 
         let image_height = data.image_height as usize;
@@ -68,13 +85,17 @@ impl Camera {
         let rows: Vec<Vec<u8>> = (0..image_height)
             .into_par_iter()
             .map(|j| {
+                if let Some(seed) = self.seed {
+                    seed_random(seed ^ (j as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+                }
                 let mut row = Vec::with_capacity(image_width * 12);
                 let j_i32 = j as i32;
                 for i in 0..self.image_width {
                     let mut pixel_color = Color::new(0.0, 0.0, 0.0);
                     for _ in 0..self.samples_per_pixel {
-                        let r = self.get_ray(i, j_i32, &data);
-                        pixel_color += self.ray_color(r, self.max_depth, world);
+                        if let Some(r) = self.get_ray(i, j_i32, &data) {
+                            pixel_color += self.ray_color(r, self.max_depth, world);
+                        }
                     }
                     write_color(&mut row, data.pixel_samples_scale * pixel_color);
                 }
@@ -89,14 +110,13 @@ impl Camera {
             })
             .collect();
 
-        let stdout = io::stdout();
-        let mut out = BufWriter::new(stdout.lock());
         writeln!(out, "P3\n{} {}\n255", self.image_width, data.image_height)
             .expect("failed to write header");
 
         for row in rows {
             out.write_all(&row).expect("failed to write pixel data");
         }
+        out.flush().expect("failed to flush output");
 
         eprintln!("\rDone.                 ");
     }
@@ -140,35 +160,80 @@ impl Camera {
             pixel00_loc,
             pixel_delta_u,
             pixel_delta_v,
+            u,
+            v,
+            w,
             defocus_disk_u,
             defocus_disk_v,
         }
     }
 
-    fn get_ray(&self, i: i32, j: i32, data: &CameraInternals) -> Ray {
+    fn get_ray(&self, i: i32, j: i32, data: &CameraInternals) -> Option<Ray> {
         let offset = self.sample_square();
         let pixel_sample = data.pixel00_loc
             + (i as f64 + offset.x()) * data.pixel_delta_u
             + (j as f64 + offset.y()) * data.pixel_delta_v;
+        let x = i as f64 + 0.5 + offset.x();
+        let y = j as f64 + 0.5 + offset.y();
+
+        // Every projection picks a lens center and a point on the plane of focus; the
+        // defocus disk is then centered on the lens so depth of field works for all of them.
+        let (lens_center, focus_point) = match self.projection {
+            Projection::Perspective => (data.center, pixel_sample),
+            Projection::Orthographic => (pixel_sample + self.focus_dist * data.w, pixel_sample),
+            Projection::Fisheye => {
+                let direction = self.fisheye_direction(x, y, data)?;
+                (data.center, data.center + self.focus_dist * direction)
+            }
+            Projection::Equirectangular => {
+                let direction = self.equirectangular_direction(x, y, data);
+                (data.center, data.center + self.focus_dist * direction)
+            }
+        };
 
         let ray_origin = if self.defocus_angle <= 0.0 {
-            data.center
+            lens_center
         } else {
-            self.defocus_disk_sample(data)
+            self.defocus_disk_sample(lens_center, data)
         };
-        let ray_direction = pixel_sample - ray_origin;
+        let ray_direction = focus_point - ray_origin;
         let ray_time = random_double();
 
-        Ray::new_with_time(ray_origin, ray_direction, ray_time)
+        Some(Ray::new_with_time(ray_origin, ray_direction, ray_time))
+    }
+
+    fn fisheye_direction(&self, x: f64, y: f64, data: &CameraInternals) -> Option<Vec3> {
+        // Equidistant mapping: the angle off the view axis grows linearly with the
+        // distance from the image center.
+        let px = x - 0.5 * self.image_width as f64;
+        let py = 0.5 * data.image_height as f64 - y;
+        let r = (px * px + py * py).sqrt();
+        let theta = r * degrees_to_radians(self.vfov) / data.image_height as f64;
+        if theta > PI {
+            return None;
+        }
+        if r <= 0.0 {
+            return Some(-data.w);
+        }
+
+        let radial = (px / r) * data.u + (py / r) * data.v;
+        Some(theta.sin() * radial - theta.cos() * data.w)
+    }
+
+    fn equirectangular_direction(&self, x: f64, y: f64, data: &CameraInternals) -> Vec3 {
+        let longitude = (x / self.image_width as f64 - 0.5) * 2.0 * PI;
+        let latitude = (0.5 - y / data.image_height as f64) * PI;
+        latitude.cos() * longitude.sin() * data.u + latitude.sin() * data.v
+            - latitude.cos() * longitude.cos() * data.w
     }
 
     fn sample_square(&self) -> Vec3 {
         Vec3::new(random_double() - 0.5, random_double() - 0.5, 0.0)
     }
 
-    fn defocus_disk_sample(&self, data: &CameraInternals) -> Point3 {
+    fn defocus_disk_sample(&self, lens_center: Point3, data: &CameraInternals) -> Point3 {
         let p = random_in_unit_disk();
-        data.center + (p[0] * data.defocus_disk_u) + (p[1] * data.defocus_disk_v)
+        lens_center + (p[0] * data.defocus_disk_u) + (p[1] * data.defocus_disk_v)
     }
 
     fn ray_color<H: Hittable>(&self, r: Ray, depth: i32, world: &H) -> Color {
@@ -200,3 +265,70 @@ impl Camera {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::the_next_week::vec3::dot;
+
+    // A square image looking from an arbitrary point, so u, v and w are not the axes.
+    fn camera(projection: Projection, vfov: f64) -> Camera {
+        Camera {
+            image_width: 100,
+            vfov,
+            lookfrom: Point3::new(1.0, 2.0, 3.0),
+            lookat: Point3::new(-2.0, 1.0, -1.0),
+            projection,
+            ..Camera::default()
+        }
+    }
+
+    fn angle_between(a: Vec3, b: Vec3) -> f64 {
+        dot(unit_vector(a), unit_vector(b)).clamp(-1.0, 1.0).acos()
+    }
+
+    #[test]
+    fn image_centers_look_along_the_view_axis() {
+        for projection in [Projection::Fisheye, Projection::Equirectangular] {
+            let cam = camera(projection, 120.0);
+            let data = cam.initialize();
+            let direction = match projection {
+                Projection::Fisheye => cam.fisheye_direction(50.0, 50.0, &data).unwrap(),
+                _ => cam.equirectangular_direction(50.0, 50.0, &data),
+            };
+            assert!((direction + data.w).length() < 1e-9, "{projection:?}: {direction:?}");
+        }
+    }
+
+    #[test]
+    fn fisheye_edges_are_half_the_field_of_view_off_axis() {
+        let cam = camera(Projection::Fisheye, 120.0);
+        let data = cam.initialize();
+        for (x, y, side) in [(50.0, 0.0, data.v), (50.0, 100.0, -data.v), (0.0, 50.0, -data.u), (100.0, 50.0, data.u)] {
+            let direction = cam.fisheye_direction(x, y, &data).unwrap();
+            assert!((direction.length() - 1.0).abs() < 1e-9);
+            assert!((angle_between(direction, -data.w) - degrees_to_radians(60.0)).abs() < 1e-9);
+            assert!(dot(direction, side) > 0.0, "({x}, {y}) leans the wrong way: {direction:?}");
+        }
+
+        // Past 180 degrees off axis there is nothing to see.
+        let wide = camera(Projection::Fisheye, 270.0);
+        let data = wide.initialize();
+        assert!(wide.fisheye_direction(50.0, 0.0, &data).is_some());
+        assert!(wide.fisheye_direction(0.0, 0.0, &data).is_none());
+    }
+
+    #[test]
+    fn equirectangular_images_wrap_around_behind_the_camera() {
+        let cam = camera(Projection::Equirectangular, 90.0);
+        let data = cam.initialize();
+        let left = cam.equirectangular_direction(0.0, 50.0, &data);
+        let right = cam.equirectangular_direction(100.0, 50.0, &data);
+        assert!((left - right).length() < 1e-9);
+        assert!((left - data.w).length() < 1e-9);
+
+        // A quarter of the way across looks left, and the top row looks straight up.
+        assert!((cam.equirectangular_direction(25.0, 50.0, &data) + data.u).length() < 1e-9);
+        assert!((cam.equirectangular_direction(50.0, 0.0, &data) - data.v).length() < 1e-9);
+    }
+}
@@ -19,7 +19,7 @@ mod vec3;
 use bvh::BvhNode;
 use camera::Camera;
 use constant_medium::ConstantMedium;
-use hittable::{make_ref, RotateY, Translate};
+use hittable::{make_ref, HittableRef, RotateY, Translate};
 use hittable_list::HittableList;
 use material::{make_mat, Dielectric, DiffuseLight, Lambertian, Metal};
 use quad::{make_box, Quad};
@@ -60,13 +60,16 @@ fn apply_overrides(cam: &mut Camera) {
     if let Some(value) = o.focus_dist {
         cam.focus_dist = value;
     }
+    if let Some(value) = crate::config::projection_override() {
+        cam.projection = value;
+    }
     if let Some(value) = o.background {
         cam.background = Color::new(value[0], value[1], value[2]);
     }
 }
 
 pub fn run(scene: Option<i32>) {
-    match scene.unwrap_or(0) {
+    let (mut cam, world) = match scene.unwrap_or(0) {
         1 => bouncing_spheres(),
         2 => checkered_spheres(),
         3 => earth(),
@@ -77,10 +80,14 @@ pub fn run(scene: Option<i32>) {
         8 => cornell_smoke(),
         9 => final_scene(800, 10000, 40),
         _ => final_scene(400, 250, 4),
-    }
+    };
+
+    apply_overrides(&mut cam);
+
+    cam.render(world.as_ref());
 }
 
-fn bouncing_spheres() {
+fn bouncing_spheres() -> (Camera, HittableRef) {
     let mut world = HittableList::new();
 
     let checker = make_tex(CheckerTexture::from_colors(
@@ -149,12 +156,10 @@ fn bouncing_spheres() {
     cam.defocus_angle = 0.6;
     cam.focus_dist = 10.0;
 
-    apply_overrides(&mut cam);
-
-    cam.render(&world);
+    (cam, make_ref(world))
 }
 
-fn checkered_spheres() {
+fn checkered_spheres() -> (Camera, HittableRef) {
     let mut world = HittableList::new();
 
     let checker = make_tex(CheckerTexture::from_colors(
@@ -188,12 +193,10 @@ fn checkered_spheres() {
 
     cam.defocus_angle = 0.0;
 
-    apply_overrides(&mut cam);
-
-    cam.render(&world);
+    (cam, make_ref(world))
 }
 
-fn earth() {
+fn earth() -> (Camera, HittableRef) {
     let earth_texture = make_tex(ImageTexture::new("earthmap.jpg"));
     let earth_surface = make_mat(Lambertian::from_texture(earth_texture));
     let globe = make_ref(Sphere::new(Point3::new(0.0, 0.0, 0.0), 2.0, earth_surface));
@@ -214,12 +217,10 @@ fn earth() {
 
     cam.defocus_angle = 0.0;
 
-    apply_overrides(&mut cam);
-
-    cam.render(&world);
+    (cam, make_ref(world))
 }
 
-fn perlin_spheres() {
+fn perlin_spheres() -> (Camera, HittableRef) {
     let mut world = HittableList::new();
 
     let pertext = make_tex(NoiseTexture::new(4.0));
@@ -248,12 +249,10 @@ fn perlin_spheres() {
 
     cam.defocus_angle = 0.0;
 
-    apply_overrides(&mut cam);
-
-    cam.render(&world);
+    (cam, make_ref(world))
 }
 
-fn quads() {
+fn quads() -> (Camera, HittableRef) {
     let mut world = HittableList::new();
 
     let left_red = make_mat(Lambertian::new(Color::new(1.0, 0.2, 0.2)));
@@ -307,12 +306,10 @@ fn quads() {
 
     cam.defocus_angle = 0.0;
 
-    apply_overrides(&mut cam);
-
-    cam.render(&world);
+    (cam, make_ref(world))
 }
 
-fn simple_light() {
+fn simple_light() -> (Camera, HittableRef) {
     let mut world = HittableList::new();
 
     let pertext = make_tex(NoiseTexture::new(4.0));
@@ -350,12 +347,10 @@ fn simple_light() {
 
     cam.defocus_angle = 0.0;
 
-    apply_overrides(&mut cam);
-
-    cam.render(&world);
+    (cam, make_ref(world))
 }
 
-fn cornell_box() {
+fn cornell_box() -> (Camera, HittableRef) {
     let mut world = HittableList::new();
 
     let red = make_mat(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
@@ -424,12 +419,10 @@ fn cornell_box() {
 
     cam.defocus_angle = 0.0;
 
-    apply_overrides(&mut cam);
-
-    cam.render(&world);
+    (cam, make_ref(world))
 }
 
-fn cornell_smoke() {
+fn cornell_smoke() -> (Camera, HittableRef) {
     let mut world = HittableList::new();
 
     let red = make_mat(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
@@ -499,12 +492,10 @@ fn cornell_smoke() {
 
     cam.defocus_angle = 0.0;
 
-    apply_overrides(&mut cam);
-
-    cam.render(&world);
+    (cam, make_ref(world))
 }
 
-fn final_scene(image_width: i32, samples_per_pixel: i32, max_depth: i32) {
+fn final_scene(image_width: i32, samples_per_pixel: i32, max_depth: i32) -> (Camera, HittableRef) {
     let mut boxes1 = HittableList::new();
     let ground = make_mat(Lambertian::new(Color::new(0.48, 0.83, 0.53)));
 
@@ -618,7 +609,78 @@ fn final_scene(image_width: i32, samples_per_pixel: i32, max_depth: i32) {
 
     cam.defocus_angle = 0.0;
 
-    apply_overrides(&mut cam);
+    (cam, make_ref(world))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden::{self, Tolerance};
+
+    // The scenes lit only by small lights stay noisy at test sample counts.
+    const NOISY: Tolerance = Tolerance { rmse: 0.1, max: 0.35 };
+
+    // Renders the scene small and seeded, and compares it with its reference image.
+    fn check(
+        name: &str,
+        scene: impl FnOnce() -> (Camera, HittableRef),
+        samples_per_pixel: i32,
+        tolerance: Tolerance,
+    ) {
+        rtweekend::seed_random(golden::SEED);
+        let (mut cam, world) = scene();
+        cam.image_width = 64;
+        cam.samples_per_pixel = samples_per_pixel;
+        cam.max_depth = 8;
+        cam.seed = Some(golden::SEED);
+
+        let mut image = Vec::new();
+        cam.render_to(world.as_ref(), &mut image);
+        golden::assert_matches_reference(&format!("the_next_week_{}", name), &image, tolerance);
+    }
+
+    #[test]
+    fn bouncing_spheres_matches_reference() {
+        check("bouncing_spheres", bouncing_spheres, 64, Tolerance::default());
+    }
 
-    cam.render(&world);
+    #[test]
+    fn checkered_spheres_matches_reference() {
+        check("checkered_spheres", checkered_spheres, 64, Tolerance::default());
+    }
+
+    #[test]
+    fn earth_matches_reference() {
+        check("earth", earth, 16, Tolerance::default());
+    }
+
+    #[test]
+    fn perlin_spheres_matches_reference() {
+        check("perlin_spheres", perlin_spheres, 64, Tolerance::default());
+    }
+
+    #[test]
+    fn quads_matches_reference() {
+        check("quads", quads, 64, Tolerance::default());
+    }
+
+    #[test]
+    fn simple_light_matches_reference() {
+        check("simple_light", simple_light, 64, Tolerance { rmse: 0.06, max: 0.2 });
+    }
+
+    #[test]
+    fn cornell_box_matches_reference() {
+        check("cornell_box", cornell_box, 64, NOISY);
+    }
+
+    #[test]
+    fn cornell_smoke_matches_reference() {
+        check("cornell_smoke", cornell_smoke, 64, NOISY);
+    }
+
+    #[test]
+    fn final_scene_matches_reference() {
+        check("final_scene", || final_scene(64, 64, 8), 64, NOISY);
+    }
 }
@@ -14,6 +14,11 @@ pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
+// Restarts this thread's random sequence from `seed`, so renders can be reproduced.
+pub fn seed_random(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
 pub fn random_double() -> f64 {
     // Returns a random real in [0,1).
     RNG.with(|rng| rng.borrow_mut().gen_range(0.0..1.0))
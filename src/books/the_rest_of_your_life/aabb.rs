@@ -49,7 +49,12 @@ impl Aabb {
         }
     }
 
-    pub fn hit(&self, r: &Ray, mut ray_t: Interval) -> bool {
+    pub fn hit(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.clip(r, ray_t).is_some()
+    }
+
+    // The part of `ray_t` for which the ray is inside the box, if any.
+    pub fn clip(&self, r: &Ray, mut ray_t: Interval) -> Option<Interval> {
         let ray_orig = r.origin();
         let ray_dir = r.direction();
 
@@ -76,6 +81,29 @@ impl Aabb {
                 }
             }
 
+            if ray_t.max <= ray_t.min {
+                return None;
+            }
+        }
+        Some(ray_t)
+    }
+
+    // Same slab test as `hit`, for callers that test many boxes against one ray and
+    // precompute the reciprocal direction once.
+    pub fn hit_inv(&self, origin: Point3, inv_dir: Vec3, mut ray_t: Interval) -> bool {
+        for axis in 0..3 {
+            let ax = self.axis_interval(axis);
+            let t0 = (ax.min - origin[axis]) * inv_dir[axis];
+            let t1 = (ax.max - origin[axis]) * inv_dir[axis];
+
+            let (t_near, t_far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            if t_near > ray_t.min {
+                ray_t.min = t_near;
+            }
+            if t_far < ray_t.max {
+                ray_t.max = t_far;
+            }
+
             if ray_t.max <= ray_t.min {
                 return false;
             }
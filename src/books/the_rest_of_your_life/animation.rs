@@ -0,0 +1,424 @@
+use std::ops::{Add, Mul, Sub};
+
+use super::aabb::Aabb;
+use super::camera::Camera;
+use super::hittable::{HitRecord, Hittable, HittableRef};
+use super::interval::Interval;
+use super::ray::Ray;
+use super::rtweekend::{degrees_to_radians, INFINITY};
+use super::vec3::{Point3, Vec3};
+
+// Animation time is measured in frames: frame n covers [n, n + 1), and a still render
+// uses frame 0, which matches the [0, 1) ray times used for motion blur. Moving
+// spheres only move over [0, 1] and motion transforms over their own time range, and
+// both hold still outside it; scenes that blur every frame of a sequence give motion
+// transforms each frame's shutter interval.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    CatmullRom,
+}
+
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    keys: Vec<(f64, T)>,
+    interpolation: Interpolation,
+}
+
+impl<T> Track<T>
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>,
+{
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self { keys: Vec::new(), interpolation }
+    }
+
+    pub fn constant(value: T) -> Self {
+        Self::new(Interpolation::Linear).key(0.0, value)
+    }
+
+    pub fn key(mut self, time: f64, value: T) -> Self {
+        let index = self.keys.partition_point(|&(t, _)| t <= time);
+        self.keys.insert(index, (time, value));
+        self
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.keys.len() <= 1
+    }
+
+    pub fn sample(&self, time: f64) -> T {
+        assert!(!self.keys.is_empty(), "sampling an empty animation track");
+
+        let last = self.keys.len() - 1;
+        if time <= self.keys[0].0 {
+            return self.keys[0].1;
+        }
+        if time >= self.keys[last].0 {
+            return self.keys[last].1;
+        }
+
+        let i = self.keys.partition_point(|&(t, _)| t <= time) - 1;
+        let (t1, p1) = self.keys[i];
+        let (t2, p2) = self.keys[i + 1];
+        let u = (time - t1) / (t2 - t1);
+
+        match self.interpolation {
+            Interpolation::Linear => p1 + (p2 - p1) * u,
+            Interpolation::CatmullRom => {
+                let (m1, m2) = self.tangents(i);
+                let u2 = u * u;
+                let u3 = u2 * u;
+                p1 * (2.0 * u3 - 3.0 * u2 + 1.0)
+                    + m1 * (u3 - 2.0 * u2 + u)
+                    + p2 * (-2.0 * u3 + 3.0 * u2)
+                    + m2 * (u3 - u2)
+            }
+        }
+    }
+
+    // Points whose convex hull contains the whole curve, for conservative bounding boxes.
+    pub fn hull_points(&self) -> Vec<T> {
+        match self.interpolation {
+            Interpolation::Linear => self.keys.iter().map(|&(_, value)| value).collect(),
+            Interpolation::CatmullRom => {
+                let mut points = Vec::with_capacity(3 * self.keys.len());
+                for i in 0..self.keys.len() {
+                    points.push(self.keys[i].1);
+                    if i + 1 < self.keys.len() {
+                        // Each Hermite segment is a cubic Bezier with these inner control points.
+                        let (m1, m2) = self.tangents(i);
+                        points.push(self.keys[i].1 + m1 * (1.0 / 3.0));
+                        points.push(self.keys[i + 1].1 - m2 * (1.0 / 3.0));
+                    }
+                }
+                points
+            }
+        }
+    }
+
+    // Hermite tangents for segment i, scaled to the segment's parameter range.
+    fn tangents(&self, i: usize) -> (T, T) {
+        let (t1, p1) = self.keys[i];
+        let (t2, p2) = self.keys[i + 1];
+        let span = t2 - t1;
+
+        let m1 = if i > 0 {
+            let (t0, p0) = self.keys[i - 1];
+            (p2 - p0) * (span / (t2 - t0))
+        } else {
+            p2 - p1
+        };
+        let m2 = if i + 2 < self.keys.len() {
+            let (t3, p3) = self.keys[i + 2];
+            (p3 - p1) * (span / (t3 - t1))
+        } else {
+            p2 - p1
+        };
+        (m1, m2)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CameraAnimation {
+    pub lookfrom: Option<Track<Point3>>,
+    pub lookat: Option<Track<Point3>>,
+    pub vup: Option<Track<Vec3>>,
+    pub vfov: Option<Track<f64>>,
+    pub defocus_angle: Option<Track<f64>>,
+    pub focus_dist: Option<Track<f64>>,
+    // Shutter times are offsets from the start of each frame.
+    pub shutter_open: Track<f64>,
+    pub shutter_close: Track<f64>,
+}
+
+impl Default for CameraAnimation {
+    fn default() -> Self {
+        Self {
+            lookfrom: None,
+            lookat: None,
+            vup: None,
+            vfov: None,
+            defocus_angle: None,
+            focus_dist: None,
+            shutter_open: Track::constant(0.0),
+            shutter_close: Track::constant(1.0),
+        }
+    }
+}
+
+impl CameraAnimation {
+    pub fn apply(&self, cam: &mut Camera, frame: i32) {
+        let time = frame as f64;
+        if let Some(track) = &self.lookfrom {
+            cam.lookfrom = track.sample(time);
+        }
+        if let Some(track) = &self.lookat {
+            cam.lookat = track.sample(time);
+        }
+        if let Some(track) = &self.vup {
+            cam.vup = track.sample(time);
+        }
+        if let Some(track) = &self.vfov {
+            cam.vfov = track.sample(time);
+        }
+        if let Some(track) = &self.defocus_angle {
+            cam.defocus_angle = track.sample(time);
+        }
+        if let Some(track) = &self.focus_dist {
+            cam.focus_dist = track.sample(time);
+        }
+        cam.shutter_open = time + self.shutter_open.sample(time);
+        cam.shutter_close = time + self.shutter_close.sample(time);
+    }
+}
+
+// Keyframed counterpart of RotateY followed by Translate. The transform is evaluated at
+// each ray's time, so objects blur across the shutter interval of every frame.
+pub struct Animated {
+    object: HittableRef,
+    rotation_y: Track<f64>,
+    translation: Track<Vec3>,
+    bbox: Aabb,
+}
+
+impl Animated {
+    pub fn new(object: HittableRef, rotation_y: Track<f64>, translation: Track<Vec3>) -> Self {
+        let bbox = object.bounding_box();
+
+        let mut min = Point3::new(INFINITY, INFINITY, INFINITY);
+        let mut max = Point3::new(-INFINITY, -INFINITY, -INFINITY);
+        if rotation_y.is_constant() {
+            let radians = degrees_to_radians(rotation_y.sample(0.0));
+            let (sin_theta, cos_theta) = radians.sin_cos();
+            for i in 0..2 {
+                for j in 0..2 {
+                    for k in 0..2 {
+                        let x = if i == 1 { bbox.x.max } else { bbox.x.min };
+                        let y = if j == 1 { bbox.y.max } else { bbox.y.min };
+                        let z = if k == 1 { bbox.z.max } else { bbox.z.min };
+
+                        let tester = Vec3::new(cos_theta * x + sin_theta * z, y, -sin_theta * x + cos_theta * z);
+                        for c in 0..3 {
+                            min[c] = min[c].min(tester[c]);
+                            max[c] = max[c].max(tester[c]);
+                        }
+                    }
+                }
+            }
+        } else {
+            // Any angle may be reached, so bound the cylinder swept around the y axis.
+            let rx = bbox.x.min.abs().max(bbox.x.max.abs());
+            let rz = bbox.z.min.abs().max(bbox.z.max.abs());
+            let radius = (rx * rx + rz * rz).sqrt();
+            min = Point3::new(-radius, bbox.y.min, -radius);
+            max = Point3::new(radius, bbox.y.max, radius);
+        }
+        let rotated = Aabb::from_points(min, max);
+
+        let mut bbox = Aabb::EMPTY;
+        for offset in translation.hull_points() {
+            bbox = Aabb::from_boxes(bbox, rotated + offset);
+        }
+
+        Self { object, rotation_y, translation, bbox }
+    }
+
+    fn pose_at(&self, time: f64) -> Pose {
+        let (sin_theta, cos_theta) = degrees_to_radians(self.rotation_y.sample(time)).sin_cos();
+        Pose { sin_theta, cos_theta, offset: self.translation.sample(time) }
+    }
+}
+
+impl Hittable for Animated {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let pose = self.pose_at(r.time());
+        let rec = self.object.hit(&pose.object_ray(r), ray_t)?;
+        Some(pose.world_hit(rec))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        let pose = self.pose_at(r.time());
+        self.object.crossings(&pose.object_ray(r), ray_t).into_iter().map(|rec| pose.world_hit(rec)).collect()
+    }
+
+    // Rotating and moving leave solid angle be, so the object's own density carries over.
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        let pose = self.pose_at(time);
+        self.object.pdf_value(pose.to_object(origin - pose.offset), pose.to_object(direction), time)
+    }
+
+    fn random(&self, origin: Point3, time: f64) -> Vec3 {
+        let pose = self.pose_at(time);
+        pose.to_world(self.object.random(pose.to_object(origin - pose.offset), time))
+    }
+}
+
+// Where an Animated object is at one time: turned about y, then moved by `offset`.
+struct Pose {
+    sin_theta: f64,
+    cos_theta: f64,
+    offset: Vec3,
+}
+
+impl Pose {
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        let (sin_theta, cos_theta) = (self.sin_theta, self.cos_theta);
+        Vec3::new(cos_theta * v.x() - sin_theta * v.z(), v.y(), sin_theta * v.x() + cos_theta * v.z())
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        let (sin_theta, cos_theta) = (self.sin_theta, self.cos_theta);
+        Vec3::new(cos_theta * v.x() + sin_theta * v.z(), v.y(), -sin_theta * v.x() + cos_theta * v.z())
+    }
+
+    fn object_ray(&self, r: &Ray) -> Ray {
+        Ray::new_with_time(self.to_object(r.origin() - self.offset), self.to_object(r.direction()), r.time())
+    }
+
+    fn world_hit(&self, mut rec: HitRecord) -> HitRecord {
+        rec.p = self.to_world(rec.p) + self.offset;
+        rec.normal = self.to_world(rec.normal);
+        rec.dpdu = self.to_world(rec.dpdu);
+        rec.dpdv = self.to_world(rec.dpdv);
+        rec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::hittable::make_ref;
+    use crate::books::the_rest_of_your_life::material::{make_mat, EmptyMaterial};
+    use crate::books::the_rest_of_your_life::quad::make_box;
+    use crate::books::the_rest_of_your_life::rtweekend::seed_random;
+    use crate::books::the_rest_of_your_life::sphere::Sphere;
+    use crate::books::the_rest_of_your_life::test_support::assert_sampler_matches_pdf;
+
+    fn contains(bbox: &Aabb, p: Point3) -> bool {
+        (0..3).all(|axis| bbox.axis_interval(axis).expand(1e-9).contains(p[axis]))
+    }
+
+    // Keys along a wiggle with uneven spacing, so neighboring segments differ in length.
+    fn wiggle(interpolation: Interpolation) -> Track<Vec3> {
+        Track::new(interpolation)
+            .key(0.0, Vec3::new(0.0, 0.0, 0.0))
+            .key(2.0, Vec3::new(3.0, 4.0, -1.0))
+            .key(3.0, Vec3::new(-2.0, 1.0, 2.0))
+            .key(7.0, Vec3::new(5.0, -3.0, 0.0))
+    }
+
+    #[test]
+    fn linear_tracks_interpolate_between_keys() {
+        let track = Track::new(Interpolation::Linear).key(2.0, 5.0).key(0.0, 1.0).key(4.0, 3.0);
+        for (time, expected) in [(0.0, 1.0), (1.0, 3.0), (2.0, 5.0), (3.5, 3.5), (4.0, 3.0)] {
+            assert!((track.sample(time) - expected).abs() < 1e-12, "at {time}");
+        }
+
+        // Before the first key and after the last the track holds its ends.
+        assert_eq!(track.sample(-1.0), 1.0);
+        assert_eq!(track.sample(10.0), 3.0);
+        assert_eq!(Track::constant(7.0).sample(3.0), 7.0);
+    }
+
+    #[test]
+    fn catmull_rom_tracks_pass_through_keys_and_keep_straight_lines() {
+        let track = wiggle(Interpolation::CatmullRom);
+        for &(time, value) in &track.keys {
+            assert!((track.sample(time) - value).length() < 1e-12, "at key {time}");
+        }
+
+        // Keys on a line with uneven spacing give back the line everywhere.
+        let line = Track::new(Interpolation::CatmullRom).key(0.0, 0.0).key(1.0, 2.0).key(3.0, 6.0).key(3.5, 7.0);
+        for i in 0..=70 {
+            let time = i as f64 / 20.0;
+            assert!((line.sample(time) - 2.0 * time).abs() < 1e-12, "at {time}");
+        }
+
+        // Between keys the curve moves smoothly: no kinks where segments meet.
+        let step = 1e-6;
+        for &(time, _) in &track.keys[1..3] {
+            let before = (track.sample(time) - track.sample(time - step)) / step;
+            let after = (track.sample(time + step) - track.sample(time)) / step;
+            assert!((before - after).length() < 1e-4, "kink at {time}");
+        }
+    }
+
+    #[test]
+    fn hull_points_enclose_the_curve() {
+        for interpolation in [Interpolation::Linear, Interpolation::CatmullRom] {
+            let track = wiggle(interpolation);
+            let hull = track.hull_points();
+            let bbox = hull.iter().fold(Aabb::EMPTY, |bbox, &p| Aabb::from_boxes(bbox, Aabb::from_points(p, p)));
+            for i in 0..=700 {
+                let time = i as f64 / 100.0;
+                let p = track.sample(time);
+                assert!(contains(&bbox, p), "{interpolation:?} at {time}: {p:?} is outside {bbox:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn bounds_contain_the_object_at_every_time() {
+        let white = make_mat(EmptyMaterial);
+        let object = make_box(Point3::new(1.0, 0.0, -0.5), Point3::new(3.0, 2.0, 0.5), white);
+        let spin = Track::new(Interpolation::Linear).key(0.0, 15.0).key(4.0, 130.0);
+        let tilt = Track::constant(40.0);
+        let path = wiggle(Interpolation::CatmullRom);
+
+        for rotation in [spin, tilt] {
+            let animated = Animated::new(object.clone(), rotation, path.clone());
+            let bbox = animated.bounding_box();
+            let local = object.bounding_box();
+            for i in 0..=700 {
+                let time = i as f64 / 100.0;
+                let pose = animated.pose_at(time);
+                for corner in 0..8 {
+                    let c = Point3::new(
+                        if corner & 1 == 0 { local.x.min } else { local.x.max },
+                        if corner & 2 == 0 { local.y.min } else { local.y.max },
+                        if corner & 4 == 0 { local.z.min } else { local.z.max },
+                    );
+                    let p = pose.to_world(c) + pose.offset;
+                    assert!(contains(&bbox, p), "corner {c:?} at {time} is outside {bbox:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hits_and_light_samples_follow_the_track() {
+        seed_random(golden::SEED);
+        let ball = make_ref(Sphere::new(Point3::new(2.0, 0.0, 0.0), 1.0, make_mat(EmptyMaterial)));
+        let spin = Track::new(Interpolation::Linear).key(0.0, 0.0).key(2.0, 180.0);
+        let path = Track::new(Interpolation::Linear).key(0.0, Vec3::new(0.0, 0.0, 0.0)).key(2.0, Vec3::new(0.0, 4.0, 0.0));
+        let animated = Animated::new(ball, spin, path);
+
+        // Halfway the ball has turned a quarter turn about y, to -z, and risen by 2.
+        let center = Point3::new(0.0, 2.0, -2.0);
+        let eye = Point3::new(0.0, 2.0, 5.0);
+        let r = Ray::new_with_time(eye, center - eye, 1.0);
+        let rec = animated.hit(&r, Interval::new(0.001, INFINITY)).expect("the ray should meet the ball");
+        assert!((rec.p - Point3::new(0.0, 2.0, -1.0)).length() < 1e-9);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        assert!(animated.hit(&Ray::new_with_time(eye, center - eye, 0.0), Interval::new(0.001, INFINITY)).is_none());
+
+        // Seen from off the axes, so the ball never sits at a pole of the test's grid.
+        let origin = Point3::new(3.0, 2.0, 1.0);
+
+        for time in [0.0, 1.0, 1.5] {
+            assert_sampler_matches_pdf(
+                &format!("animated ball at time {time}"),
+                || animated.random(origin, time),
+                |d| animated.pdf_value(origin, d, time),
+            );
+        }
+        assert!(animated.pdf_value(origin, center - origin, 1.0) > 0.0);
+    }
+}
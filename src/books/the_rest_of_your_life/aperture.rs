@@ -0,0 +1,175 @@
+use super::rtw_image::RtwImage;
+use super::rtweekend::{random_double, random_int, PI};
+use super::vec3::{random_in_unit_disk, Vec3};
+
+pub enum Aperture {
+    Circle,
+    Polygon { blades: i32, rotation: f64 },
+    Mask(ApertureMask),
+}
+
+impl Aperture {
+    pub fn polygon(blades: i32, rotation: f64) -> Self {
+        Self::Polygon { blades: blades.max(3), rotation }
+    }
+
+    pub fn from_image(filename: &str) -> Self {
+        Self::Mask(ApertureMask::new(filename))
+    }
+
+    // "circle", a number of blades, or the file name of a mask image.
+    pub fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "circle" | "disk" => Aperture::Circle,
+            _ => match name.parse::<i32>() {
+                Ok(blades) => Aperture::polygon(blades, 0.0),
+                Err(_) => Aperture::from_image(name),
+            },
+        }
+    }
+
+    // Returns a point in the [-1,1] square (z = 0) distributed over the aperture opening.
+    pub fn sample(&self) -> Vec3 {
+        match self {
+            Aperture::Circle => random_in_unit_disk(),
+            Aperture::Polygon { blades, rotation } => sample_polygon(*blades, *rotation),
+            Aperture::Mask(mask) => mask.sample(),
+        }
+    }
+}
+
+fn sample_polygon(blades: i32, rotation: f64) -> Vec3 {
+    // A regular polygon inscribed in the unit circle is a fan of equal triangles, so pick
+    // one uniformly and then a uniform point inside it.
+    let blade = random_int(0, blades - 1) as f64;
+    let step = 2.0 * PI / blades as f64;
+    let a0 = rotation + blade * step;
+    let a1 = a0 + step;
+
+    let mut s = random_double();
+    let mut t = random_double();
+    if s + t > 1.0 {
+        s = 1.0 - s;
+        t = 1.0 - t;
+    }
+
+    Vec3::new(
+        s * a0.cos() + t * a1.cos(),
+        s * a0.sin() + t * a1.sin(),
+        0.0,
+    )
+}
+
+pub struct ApertureMask {
+    width: i32,
+    height: i32,
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    pub fn new(filename: &str) -> Self {
+        let image = RtwImage::new(filename);
+        let width = image.width();
+        let height = image.height();
+
+        // Pixel brightness is the transmission of the aperture at that point.
+        let mut transmission = Vec::with_capacity((width * height).max(0) as usize);
+        for j in 0..height {
+            for i in 0..width {
+                let pixel = image.pixel(i, j);
+                transmission.push((pixel[0] + pixel[1] + pixel[2]) as f64 / 3.0);
+            }
+        }
+
+        let mask = Self::from_transmission(width, height, &transmission);
+        if mask.cdf.is_empty() {
+            eprintln!("WARNING: Aperture mask '{}' is fully opaque; using a pinhole.", filename);
+        }
+        mask
+    }
+
+    // One transmission value per pixel, row by row from the top.
+    pub fn from_transmission(width: i32, height: i32, transmission: &[f64]) -> Self {
+        let mut cdf = Vec::with_capacity(transmission.len());
+        let mut total = 0.0;
+        for &t in transmission {
+            total += t;
+            cdf.push(total);
+        }
+        if total <= 0.0 {
+            cdf.clear();
+        }
+
+        Self { width, height, cdf }
+    }
+
+    fn sample(&self) -> Vec3 {
+        let Some(&total) = self.cdf.last() else {
+            return Vec3::new(0.0, 0.0, 0.0);
+        };
+
+        let target = random_double() * total;
+        let index = self.cdf.partition_point(|&c| c <= target).min(self.cdf.len() - 1) as i32;
+        let i = index % self.width;
+        let j = index / self.width;
+
+        // Image rows run top to bottom; aperture y runs bottom to top.
+        let x = (i as f64 + random_double()) / self.width as f64;
+        let y = (j as f64 + random_double()) / self.height as f64;
+        Vec3::new(2.0 * x - 1.0, 1.0 - 2.0 * y, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: usize = 10_000;
+
+    #[test]
+    fn polygon_samples_stay_inside_the_blades() {
+        for (blades, rotation) in [(3, 0.0), (5, 0.3), (6, PI / 6.0), (9, 1.0)] {
+            let aperture = Aperture::polygon(blades, rotation);
+            // Each edge is an apothem away from the center along its mid-angle.
+            let step = 2.0 * PI / blades as f64;
+            let apothem = (step / 2.0).cos();
+            for _ in 0..SAMPLES {
+                let p = aperture.sample();
+                assert_eq!(p.z(), 0.0);
+                for k in 0..blades {
+                    let mid = rotation + (k as f64 + 0.5) * step;
+                    let distance = p.x() * mid.cos() + p.y() * mid.sin();
+                    assert!(distance <= apothem + 1e-12, "{} blades: {:?} is outside", blades, p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mask_samples_stay_in_open_pixels() {
+        // A 4x4 mask open only in the top-right pixel and, more faintly, the bottom-left one.
+        let mut transmission = [0.0; 16];
+        transmission[3] = 1.0;
+        transmission[12] = 0.25;
+        let aperture = Aperture::Mask(ApertureMask::from_transmission(4, 4, &transmission));
+
+        let mut top_right = 0;
+        for _ in 0..SAMPLES {
+            let p = aperture.sample();
+            if p.x() >= 0.5 && p.y() >= 0.5 {
+                top_right += 1;
+            } else {
+                assert!(p.x() <= -0.5 && p.y() <= -0.5, "{:?} is in a closed pixel", p);
+            }
+            assert!(p.x().abs() <= 1.0 && p.y().abs() <= 1.0, "{:?} is off the mask", p);
+        }
+        let share = top_right as f64 / SAMPLES as f64;
+        assert!((share - 0.8).abs() < 0.03, "top right pixel gets {}", share);
+    }
+
+    #[test]
+    fn opaque_masks_sample_a_pinhole() {
+        let aperture = Aperture::Mask(ApertureMask::from_transmission(2, 2, &[0.0; 4]));
+        assert_eq!(aperture.sample().length(), 0.0);
+    }
+}
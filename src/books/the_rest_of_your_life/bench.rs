@@ -0,0 +1,172 @@
+use std::time::Instant;
+
+use super::bvh::BvhNode;
+use super::flat_bvh::{BvhOptions, FlatBvh};
+use super::hittable::{make_ref, Hittable};
+use super::hittable_list::HittableList;
+use super::interval::Interval;
+use super::material::{make_mat, Lambertian};
+use super::qbvh::{Qbvh, WIDTH};
+use super::ray::Ray;
+use super::rtweekend::INFINITY;
+use super::sphere::Sphere;
+use super::vec3::{random_unit_vector, Color, Point3, Vec3};
+
+const RAY_COUNT: usize = 1_000_000;
+
+// The 1000-sphere cluster from the_next_week's final scene.
+fn sphere_cluster() -> HittableList {
+    let mut spheres = HittableList::new();
+    let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    for _ in 0..1000 {
+        spheres.add(make_ref(Sphere::new(Vec3::random_range(0.0, 165.0), 10.0, white.clone())));
+    }
+    spheres
+}
+
+// Rays from all around the cluster aimed at random points inside it, the incoherent
+// workload the cluster sees from secondary bounces in the final scene.
+fn benchmark_rays() -> Vec<Ray> {
+    let center = Point3::new(82.5, 82.5, 82.5);
+    (0..RAY_COUNT)
+        .map(|_| {
+            let origin = center + 400.0 * random_unit_vector();
+            let target = Vec3::random_range(0.0, 165.0);
+            Ray::new(origin, target - origin)
+        })
+        .collect()
+}
+
+// A pinhole camera's primary rays into the cluster, row by row, so that every group of
+// WIDTH consecutive rays is a coherent packet.
+fn primary_rays() -> Vec<Ray> {
+    let origin = Point3::new(82.5, 82.5, -400.0);
+    let side = (RAY_COUNT as f64).sqrt() as usize / WIDTH * WIDTH;
+    let mut rays = Vec::with_capacity(side * side);
+    for y in 0..side {
+        for x in 0..side {
+            let target = Point3::new(
+                -40.0 + 245.0 * x as f64 / side as f64,
+                -40.0 + 245.0 * y as f64 / side as f64,
+                82.5,
+            );
+            rays.push(Ray::new(origin, target - origin));
+        }
+    }
+    rays
+}
+
+fn time_traversal<H: Hittable>(name: &str, world: &H, rays: &[Ray]) -> (f64, Vec<Option<f64>>) {
+    let start = Instant::now();
+    let hits: Vec<Option<f64>> = rays
+        .iter()
+        .map(|r| world.hit(r, Interval::new(0.001, INFINITY)).map(|rec| rec.t))
+        .collect();
+    report(name, start.elapsed().as_secs_f64(), hits)
+}
+
+fn time_packets(name: &str, world: &Qbvh, rays: &[Ray]) -> (f64, Vec<Option<f64>>) {
+    let start = Instant::now();
+    let mut hits = Vec::with_capacity(rays.len());
+    for chunk in rays.chunks_exact(WIDTH) {
+        let packet: &[Ray; WIDTH] = chunk.try_into().unwrap();
+        hits.extend(world.hit_packet(packet, Interval::new(0.001, INFINITY)).map(|rec| rec.map(|rec| rec.t)));
+    }
+    report(name, start.elapsed().as_secs_f64(), hits)
+}
+
+fn report(name: &str, seconds: f64, hits: Vec<Option<f64>>) -> (f64, Vec<Option<f64>>) {
+    println!(
+        "{:<10} {:>8.3} s  {:>8.2} Mrays/s  {} hits",
+        name,
+        seconds,
+        hits.len() as f64 / seconds / 1e6,
+        hits.iter().filter(|t| t.is_some()).count()
+    );
+    (seconds, hits)
+}
+
+fn report_mismatches(a_name: &str, a: &[Option<f64>], b_name: &str, b: &[Option<f64>]) {
+    let mismatches = a
+        .iter()
+        .zip(b)
+        .filter(|(a, b)| match (a, b) {
+            (Some(a), Some(b)) => a.to_bits() != b.to_bits(),
+            (None, None) => false,
+            _ => true,
+        })
+        .count();
+    if mismatches > 0 {
+        println!("WARNING: {} rays disagree between {} and {}", mismatches, a_name, b_name);
+    }
+}
+
+pub fn run() {
+    let cluster = sphere_cluster();
+    let rays = benchmark_rays();
+
+    let mut tree_objects = HittableList::new();
+    let mut flat_objects = HittableList::new();
+    for object in &cluster.objects {
+        tree_objects.add(object.clone());
+        flat_objects.add(object.clone());
+    }
+
+    let start = Instant::now();
+    let tree = BvhNode::new(tree_objects);
+    let tree_build = start.elapsed().as_secs_f64();
+    let start = Instant::now();
+    let flat = FlatBvh::new(flat_objects);
+    let flat_build = start.elapsed().as_secs_f64();
+
+    println!("{} spheres, {} rays (single thread)", cluster.objects.len(), rays.len());
+    println!("build: BvhNode {:.3} ms, FlatBvh {:.3} ms", tree_build * 1e3, flat_build * 1e3);
+
+    let (tree_time, tree_hits) = time_traversal("BvhNode", &tree, &rays);
+    let (flat_time, flat_hits) = time_traversal("FlatBvh", &flat, &rays);
+
+    println!("speedup: {:.2}x", tree_time / flat_time);
+    report_mismatches("BvhNode", &tree_hits, "FlatBvh", &flat_hits);
+
+    let mut wide_objects = HittableList::new();
+    for object in &cluster.objects {
+        wide_objects.add(object.clone());
+    }
+    let wide = Qbvh::new(wide_objects);
+    let simd = if wide.uses_simd() { "AVX" } else { "scalar" };
+
+    println!();
+    println!("4-wide BVH ({} node test):", simd);
+    let (wide_time, wide_hits) = time_traversal("Qbvh", &wide, &rays);
+    println!("speedup over FlatBvh: {:.2}x", flat_time / wide_time);
+    report_mismatches("FlatBvh", &flat_hits, "Qbvh", &wide_hits);
+
+    let primary = primary_rays();
+    println!();
+    println!("{} coherent primary rays:", primary.len());
+    let (_, flat_primary) = time_traversal("FlatBvh", &flat, &primary);
+    let (single_time, single_hits) = time_traversal("Qbvh", &wide, &primary);
+    let (packet_time, packet_hits) = time_packets("packets", &wide, &primary);
+    println!("packet speedup: {:.2}x", single_time / packet_time);
+    report_mismatches("FlatBvh", &flat_primary, "Qbvh", &single_hits);
+    report_mismatches("Qbvh", &single_hits, "packets", &packet_hits);
+
+    println!();
+    println!("FlatBvh quality by leaf size:");
+    for max_leaf_size in [1, 2, 4, 8] {
+        let mut objects = HittableList::new();
+        for object in &cluster.objects {
+            objects.add(object.clone());
+        }
+        let options = BvhOptions { max_leaf_size, ..BvhOptions::default() };
+        let bvh = FlatBvh::with_options(objects, options);
+        let traversal = bvh.measure(&rays);
+        println!(
+            "  max leaf {}: {}; per ray {:.1} nodes, {:.1} primitives",
+            max_leaf_size,
+            bvh.stats(),
+            traversal.nodes_per_ray(),
+            traversal.primitives_per_ray()
+        );
+    }
+}
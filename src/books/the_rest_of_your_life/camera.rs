@@ -1,15 +1,22 @@
+use std::fs::File;
 use std::io::{self, BufWriter, Write};
+use std::ops::Range;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use rayon::prelude::*;
 
+use crate::config::Projection;
+
+use super::animation::CameraAnimation;
+use super::aperture::Aperture;
 use super::color::write_color;
 use super::hittable::{Hittable, HittableRef};
 use super::interval::Interval;
+use super::lens::RealisticLens;
 use super::pdf::{make_pdf, HittablePdf, MixturePdf, Pdf};
-use super::ray::Ray;
-use super::rtweekend::{degrees_to_radians, random_double, INFINITY};
+use super::ray::{Ray, RayCone};
+use super::rtweekend::{degrees_to_radians, random_double, seed_random, INFINITY, PI};
 use super::vec3::{
-    cross, random_in_unit_disk, unit_vector, Color, Point3, Vec3,
+    cross, dot, unit_vector, Color, Point3, Vec3,
 };
 
 pub struct Camera {
@@ -26,6 +33,15 @@ pub struct Camera {
 
     pub defocus_angle: f64,
     pub focus_dist: f64,
+
+    pub projection: Projection,
+    // Makes renders repeatable: each row restarts the random sequence from this seed.
+    pub seed: Option<u64>,
+    pub aperture: Aperture,
+    pub lens: Option<RealisticLens>,
+
+    pub shutter_open: f64,
+    pub shutter_close: f64,
 }
 
 impl Default for Camera {
@@ -42,6 +58,12 @@ impl Default for Camera {
             vup: Vec3::new(0.0, 1.0, 0.0),
             defocus_angle: 0.0,
             focus_dist: 10.0,
+            projection: Projection::Perspective,
+            seed: None,
+            aperture: Aperture::Circle,
+            lens: None,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
         }
     }
 }
@@ -55,12 +77,66 @@ struct CameraInternals {
     pixel00_loc: Point3,
     pixel_delta_u: Vec3,
     pixel_delta_v: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
+    lens: Option<RealisticLens>,
+    film_width: f64,
+    film_height: f64,
+    pixel_cone: RayCone,
 }
 
 impl Camera {
     pub fn render<H: Hittable>(&self, world: &H, lights: HittableRef) {
+        let stdout = io::stdout();
+        let mut out = BufWriter::new(stdout.lock());
+        self.render_to(world, lights, &mut out);
+    }
+
+    pub fn render_sequence<H: Hittable>(
+        &mut self,
+        world: &H,
+        lights: HittableRef,
+        animation: &CameraAnimation,
+        frames: Range<i32>,
+    ) {
+        for frame in frames {
+            animation.apply(self, frame);
+            self.render_frame(world, lights.clone(), frame);
+        }
+    }
+
+    // Like render_sequence, but lets `update` change the world before each frame, e.g. to
+    // move objects and refit their BVH. It runs after the camera animation is applied, so
+    // it can read the frame's shutter interval from the camera.
+    pub fn render_sequence_with<H, F>(
+        &mut self,
+        world: &mut H,
+        lights: HittableRef,
+        animation: &CameraAnimation,
+        frames: Range<i32>,
+        mut update: F,
+    ) where
+        H: Hittable,
+        F: FnMut(&mut H, &Camera, i32),
+    {
+        for frame in frames {
+            animation.apply(self, frame);
+            update(world, self, frame);
+            self.render_frame(world, lights.clone(), frame);
+        }
+    }
+
+    fn render_frame<H: Hittable>(&self, world: &H, lights: HittableRef, frame: i32) {
+        let filename = format!("frame_{:04}.ppm", frame);
+        let file = File::create(&filename).unwrap_or_else(|e| panic!("failed to create {}: {}", filename, e));
+        eprintln!("Rendering frame {} to {}", frame, filename);
+        self.render_to(world, lights, &mut BufWriter::new(file));
+    }
+
+    pub fn render_to<H: Hittable, W: Write>(&self, world: &H, lights: HittableRef, out: &mut W) {
         let data = self.initialize();
 
         let image_height = data.image_height as usize;
@@ -70,14 +146,18 @@ impl Camera {
         let rows: Vec<Vec<u8>> = (0..image_height)
             .into_par_iter()
             .map(|j| {
+                if let Some(seed) = self.seed {
+                    seed_random(seed ^ (j as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+                }
                 let mut row = Vec::with_capacity(image_width * 12);
                 let j_i32 = j as i32;
                 for i in 0..self.image_width {
                     let mut pixel_color = Color::new(0.0, 0.0, 0.0);
                     for s_j in 0..data.sqrt_spp {
                         for s_i in 0..data.sqrt_spp {
-                            let r = self.get_ray(i, j_i32, s_i, s_j, &data);
-                            pixel_color += self.ray_color(r, self.max_depth, world, lights.clone());
+                            if let Some(r) = self.get_ray(i, j_i32, s_i, s_j, &data) {
+                                pixel_color += self.ray_color(r, self.max_depth, world, lights.clone());
+                            }
                         }
                     }
                     write_color(&mut row, data.pixel_samples_scale * pixel_color);
@@ -93,14 +173,13 @@ impl Camera {
             })
             .collect();
 
-        let stdout = io::stdout();
-        let mut out = BufWriter::new(stdout.lock());
         writeln!(out, "P3\n{} {}\n255", self.image_width, data.image_height)
             .expect("failed to write header");
 
         for row in rows {
             out.write_all(&row).expect("failed to write pixel data");
         }
+        out.flush().expect("failed to flush output");
 
         eprintln!("\rDone.                 ");
     }
@@ -139,6 +218,32 @@ impl Camera {
         let defocus_disk_u = u * defocus_radius;
         let defocus_disk_v = v * defocus_radius;
 
+        // A realistic lens replaces vfov and the defocus disk: the field of view follows from
+        // the film size and focal length, and depth of field from the aperture stop.
+        let lens = self.lens.as_ref().map(|lens| lens.focused(self.focus_dist));
+        let (film_width, film_height) = match &lens {
+            Some(lens) => {
+                let diagonal = (self.image_width as f64).hypot(image_height as f64);
+                (
+                    lens.film_diagonal() * self.image_width as f64 / diagonal,
+                    lens.film_diagonal() * image_height as f64 / diagonal,
+                )
+            }
+            None => (0.0, 0.0),
+        };
+
+        // Each camera ray stands for a cone one pixel across.
+        let pixel_cone = match (&lens, self.projection) {
+            (Some(lens), _) => RayCone {
+                width: 0.0,
+                spread: lens.focal_length().map_or(0.0, |f| film_height / image_height as f64 / f),
+            },
+            (None, Projection::Perspective) => RayCone { width: 0.0, spread: pixel_delta_v.length() / self.focus_dist },
+            (None, Projection::Orthographic) => RayCone { width: pixel_delta_v.length(), spread: 0.0 },
+            (None, Projection::Fisheye) => RayCone { width: 0.0, spread: theta / image_height as f64 },
+            (None, Projection::Equirectangular) => RayCone { width: 0.0, spread: PI / image_height as f64 },
+        };
+
         CameraInternals {
             image_height,
             pixel_samples_scale,
@@ -148,26 +253,97 @@ impl Camera {
             pixel00_loc,
             pixel_delta_u,
             pixel_delta_v,
+            u,
+            v,
+            w,
             defocus_disk_u,
             defocus_disk_v,
+            lens,
+            film_width,
+            film_height,
+            pixel_cone,
         }
     }
 
-    fn get_ray(&self, i: i32, j: i32, s_i: i32, s_j: i32, data: &CameraInternals) -> Ray {
+    fn get_ray(&self, i: i32, j: i32, s_i: i32, s_j: i32, data: &CameraInternals) -> Option<Ray> {
         let offset = self.sample_square_stratified(s_i, s_j, data.recip_sqrt_spp);
+        if let Some(lens) = &data.lens {
+            return self.lens_ray(i, j, offset, lens, data);
+        }
+
         let pixel_sample = data.pixel00_loc
             + (i as f64 + offset.x()) * data.pixel_delta_u
             + (j as f64 + offset.y()) * data.pixel_delta_v;
+        let x = i as f64 + 0.5 + offset.x();
+        let y = j as f64 + 0.5 + offset.y();
+
+        // Every projection picks a lens center and a point on the plane of focus; the
+        // defocus disk is then centered on the lens so depth of field works for all of them.
+        let (lens_center, focus_point) = match self.projection {
+            Projection::Perspective => (data.center, pixel_sample),
+            Projection::Orthographic => (pixel_sample + self.focus_dist * data.w, pixel_sample),
+            Projection::Fisheye => {
+                let direction = self.fisheye_direction(x, y, data)?;
+                (data.center, data.center + self.focus_dist * direction)
+            }
+            Projection::Equirectangular => {
+                let direction = self.equirectangular_direction(x, y, data);
+                (data.center, data.center + self.focus_dist * direction)
+            }
+        };
 
         let ray_origin = if self.defocus_angle <= 0.0 {
-            data.center
+            lens_center
         } else {
-            self.defocus_disk_sample(data)
+            self.defocus_disk_sample(lens_center, data)
         };
-        let ray_direction = pixel_sample - ray_origin;
-        let ray_time = random_double();
+        let ray_direction = focus_point - ray_origin;
+        let ray_time = self.sample_time();
 
-        Ray::new_with_time(ray_origin, ray_direction, ray_time)
+        Some(Ray::new_with_time(ray_origin, ray_direction, ray_time).with_cone(data.pixel_cone))
+    }
+
+    fn lens_ray(&self, i: i32, j: i32, offset: Vec3, lens: &RealisticLens, data: &CameraInternals) -> Option<Ray> {
+        let film_x = ((i as f64 + 0.5 + offset.x()) / self.image_width as f64 - 0.5) * data.film_width;
+        let film_y = ((j as f64 + 0.5 + offset.y()) / data.image_height as f64 - 0.5) * data.film_height;
+        let r = lens.sample_ray(film_x, film_y)?;
+
+        // Lens space looks down +z with y up; the camera looks down -w with v up.
+        let o = r.origin();
+        let d = r.direction();
+        let ray_origin = data.center + o.x() * data.u + o.y() * data.v - o.z() * data.w;
+        let ray_direction = d.x() * data.u + d.y() * data.v - d.z() * data.w;
+
+        Some(Ray::new_with_time(ray_origin, ray_direction, self.sample_time()).with_cone(data.pixel_cone))
+    }
+
+    fn fisheye_direction(&self, x: f64, y: f64, data: &CameraInternals) -> Option<Vec3> {
+        // Equidistant mapping: the angle off the view axis grows linearly with the
+        // distance from the image center.
+        let px = x - 0.5 * self.image_width as f64;
+        let py = 0.5 * data.image_height as f64 - y;
+        let r = (px * px + py * py).sqrt();
+        let theta = r * degrees_to_radians(self.vfov) / data.image_height as f64;
+        if theta > PI {
+            return None;
+        }
+        if r <= 0.0 {
+            return Some(-data.w);
+        }
+
+        let radial = (px / r) * data.u + (py / r) * data.v;
+        Some(theta.sin() * radial - theta.cos() * data.w)
+    }
+
+    fn equirectangular_direction(&self, x: f64, y: f64, data: &CameraInternals) -> Vec3 {
+        let longitude = (x / self.image_width as f64 - 0.5) * 2.0 * PI;
+        let latitude = (0.5 - y / data.image_height as f64) * PI;
+        latitude.cos() * longitude.sin() * data.u + latitude.sin() * data.v
+            - latitude.cos() * longitude.cos() * data.w
+    }
+
+    fn sample_time(&self) -> f64 {
+        self.shutter_open + random_double() * (self.shutter_close - self.shutter_open)
     }
 
     fn sample_square_stratified(&self, s_i: i32, s_j: i32, recip_sqrt_spp: f64) -> Vec3 {
@@ -176,9 +352,9 @@ impl Camera {
         Vec3::new(px, py, 0.0)
     }
 
-    fn defocus_disk_sample(&self, data: &CameraInternals) -> Point3 {
-        let p = random_in_unit_disk();
-        data.center + (p[0] * data.defocus_disk_u) + (p[1] * data.defocus_disk_v)
+    fn defocus_disk_sample(&self, lens_center: Point3, data: &CameraInternals) -> Point3 {
+        let p = self.aperture.sample();
+        lens_center + (p[0] * data.defocus_disk_u) + (p[1] * data.defocus_disk_v)
     }
 
     fn ray_color<H: Hittable>(&self, r: Ray, depth: i32, world: &H, lights: HittableRef) -> Color {
@@ -186,10 +362,17 @@ impl Camera {
             return Color::new(0.0, 0.0, 0.0);
         }
 
-        let Some(rec) = world.hit(&r, Interval::new(0.001, INFINITY)) else {
+        let Some(mut rec) = world.hit(&r, Interval::new(0.001, INFINITY)) else {
             return self.background;
         };
 
+        // The ray's cone meets the surface at an angle, stretching its footprint. Rays
+        // leaving the hit carry the cone on from there.
+        let cone_width = r.cone().width_at(rec.t * r.direction().length());
+        let cos_theta = dot(unit_vector(r.direction()), rec.normal).abs();
+        rec.footprint = cone_width / cos_theta.max(1e-3);
+        let cone = RayCone { width: cone_width, spread: r.cone().spread };
+
         let emitted = rec.mat.emitted(&r, &rec, rec.u, rec.v, rec.p);
 
         let Some(srec) = rec.mat.scatter(&r, &rec) else {
@@ -210,10 +393,10 @@ impl Camera {
                 }
                 return emitted
                     + (srec.attenuation
-                        * self.ray_color(srec.skip_pdf_ray, depth - 1, world, lights)
+                        * self.ray_color(srec.skip_pdf_ray.with_cone(cone), depth - 1, world, lights)
                         / p);
             }
-            return emitted + srec.attenuation * self.ray_color(srec.skip_pdf_ray, depth - 1, world, lights);
+            return emitted + srec.attenuation * self.ray_color(srec.skip_pdf_ray.with_cone(cone), depth - 1, world, lights);
         }
 
         let Some(pdf_ptr) = srec.pdf_ptr else {
@@ -236,20 +419,85 @@ impl Camera {
             return emitted;
         }
 
-        let light_pdf = make_pdf(HittablePdf::new(lights.clone(), rec.p));
+        let light_pdf = make_pdf(HittablePdf::new(lights.clone(), rec.p).with_time(r.time()));
         let mixed_pdf = MixturePdf::new(light_pdf, pdf_ptr);
 
-        let scattered = Ray::new_with_time(rec.p, mixed_pdf.generate(), r.time());
+        let scattered = Ray::new_with_time(rec.p, mixed_pdf.generate(), r.time()).with_cone(cone);
         let pdf_value = mixed_pdf.value(scattered.direction());
         if pdf_value <= 0.0 {
             return emitted;
         }
 
-        let scattering_pdf = rec.mat.scattering_pdf(&r, &rec, &scattered);
+        let scattering = rec.mat.scattering(&r, &rec, srec.attenuation, &scattered);
         let sample_color = self.ray_color(scattered, depth - 1, world, lights);
-        let color_from_scatter =
-            srec.attenuation * scattering_pdf * sample_color / (pdf_value * rr_prob);
+        let color_from_scatter = scattering * sample_color / (pdf_value * rr_prob);
 
         emitted + color_from_scatter
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A square image looking from an arbitrary point, so u, v and w are not the axes.
+    fn camera(projection: Projection, vfov: f64) -> Camera {
+        Camera {
+            image_width: 100,
+            vfov,
+            lookfrom: Point3::new(1.0, 2.0, 3.0),
+            lookat: Point3::new(-2.0, 1.0, -1.0),
+            projection,
+            ..Camera::default()
+        }
+    }
+
+    fn angle_between(a: Vec3, b: Vec3) -> f64 {
+        dot(unit_vector(a), unit_vector(b)).clamp(-1.0, 1.0).acos()
+    }
+
+    #[test]
+    fn image_centers_look_along_the_view_axis() {
+        for projection in [Projection::Fisheye, Projection::Equirectangular] {
+            let cam = camera(projection, 120.0);
+            let data = cam.initialize();
+            let direction = match projection {
+                Projection::Fisheye => cam.fisheye_direction(50.0, 50.0, &data).unwrap(),
+                _ => cam.equirectangular_direction(50.0, 50.0, &data),
+            };
+            assert!((direction + data.w).length() < 1e-9, "{projection:?}: {direction:?}");
+        }
+    }
+
+    #[test]
+    fn fisheye_edges_are_half_the_field_of_view_off_axis() {
+        let cam = camera(Projection::Fisheye, 120.0);
+        let data = cam.initialize();
+        for (x, y, side) in [(50.0, 0.0, data.v), (50.0, 100.0, -data.v), (0.0, 50.0, -data.u), (100.0, 50.0, data.u)] {
+            let direction = cam.fisheye_direction(x, y, &data).unwrap();
+            assert!((direction.length() - 1.0).abs() < 1e-9);
+            assert!((angle_between(direction, -data.w) - degrees_to_radians(60.0)).abs() < 1e-9);
+            assert!(dot(direction, side) > 0.0, "({x}, {y}) leans the wrong way: {direction:?}");
+        }
+
+        // Past 180 degrees off axis there is nothing to see.
+        let wide = camera(Projection::Fisheye, 270.0);
+        let data = wide.initialize();
+        assert!(wide.fisheye_direction(50.0, 0.0, &data).is_some());
+        assert!(wide.fisheye_direction(0.0, 0.0, &data).is_none());
+    }
+
+    #[test]
+    fn equirectangular_images_wrap_around_behind_the_camera() {
+        let cam = camera(Projection::Equirectangular, 90.0);
+        let data = cam.initialize();
+        let left = cam.equirectangular_direction(0.0, 50.0, &data);
+        let right = cam.equirectangular_direction(100.0, 50.0, &data);
+        assert!((left - right).length() < 1e-9);
+        assert!((left - data.w).length() < 1e-9);
+
+        // A quarter of the way across looks left, and the top row looks straight up.
+        assert!((cam.equirectangular_direction(25.0, 50.0, &data) + data.u).length() < 1e-9);
+        assert!((cam.equirectangular_direction(50.0, 0.0, &data) - data.v).length() < 1e-9);
+    }
+}
@@ -75,6 +75,13 @@ impl Hittable for ConstantMedium {
             u: 0.0,
             v: 0.0,
             front_face: true,
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
+            footprint: 0.0,
+            object_p: p,
+            object_normal: Vec3::new(1.0, 0.0, 0.0),
+            object_dpdu: Vec3::new(0.0, 0.0, 0.0),
+            object_dpdv: Vec3::new(0.0, 0.0, 0.0),
         })
     }
 
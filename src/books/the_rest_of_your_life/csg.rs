@@ -0,0 +1,299 @@
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable, HittableRef};
+use super::interval::Interval;
+use super::ray::Ray;
+use super::rtweekend::INFINITY;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOp {
+    fn inside(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            CsgOp::Union => in_a || in_b,
+            CsgOp::Intersection => in_a && in_b,
+            CsgOp::Difference => in_a && !in_b,
+        }
+    }
+}
+
+// Combines two closed objects. The ray's crossings of both children are merged along the
+// whole line, so the children's inside/outside state is known even for rays starting on
+// or inside them, and the node reports the crossings where the combined state changes.
+// Surfaces carved out by the second object of a difference keep that object's material.
+// Alpha masks cut holes in the combined surface; they don't change what is solid, so a
+// masked face of one child doesn't uncover the other child's faces inside the union.
+pub struct Csg {
+    op: CsgOp,
+    a: HittableRef,
+    b: HittableRef,
+    bbox: Aabb,
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, a: HittableRef, b: HittableRef) -> Self {
+        let (box_a, box_b) = (a.bounding_box(), b.bounding_box());
+        let bbox = match op {
+            CsgOp::Union => Aabb::from_boxes(box_a, box_b),
+            CsgOp::Intersection => overlap(&box_a, &box_b),
+            CsgOp::Difference => box_a,
+        };
+        Self { op, a, b, bbox }
+    }
+
+    pub fn union(a: HittableRef, b: HittableRef) -> Self {
+        Self::new(CsgOp::Union, a, b)
+    }
+
+    pub fn intersection(a: HittableRef, b: HittableRef) -> Self {
+        Self::new(CsgOp::Intersection, a, b)
+    }
+
+    pub fn difference(a: HittableRef, b: HittableRef) -> Self {
+        Self::new(CsgOp::Difference, a, b)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        self.crossings(r, ray_t).into_iter().find(|rec| rec.mat.is_opaque(rec))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        if !self.bbox.hit(r, ray_t) {
+            return Vec::new();
+        }
+
+        // Whether the ray is inside each child at ray_t.min depends on every crossing before
+        // it, but nothing past ray_t.max can change what is reported.
+        let children_t = Interval::new(-INFINITY, ray_t.max);
+        let mut events: Vec<(HitRecord, bool)> = Vec::new();
+        events.extend(self.a.crossings(r, children_t).into_iter().map(|rec| (rec, true)));
+        events.extend(self.b.crossings(r, children_t).into_iter().map(|rec| (rec, false)));
+        events.sort_by(|x, y| x.0.t.total_cmp(&y.0.t));
+
+        let mut in_a = false;
+        let mut in_b = false;
+        let mut inside = false;
+        let mut crossings = Vec::new();
+        let mut events = events.into_iter().peekable();
+        while let Some(first) = events.next() {
+            // Children crossed at the same point, where their surfaces touch, change state
+            // together; taken one at a time they would add a crossing of no thickness.
+            let mut group = vec![first];
+            while let Some(next) = events.next_if(|next| next.0.t - group[0].0.t <= 1e-9 * (1.0 + group[0].0.t.abs())) {
+                group.push(next);
+            }
+
+            // A front face crossing enters the child, anything else leaves it.
+            let before = (in_a, in_b);
+            for (rec, from_a) in &group {
+                if *from_a {
+                    in_a = rec.front_face;
+                } else {
+                    in_b = rec.front_face;
+                }
+            }
+
+            let now_inside = self.op.inside(in_a, in_b);
+            if now_inside == inside {
+                continue;
+            }
+            inside = now_inside;
+
+            // Report the surface of the child that makes the change.
+            let makes_change = |(rec, from_a): &(HitRecord, bool)| {
+                let (a, b) = if *from_a { (rec.front_face, before.1) } else { (before.0, rec.front_face) };
+                self.op.inside(a, b) == now_inside
+            };
+            let index = group.iter().position(makes_change).unwrap_or(0);
+            let (mut rec, from_a) = group.swap_remove(index);
+
+            if ray_t.surrounds(rec.t) {
+                let mut outward_normal = if rec.front_face { rec.normal } else { -rec.normal };
+                if self.op == CsgOp::Difference && !from_a {
+                    outward_normal = -outward_normal;
+                }
+                rec.set_face_normal(r, outward_normal);
+                crossings.push(rec);
+            }
+        }
+
+        crossings
+    }
+}
+
+fn overlap(a: &Aabb, b: &Aabb) -> Aabb {
+    let axis = |n: usize| {
+        let (x, y) = (a.axis_interval(n), b.axis_interval(n));
+        Interval::new(x.min.max(y.min), x.max.min(y.max))
+    };
+    let (x, y, z) = (axis(0), axis(1), axis(2));
+    if x.size() < 0.0 || y.size() < 0.0 || z.size() < 0.0 {
+        return Aabb::EMPTY;
+    }
+    Aabb::new(x, y, z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::the_rest_of_your_life::hittable::{make_ref, RotateY, Translate};
+    use crate::books::the_rest_of_your_life::hittable_list::HittableList;
+    use crate::books::the_rest_of_your_life::material::{make_mat, AlphaMask, EmptyMaterial, MaterialRef};
+    use crate::books::the_rest_of_your_life::sphere::Sphere;
+    use crate::books::the_rest_of_your_life::texture::{make_tex, SolidColor};
+    use crate::books::the_rest_of_your_life::transform::{MotionTransform, Transform};
+    use crate::books::the_rest_of_your_life::vec3::{Point3, Vec3};
+
+    fn ball(x: f64, radius: f64, mat: MaterialRef) -> HittableRef {
+        make_ref(Sphere::new(Point3::new(x, 0.0, 0.0), radius, mat))
+    }
+
+    // Two unit balls overlapping along x: a spans [-1.5, 0.5] and b spans [-0.5, 1.5].
+    fn pair() -> (HittableRef, HittableRef) {
+        (ball(-0.5, 1.0, make_mat(EmptyMaterial)), ball(0.5, 1.0, make_mat(EmptyMaterial)))
+    }
+
+    // Where a ray along +x from `start` crosses the object, and whether it goes in there.
+    fn crossings_from<H: Hittable>(object: &H, start: f64, ray_t: Interval) -> Vec<(f64, bool)> {
+        let r = Ray::new(Point3::new(start, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        object
+            .crossings(&r, ray_t)
+            .into_iter()
+            .map(|rec| {
+                // The normal faces the ray, and points out of the solid on the way in.
+                assert!(rec.normal.x() < 0.0, "normal {:?} at {}", rec.normal, rec.p.x());
+                (rec.p.x(), rec.front_face)
+            })
+            .collect()
+    }
+
+    fn assert_crossings(name: &str, actual: Vec<(f64, bool)>, expected: &[(f64, bool)]) {
+        assert_eq!(actual.len(), expected.len(), "{}: crosses at {:?}", name, actual);
+        for (&(x, entering), &(expected_x, expected_entering)) in actual.iter().zip(expected) {
+            assert!(
+                (x - expected_x).abs() < 1e-9 && entering == expected_entering,
+                "{}: crosses at {:?}, expected {:?}",
+                name,
+                actual,
+                expected
+            );
+        }
+    }
+
+    const FROM_OUTSIDE: Interval = Interval { min: 0.001, max: INFINITY };
+
+    #[test]
+    fn operations_combine_the_crossings() {
+        let (a, b) = pair();
+        let cases = [
+            ("union", Csg::union(a.clone(), b.clone()), vec![(-1.5, true), (1.5, false)]),
+            ("intersection", Csg::intersection(a.clone(), b.clone()), vec![(-0.5, true), (0.5, false)]),
+            ("difference", Csg::difference(a.clone(), b.clone()), vec![(-1.5, true), (-0.5, false)]),
+            ("reversed difference", Csg::difference(b, a), vec![(0.5, true), (1.5, false)]),
+        ];
+        for (name, csg, expected) in cases {
+            assert_crossings(name, crossings_from(&csg, -5.0, FROM_OUTSIDE), &expected);
+        }
+    }
+
+    #[test]
+    fn nested_operations_combine_their_children() {
+        let (a, b) = pair();
+        let hollow = make_ref(Csg::union(a, b));
+        let core = ball(0.0, 0.25, make_mat(EmptyMaterial));
+        let csg = Csg::difference(hollow, core.clone());
+        let expected = [(-1.5, true), (-0.25, false), (0.25, true), (1.5, false)];
+        assert_crossings("hollowed union", crossings_from(&csg, -5.0, FROM_OUTSIDE), &expected);
+
+        // Putting the core back fills the hollow.
+        let refilled = Csg::union(make_ref(csg), core);
+        assert_crossings("refilled", crossings_from(&refilled, -5.0, FROM_OUTSIDE), &[(-1.5, true), (1.5, false)]);
+    }
+
+    #[test]
+    fn rays_starting_inside_see_only_the_way_out() {
+        let (a, b) = pair();
+        // Inside a alone, then inside both.
+        let difference = Csg::difference(a.clone(), b.clone());
+        assert_crossings("difference", crossings_from(&difference, -1.0, FROM_OUTSIDE), &[(-0.5, false)]);
+        let intersection = Csg::intersection(a.clone(), b.clone());
+        assert_crossings("intersection", crossings_from(&intersection, 0.0, FROM_OUTSIDE), &[(0.5, false)]);
+        // Inside b but not a, which the difference counts as outside.
+        let union = Csg::union(a.clone(), b.clone());
+        assert_crossings("union", crossings_from(&union, 1.0, FROM_OUTSIDE), &[(1.5, false)]);
+        assert_crossings("difference from b", crossings_from(&difference, 0.9, FROM_OUTSIDE), &[]);
+    }
+
+    #[test]
+    fn crossings_stay_within_ray_t() {
+        let (a, b) = pair();
+        let union = Csg::union(a.clone(), b.clone());
+        // From x = -5, t runs 3.5 to 6.5 across the union.
+        assert_crossings("before", crossings_from(&union, -5.0, Interval::new(0.001, 3.0)), &[]);
+        assert_crossings("first", crossings_from(&union, -5.0, Interval::new(0.001, 4.0)), &[(-1.5, true)]);
+        assert_crossings("last", crossings_from(&union, -5.0, Interval::new(4.0, 10.0)), &[(1.5, false)]);
+
+        let r = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(union.hit(&r, Interval::new(0.001, 3.0)).is_none());
+        let rec = union.hit(&r, Interval::new(4.0, 10.0)).expect("hits the far side");
+        assert!((rec.t - 6.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn alpha_masks_cut_holes_in_the_combined_surface() {
+        let clear = make_mat(AlphaMask::new(make_mat(EmptyMaterial), make_tex(SolidColor::from_value(0.0))));
+        let a = ball(-0.5, 1.0, clear);
+        let b = ball(0.5, 1.0, make_mat(EmptyMaterial));
+        let r = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        // The union's near side is all a's and cut away; the inside of b stays hidden.
+        let union = Csg::union(a.clone(), b.clone());
+        let rec = union.hit(&r, FROM_OUTSIDE).expect("hits b's far side");
+        assert!((rec.p.x() - 1.5).abs() < 1e-9, "hits at {:?}", rec.p);
+
+        // Faces carved by b keep b's material and stay.
+        let difference = Csg::difference(a, b);
+        let rec = difference.hit(&r, FROM_OUTSIDE).expect("hits the carved face");
+        assert!((rec.p.x() + 0.5).abs() < 1e-9, "hits at {:?}", rec.p);
+
+        // The same through the enum, as the world sees it.
+        let world = make_ref(union);
+        assert!((world.hit(&r, FROM_OUTSIDE).expect("hits").p.x() - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn masks_survive_wrapping_a_child() {
+        let clear = || make_mat(AlphaMask::new(make_mat(EmptyMaterial), make_tex(SolidColor::from_value(0.0))));
+        let b = ball(0.5, 1.0, make_mat(EmptyMaterial));
+        let r = Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+
+        // The same cut-away ball at x = -0.5, behind each kind of wrapper.
+        let at_origin = || ball(0.0, 1.0, clear());
+        let shift = Transform::from_translation(Vec3::new(-0.5, 0.0, 0.0));
+        let wrapped: Vec<(&str, HittableRef)> = vec![
+            ("translated", make_ref(Translate::new(at_origin(), Vec3::new(-0.5, 0.0, 0.0)))),
+            ("rotated", make_ref(RotateY::new(make_ref(Sphere::new(Point3::new(0.0, 0.0, -0.5), 1.0, clear())), 90.0))),
+            ("listed", make_ref(HittableList::from(ball(-0.5, 1.0, clear())))),
+            ("moving", make_ref(MotionTransform::new(at_origin(), shift, shift))),
+        ];
+        for (name, a) in wrapped {
+            let union = Csg::union(a.clone(), b.clone());
+            let rec = union.hit(&r, FROM_OUTSIDE).unwrap_or_else(|| panic!("{name}: misses the union"));
+            assert!((rec.p.x() - 1.5).abs() < 1e-9, "{name}: union hits at {:?}", rec.p);
+
+            let difference = Csg::difference(a, b.clone());
+            let rec = difference.hit(&r, FROM_OUTSIDE).unwrap_or_else(|| panic!("{name}: misses the difference"));
+            assert!((rec.p.x() + 0.5).abs() < 1e-9, "{name}: difference hits at {:?}", rec.p);
+        }
+    }
+}
@@ -0,0 +1,709 @@
+use std::fmt;
+
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable, HittableRef};
+use super::hittable_list::HittableList;
+use super::interval::Interval;
+use super::ray::Ray;
+use super::rtweekend::{random_int, INFINITY};
+use super::vec3::{Point3, Vec3};
+
+const STACK_SIZE: usize = 64;
+// Below this depth the builder only takes median splits, which bounds the tree depth (and
+// so the traversal stack) whatever the SAH decided higher up.
+const MEDIAN_SPLIT_DEPTH: usize = 40;
+
+#[derive(Clone, Copy, Debug)]
+pub struct BvhOptions {
+    // Nodes with more objects than this are always split. Leaves hold at least one and at
+    // most u16::MAX objects, whatever this says.
+    pub max_leaf_size: usize,
+    pub buckets: usize,
+    // Relative costs of stepping into a child node and of testing one primitive.
+    pub traversal_cost: f64,
+    pub intersection_cost: f64,
+    // `update` rebuilds instead of refitting once the SAH cost exceeds this multiple of
+    // the cost right after the last build.
+    pub rebuild_threshold: f64,
+}
+
+impl Default for BvhOptions {
+    fn default() -> Self {
+        Self { max_leaf_size: 4, buckets: 12, traversal_cost: 1.0, intersection_cost: 1.0, rebuild_threshold: 1.3 }
+    }
+}
+
+// A primitive's bounds cached for the duration of a build, along with its position in the
+// list the BVH was created from.
+#[derive(Clone, Copy)]
+struct BuildItem {
+    bbox: Aabb,
+    centroid: Vec3,
+    index: u32,
+}
+
+// Interior nodes store their first child directly after themselves and the second child at
+// `offset`; leaves store `count` primitives starting at `offset`.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct LinearNode {
+    pub(super) bbox: Aabb,
+    pub(super) offset: u32,
+    pub(super) count: u16,
+    axis: u8,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraversalStats {
+    pub rays: usize,
+    pub nodes_visited: usize,
+    pub primitives_tested: usize,
+}
+
+// What a traversal counts as it goes. Plain hits count nothing, so the counting compiles
+// away outside `measure`.
+trait TraversalCounter {
+    fn ray(&mut self) {}
+    fn node(&mut self) {}
+    fn primitives(&mut self, _count: usize) {}
+}
+
+impl TraversalCounter for () {}
+
+impl TraversalCounter for TraversalStats {
+    fn ray(&mut self) {
+        self.rays += 1;
+    }
+
+    fn node(&mut self) {
+        self.nodes_visited += 1;
+    }
+
+    fn primitives(&mut self, count: usize) {
+        self.primitives_tested += count;
+    }
+}
+
+impl TraversalStats {
+    pub fn nodes_per_ray(&self) -> f64 {
+        self.nodes_visited as f64 / self.rays.max(1) as f64
+    }
+
+    pub fn primitives_per_ray(&self) -> f64 {
+        self.primitives_tested as f64 / self.rays.max(1) as f64
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BvhStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub max_depth: usize,
+    pub max_leaf_size: usize,
+    pub sah_cost: f64,
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes ({} leaves), depth {}, largest leaf {}, SAH cost {:.2}",
+            self.node_count, self.leaf_count, self.max_depth, self.max_leaf_size, self.sah_cost
+        )
+    }
+}
+
+// A BVH laid out as a flat array in depth-first order, traversed iteratively. Children are
+// visited near-first along the split axis, and far children are culled against the
+// closest hit found so far. Splits are chosen with a binned surface area heuristic over
+// all three axes, and a node becomes a leaf whenever that is cheaper than splitting.
+//
+// For animation the hierarchy can be refitted to moved primitives without re-sorting
+// them, which is much cheaper than a rebuild as long as the objects don't move far.
+pub struct FlatBvh {
+    nodes: Vec<LinearNode>,
+    primitives: Vec<HittableRef>,
+    // Position of each primitive in the list the BVH was created from.
+    indices: Vec<u32>,
+    options: BvhOptions,
+    built_cost: f64,
+}
+
+impl FlatBvh {
+    pub fn new(list: HittableList) -> Self {
+        Self::with_options(list, BvhOptions::default())
+    }
+
+    pub fn with_options(list: HittableList, mut options: BvhOptions) -> Self {
+        options.max_leaf_size = options.max_leaf_size.clamp(1, u16::MAX as usize);
+        let indices = (0..list.objects.len() as u32).collect();
+        let mut bvh = Self { nodes: Vec::new(), primitives: list.objects, indices, options, built_cost: 0.0 };
+        bvh.rebuild();
+        bvh
+    }
+
+    // Builds the hierarchy from scratch around the primitives' current bounds.
+    pub fn rebuild(&mut self) {
+        let mut items: Vec<BuildItem> = self
+            .primitives
+            .iter()
+            .zip(&self.indices)
+            .map(|(object, &index)| {
+                let bbox = object.bounding_box();
+                BuildItem { bbox, centroid: centroid(&bbox), index }
+            })
+            .collect();
+
+        self.nodes.clear();
+        self.nodes.reserve(2 * items.len());
+        if !items.is_empty() {
+            Self::build(&mut items, 0, 0, &self.options, &mut self.nodes);
+        }
+
+        // Put the primitives in leaf order.
+        let mut by_index: Vec<Option<HittableRef>> = vec![None; self.primitives.len()];
+        for (object, &index) in self.primitives.drain(..).zip(&self.indices) {
+            by_index[index as usize] = Some(object);
+        }
+        self.primitives = items.iter().map(|item| by_index[item.index as usize].take().unwrap()).collect();
+        self.indices = items.iter().map(|item| item.index).collect();
+
+        self.built_cost = self.stats().sah_cost;
+    }
+
+    // Recomputes every node's bounding box from the primitives' current bounds, bottom up,
+    // keeping the tree structure and primitive order.
+    pub fn refit(&mut self) {
+        // Children always come after their parent in the array.
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            self.nodes[i].bbox = if node.count > 0 {
+                let start = node.offset as usize;
+                self.primitives[start..start + node.count as usize]
+                    .iter()
+                    .fold(Aabb::EMPTY, |bbox, object| Aabb::from_boxes(bbox, object.bounding_box()))
+            } else {
+                Aabb::from_boxes(self.nodes[i + 1].bbox, self.nodes[node.offset as usize].bbox)
+            };
+        }
+    }
+
+    // Calls `f` with every primitive and its position in the original list, so it can be
+    // replaced or moved, then refits. Falls back to a full rebuild when refitting has made
+    // the tree too much worse than a fresh build; returns whether it rebuilt.
+    pub fn update<F: FnMut(usize, &mut HittableRef)>(&mut self, mut f: F) -> bool {
+        for (object, &index) in self.primitives.iter_mut().zip(&self.indices) {
+            f(index as usize, object);
+        }
+        self.refit();
+
+        if self.degradation() > self.options.rebuild_threshold {
+            self.rebuild();
+            return true;
+        }
+        false
+    }
+
+    // SAH cost relative to the cost right after the last build.
+    pub fn degradation(&self) -> f64 {
+        if self.built_cost > 0.0 {
+            self.stats().sah_cost / self.built_cost
+        } else {
+            1.0
+        }
+    }
+
+    fn build(
+        items: &mut [BuildItem],
+        first: usize,
+        depth: usize,
+        options: &BvhOptions,
+        nodes: &mut Vec<LinearNode>,
+    ) -> usize {
+        let mut bbox = Aabb::EMPTY;
+        let mut centroid_bounds = Aabb::EMPTY;
+        for item in items.iter() {
+            bbox = Aabb::from_boxes(bbox, item.bbox);
+            centroid_bounds = Aabb::from_boxes(centroid_bounds, Aabb::from_points(item.centroid, item.centroid));
+        }
+
+        let index = nodes.len();
+        nodes.push(LinearNode { bbox, offset: first as u32, count: items.len() as u16, axis: 0 });
+
+        let must_split = items.len() > options.max_leaf_size;
+        let split = if depth >= MEDIAN_SPLIT_DEPTH {
+            None
+        } else {
+            sah_split(items, &bbox, &centroid_bounds, options)
+        };
+
+        let (axis, mid) = match split {
+            Some(split) => split,
+            None if must_split => {
+                let axis = centroid_bounds.longest_axis();
+                items.sort_by(|a, b| a.bbox.axis_interval(axis).min.total_cmp(&b.bbox.axis_interval(axis).min));
+                (axis, items.len() / 2)
+            }
+            None => return index,
+        };
+
+        let (left, right) = items.split_at_mut(mid);
+        Self::build(left, first, depth + 1, options, nodes);
+        let second = Self::build(right, first + mid, depth + 1, options, nodes);
+
+        nodes[index].offset = second as u32;
+        nodes[index].count = 0;
+        nodes[index].axis = axis as u8;
+        index
+    }
+
+    // Hands the node array and the primitives it indexes to wider BVH layouts.
+    pub(super) fn into_parts(self) -> (Vec<LinearNode>, Vec<HittableRef>) {
+        (self.nodes, self.primitives)
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats { node_count: self.nodes.len(), leaf_count: 0, max_depth: 0, max_leaf_size: 0, sah_cost: 0.0 };
+        if self.nodes.is_empty() {
+            return stats;
+        }
+
+        let root_area = self.nodes[0].bbox.surface_area();
+        let mut stack = vec![(0usize, 1usize)];
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index];
+            let area_ratio = node.bbox.surface_area() / root_area;
+            stats.max_depth = stats.max_depth.max(depth);
+
+            if node.count > 0 {
+                stats.leaf_count += 1;
+                stats.max_leaf_size = stats.max_leaf_size.max(node.count as usize);
+                stats.sah_cost += area_ratio * node.count as f64 * self.options.intersection_cost;
+            } else {
+                stats.sah_cost += area_ratio * self.options.traversal_cost;
+                stack.push((index + 1, depth + 1));
+                stack.push((node.offset as usize, depth + 1));
+            }
+        }
+
+        stats
+    }
+
+    pub fn measure(&self, rays: &[Ray]) -> TraversalStats {
+        let mut stats = TraversalStats::default();
+        for r in rays {
+            self.traverse(r, Interval::new(0.001, INFINITY), &mut stats);
+        }
+        stats
+    }
+
+    fn traverse<C: TraversalCounter>(&self, r: &Ray, ray_t: Interval, counter: &mut C) -> Option<HitRecord> {
+        counter.ray();
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let origin = r.origin();
+        let dir = r.direction();
+        let inv_dir = Vec3::new(1.0 / dir.x(), 1.0 / dir.y(), 1.0 / dir.z());
+        let dir_is_neg = [inv_dir.x() < 0.0, inv_dir.y() < 0.0, inv_dir.z() < 0.0];
+
+        let mut closest = None;
+        let mut closest_t = ray_t.max;
+        let mut stack = [0usize; STACK_SIZE];
+        let mut stack_len = 0;
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+            counter.node();
+            if node.bbox.hit_inv(origin, inv_dir, Interval::new(ray_t.min, closest_t)) {
+                if node.count > 0 {
+                    let start = node.offset as usize;
+                    counter.primitives(node.count as usize);
+                    for object in &self.primitives[start..start + node.count as usize] {
+                        if let Some(rec) = object.hit(r, Interval::new(ray_t.min, closest_t)) {
+                            closest_t = rec.t;
+                            closest = Some(rec);
+                        }
+                    }
+                } else {
+                    // Children are ordered along the split axis, so the second one is nearer
+                    // when the ray travels in the negative direction.
+                    let (near, far) = if dir_is_neg[node.axis as usize] {
+                        (node.offset as usize, current + 1)
+                    } else {
+                        (current + 1, node.offset as usize)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    current = near;
+                    continue;
+                }
+            }
+
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            current = stack[stack_len];
+        }
+
+        closest
+    }
+}
+
+fn centroid(b: &Aabb) -> Vec3 {
+    Vec3::new(
+        0.5 * (b.x.min + b.x.max),
+        0.5 * (b.y.min + b.y.max),
+        0.5 * (b.z.min + b.z.max),
+    )
+}
+
+// Returns the cheapest (axis, mid) split of `items`, reordering them so [..mid] is the
+// left child, or None when keeping them in a single leaf is at least as cheap.
+fn sah_split(
+    items: &mut [BuildItem],
+    bbox: &Aabb,
+    centroid_bounds: &Aabb,
+    options: &BvhOptions,
+) -> Option<(usize, usize)> {
+    #[derive(Clone, Copy)]
+    struct Bucket {
+        count: usize,
+        bbox: Aabb,
+    }
+
+    let count = items.len();
+    if count <= 1 {
+        return None;
+    }
+
+    let num_buckets = options.buckets.max(2);
+    let bucket_index = |item: &BuildItem, axis: usize| {
+        let extent = centroid_bounds.axis_interval(axis);
+        let offset = (item.centroid[axis] - extent.min) / extent.size();
+        ((offset * num_buckets as f64) as usize).min(num_buckets - 1)
+    };
+
+    let parent_area = bbox.surface_area();
+    let mut best: Option<(f64, usize, usize)> = None;
+
+    for axis in 0..3 {
+        if centroid_bounds.axis_interval(axis).size() <= 1e-12 {
+            continue;
+        }
+
+        let mut buckets = vec![Bucket { count: 0, bbox: Aabb::EMPTY }; num_buckets];
+        for item in items.iter() {
+            let idx = bucket_index(item, axis);
+            buckets[idx].count += 1;
+            buckets[idx].bbox = Aabb::from_boxes(buckets[idx].bbox, item.bbox);
+        }
+
+        let mut right_area = vec![0.0; num_buckets];
+        let mut right_count = vec![0usize; num_buckets];
+        let mut accum_bbox = Aabb::EMPTY;
+        let mut accum_count = 0usize;
+        for i in (1..num_buckets).rev() {
+            accum_count += buckets[i].count;
+            accum_bbox = Aabb::from_boxes(accum_bbox, buckets[i].bbox);
+            right_area[i] = accum_bbox.surface_area();
+            right_count[i] = accum_count;
+        }
+
+        let mut left_bbox = Aabb::EMPTY;
+        let mut left_count = 0usize;
+        for split in 0..(num_buckets - 1) {
+            left_count += buckets[split].count;
+            left_bbox = Aabb::from_boxes(left_bbox, buckets[split].bbox);
+            if left_count == 0 || right_count[split + 1] == 0 {
+                continue;
+            }
+
+            let cost = options.traversal_cost
+                + options.intersection_cost
+                    * (left_bbox.surface_area() * left_count as f64
+                        + right_area[split + 1] * right_count[split + 1] as f64)
+                    / parent_area;
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, split));
+            }
+        }
+    }
+
+    let (cost, axis, split) = best?;
+    let leaf_cost = options.intersection_cost * count as f64;
+    if count <= options.max_leaf_size && leaf_cost <= cost {
+        return None;
+    }
+
+    let mut mid = 0;
+    for i in 0..count {
+        if bucket_index(&items[i], axis) <= split {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    Some((axis, mid))
+}
+
+impl Hittable for FlatBvh {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        self.traverse(r, ray_t, &mut ())
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |node| node.bbox)
+    }
+
+    // Picks primitives uniformly, like HittableList, so a BVH of lights can be sampled.
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        let weight = 1.0 / self.primitives.len() as f64;
+        self.primitives.iter().map(|object| weight * object.pdf_value(origin, direction, time)).sum()
+    }
+
+    fn random(&self, origin: Point3, time: f64) -> Vec3 {
+        let index = random_int(0, self.primitives.len() as i32 - 1) as usize;
+        self.primitives[index].random(origin, time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::hittable::make_ref;
+    use crate::books::the_rest_of_your_life::material::{make_mat, EmptyMaterial};
+    use crate::books::the_rest_of_your_life::rtweekend::{random_double, seed_random};
+    use crate::books::the_rest_of_your_life::sphere::Sphere;
+    use crate::books::the_rest_of_your_life::vec3::{random_unit_vector, Point3};
+
+    // Spheres in a few tight clusters and a sparse cloud, with some exactly on top of each
+    // other so the builder also sees centroids it can't separate.
+    fn scene() -> HittableList {
+        let mut list = HittableList::new();
+        let mat = make_mat(EmptyMaterial);
+        let clusters = [Point3::new(-8.0, 0.0, 0.0), Point3::new(6.0, 3.0, -2.0), Point3::new(0.0, -5.0, 7.0)];
+        for (i, &cluster) in clusters.iter().enumerate() {
+            for _ in 0..40 + 20 * i {
+                let center = cluster + 1.5 * Vec3::random_range(-1.0, 1.0);
+                list.add(make_ref(Sphere::new(center, 0.05 + 0.2 * random_double(), mat.clone())));
+            }
+        }
+        for _ in 0..60 {
+            let center = 12.0 * Vec3::random_range(-1.0, 1.0);
+            list.add(make_ref(Sphere::new(center, 0.1 + 0.8 * random_double(), mat.clone())));
+        }
+        for _ in 0..10 {
+            list.add(make_ref(Sphere::new(Point3::new(2.0, 2.0, 2.0), 0.5, mat.clone())));
+        }
+        list
+    }
+
+    fn scene_copy(list: &HittableList) -> HittableList {
+        let mut copy = HittableList::new();
+        for object in &list.objects {
+            copy.add(object.clone());
+        }
+        copy
+    }
+
+    fn rays(count: usize) -> Vec<Ray> {
+        (0..count)
+            .map(|_| {
+                // From all around the scene, aimed somewhere inside it.
+                let origin = 20.0 * random_unit_vector();
+                let target = 10.0 * Vec3::random_range(-1.0, 1.0);
+                Ray::new(origin, target - origin)
+            })
+            .chain((0..count / 4).map(|_| Ray::new(Point3::new(0.0, 0.0, 0.0), random_unit_vector())))
+            .collect()
+    }
+
+    fn assert_same_hits(name: &str, bvh: &FlatBvh, list: &HittableList, rays: &[Ray]) {
+        let ray_t = Interval::new(0.001, INFINITY);
+        let mut hits = 0;
+        for r in rays {
+            let expected = list.hit(r, ray_t).map(|rec| rec.t);
+            let actual = bvh.hit(r, ray_t).map(|rec| rec.t);
+            match (expected, actual) {
+                (None, None) => {}
+                (Some(e), Some(a)) if (e - a).abs() <= 1e-9 * e.max(1.0) => hits += 1,
+                _ => panic!("{}: ray {:?} hits at {:?}, expected {:?}", name, r, actual, expected),
+            }
+        }
+        assert!(hits > rays.len() / 10, "{}: only {} of {} rays hit anything", name, hits, rays.len());
+    }
+
+    fn option_sets() -> Vec<(&'static str, BvhOptions)> {
+        let default = BvhOptions::default();
+        vec![
+            ("default", default),
+            ("single primitive leaves", BvhOptions { max_leaf_size: 1, ..default }),
+            ("large leaves", BvhOptions { max_leaf_size: 16, ..default }),
+            ("two buckets", BvhOptions { buckets: 2, ..default }),
+            ("costly traversal", BvhOptions { traversal_cost: 8.0, max_leaf_size: 8, ..default }),
+        ]
+    }
+
+    #[test]
+    fn sah_builds_find_the_same_hits_as_a_list() {
+        seed_random(golden::SEED);
+        let list = scene();
+        let rays = rays(4000);
+        for (name, options) in option_sets() {
+            let bvh = FlatBvh::with_options(scene_copy(&list), options);
+            assert_same_hits(name, &bvh, &list, &rays);
+        }
+    }
+
+    #[test]
+    fn empty_leaf_limits_still_build() {
+        seed_random(golden::SEED);
+        let list = scene();
+        let options = BvhOptions { max_leaf_size: 0, ..BvhOptions::default() };
+        let bvh = FlatBvh::with_options(scene_copy(&list), options);
+        assert_eq!(bvh.stats().max_leaf_size, 1);
+        assert_same_hits("no leaf limit", &bvh, &list, &rays(500));
+    }
+
+    #[test]
+    fn leaves_respect_max_leaf_size() {
+        seed_random(golden::SEED);
+        let list = scene();
+        for (name, options) in option_sets() {
+            let bvh = FlatBvh::with_options(scene_copy(&list), options);
+            let stats = bvh.stats();
+            assert!(
+                stats.max_leaf_size <= options.max_leaf_size,
+                "{}: a leaf holds {} primitives",
+                name,
+                stats.max_leaf_size
+            );
+
+            // Every primitive is in exactly one leaf.
+            let mut seen = vec![false; list.objects.len()];
+            for node in bvh.nodes.iter().filter(|node| node.count > 0) {
+                for &index in &bvh.indices[node.offset as usize..node.offset as usize + node.count as usize] {
+                    assert!(!seen[index as usize], "{}: primitive {} is in two leaves", name, index);
+                    seen[index as usize] = true;
+                }
+            }
+            assert!(seen.iter().all(|&s| s), "{}: a primitive is in no leaf", name);
+        }
+    }
+
+    fn ball(center: Point3) -> HittableRef {
+        make_ref(Sphere::new(center, 0.3, make_mat(EmptyMaterial)))
+    }
+
+    // Balls on a grid, and where each one is.
+    fn grid() -> (HittableList, Vec<Point3>) {
+        let mut list = HittableList::new();
+        let mut centers = Vec::new();
+        for i in 0..6 {
+            for j in 0..6 {
+                for k in 0..6 {
+                    let center = Point3::new(2.0 * i as f64 - 5.0, 2.0 * j as f64 - 5.0, 2.0 * k as f64 - 5.0);
+                    list.add(ball(center));
+                    centers.push(center);
+                }
+            }
+        }
+        (list, centers)
+    }
+
+    fn moved(centers: &[Point3]) -> HittableList {
+        let mut list = HittableList::new();
+        for &center in centers {
+            list.add(ball(center));
+        }
+        list
+    }
+
+    // Every node's box holds what is below it.
+    fn assert_bounds_enclose(bvh: &FlatBvh) {
+        let encloses = |outer: &Aabb, inner: &Aabb| {
+            (0..3).all(|axis| {
+                let (o, i) = (outer.axis_interval(axis), inner.axis_interval(axis));
+                o.min <= i.min && i.max <= o.max
+            })
+        };
+        for (index, node) in bvh.nodes.iter().enumerate() {
+            if node.count > 0 {
+                let start = node.offset as usize;
+                for object in &bvh.primitives[start..start + node.count as usize] {
+                    assert!(encloses(&node.bbox, &object.bounding_box()), "leaf {} misses a primitive", index);
+                }
+            } else {
+                assert!(encloses(&node.bbox, &bvh.nodes[index + 1].bbox), "node {} misses its first child", index);
+                assert!(encloses(&node.bbox, &bvh.nodes[node.offset as usize].bbox), "node {} misses its second child", index);
+            }
+        }
+    }
+
+    #[test]
+    fn refit_follows_small_motions() {
+        seed_random(golden::SEED);
+        let (list, mut centers) = grid();
+        let mut bvh = FlatBvh::new(list);
+        let rays = rays(2000);
+
+        for _ in 0..3 {
+            for center in centers.iter_mut() {
+                *center += 0.2 * Vec3::random_range(-1.0, 1.0);
+            }
+            let rebuilt = bvh.update(|index, object| *object = ball(centers[index]));
+            assert!(!rebuilt, "rebuilt after a small motion, degradation {}", bvh.degradation());
+
+            let expected = moved(&centers);
+            assert_bounds_enclose(&bvh);
+            let (root, all) = (bvh.bounding_box(), expected.bounding_box());
+            for axis in 0..3 {
+                let (a, b) = (root.axis_interval(axis), all.axis_interval(axis));
+                assert!((a.min - b.min).abs() < 1e-12 && (a.max - b.max).abs() < 1e-12, "root bounds {:?}", root);
+            }
+            assert_same_hits("refit", &bvh, &expected, &rays);
+        }
+    }
+
+    #[test]
+    fn refit_alone_stays_correct_after_large_motions() {
+        seed_random(golden::SEED);
+        let (list, mut centers) = grid();
+        let options = BvhOptions { rebuild_threshold: INFINITY, ..BvhOptions::default() };
+        let mut bvh = FlatBvh::with_options(list, options);
+
+        for center in centers.iter_mut() {
+            *center = 6.0 * Vec3::random_range(-1.0, 1.0);
+        }
+        assert!(!bvh.update(|index, object| *object = ball(centers[index])));
+        assert!(bvh.degradation() > BvhOptions::default().rebuild_threshold);
+        assert_bounds_enclose(&bvh);
+        assert_same_hits("refit after shuffling", &bvh, &moved(&centers), &rays(2000));
+    }
+
+    #[test]
+    fn update_rebuilds_past_the_threshold() {
+        seed_random(golden::SEED);
+        let (list, mut centers) = grid();
+        let mut bvh = FlatBvh::new(list);
+        let threshold = bvh.options.rebuild_threshold;
+
+        // Shuffling the balls around the grid leaves every leaf spanning the whole scene.
+        for center in centers.iter_mut() {
+            *center = 6.0 * Vec3::random_range(-1.0, 1.0);
+        }
+        let options = BvhOptions { rebuild_threshold: INFINITY, ..BvhOptions::default() };
+        let mut refitted = FlatBvh::with_options(grid().0, options);
+        refitted.update(|index, object| *object = ball(centers[index]));
+        assert!(refitted.degradation() > threshold, "shuffling degrades the tree by only {}", refitted.degradation());
+
+        assert!(bvh.update(|index, object| *object = ball(centers[index])), "kept a degraded tree");
+        assert_eq!(bvh.degradation(), 1.0);
+        assert!(bvh.stats().sah_cost < refitted.stats().sah_cost);
+        assert_bounds_enclose(&bvh);
+        assert_same_hits("rebuilt", &bvh, &moved(&centers), &rays(2000));
+    }
+}
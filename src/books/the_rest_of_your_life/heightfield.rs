@@ -0,0 +1,353 @@
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::MaterialRef;
+use super::perlin::Perlin;
+use super::ray::Ray;
+use super::rtw_image::RtwImage;
+use super::vec3::{cross, dot, unit_vector, Point3, Vec3};
+
+// A terrain over a regular grid of height samples, rising from `corner` along +y. The grid
+// spans `size.x()` by `size.z()` and heights run from 0 to `size.y()`. Each grid cell is
+// split into two triangles, shaded with normals interpolated from the neighbouring samples.
+// u and v run across the grid, with v = 1 on the first row so image textures line up with
+// the image the heights came from.
+pub struct Heightfield {
+    heights: Vec<f64>,
+    normals: Vec<Vec3>,
+    // The lowest and highest sample around each cell, to skip cells the ray passes over.
+    cell_bounds: Vec<Interval>,
+    columns: usize,
+    rows: usize,
+    corner: Point3,
+    cell_size: (f64, f64),
+    mat: MaterialRef,
+    bbox: Aabb,
+}
+
+impl Heightfield {
+    // `samples` holds `columns` by `rows` heights between 0 and 1, row by row.
+    pub fn new(samples: Vec<f64>, columns: usize, rows: usize, corner: Point3, size: Vec3, mat: MaterialRef) -> Self {
+        assert!(columns >= 2 && rows >= 2, "a heightfield needs at least 2x2 samples");
+        assert_eq!(samples.len(), columns * rows);
+
+        let heights: Vec<f64> = samples.iter().map(|h| corner.y() + h * size.y()).collect();
+        let cell_size = (size.x() / (columns - 1) as f64, size.z() / (rows - 1) as f64);
+
+        let height = |i: usize, j: usize| heights[j * columns + i];
+        let mut normals = Vec::with_capacity(heights.len());
+        for j in 0..rows {
+            for i in 0..columns {
+                // Central differences, one-sided at the edges.
+                let (i0, i1) = (i.saturating_sub(1), (i + 1).min(columns - 1));
+                let (j0, j1) = (j.saturating_sub(1), (j + 1).min(rows - 1));
+                let dx = (height(i1, j) - height(i0, j)) / ((i1 - i0) as f64 * cell_size.0);
+                let dz = (height(i, j1) - height(i, j0)) / ((j1 - j0) as f64 * cell_size.1);
+                normals.push(unit_vector(Vec3::new(-dx, 1.0, -dz)));
+            }
+        }
+
+        let mut cell_bounds = Vec::with_capacity((columns - 1) * (rows - 1));
+        for j in 0..rows - 1 {
+            for i in 0..columns - 1 {
+                let corners = [height(i, j), height(i + 1, j), height(i, j + 1), height(i + 1, j + 1)];
+                let low = corners.iter().copied().fold(f64::INFINITY, f64::min);
+                let high = corners.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                cell_bounds.push(Interval::new(low, high));
+            }
+        }
+
+        let low = heights.iter().copied().fold(f64::INFINITY, f64::min);
+        let high = heights.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let bbox = Aabb::new(
+            Interval::new(corner.x(), corner.x() + size.x()),
+            Interval::new(low, high),
+            Interval::new(corner.z(), corner.z() + size.z()),
+        );
+
+        Self { heights, normals, cell_bounds, columns, rows, corner, cell_size, mat, bbox }
+    }
+
+    // Heights from the brightness of each pixel, with the image's top row along the -z edge.
+    pub fn from_image(image: &RtwImage, corner: Point3, size: Vec3, mat: MaterialRef) -> Self {
+        let (columns, rows) = (image.width().max(2) as usize, image.height().max(2) as usize);
+        let mut samples = Vec::with_capacity(columns * rows);
+        for y in 0..rows as i32 {
+            for x in 0..columns as i32 {
+                let [r, g, b] = image.pixel(x, y).map(|c| c as f64);
+                samples.push(0.2126 * r + 0.7152 * g + 0.0722 * b);
+            }
+        }
+        Self::new(samples, columns, rows, corner, size, mat)
+    }
+
+    // Heights from Perlin turbulence sampled over `columns` by `rows` points, `scale` noise
+    // cells across, stretched to fill the full height range.
+    pub fn from_perlin(
+        noise: &Perlin,
+        scale: f64,
+        columns: usize,
+        rows: usize,
+        corner: Point3,
+        size: Vec3,
+        mat: MaterialRef,
+    ) -> Self {
+        let mut samples = Vec::with_capacity(columns * rows);
+        for j in 0..rows {
+            for i in 0..columns {
+                let p = Point3::new(i as f64 / columns as f64, 0.0, j as f64 / rows as f64) * scale;
+                samples.push(noise.turb(p, 7));
+            }
+        }
+
+        let low = samples.iter().copied().fold(f64::INFINITY, f64::min);
+        let high = samples.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let range = if high > low { high - low } else { 1.0 };
+        for sample in &mut samples {
+            *sample = (*sample - low) / range;
+        }
+        Self::new(samples, columns, rows, corner, size, mat)
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Point3 {
+        Point3::new(
+            self.corner.x() + i as f64 * self.cell_size.0,
+            self.heights[j * self.columns + i],
+            self.corner.z() + j as f64 * self.cell_size.1,
+        )
+    }
+
+    // The nearest hit on the two triangles of cell (i, j), as t and the interpolated normal.
+    fn hit_cell(&self, r: &Ray, ray_t: Interval, i: usize, j: usize) -> Option<(f64, Vec3)> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let mut nearest: Option<(f64, Vec3)> = None;
+        for triangle in [[0, 1, 2], [0, 2, 3]] {
+            let [a, b, c] = triangle.map(|k| corners[k]);
+            let limit = nearest.map_or(ray_t, |(t, _)| Interval::new(ray_t.min, t));
+            let hit = intersect_triangle(
+                r,
+                limit,
+                [self.vertex(a.0, a.1), self.vertex(b.0, b.1), self.vertex(c.0, c.1)],
+            );
+            if let Some((t, b1, b2)) = hit {
+                let normal = (1.0 - b1 - b2) * self.normals[a.1 * self.columns + a.0]
+                    + b1 * self.normals[b.1 * self.columns + b.0]
+                    + b2 * self.normals[c.1 * self.columns + c.0];
+                nearest = Some((t, normal));
+            }
+        }
+        nearest
+    }
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let span = self.bbox.clip(r, ray_t)?;
+        let origin = r.origin();
+        let direction = r.direction();
+
+        // Walk the cells under the ray in order with a 2D DDA.
+        let cells = (self.columns - 1, self.rows - 1);
+        let entry = r.at(span.min);
+        let cell_of = |x: f64, corner: f64, size: f64, count: usize| {
+            (((x - corner) / size).floor().max(0.0) as usize).min(count - 1)
+        };
+        let mut i = cell_of(entry.x(), self.corner.x(), self.cell_size.0, cells.0);
+        let mut j = cell_of(entry.z(), self.corner.z(), self.cell_size.1, cells.1);
+
+        let step = |d: f64| if d > 0.0 { 1 } else { -1 };
+        let (step_i, step_j) = (step(direction.x()), step(direction.z()));
+        let next_boundary = |cell: usize, s: i32, corner: f64, size: f64, o: f64, d: f64| {
+            if d == 0.0 {
+                return f64::INFINITY;
+            }
+            let edge = corner + (cell as f64 + if s > 0 { 1.0 } else { 0.0 }) * size;
+            (edge - o) / d
+        };
+        let mut t_next_i = next_boundary(i, step_i, self.corner.x(), self.cell_size.0, origin.x(), direction.x());
+        let mut t_next_j = next_boundary(j, step_j, self.corner.z(), self.cell_size.1, origin.z(), direction.z());
+        let delta_i = (self.cell_size.0 / direction.x()).abs();
+        let delta_j = (self.cell_size.1 / direction.z()).abs();
+
+        let mut t_enter = span.min;
+        loop {
+            let t_exit = t_next_i.min(t_next_j).min(span.max);
+
+            // Only test the triangles if the ray's height over the cell reaches them.
+            let (y0, y1) = (origin.y() + t_enter * direction.y(), origin.y() + t_exit * direction.y());
+            let bounds = self.cell_bounds[j * cells.0 + i];
+            if y0.min(y1) <= bounds.max
+                && y0.max(y1) >= bounds.min
+                && let Some((t, normal)) = self.hit_cell(r, ray_t, i, j)
+            {
+                let p = r.at(t);
+                let u = (p.x() - self.corner.x()) / (cells.0 as f64 * self.cell_size.0);
+                let v = 1.0 - (p.z() - self.corner.z()) / (cells.1 as f64 * self.cell_size.1);
+                let dpdu = Vec3::new(cells.0 as f64 * self.cell_size.0, 0.0, 0.0);
+                let dpdv = Vec3::new(0.0, 0.0, -(cells.1 as f64) * self.cell_size.1);
+                return Some(
+                    HitRecord::new(p, t, r, unit_vector(normal), self.mat.clone(), u, v).with_derivatives(dpdu, dpdv),
+                );
+            }
+
+            if t_exit >= span.max {
+                return None;
+            }
+            if t_next_i < t_next_j {
+                if (step_i < 0 && i == 0) || (step_i > 0 && i + 1 == cells.0) {
+                    return None;
+                }
+                i = (i as i32 + step_i) as usize;
+                t_next_i += delta_i;
+            } else {
+                if (step_j < 0 && j == 0) || (step_j > 0 && j + 1 == cells.1) {
+                    return None;
+                }
+                j = (j as i32 + step_j) as usize;
+                t_next_j += delta_j;
+            }
+            t_enter = t_exit;
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+// Moller-Trumbore ray/triangle intersection, returning t and the barycentric weights of
+// the second and third vertices.
+fn intersect_triangle(r: &Ray, ray_t: Interval, [a, b, c]: [Point3; 3]) -> Option<(f64, f64, f64)> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let pvec = cross(r.direction(), edge2);
+    let det = dot(edge1, pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = r.origin() - a;
+    let b1 = dot(tvec, pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let qvec = cross(tvec, edge1);
+    let b2 = dot(r.direction(), qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = dot(edge2, qvec) * inv_det;
+    if !ray_t.surrounds(t) {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::material::{make_mat, EmptyMaterial};
+    use crate::books::the_rest_of_your_life::rtweekend::{random_double, random_double_range, seed_random, INFINITY};
+
+    // Rough terrain over uneven cells, away from the origin.
+    fn terrain() -> Heightfield {
+        let (columns, rows) = (9, 7);
+        let samples = (0..columns * rows).map(|_| random_double()).collect();
+        Heightfield::new(samples, columns, rows, Point3::new(-3.0, -1.0, 2.0), Vec3::new(5.0, 2.0, 3.0), make_mat(EmptyMaterial))
+    }
+
+    // The nearest hit over every triangle of the grid.
+    fn brute_force(field: &Heightfield, r: &Ray, ray_t: Interval) -> Option<(f64, Vec3)> {
+        let mut nearest: Option<(f64, Vec3)> = None;
+        for j in 0..field.rows - 1 {
+            for i in 0..field.columns - 1 {
+                let limit = nearest.map_or(ray_t, |(t, _)| Interval::new(ray_t.min, t));
+                if let Some(hit) = field.hit_cell(r, limit, i, j) {
+                    nearest = Some(hit);
+                }
+            }
+        }
+        nearest
+    }
+
+    fn random_point_over(bbox: &Aabb, margin: f64) -> Point3 {
+        let [x, y, z] = [0, 1, 2].map(|axis| {
+            let a = bbox.axis_interval(axis);
+            random_double_range(a.min - margin, a.max + margin)
+        });
+        Point3::new(x, y, z)
+    }
+
+    fn assert_same_hit(field: &Heightfield, r: &Ray, ray_t: Interval) -> bool {
+        let expected = brute_force(field, r, ray_t);
+        let actual = field.hit(r, ray_t);
+        match (expected, actual) {
+            (None, None) => false,
+            (Some((t, normal)), Some(rec)) => {
+                assert!((rec.t - t).abs() < 1e-9, "{:?} hits at t {}, expected {}", r.direction(), rec.t, t);
+                assert!((rec.normal - unit_vector(normal)).length() < 1e-9 || (rec.normal + unit_vector(normal)).length() < 1e-9);
+                true
+            }
+            (expected, actual) => {
+                panic!("{:?} from {:?}: expected {:?}, walked to {:?}", r.direction(), r.origin(), expected, actual.map(|rec| rec.t))
+            }
+        }
+    }
+
+    #[test]
+    fn walking_the_grid_finds_the_nearest_triangle() {
+        seed_random(golden::SEED);
+        let field = terrain();
+        let bbox = field.bounding_box();
+        let mut hits = 0;
+        for _ in 0..5000 {
+            // From anywhere around the terrain, including under it and inside its box, to
+            // anywhere over it.
+            let origin = random_point_over(&bbox, 2.0);
+            let target = random_point_over(&bbox, 0.5);
+            if assert_same_hit(&field, &Ray::new(origin, target - origin), Interval::new(0.001, INFINITY)) {
+                hits += 1;
+            }
+        }
+        assert!(hits > 1000, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn walking_handles_rays_along_the_grid() {
+        seed_random(golden::SEED);
+        let field = terrain();
+        let bbox = field.bounding_box();
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let origin = random_point_over(&bbox, 1.0);
+            let along = random_double_range(-1.0, 1.0);
+            let down = random_double_range(-1.0, 0.2);
+            for direction in [
+                Vec3::new(along, down, 0.0),
+                Vec3::new(0.0, down, along),
+                Vec3::new(0.0, -1.0, 0.0),
+            ] {
+                if assert_same_hit(&field, &Ray::new(origin, direction), Interval::new(0.001, INFINITY)) {
+                    hits += 1;
+                }
+            }
+        }
+        assert!(hits > 500, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn walking_stays_within_ray_t() {
+        seed_random(golden::SEED);
+        let field = terrain();
+        let bbox = field.bounding_box();
+        for _ in 0..2000 {
+            let origin = random_point_over(&bbox, 2.0);
+            let target = random_point_over(&bbox, 0.5);
+            let (near, far) = (random_double_range(0.0, 1.0), random_double_range(1.0, 2.0));
+            assert_same_hit(&field, &Ray::new(origin, target - origin), Interval::new(near, far));
+        }
+    }
+}
@@ -1,17 +1,29 @@
 use std::sync::Arc;
 
 use super::aabb::Aabb;
+use super::animation::Animated;
 use super::bvh::BvhNode;
 use super::constant_medium::ConstantMedium;
+use super::csg::Csg;
+use super::flat_bvh::FlatBvh;
+use super::heightfield::Heightfield;
 use super::hittable_list::HittableList;
+use super::instance::Instance;
 use super::interval::Interval;
 use super::material::MaterialRef;
+use super::qbvh::Qbvh;
 use super::quad::Quad;
+use super::quadric::{Cone, Cylinder, Disk};
 use super::ray::Ray;
 use super::rtweekend::{degrees_to_radians, INFINITY};
+use super::sdf::DistanceField;
 use super::sphere::Sphere;
-use super::vec3::{dot, Point3, Vec3};
+use super::texture::UvFootprint;
+use super::torus::Torus;
+use super::transform::MotionTransform;
+use super::vec3::{cross, dot, unit_vector, Point3, Vec3};
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
@@ -20,6 +32,18 @@ pub struct HitRecord {
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    // How p moves with u and v. Zero where a shape doesn't say, which leaves textures
+    // unfiltered.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    // The width of surface the ray covers around p, set by the camera from the ray cone.
+    pub footprint: f64,
+    // The point, outward normal and derivatives in the shape's own space, before transforms
+    // and instancing moved it, for textures that stay put on the object.
+    pub object_p: Point3,
+    pub object_normal: Vec3,
+    pub object_dpdu: Vec3,
+    pub object_dpdv: Vec3,
 }
 
 impl HitRecord {
@@ -40,11 +64,53 @@ impl HitRecord {
             u,
             v,
             front_face: false,
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
+            footprint: 0.0,
+            object_p: p,
+            object_normal: outward_normal,
+            object_dpdu: Vec3::new(0.0, 0.0, 0.0),
+            object_dpdv: Vec3::new(0.0, 0.0, 0.0),
         };
         rec.set_face_normal(r, outward_normal);
         rec
     }
 
+    pub fn with_derivatives(mut self, dpdu: Vec3, dpdv: Vec3) -> Self {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self.object_dpdu = dpdu;
+        self.object_dpdv = dpdv;
+        self
+    }
+
+    // The footprint measured in u and v, for picking a texture's level of detail.
+    pub fn uv_footprint(&self) -> UvFootprint {
+        let extent = |dp: Vec3| {
+            let length = dp.length();
+            if length > 0.0 { self.footprint / length } else { 0.0 }
+        };
+        UvFootprint { du: extent(self.dpdu), dv: extent(self.dpdv) }
+    }
+
+    // Unit tangents along u and v at right angles to the normal, or None where the shape
+    // gives no derivatives to orient them.
+    pub fn tangent_frame(&self) -> Option<(Vec3, Vec3)> {
+        let tangent = self.dpdu - dot(self.dpdu, self.normal) * self.normal;
+        if tangent.near_zero() {
+            return None;
+        }
+        let tangent = unit_vector(tangent);
+        let bitangent = cross(self.normal, tangent);
+        let bitangent = if dot(bitangent, self.dpdv) < 0.0 { -bitangent } else { bitangent };
+        Some((tangent, bitangent))
+    }
+
+    // The normal on the outside of the surface, whichever side the ray came from.
+    pub fn outward_normal(&self) -> Vec3 {
+        if self.front_face { self.normal } else { -self.normal }
+    }
+
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3) {
         self.front_face = dot(r.direction(), outward_normal) < 0.0;
         self.normal = if self.front_face { outward_normal } else { -outward_normal };
@@ -55,13 +121,41 @@ pub trait Hittable: Send + Sync {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord>;
     fn bounding_box(&self) -> Aabb;
 
-    fn pdf_value(&self, _origin: Point3, _direction: Vec3) -> f64 {
+    fn pdf_value(&self, _origin: Point3, _direction: Vec3, _time: f64) -> f64 {
         0.0
     }
 
-    fn random(&self, _origin: Point3) -> Vec3 {
+    fn random(&self, _origin: Point3, _time: f64) -> Vec3 {
         Vec3::new(1.0, 0.0, 0.0)
     }
+
+    // Every point within ray_t where the ray crosses the object's boundary, nearest first.
+    // For closed objects `front_face` tells entries from exits. The default steps from one
+    // hit to the next, the same way ConstantMedium finds its boundary.
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        let mut crossings = Vec::new();
+        let mut t_min = ray_t.min;
+        while let Some(rec) = self.hit(r, Interval::new(t_min, ray_t.max)) {
+            t_min = (rec.t + 0.0001).max(rec.t.next_up());
+            crossings.push(rec);
+        }
+        crossings
+    }
+}
+
+// The nearest hit on a shape that its material's mask doesn't cut away. Rays step past
+// cut-out hits the same way `crossings` steps from one hit to the next. Light sampling
+// still aims at the whole shape, so its density stays that of the shape; rays sent through
+// a cut-out part carry on through the world like any other.
+fn hit_opaque<H: Hittable>(object: &H, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    let mut t_min = ray_t.min;
+    loop {
+        let rec = object.hit(r, Interval::new(t_min, ray_t.max))?;
+        if rec.mat.is_opaque(&rec) {
+            return Some(rec);
+        }
+        t_min = (rec.t + 0.0001).max(rec.t.next_up());
+    }
 }
 
 pub type HittableRef = Arc<HittableObject>;
@@ -83,18 +177,31 @@ impl Translate {
     }
 }
 
-impl Hittable for Translate {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let offset_r = Ray::new_with_time(r.origin() - self.offset, r.direction(), r.time());
+impl Translate {
+    fn to_object(&self, r: &Ray) -> Ray {
+        Ray::new_with_time(r.origin() - self.offset, r.direction(), r.time())
+    }
 
-        let mut rec = self.object.hit(&offset_r, ray_t)?;
+    fn to_world(&self, mut rec: HitRecord) -> HitRecord {
         rec.p += self.offset;
-        Some(rec)
+        rec
+    }
+}
+
+impl Hittable for Translate {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let rec = self.object.hit(&self.to_object(r), ray_t)?;
+        Some(self.to_world(rec))
     }
 
     fn bounding_box(&self) -> Aabb {
         self.bbox
     }
+
+    // Forwarded rather than stepped through hit(), which would skip masked-out crossings.
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        self.object.crossings(&self.to_object(r), ray_t).into_iter().map(|rec| self.to_world(rec)).collect()
+    }
 }
 
 pub struct RotateY {
@@ -139,8 +246,8 @@ impl RotateY {
     }
 }
 
-impl Hittable for RotateY {
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+impl RotateY {
+    fn to_object(&self, r: &Ray) -> Ray {
         let origin = Point3::new(
             (self.cos_theta * r.origin().x()) - (self.sin_theta * r.origin().z()),
             r.origin().y(),
@@ -153,37 +260,59 @@ impl Hittable for RotateY {
             (self.sin_theta * r.direction().x()) + (self.cos_theta * r.direction().z()),
         );
 
-        let rotated_r = Ray::new_with_time(origin, direction, r.time());
-
-        let mut rec = self.object.hit(&rotated_r, ray_t)?;
-
-        rec.p = Point3::new(
-            (self.cos_theta * rec.p.x()) + (self.sin_theta * rec.p.z()),
-            rec.p.y(),
-            (-self.sin_theta * rec.p.x()) + (self.cos_theta * rec.p.z()),
-        );
+        Ray::new_with_time(origin, direction, r.time())
+    }
 
-        rec.normal = Vec3::new(
-            (self.cos_theta * rec.normal.x()) + (self.sin_theta * rec.normal.z()),
-            rec.normal.y(),
-            (-self.sin_theta * rec.normal.x()) + (self.cos_theta * rec.normal.z()),
-        );
+    fn to_world(&self, mut rec: HitRecord) -> HitRecord {
+        let to_world = |v: Vec3| {
+            Vec3::new(
+                (self.cos_theta * v.x()) + (self.sin_theta * v.z()),
+                v.y(),
+                (-self.sin_theta * v.x()) + (self.cos_theta * v.z()),
+            )
+        };
+        rec.p = to_world(rec.p);
+        rec.normal = to_world(rec.normal);
+        rec.dpdu = to_world(rec.dpdu);
+        rec.dpdv = to_world(rec.dpdv);
+        rec
+    }
+}
 
-        Some(rec)
+impl Hittable for RotateY {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let rec = self.object.hit(&self.to_object(r), ray_t)?;
+        Some(self.to_world(rec))
     }
 
     fn bounding_box(&self) -> Aabb {
         self.bbox
     }
+
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        self.object.crossings(&self.to_object(r), ray_t).into_iter().map(|rec| self.to_world(rec)).collect()
+    }
 }
 
 pub enum HittableObject {
     Sphere(Sphere),
+    Disk(Disk),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Torus(Torus),
+    DistanceField(DistanceField),
+    Heightfield(Heightfield),
     Quad(Quad),
     ConstantMedium(ConstantMedium),
     Translate(Translate),
     RotateY(RotateY),
+    Animated(Animated),
+    MotionTransform(MotionTransform),
+    Instance(Instance),
+    Csg(Csg),
     Bvh(BvhNode),
+    FlatBvh(FlatBvh),
+    Qbvh(Qbvh),
     List(HittableList),
 }
 
@@ -193,6 +322,42 @@ impl From<Sphere> for HittableObject {
     }
 }
 
+impl From<Disk> for HittableObject {
+    fn from(value: Disk) -> Self {
+        Self::Disk(value)
+    }
+}
+
+impl From<Cylinder> for HittableObject {
+    fn from(value: Cylinder) -> Self {
+        Self::Cylinder(value)
+    }
+}
+
+impl From<Cone> for HittableObject {
+    fn from(value: Cone) -> Self {
+        Self::Cone(value)
+    }
+}
+
+impl From<Torus> for HittableObject {
+    fn from(value: Torus) -> Self {
+        Self::Torus(value)
+    }
+}
+
+impl From<DistanceField> for HittableObject {
+    fn from(value: DistanceField) -> Self {
+        Self::DistanceField(value)
+    }
+}
+
+impl From<Heightfield> for HittableObject {
+    fn from(value: Heightfield) -> Self {
+        Self::Heightfield(value)
+    }
+}
+
 impl From<Quad> for HittableObject {
     fn from(value: Quad) -> Self {
         Self::Quad(value)
@@ -217,12 +382,48 @@ impl From<RotateY> for HittableObject {
     }
 }
 
+impl From<Animated> for HittableObject {
+    fn from(value: Animated) -> Self {
+        Self::Animated(value)
+    }
+}
+
+impl From<MotionTransform> for HittableObject {
+    fn from(value: MotionTransform) -> Self {
+        Self::MotionTransform(value)
+    }
+}
+
+impl From<Instance> for HittableObject {
+    fn from(value: Instance) -> Self {
+        Self::Instance(value)
+    }
+}
+
+impl From<Csg> for HittableObject {
+    fn from(value: Csg) -> Self {
+        Self::Csg(value)
+    }
+}
+
 impl From<BvhNode> for HittableObject {
     fn from(value: BvhNode) -> Self {
         Self::Bvh(value)
     }
 }
 
+impl From<FlatBvh> for HittableObject {
+    fn from(value: FlatBvh) -> Self {
+        Self::FlatBvh(value)
+    }
+}
+
+impl From<Qbvh> for HittableObject {
+    fn from(value: Qbvh) -> Self {
+        Self::Qbvh(value)
+    }
+}
+
 impl From<HittableList> for HittableObject {
     fn from(value: HittableList) -> Self {
         Self::List(value)
@@ -232,12 +433,24 @@ impl From<HittableList> for HittableObject {
 impl Hittable for HittableObject {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
         match self {
-            HittableObject::Sphere(object) => object.hit(r, ray_t),
-            HittableObject::Quad(object) => object.hit(r, ray_t),
+            HittableObject::Sphere(object) => hit_opaque(object, r, ray_t),
+            HittableObject::Disk(object) => hit_opaque(object, r, ray_t),
+            HittableObject::Cylinder(object) => hit_opaque(object, r, ray_t),
+            HittableObject::Cone(object) => hit_opaque(object, r, ray_t),
+            HittableObject::Torus(object) => hit_opaque(object, r, ray_t),
+            HittableObject::DistanceField(object) => hit_opaque(object, r, ray_t),
+            HittableObject::Heightfield(object) => hit_opaque(object, r, ray_t),
+            HittableObject::Quad(object) => hit_opaque(object, r, ray_t),
             HittableObject::ConstantMedium(object) => object.hit(r, ray_t),
             HittableObject::Translate(object) => object.hit(r, ray_t),
             HittableObject::RotateY(object) => object.hit(r, ray_t),
+            HittableObject::Animated(object) => object.hit(r, ray_t),
+            HittableObject::MotionTransform(object) => object.hit(r, ray_t),
+            HittableObject::Instance(object) => object.hit(r, ray_t),
+            HittableObject::Csg(object) => object.hit(r, ray_t),
             HittableObject::Bvh(object) => object.hit(r, ray_t),
+            HittableObject::FlatBvh(object) => object.hit(r, ray_t),
+            HittableObject::Qbvh(object) => object.hit(r, ray_t),
             HittableObject::List(object) => object.hit(r, ray_t),
         }
     }
@@ -245,36 +458,96 @@ impl Hittable for HittableObject {
     fn bounding_box(&self) -> Aabb {
         match self {
             HittableObject::Sphere(object) => object.bounding_box(),
+            HittableObject::Disk(object) => object.bounding_box(),
+            HittableObject::Cylinder(object) => object.bounding_box(),
+            HittableObject::Cone(object) => object.bounding_box(),
+            HittableObject::Torus(object) => object.bounding_box(),
+            HittableObject::DistanceField(object) => object.bounding_box(),
+            HittableObject::Heightfield(object) => object.bounding_box(),
             HittableObject::Quad(object) => object.bounding_box(),
             HittableObject::ConstantMedium(object) => object.bounding_box(),
             HittableObject::Translate(object) => object.bounding_box(),
             HittableObject::RotateY(object) => object.bounding_box(),
+            HittableObject::Animated(object) => object.bounding_box(),
+            HittableObject::MotionTransform(object) => object.bounding_box(),
+            HittableObject::Instance(object) => object.bounding_box(),
+            HittableObject::Csg(object) => object.bounding_box(),
             HittableObject::Bvh(object) => object.bounding_box(),
+            HittableObject::FlatBvh(object) => object.bounding_box(),
+            HittableObject::Qbvh(object) => object.bounding_box(),
             HittableObject::List(object) => object.bounding_box(),
         }
     }
 
-    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        match self {
+            HittableObject::Sphere(object) => object.pdf_value(origin, direction, time),
+            HittableObject::Disk(object) => object.pdf_value(origin, direction, time),
+            HittableObject::Cylinder(object) => object.pdf_value(origin, direction, time),
+            HittableObject::Cone(object) => object.pdf_value(origin, direction, time),
+            HittableObject::Torus(object) => object.pdf_value(origin, direction, time),
+            HittableObject::DistanceField(object) => object.pdf_value(origin, direction, time),
+            HittableObject::Heightfield(object) => object.pdf_value(origin, direction, time),
+            HittableObject::Quad(object) => object.pdf_value(origin, direction, time),
+            HittableObject::ConstantMedium(object) => object.pdf_value(origin, direction, time),
+            HittableObject::Translate(object) => object.pdf_value(origin, direction, time),
+            HittableObject::RotateY(object) => object.pdf_value(origin, direction, time),
+            HittableObject::Animated(object) => object.pdf_value(origin, direction, time),
+            HittableObject::MotionTransform(object) => object.pdf_value(origin, direction, time),
+            HittableObject::Instance(object) => object.pdf_value(origin, direction, time),
+            HittableObject::Csg(object) => object.pdf_value(origin, direction, time),
+            HittableObject::Bvh(object) => object.pdf_value(origin, direction, time),
+            HittableObject::FlatBvh(object) => object.pdf_value(origin, direction, time),
+            HittableObject::Qbvh(object) => object.pdf_value(origin, direction, time),
+            HittableObject::List(object) => object.pdf_value(origin, direction, time),
+        }
+    }
+
+    fn random(&self, origin: Point3, time: f64) -> Vec3 {
         match self {
-            HittableObject::Sphere(object) => object.pdf_value(origin, direction),
-            HittableObject::Quad(object) => object.pdf_value(origin, direction),
-            HittableObject::ConstantMedium(object) => object.pdf_value(origin, direction),
-            HittableObject::Translate(object) => object.pdf_value(origin, direction),
-            HittableObject::RotateY(object) => object.pdf_value(origin, direction),
-            HittableObject::Bvh(object) => object.pdf_value(origin, direction),
-            HittableObject::List(object) => object.pdf_value(origin, direction),
+            HittableObject::Sphere(object) => object.random(origin, time),
+            HittableObject::Disk(object) => object.random(origin, time),
+            HittableObject::Cylinder(object) => object.random(origin, time),
+            HittableObject::Cone(object) => object.random(origin, time),
+            HittableObject::Torus(object) => object.random(origin, time),
+            HittableObject::DistanceField(object) => object.random(origin, time),
+            HittableObject::Heightfield(object) => object.random(origin, time),
+            HittableObject::Quad(object) => object.random(origin, time),
+            HittableObject::ConstantMedium(object) => object.random(origin, time),
+            HittableObject::Translate(object) => object.random(origin, time),
+            HittableObject::RotateY(object) => object.random(origin, time),
+            HittableObject::Animated(object) => object.random(origin, time),
+            HittableObject::MotionTransform(object) => object.random(origin, time),
+            HittableObject::Instance(object) => object.random(origin, time),
+            HittableObject::Csg(object) => object.random(origin, time),
+            HittableObject::Bvh(object) => object.random(origin, time),
+            HittableObject::FlatBvh(object) => object.random(origin, time),
+            HittableObject::Qbvh(object) => object.random(origin, time),
+            HittableObject::List(object) => object.random(origin, time),
         }
     }
 
-    fn random(&self, origin: Point3) -> Vec3 {
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
         match self {
-            HittableObject::Sphere(object) => object.random(origin),
-            HittableObject::Quad(object) => object.random(origin),
-            HittableObject::ConstantMedium(object) => object.random(origin),
-            HittableObject::Translate(object) => object.random(origin),
-            HittableObject::RotateY(object) => object.random(origin),
-            HittableObject::Bvh(object) => object.random(origin),
-            HittableObject::List(object) => object.random(origin),
+            HittableObject::Sphere(object) => object.crossings(r, ray_t),
+            HittableObject::Disk(object) => object.crossings(r, ray_t),
+            HittableObject::Cylinder(object) => object.crossings(r, ray_t),
+            HittableObject::Cone(object) => object.crossings(r, ray_t),
+            HittableObject::Torus(object) => object.crossings(r, ray_t),
+            HittableObject::DistanceField(object) => object.crossings(r, ray_t),
+            HittableObject::Heightfield(object) => object.crossings(r, ray_t),
+            HittableObject::Quad(object) => object.crossings(r, ray_t),
+            HittableObject::ConstantMedium(object) => object.crossings(r, ray_t),
+            HittableObject::Translate(object) => object.crossings(r, ray_t),
+            HittableObject::RotateY(object) => object.crossings(r, ray_t),
+            HittableObject::Animated(object) => object.crossings(r, ray_t),
+            HittableObject::MotionTransform(object) => object.crossings(r, ray_t),
+            HittableObject::Instance(object) => object.crossings(r, ray_t),
+            HittableObject::Csg(object) => object.crossings(r, ray_t),
+            HittableObject::Bvh(object) => object.crossings(r, ray_t),
+            HittableObject::FlatBvh(object) => object.crossings(r, ray_t),
+            HittableObject::Qbvh(object) => object.crossings(r, ray_t),
+            HittableObject::List(object) => object.crossings(r, ray_t),
         }
     }
 }
@@ -57,20 +57,27 @@ impl Hittable for HittableList {
         self.bbox
     }
 
-    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
         let weight = 1.0 / self.objects.len() as f64;
         let mut sum = 0.0;
 
         for object in &self.objects {
-            sum += weight * object.pdf_value(origin, direction);
+            sum += weight * object.pdf_value(origin, direction, time);
         }
 
         sum
     }
 
-    fn random(&self, origin: Point3) -> Vec3 {
+    fn random(&self, origin: Point3, time: f64) -> Vec3 {
         let int_size = self.objects.len() as i32;
         let index = random_int(0, int_size - 1) as usize;
-        self.objects[index].random(origin)
+        self.objects[index].random(origin, time)
+    }
+
+    // Every object's crossings, merged; stepping through hit() would skip masked-out ones.
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        let mut crossings: Vec<HitRecord> = self.objects.iter().flat_map(|object| object.crossings(r, ray_t)).collect();
+        crossings.sort_by(|a, b| a.t.total_cmp(&b.t));
+        crossings
     }
 }
@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use super::aabb::Aabb;
+use super::flat_bvh::FlatBvh;
+use super::hittable::{make_ref, HitRecord, Hittable};
+use super::hittable_list::HittableList;
+use super::interval::Interval;
+use super::ray::Ray;
+use super::transform::Transform;
+use super::vec3::{Point3, Vec3};
+
+// A bottom-level acceleration structure. It is built once in the model's own space and
+// shared by every instance that places the model in the scene, so keep it around (rather
+// than the original list) when rebuilding the top level for a new frame.
+pub type Blas = Arc<FlatBvh>;
+
+pub fn build_blas(model: HittableList) -> Blas {
+    Arc::new(FlatBvh::new(model))
+}
+
+// Builds the top-level acceleration structure over a set of instances. Only the instance
+// bounds are sorted, so this stays cheap however large the shared models are.
+pub fn build_tlas(instances: Vec<Instance>) -> FlatBvh {
+    let mut list = HittableList::new();
+    for instance in instances {
+        list.add(make_ref(instance));
+    }
+    FlatBvh::new(list)
+}
+
+// One placement of a shared model. Costs a transform and a bounding box per copy.
+pub struct Instance {
+    blas: Blas,
+    transform: Transform,
+    bbox: Aabb,
+}
+
+impl Instance {
+    pub fn new(blas: Blas, transform: Transform) -> Self {
+        let bbox = transform.apply_box(&blas.bounding_box());
+        Self { blas, transform, bbox }
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let rec = self.blas.hit(&self.transform.inverse_ray(r), ray_t)?;
+        Some(self.transform.apply_hit(rec))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        let crossings = self.blas.crossings(&self.transform.inverse_ray(r), ray_t);
+        crossings.into_iter().map(|rec| self.transform.apply_hit(rec)).collect()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        let local_direction = self.transform.inverse_vector(direction / direction.length());
+        let pdf = self.blas.pdf_value(self.transform.inverse_point(origin), local_direction, time);
+
+        // Scaling squeezes and stretches solid angle; rotating and moving leave it be.
+        let s = self.transform.scale;
+        pdf / (s.x() * s.y() * s.z() * local_direction.length().powi(3))
+    }
+
+    fn random(&self, origin: Point3, time: f64) -> Vec3 {
+        self.transform.apply_vector(self.blas.random(self.transform.inverse_point(origin), time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::material::{make_mat, EmptyMaterial};
+    use crate::books::the_rest_of_your_life::quad::Quad;
+    use crate::books::the_rest_of_your_life::rtweekend::{random_double_range, seed_random, INFINITY};
+    use crate::books::the_rest_of_your_life::sphere::Sphere;
+    use crate::books::the_rest_of_your_life::test_support::assert_sampler_matches_pdf;
+    use crate::books::the_rest_of_your_life::transform::Quat;
+    use crate::books::the_rest_of_your_life::vec3::random_unit_vector;
+
+    // A ball and two panels, placed by `place` and `turn`. The ball is left out when the
+    // model will be squashed, since a squashed ball is no longer a sphere to compare with.
+    fn model(with_ball: bool, place: impl Fn(Point3) -> Point3, turn: impl Fn(Vec3) -> Vec3, size: f64) -> HittableList {
+        let mat = make_mat(EmptyMaterial);
+        let mut model = HittableList::new();
+        if with_ball {
+            model.add(make_ref(Sphere::new(place(Point3::new(0.5, 0.0, 0.0)), size * 0.5, mat.clone())));
+        }
+        let panels = [
+            (Point3::new(-1.0, -1.0, -0.5), Vec3::new(1.5, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0)),
+            (Point3::new(-1.0, 0.5, -1.0), Vec3::new(0.0, 0.0, 2.0), Vec3::new(1.0, 0.0, 0.0)),
+        ];
+        for (q, u, v) in panels {
+            model.add(make_ref(Quad::new(place(q), turn(u), turn(v), mat.clone())));
+        }
+        model
+    }
+
+    fn transforms() -> Vec<(bool, Transform)> {
+        let axis = Vec3::new(1.0, 1.0, 0.0);
+        vec![
+            (true, Transform::new(Vec3::new(3.0, -1.0, 2.0), Quat::from_axis_angle(axis, 40.0), Vec3::new(1.5, 1.5, 1.5))),
+            (false, Transform::new(Vec3::new(-2.0, 0.5, 1.0), Quat::from_axis_angle(axis, -75.0), Vec3::new(2.0, 0.5, 1.0))),
+        ]
+    }
+
+    fn contains(outer: &Aabb, inner: &Aabb) -> bool {
+        (0..3).all(|axis| {
+            let (o, i) = (outer.axis_interval(axis), inner.axis_interval(axis));
+            o.min <= i.min && i.max <= o.max
+        })
+    }
+
+    #[test]
+    fn hits_match_a_transformed_copy() {
+        seed_random(golden::SEED);
+        for (with_ball, transform) in transforms() {
+            let blas = build_blas(model(with_ball, |p| p, |v| v, 1.0));
+            let instance = Instance::new(blas, transform);
+            let copy = model(with_ball, |p| transform.apply_point(p), |v| transform.apply_vector(v), transform.scale.x());
+
+            let center = transform.translation;
+            for _ in 0..2000 {
+                let origin = center + 6.0 * random_unit_vector();
+                let target = center + random_double_range(0.0, 2.0) * random_unit_vector();
+                let r = Ray::new(origin, target - origin);
+                let ray_t = Interval::new(0.001, INFINITY);
+                match (instance.hit(&r, ray_t), copy.hit(&r, ray_t)) {
+                    (None, None) => {}
+                    (Some(a), Some(b)) => {
+                        assert!((a.t - b.t).abs() < 1e-9, "t {} against {}", a.t, b.t);
+                        assert!((a.p - b.p).length() < 1e-9, "p {:?} against {:?}", a.p, b.p);
+                        assert!((a.normal - b.normal).length() < 1e-9, "normal {:?} against {:?}", a.normal, b.normal);
+                        assert_eq!(a.front_face, b.front_face);
+                    }
+                    (a, b) => panic!("instance hit {:?}, copy hit {:?}", a.map(|r| r.t), b.map(|r| r.t)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn top_level_bounds_contain_every_instance() {
+        seed_random(golden::SEED);
+        let blas = build_blas(model(true, |p| p, |v| v, 1.0));
+        let local = blas.bounding_box();
+        let mut instances = Vec::new();
+        for _ in 0..50 {
+            let rotation = Quat::from_axis_angle(random_unit_vector(), random_double_range(0.0, 360.0));
+            let scale = Vec3::new(random_double_range(0.5, 2.0), random_double_range(0.5, 2.0), random_double_range(0.5, 2.0));
+            instances.push(Instance::new(blas.clone(), Transform::new(20.0 * random_unit_vector(), rotation, scale)));
+        }
+
+        // Every corner of every placed model is inside its instance's box and the top level's.
+        let placed: Vec<(Transform, Aabb)> = instances.iter().map(|i| (*i.transform(), i.bounding_box())).collect();
+        let tlas = build_tlas(instances);
+        for (transform, bbox) in placed {
+            for n in 0..8 {
+                let corner = Point3::new(
+                    if n & 1 == 0 { local.x.min } else { local.x.max },
+                    if n & 2 == 0 { local.y.min } else { local.y.max },
+                    if n & 4 == 0 { local.z.min } else { local.z.max },
+                );
+                let p = transform.apply_point(corner);
+                for outer in [&bbox, &tlas.bounding_box()] {
+                    assert!((0..3).all(|axis| outer.axis_interval(axis).expand(1e-9).contains(p[axis])), "{p:?} is outside {outer:?}");
+                }
+            }
+            assert!(contains(&tlas.bounding_box(), &bbox));
+        }
+    }
+
+    #[test]
+    fn instanced_lights_can_be_sampled() {
+        for (_, transform) in transforms() {
+            let instance = Instance::new(build_blas(model(false, |p| p, |v| v, 1.0)), transform);
+            // Close to both panels, so they cover much of the sphere of directions.
+            let origin = transform.apply_point(Point3::new(-0.2, 0.0, 0.5));
+            assert_sampler_matches_pdf(
+                &format!("panels scaled by {:?}", transform.scale),
+                || instance.random(origin, 0.0),
+                |d| instance.pdf_value(origin, d, 0.0),
+            );
+
+        }
+    }
+}
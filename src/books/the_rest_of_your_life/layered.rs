@@ -0,0 +1,248 @@
+// A clear coating over another material, like varnish on wood or the lacquer over car paint.
+//
+// The layers are evaluated stochastically: a ray either reflects off the coating, as often
+// as its Fresnel reflectance says, or passes through to the base and back out. On the way
+// through, light is tinted by what the coating absorbs along its slanted path, and on the
+// way out it loses what the coating reflects back in. The coating is thin enough that the
+// base sees the directions outside it unbent.
+
+use super::hittable::HitRecord;
+use super::material::{Material, MaterialRef, ScatterRecord};
+use super::ray::Ray;
+use super::rtweekend::random_double;
+use super::thin_film::ThinFilm;
+use super::vec3::{dot, reflect, unit_vector, Color, Point3, Vec3};
+
+pub struct Coated {
+    base: MaterialRef,
+    ior: f64,
+    tint: Color,
+    film: Option<ThinFilm>,
+}
+
+impl Coated {
+    pub fn new(base: MaterialRef, ior: f64) -> Self {
+        Self { base, ior, tint: Color::new(1.0, 1.0, 1.0), film: None }
+    }
+
+    // The color light takes on crossing the coating once, straight through. Slanted paths
+    // are longer and take on more.
+    pub fn with_tint(mut self, tint: Color) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    // A film on top of the coating, for paints that shift color with the angle.
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    // What the coating reflects of light arriving along `r_in`, and how often `scatter`
+    // reflects off it rather than passing through to the base.
+    fn reflection(&self, r_in: &Ray, rec: &HitRecord) -> (Color, f64) {
+        let cos_theta = (-dot(unit_vector(r_in.direction()), rec.normal)).clamp(0.0, 1.0);
+        let reflectance = self.reflectance(rec, cos_theta);
+        (reflectance, (reflectance.x() + reflectance.y() + reflectance.z()) / 3.0)
+    }
+
+    // What the coating reflects of light arriving at cos_theta from the normal.
+    fn reflectance(&self, rec: &HitRecord, cos_theta: f64) -> Color {
+        match &self.film {
+            Some(film) => film.over_dielectric(rec, cos_theta, 1.0, self.ior),
+            None => {
+                let r0 = ((1.0 - self.ior) / (1.0 + self.ior)).powi(2);
+                let r = r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5);
+                Color::new(r, r, r)
+            }
+        }
+    }
+
+    // What is left of light crossing the coating once at cos_theta outside it.
+    fn absorption(&self, cos_theta: f64) -> Color {
+        let cos_inside = (1.0 - (1.0 - cos_theta * cos_theta) / (self.ior * self.ior)).max(0.0).sqrt();
+        let length = 1.0 / cos_inside.max(1e-3);
+        Color::new(self.tint.x().powf(length), self.tint.y().powf(length), self.tint.z().powf(length))
+    }
+
+    // The share of light leaving the base along `direction` that makes it out through the
+    // coating. Light the base sends inwards, as glass does, doesn't cross it again.
+    fn exit(&self, rec: &HitRecord, direction: Vec3) -> Color {
+        let cos_theta = dot(unit_vector(direction), rec.normal);
+        if cos_theta <= 0.0 {
+            return Color::new(1.0, 1.0, 1.0);
+        }
+        (Color::new(1.0, 1.0, 1.0) - self.reflectance(rec, cos_theta)) * self.absorption(cos_theta)
+    }
+}
+
+impl Material for Coated {
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Point3) -> Color {
+        self.base.emitted(r_in, rec, u, v, p)
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        // The coating is on the outside only.
+        if !rec.front_face {
+            return self.base.scatter(r_in, rec);
+        }
+
+        let unit_direction = unit_vector(r_in.direction());
+        let cos_theta = (-dot(unit_direction, rec.normal)).clamp(0.0, 1.0);
+        let (reflectance, p) = self.reflection(r_in, rec);
+        if random_double() < p {
+            return Some(ScatterRecord {
+                attenuation: reflectance / p,
+                pdf_ptr: None,
+                skip_pdf: true,
+                skip_pdf_ray: Ray::new_with_time(rec.p, reflect(unit_direction, rec.normal), r_in.time()),
+            });
+        }
+
+        let mut srec = self.base.scatter(r_in, rec)?;
+        srec.attenuation = srec.attenuation * (Color::new(1.0, 1.0, 1.0) - reflectance) / (1.0 - p)
+            * self.absorption(cos_theta);
+        // Sampled directions get their way out in `scattering`.
+        if srec.skip_pdf {
+            srec.attenuation = srec.attenuation * self.exit(rec, srec.skip_pdf_ray.direction());
+        }
+        Some(srec)
+    }
+
+    // The base's density, for the rays that get through the coating to it.
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let pdf = self.base.scattering_pdf(r_in, rec, scattered);
+        if !rec.front_face {
+            return pdf;
+        }
+        let (_, p) = self.reflection(r_in, rec);
+        (1.0 - p) * pdf
+    }
+
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, attenuation: Color, scattered: &Ray) -> Color {
+        let f = self.base.scattering(r_in, rec, attenuation, scattered);
+        if !rec.front_face {
+            return f;
+        }
+        f * self.exit(rec, scattered.direction())
+    }
+
+    fn is_opaque(&self, rec: &HitRecord) -> bool {
+        self.base.is_opaque(rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::material::{make_mat, Lambertian, Metal};
+    use crate::books::the_rest_of_your_life::rtweekend::seed_random;
+    use crate::books::the_rest_of_your_life::vec3::random_unit_vector;
+    use crate::books::the_rest_of_your_life::test_support::{incoming_directions, integrate_directions, mean_weight, surface_hit};
+
+    // The fraction of the light arriving from `incoming` that the material sends back out.
+    fn albedo(mat: &MaterialRef, incoming: Vec3) -> Color {
+        let (r_in, rec) = surface_hit(mat, incoming);
+        mean_weight(mat, &r_in, &rec, 50_000)
+    }
+
+    #[test]
+    fn a_coat_on_black_reflects_by_fresnel() {
+        seed_random(golden::SEED);
+        let coated = make_mat(Coated::new(make_mat(Lambertian::new(Color::new(0.0, 0.0, 0.0))), 1.5));
+        for incoming in incoming_directions() {
+            let expected = 0.04 + 0.96 * (1.0 - incoming.z()).powi(5);
+            let reflected = albedo(&coated, incoming);
+            assert!(
+                (reflected.x() - expected).abs() < 5e-3,
+                "lit from {:?}: reflects {:?}, expected {}",
+                incoming,
+                reflected,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn scattering_pdf_covers_the_light_through_the_coat() {
+        seed_random(golden::SEED);
+        let coated = make_mat(Coated::new(make_mat(Lambertian::new(Color::new(1.0, 1.0, 1.0))), 1.5));
+        for incoming in incoming_directions() {
+            let (r_in, rec) = surface_hit(&coated, incoming);
+            let through = 1.0 - (0.04 + 0.96 * (1.0 - incoming.z()).powi(5));
+            let total = integrate_directions(200, |d| coated.scattering_pdf(&r_in, &rec, &Ray::new(rec.p, d)));
+            assert!((total - through).abs() < 0.01, "lit from {:?}: pdf integrates to {}, expected {}", incoming, total, through);
+        }
+    }
+
+    #[test]
+    fn coats_add_no_light() {
+        seed_random(golden::SEED);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let coats = [
+            ("varnished lambertian", Coated::new(make_mat(Lambertian::new(white)), 1.5), 0.85),
+            ("lacquered mirror", Coated::new(make_mat(Metal::new(white, 0.0)), 1.5), 0.7),
+            ("tinted", Coated::new(make_mat(Lambertian::new(white)), 1.5).with_tint(Color::new(0.9, 0.6, 0.3)), 0.0),
+            ("iridescent", Coated::new(make_mat(Lambertian::new(white)), 1.5).with_thin_film(ThinFilm::new(400.0, 1.3)), 0.0),
+        ];
+        for (name, coat, at_least) in coats {
+            let mat = make_mat(coat);
+            for incoming in incoming_directions() {
+                let reflected = albedo(&mat, incoming);
+                for c in 0..3 {
+                    assert!(
+                        (at_least..=1.01).contains(&reflected[c]),
+                        "{} lit from {:?}: reflects {:?}",
+                        name,
+                        incoming,
+                        reflected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn light_through_the_coat_is_reciprocal() {
+        seed_random(golden::SEED);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let coats = [
+            ("tinted", Coated::new(make_mat(Lambertian::new(white)), 1.5).with_tint(Color::new(0.9, 0.6, 0.3))),
+            ("iridescent", Coated::new(make_mat(Lambertian::new(white)), 1.5).with_thin_film(ThinFilm::new(400.0, 1.3))),
+        ];
+        for (name, coat) in coats {
+            let mat = make_mat(Lambertian::new(white));
+            // What the camera weights light through the coat by, per unit of outgoing cosine:
+            // the base's reflection, dimmed by the coating on the way in and on the way out.
+            let bsdf = |incoming: Vec3, outgoing: Vec3| {
+                let (r_in, rec) = surface_hit(&mat, incoming);
+                let srec = loop {
+                    let srec = coat.scatter(&r_in, &rec).expect("the coat scatters");
+                    if !srec.skip_pdf {
+                        break srec;
+                    }
+                };
+                let (_, p) = coat.reflection(&r_in, &rec);
+                (1.0 - p) * coat.scattering(&r_in, &rec, srec.attenuation, &Ray::new(rec.p, outgoing)) / outgoing.z()
+            };
+            for _ in 0..1000 {
+                let (a, b) = (random_unit_vector(), random_unit_vector());
+                let (a, b) = (Vec3::new(a.x(), a.y(), a.z().abs()), Vec3::new(b.x(), b.y(), b.z().abs()));
+                if a.z() < 0.05 || b.z() < 0.05 {
+                    continue;
+                }
+                let (forward, backward) = (bsdf(a, b), bsdf(b, a));
+                assert!(
+                    (forward - backward).length() <= 1e-9 * forward.length().max(1.0),
+                    "{}: f({:?}, {:?}) = {:?} but the reverse is {:?}",
+                    name,
+                    a,
+                    b,
+                    forward,
+                    backward
+                );
+            }
+        }
+    }
+}
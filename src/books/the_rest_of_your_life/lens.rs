@@ -0,0 +1,356 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::ray::Ray;
+use super::vec3::{dot, random_in_unit_disk, unit_vector, Point3, Vec3};
+
+#[derive(Clone, Copy, Debug)]
+pub struct LensElement {
+    pub curvature_radius: f64,
+    pub thickness: f64,
+    pub eta: f64,
+    pub aperture_radius: f64,
+}
+
+impl LensElement {
+    fn is_stop(&self) -> bool {
+        self.curvature_radius == 0.0
+    }
+}
+
+// A sequence of spherical lens interfaces, listed from the scene side towards the film,
+// in the same table layout as PBRT lens files: curvature radius, thickness, index of
+// refraction and aperture diameter per row, with a zero radius marking the aperture stop.
+//
+// Lens space follows PBRT: the film sits at z = 0, the rear element at z = -rear_z() and
+// the optical axis points along -z towards the scene.
+#[derive(Clone, Debug)]
+pub struct RealisticLens {
+    elements: Vec<LensElement>,
+    film_diagonal: f64,
+}
+
+impl RealisticLens {
+    pub fn parse(text: &str, aperture_diameter: f64, film_diagonal: f64) -> Result<Self, String> {
+        let mut elements = Vec::new();
+
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values = line
+                .split_whitespace()
+                .map(|token| token.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|e| format!("line {}: {}", line_no + 1, e))?;
+            if values.len() != 4 {
+                return Err(format!("line {}: expected 4 columns, found {}", line_no + 1, values.len()));
+            }
+
+            let curvature_radius = values[0];
+            let mut diameter = values[3];
+            if curvature_radius == 0.0 {
+                if aperture_diameter.is_infinite() {
+                    // Wide open.
+                } else if aperture_diameter > diameter {
+                    eprintln!(
+                        "WARNING: Aperture diameter {} is larger than the lens stop {}; clamping.",
+                        aperture_diameter, diameter
+                    );
+                } else {
+                    diameter = aperture_diameter;
+                }
+            }
+
+            elements.push(LensElement {
+                curvature_radius,
+                thickness: values[1],
+                // Air gaps and the stop are written with an index of zero.
+                eta: if values[2] == 0.0 { 1.0 } else { values[2] },
+                aperture_radius: diameter / 2.0,
+            });
+        }
+
+        if elements.is_empty() {
+            return Err("lens description has no elements".to_string());
+        }
+
+        Ok(Self { elements, film_diagonal })
+    }
+
+    pub fn load(filename: &str, aperture_diameter: f64, film_diagonal: f64) -> Result<Self, String> {
+        let mut candidates = Vec::new();
+        if let Ok(lensdir) = env::var("RTW_LENSES") {
+            candidates.push(Path::new(&lensdir).join(filename));
+        }
+        candidates.push(PathBuf::from(filename));
+        let mut prefix = PathBuf::new();
+        for _ in 0..7 {
+            candidates.push(prefix.join("lenses").join(filename));
+            prefix.push("..");
+        }
+
+        for candidate in candidates {
+            if let Ok(text) = fs::read_to_string(&candidate) {
+                return Self::parse(&text, aperture_diameter, film_diagonal)
+                    .map_err(|e| format!("{}: {}", candidate.display(), e));
+            }
+        }
+
+        Err(format!("could not load lens file '{}'", filename))
+    }
+
+    // Lens files are usually in millimetres; scale everything into scene units.
+    pub fn scaled(mut self, factor: f64) -> Self {
+        for element in &mut self.elements {
+            element.curvature_radius *= factor;
+            element.thickness *= factor;
+            element.aperture_radius *= factor;
+        }
+        self.film_diagonal *= factor;
+        self
+    }
+
+    pub fn film_diagonal(&self) -> f64 {
+        self.film_diagonal
+    }
+
+    // Returns a copy whose film-to-rear-element distance brings `focus_distance` (measured
+    // from the film) into focus, using a thick lens approximation of the whole system.
+    pub fn focused(&self, focus_distance: f64) -> Self {
+        let mut lens = self.clone();
+        if let Some(((pz0, fz0), (pz1, _))) = self.thick_lens_approximation() {
+            let f = fz0 - pz0;
+            let z = -focus_distance;
+            let c = (pz1 - z - pz0) * (pz1 - z - 4.0 * f - pz0);
+            if c >= 0.0 {
+                let delta = 0.5 * (pz1 - z + pz0 - c.sqrt());
+                let last = lens.elements.len() - 1;
+                lens.elements[last].thickness = self.rear_z() + delta;
+            } else {
+                eprintln!("WARNING: Lens cannot focus at distance {}.", focus_distance);
+            }
+        }
+        lens
+    }
+
+    // The effective focal length of the whole system, from its thick lens approximation.
+    pub fn focal_length(&self) -> Option<f64> {
+        let ((pz0, fz0), _) = self.thick_lens_approximation()?;
+        Some((fz0 - pz0).abs())
+    }
+
+    pub fn rear_z(&self) -> f64 {
+        self.elements[self.elements.len() - 1].thickness
+    }
+
+    pub fn front_z(&self) -> f64 {
+        self.elements.iter().map(|e| e.thickness).sum()
+    }
+
+    pub fn rear_radius(&self) -> f64 {
+        self.elements[self.elements.len() - 1].aperture_radius
+    }
+
+    // Generates a lens-space ray leaving the front element for a point on the film, or
+    // None when the sampled path is blocked inside the lens barrel.
+    pub fn sample_ray(&self, film_x: f64, film_y: f64) -> Option<Ray> {
+        let p_film = Point3::new(-film_x, film_y, 0.0);
+        let d = random_in_unit_disk();
+        let p_rear = Point3::new(self.rear_radius() * d.x(), self.rear_radius() * d.y(), self.rear_z());
+        self.trace_from_film(Ray::new(p_film, p_rear - p_film))
+    }
+
+    pub fn trace_from_film(&self, r_camera: Ray) -> Option<Ray> {
+        let mut element_z = 0.0;
+        let mut r_lens = flip_z(r_camera);
+
+        for i in (0..self.elements.len()).rev() {
+            let element = self.elements[i];
+            element_z -= element.thickness;
+
+            let (t, normal) = if element.is_stop() {
+                if r_lens.direction().z() >= 0.0 {
+                    return None;
+                }
+                ((element_z - r_lens.origin().z()) / r_lens.direction().z(), None)
+            } else {
+                let radius = element.curvature_radius;
+                let (t, n) = intersect_spherical_element(radius, element_z + radius, &r_lens)?;
+                (t, Some(n))
+            };
+
+            let p_hit = r_lens.at(t);
+            if p_hit.x() * p_hit.x() + p_hit.y() * p_hit.y() > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+
+            let mut direction = r_lens.direction();
+            if let Some(n) = normal {
+                let eta_i = element.eta;
+                let eta_t = if i > 0 { self.elements[i - 1].eta } else { 1.0 };
+                direction = refract(unit_vector(-direction), n, eta_i / eta_t)?;
+            }
+            r_lens = Ray::new(p_hit, direction);
+        }
+
+        Some(flip_z(r_lens))
+    }
+
+    pub fn trace_from_scene(&self, r_camera: Ray) -> Option<Ray> {
+        let mut element_z = -self.front_z();
+        let mut r_lens = flip_z(r_camera);
+
+        for i in 0..self.elements.len() {
+            let element = self.elements[i];
+
+            let (t, normal) = if element.is_stop() {
+                if r_lens.direction().z() <= 0.0 {
+                    return None;
+                }
+                ((element_z - r_lens.origin().z()) / r_lens.direction().z(), None)
+            } else {
+                let radius = element.curvature_radius;
+                let (t, n) = intersect_spherical_element(radius, element_z + radius, &r_lens)?;
+                (t, Some(n))
+            };
+
+            let p_hit = r_lens.at(t);
+            if p_hit.x() * p_hit.x() + p_hit.y() * p_hit.y() > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+
+            let mut direction = r_lens.direction();
+            if let Some(n) = normal {
+                let eta_i = if i == 0 { 1.0 } else { self.elements[i - 1].eta };
+                let eta_t = element.eta;
+                direction = refract(unit_vector(-direction), n, eta_i / eta_t)?;
+            }
+            r_lens = Ray::new(p_hit, direction);
+            element_z += element.thickness;
+        }
+
+        Some(flip_z(r_lens))
+    }
+
+    // Principal plane and focal point z values for the scene side and the film side.
+    fn thick_lens_approximation(&self) -> Option<((f64, f64), (f64, f64))> {
+        let x = 0.001 * self.film_diagonal;
+
+        let r_scene = Ray::new(Point3::new(x, 0.0, self.front_z() + 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let r_film = self.trace_from_scene(r_scene)?;
+        let scene_side = cardinal_points(r_scene, r_film);
+
+        let r_film = Ray::new(Point3::new(x, 0.0, self.rear_z() - 1.0), Vec3::new(0.0, 0.0, 1.0));
+        let r_scene = self.trace_from_film(r_film)?;
+        let film_side = cardinal_points(r_film, r_scene);
+
+        Some((scene_side, film_side))
+    }
+}
+
+fn flip_z(r: Ray) -> Ray {
+    let o = r.origin();
+    let d = r.direction();
+    Ray::new(Point3::new(o.x(), o.y(), -o.z()), Vec3::new(d.x(), d.y(), -d.z()))
+}
+
+fn cardinal_points(r_in: Ray, r_out: Ray) -> (f64, f64) {
+    let tf = -r_out.origin().x() / r_out.direction().x();
+    let fz = -r_out.at(tf).z();
+    let tp = (r_in.origin().x() - r_out.origin().x()) / r_out.direction().x();
+    let pz = -r_out.at(tp).z();
+    (pz, fz)
+}
+
+fn intersect_spherical_element(radius: f64, z_center: f64, r: &Ray) -> Option<(f64, Vec3)> {
+    let o = r.origin() - Vec3::new(0.0, 0.0, z_center);
+    let d = r.direction();
+    let a = d.length_squared();
+    let b = 2.0 * dot(d, o);
+    let c = o.length_squared() - radius * radius;
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sqrtd = discriminant.sqrt();
+    let q = if b < 0.0 { -0.5 * (b - sqrtd) } else { -0.5 * (b + sqrtd) };
+    let t0 = q / a;
+    let t1 = c / q;
+    let (t0, t1) = if t0 > t1 { (t1, t0) } else { (t0, t1) };
+
+    // Convex and concave interfaces are hit on opposite halves of their sphere.
+    let use_closer_t = (d.z() > 0.0) ^ (radius < 0.0);
+    let t = if use_closer_t { t0 } else { t1 };
+    if t < 0.0 {
+        return None;
+    }
+
+    let mut n = unit_vector(o + t * d);
+    if dot(n, -d) < 0.0 {
+        n = -n;
+    }
+    Some((t, n))
+}
+
+fn refract(wi: Vec3, n: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_theta_i = dot(n, wi);
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = eta * eta * sin2_theta_i;
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(eta * -wi + (eta * cos_theta_i - cos_theta_t) * n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A full-frame 35mm film.
+    const FILM_DIAGONAL: f64 = 43.27;
+
+    fn dgauss() -> RealisticLens {
+        RealisticLens::load("dgauss.50mm.dat", f64::INFINITY, FILM_DIAGONAL).expect("lens file loads")
+    }
+
+    #[test]
+    fn dgauss_has_a_50mm_focal_length() {
+        let f = dgauss().focal_length().expect("lens focuses parallel light");
+        assert!((f - 50.0).abs() < 2.0, "focal length {}", f);
+    }
+
+    #[test]
+    fn scaling_scales_the_focal_length() {
+        let f = dgauss().scaled(0.001).focal_length().expect("lens focuses parallel light");
+        assert!((f - 0.05).abs() < 0.002, "focal length {}", f);
+    }
+
+    #[test]
+    fn focused_lens_images_the_focus_point_on_the_film() {
+        let focus_distance = 1000.0;
+        let lens = dgauss().focused(focus_distance);
+        // Light from a point on the axis at the focus distance converges back onto the axis
+        // at the film.
+        let source = Point3::new(0.0, 0.0, focus_distance);
+        for offset in [1.0, 3.0, 6.0] {
+            let toward = Point3::new(offset, 0.0, lens.front_z());
+            let r = lens.trace_from_scene(Ray::new(source, toward - source)).expect("ray passes the lens");
+            let t = -r.origin().z() / r.direction().z();
+            let at_film = r.at(t);
+            assert!(at_film.x().abs() < 0.05, "ray {} off axis lands at {:?}", offset, at_film);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_bad_rows() {
+        assert!(RealisticLens::parse("# nothing\n", 1.0, 1.0).is_err());
+        assert!(RealisticLens::parse("10 1 1.5\n", 1.0, 1.0).is_err());
+        assert!(RealisticLens::parse("10 1 1.5 x\n", 1.0, 1.0).is_err());
+    }
+}
@@ -1,12 +1,16 @@
 use std::sync::Arc;
 
 use super::hittable::HitRecord;
+use super::layered::Coated;
 use super::pdf::{make_pdf, CosinePdf, PdfRef, SpherePdf};
+use super::principled::Principled;
 use super::ray::Ray;
 use super::rtweekend::random_double;
+use super::subsurface::Subsurface;
 use super::texture::{make_tex, SolidColor, TextureRef};
+use super::thin_film::ThinFilm;
 use super::vec3::{
-    dot, random_unit_vector, reflect, refract, unit_vector, Color, Point3, Vec3,
+    cross, dot, random_unit_vector, reflect, refract, unit_vector, Color, Point3, Vec3,
 };
 
 pub struct ScatterRecord {
@@ -25,9 +29,23 @@ pub trait Material: Send + Sync {
         None
     }
 
+    // The density of the directions `scatter` draws, including how often it picks the part
+    // of the material that has one.
     fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
         0.0
     }
+
+    // The BSDF times the cosine, per channel, scaled by the attenuation `scatter` gave. This,
+    // not the scattering pdf, is what the camera weights scattered rays by. Only materials
+    // whose color changes with direction need more than the scattering pdf.
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, attenuation: Color, scattered: &Ray) -> Color {
+        attenuation * self.scattering_pdf(r_in, rec, scattered)
+    }
+
+    // Whether the surface is there at the hit. Rays pass through hits that aren't.
+    fn is_opaque(&self, _rec: &HitRecord) -> bool {
+        true
+    }
 }
 
 pub type MaterialRef = Arc<MaterialObject>;
@@ -53,7 +71,7 @@ impl Lambertian {
 impl Material for Lambertian {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         Some(ScatterRecord {
-            attenuation: self.tex.value(rec.u, rec.v, rec.p),
+            attenuation: self.tex.sample(rec),
             pdf_ptr: Some(make_pdf(CosinePdf::new(rec.normal))),
             skip_pdf: false,
             skip_pdf_ray: Ray::new_with_time(rec.p, rec.normal, r_in.time()),
@@ -71,23 +89,45 @@ impl Material for Lambertian {
 }
 
 pub struct Metal {
-    albedo: Color,
-    fuzz: f64,
+    albedo: TextureRef,
+    fuzz: TextureRef,
+    film: Option<ThinFilm>,
 }
 
 impl Metal {
     pub fn new(albedo: Color, fuzz: f64) -> Self {
-        Self { albedo, fuzz: if fuzz < 1.0 { fuzz } else { 1.0 } }
+        Self::from_textures(make_tex(SolidColor::new(albedo)), make_tex(SolidColor::from_value(fuzz)))
+    }
+
+    // The fuzz texture works as a roughness map, clamped to [0, 1].
+    pub fn from_textures(albedo: TextureRef, fuzz: TextureRef) -> Self {
+        Self { albedo, fuzz, film: None }
+    }
+
+    // A film over the metal, like the oxide colors on heated steel or titanium.
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
     }
 }
 
 impl Material for Metal {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let fuzz = self.fuzz.sample_scalar(rec).clamp(0.0, 1.0);
         let reflected = reflect(r_in.direction(), rec.normal);
-        let reflected = unit_vector(reflected) + self.fuzz * random_unit_vector();
+        let reflected = unit_vector(reflected) + fuzz * random_unit_vector();
+
+        let albedo = self.albedo.sample(rec);
+        let attenuation = match &self.film {
+            Some(film) => {
+                let cos_theta = (-dot(unit_vector(r_in.direction()), rec.normal)).clamp(0.0, 1.0);
+                film.over_conductor(rec, cos_theta, albedo)
+            }
+            None => albedo,
+        };
 
         Some(ScatterRecord {
-            attenuation: self.albedo,
+            attenuation,
             pdf_ptr: None,
             skip_pdf: true,
             skip_pdf_ray: Ray::new_with_time(rec.p, reflected, r_in.time()),
@@ -96,15 +136,27 @@ impl Material for Metal {
 }
 
 pub struct Dielectric {
-    refraction_index: f64,
+    refraction_index: TextureRef,
+    film: Option<ThinFilm>,
 }
 
 impl Dielectric {
     pub fn new(refraction_index: f64) -> Self {
-        Self { refraction_index }
+        Self::from_texture(make_tex(SolidColor::from_value(refraction_index)))
+    }
+
+    pub fn from_texture(refraction_index: TextureRef) -> Self {
+        Self { refraction_index, film: None }
     }
 
-    fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
+    // A film on the surface. Over an index of 1 this is a soap bubble: a film of water
+    // with air on both sides.
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    pub(super) fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
         let mut r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
         r0 *= r0;
         r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
@@ -113,18 +165,35 @@ impl Dielectric {
 
 impl Material for Dielectric {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
-        let ri = if rec.front_face { 1.0 / self.refraction_index } else { self.refraction_index };
+        let refraction_index = self.refraction_index.sample_scalar(rec);
+        let ri = if rec.front_face { 1.0 / refraction_index } else { refraction_index };
 
         let unit_direction = unit_vector(r_in.direction());
         let cos_theta = (-dot(unit_direction, rec.normal)).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
-        let cannot_refract = ri * sin_theta > 1.0;
-        let direction = if cannot_refract || Self::reflectance(cos_theta, ri) > random_double() {
-            reflect(unit_direction, rec.normal)
-        } else {
-            refract(unit_direction, rec.normal, ri)
+        let (direction, attenuation) = match &self.film {
+            None => {
+                let cannot_refract = ri * sin_theta > 1.0;
+                let direction = if cannot_refract || Self::reflectance(cos_theta, ri) > random_double() {
+                    reflect(unit_direction, rec.normal)
+                } else {
+                    refract(unit_direction, rec.normal, ri)
+                };
+                (direction, Color::new(1.0, 1.0, 1.0))
+            }
+            Some(film) => {
+                // The film reflects each color differently. Choose by the average and
+                // reweight, so every color gets its own share on average.
+                let (outside, inside) = if rec.front_face { (1.0, refraction_index) } else { (refraction_index, 1.0) };
+                let reflectance = film.over_dielectric(rec, cos_theta, outside, inside);
+                let p = (reflectance.x() + reflectance.y() + reflectance.z()) / 3.0;
+                if random_double() < p {
+                    (reflect(unit_direction, rec.normal), reflectance / p)
+                } else {
+                    (refract(unit_direction, rec.normal, ri), (Color::new(1.0, 1.0, 1.0) - reflectance) / (1.0 - p))
+                }
+            }
         };
 
         Some(ScatterRecord {
@@ -152,11 +221,11 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn emitted(&self, _r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Point3) -> Color {
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord, _u: f64, _v: f64, _p: Point3) -> Color {
         if !rec.front_face {
             return Color::new(0.0, 0.0, 0.0);
         }
-        self.tex.value(u, v, p)
+        self.tex.sample(rec)
     }
 }
 
@@ -177,7 +246,7 @@ impl Isotropic {
 impl Material for Isotropic {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         Some(ScatterRecord {
-            attenuation: self.tex.value(rec.u, rec.v, rec.p),
+            attenuation: self.tex.sample(rec),
             pdf_ptr: Some(make_pdf(SpherePdf)),
             skip_pdf: false,
             skip_pdf_ray: Ray::new_with_time(rec.p, Vec3::new(1.0, 0.0, 0.0), r_in.time()),
@@ -189,6 +258,168 @@ impl Material for Isotropic {
     }
 }
 
+// Where a ShadingNormal takes its normals from.
+pub enum NormalSource {
+    // A tangent-space normal map: red, green and blue, mapped from [0, 1] to [-1, 1], give
+    // the normal along dpdu, along dpdv and out of the surface. Load images of normals with
+    // ColorSpace::Linear.
+    NormalMap(TextureRef),
+    // A height texture, scaled, that raises the surface along its normal without moving it.
+    BumpMap(TextureRef, f64),
+}
+
+// Another material seen through a normal bent by a map, for detail too fine to model. Only
+// shading changes; rays still hit the geometric surface. Shapes that don't give dpdu and
+// dpdv have no frame to orient a map and keep their normals.
+pub struct ShadingNormal {
+    base: MaterialRef,
+    source: NormalSource,
+}
+
+impl ShadingNormal {
+    pub fn new(base: MaterialRef, source: NormalSource) -> Self {
+        Self { base, source }
+    }
+
+    pub fn normal_map(base: MaterialRef, map: TextureRef) -> Self {
+        Self::new(base, NormalSource::NormalMap(map))
+    }
+
+    pub fn bump_map(base: MaterialRef, height: TextureRef, scale: f64) -> Self {
+        Self::new(base, NormalSource::BumpMap(height, scale))
+    }
+
+    // The hit as the base material sees it.
+    fn shade(&self, rec: &HitRecord) -> HitRecord {
+        let Some((tangent, bitangent)) = rec.tangent_frame() else {
+            return rec.clone();
+        };
+        let outward = rec.outward_normal();
+
+        let normal = match &self.source {
+            NormalSource::NormalMap(map) => {
+                let c = 2.0 * map.sample(rec) - Color::new(1.0, 1.0, 1.0);
+                c.x() * tangent + c.y() * bitangent + c.z() * outward
+            }
+            NormalSource::BumpMap(height, scale) => {
+                // Differences over half the footprint, or a small fixed step without one. The
+                // neighbouring lookups move the whole hit, so textures placed in object space
+                // or projected from it see the same offset as image textures do.
+                let footprint = rec.uv_footprint();
+                let du = if footprint.du > 0.0 { 0.5 * footprint.du } else { 0.0005 };
+                let dv = if footprint.dv > 0.0 { 0.5 * footprint.dv } else { 0.0005 };
+                let h = |du: f64, dv: f64| {
+                    let mut shifted = rec.clone();
+                    shifted.u += du;
+                    shifted.v += dv;
+                    shifted.p += du * rec.dpdu + dv * rec.dpdv;
+                    shifted.object_p += du * rec.object_dpdu + dv * rec.object_dpdv;
+                    scale * height.sample_scalar(&shifted)
+                };
+
+                let center = h(0.0, 0.0);
+                let dhdu = (h(du, 0.0) - center) / du;
+                let dhdv = (h(0.0, dv) - center) / dv;
+                let normal = cross(rec.dpdu + dhdu * outward, rec.dpdv + dhdv * outward);
+                if dot(normal, outward) < 0.0 { -normal } else { normal }
+            }
+        };
+        if normal.near_zero() {
+            return rec.clone();
+        }
+
+        let mut shaded = rec.clone();
+        let normal = unit_vector(normal);
+        shaded.normal = if rec.front_face { normal } else { -normal };
+        shaded
+    }
+}
+
+impl Material for ShadingNormal {
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Point3) -> Color {
+        self.base.emitted(r_in, &self.shade(rec), u, v, p)
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.base.scatter(r_in, &self.shade(rec))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.base.scattering_pdf(r_in, &self.shade(rec), scattered)
+    }
+
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, attenuation: Color, scattered: &Ray) -> Color {
+        self.base.scattering(r_in, &self.shade(rec), attenuation, scattered)
+    }
+
+    fn is_opaque(&self, rec: &HitRecord) -> bool {
+        self.base.is_opaque(rec)
+    }
+}
+
+// How an AlphaMask turns opacity into a hit or a miss.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaskMode {
+    // Solid where the opacity reaches the threshold, and cut away elsewhere.
+    Threshold(f64),
+    // Solid with a probability equal to the opacity, so partly opaque areas average out to
+    // the right amount of coverage.
+    Stochastic,
+}
+
+impl Default for MaskMode {
+    fn default() -> Self {
+        MaskMode::Threshold(0.5)
+    }
+}
+
+// Another material with parts cut away by an opacity texture, for leaves, fences and
+// decals. Shapes skip the hits the mask removes and report whatever lies beyond, for
+// camera rays and rays aimed at lights alike.
+pub struct AlphaMask {
+    base: MaterialRef,
+    opacity: TextureRef,
+    mode: MaskMode,
+}
+
+impl AlphaMask {
+    pub fn new(base: MaterialRef, opacity: TextureRef) -> Self {
+        Self { base, opacity, mode: MaskMode::default() }
+    }
+
+    pub fn with_mode(mut self, mode: MaskMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+impl Material for AlphaMask {
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: Point3) -> Color {
+        self.base.emitted(r_in, rec, u, v, p)
+    }
+
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.base.scatter(r_in, rec)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.base.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, attenuation: Color, scattered: &Ray) -> Color {
+        self.base.scattering(r_in, rec, attenuation, scattered)
+    }
+
+    fn is_opaque(&self, rec: &HitRecord) -> bool {
+        let opacity = self.opacity.sample_scalar(rec);
+        let opaque = match self.mode {
+            MaskMode::Threshold(threshold) => opacity >= threshold,
+            MaskMode::Stochastic => random_double() < opacity,
+        };
+        opaque && self.base.is_opaque(rec)
+    }
+}
+
 pub struct EmptyMaterial;
 impl Material for EmptyMaterial {}
 
@@ -198,6 +429,11 @@ pub enum MaterialObject {
     Dielectric(Dielectric),
     DiffuseLight(DiffuseLight),
     Isotropic(Isotropic),
+    ShadingNormal(ShadingNormal),
+    AlphaMask(AlphaMask),
+    Principled(Principled),
+    Coated(Coated),
+    Subsurface(Subsurface),
     EmptyMaterial(EmptyMaterial),
 }
 
@@ -231,6 +467,36 @@ impl From<Isotropic> for MaterialObject {
     }
 }
 
+impl From<ShadingNormal> for MaterialObject {
+    fn from(value: ShadingNormal) -> Self {
+        Self::ShadingNormal(value)
+    }
+}
+
+impl From<AlphaMask> for MaterialObject {
+    fn from(value: AlphaMask) -> Self {
+        Self::AlphaMask(value)
+    }
+}
+
+impl From<Principled> for MaterialObject {
+    fn from(value: Principled) -> Self {
+        Self::Principled(value)
+    }
+}
+
+impl From<Coated> for MaterialObject {
+    fn from(value: Coated) -> Self {
+        Self::Coated(value)
+    }
+}
+
+impl From<Subsurface> for MaterialObject {
+    fn from(value: Subsurface) -> Self {
+        Self::Subsurface(value)
+    }
+}
+
 impl From<EmptyMaterial> for MaterialObject {
     fn from(value: EmptyMaterial) -> Self {
         Self::EmptyMaterial(value)
@@ -245,6 +511,11 @@ impl MaterialObject {
             MaterialObject::Dielectric(mat) => mat.emitted(r_in, rec, u, v, p),
             MaterialObject::DiffuseLight(mat) => mat.emitted(r_in, rec, u, v, p),
             MaterialObject::Isotropic(mat) => mat.emitted(r_in, rec, u, v, p),
+            MaterialObject::ShadingNormal(mat) => mat.emitted(r_in, rec, u, v, p),
+            MaterialObject::AlphaMask(mat) => mat.emitted(r_in, rec, u, v, p),
+            MaterialObject::Principled(mat) => mat.emitted(r_in, rec, u, v, p),
+            MaterialObject::Coated(mat) => mat.emitted(r_in, rec, u, v, p),
+            MaterialObject::Subsurface(mat) => mat.emitted(r_in, rec, u, v, p),
             MaterialObject::EmptyMaterial(mat) => mat.emitted(r_in, rec, u, v, p),
         }
     }
@@ -256,6 +527,11 @@ impl MaterialObject {
             MaterialObject::Dielectric(mat) => mat.scatter(r_in, rec),
             MaterialObject::DiffuseLight(mat) => mat.scatter(r_in, rec),
             MaterialObject::Isotropic(mat) => mat.scatter(r_in, rec),
+            MaterialObject::ShadingNormal(mat) => mat.scatter(r_in, rec),
+            MaterialObject::AlphaMask(mat) => mat.scatter(r_in, rec),
+            MaterialObject::Principled(mat) => mat.scatter(r_in, rec),
+            MaterialObject::Coated(mat) => mat.scatter(r_in, rec),
+            MaterialObject::Subsurface(mat) => mat.scatter(r_in, rec),
             MaterialObject::EmptyMaterial(mat) => mat.scatter(r_in, rec),
         }
     }
@@ -267,7 +543,259 @@ impl MaterialObject {
             MaterialObject::Dielectric(mat) => mat.scattering_pdf(r_in, rec, scattered),
             MaterialObject::DiffuseLight(mat) => mat.scattering_pdf(r_in, rec, scattered),
             MaterialObject::Isotropic(mat) => mat.scattering_pdf(r_in, rec, scattered),
+            MaterialObject::ShadingNormal(mat) => mat.scattering_pdf(r_in, rec, scattered),
+            MaterialObject::AlphaMask(mat) => mat.scattering_pdf(r_in, rec, scattered),
+            MaterialObject::Principled(mat) => mat.scattering_pdf(r_in, rec, scattered),
+            MaterialObject::Coated(mat) => mat.scattering_pdf(r_in, rec, scattered),
+            MaterialObject::Subsurface(mat) => mat.scattering_pdf(r_in, rec, scattered),
             MaterialObject::EmptyMaterial(mat) => mat.scattering_pdf(r_in, rec, scattered),
         }
     }
+
+    pub fn scattering(&self, r_in: &Ray, rec: &HitRecord, attenuation: Color, scattered: &Ray) -> Color {
+        match self {
+            MaterialObject::Lambertian(mat) => mat.scattering(r_in, rec, attenuation, scattered),
+            MaterialObject::Metal(mat) => mat.scattering(r_in, rec, attenuation, scattered),
+            MaterialObject::Dielectric(mat) => mat.scattering(r_in, rec, attenuation, scattered),
+            MaterialObject::DiffuseLight(mat) => mat.scattering(r_in, rec, attenuation, scattered),
+            MaterialObject::Isotropic(mat) => mat.scattering(r_in, rec, attenuation, scattered),
+            MaterialObject::ShadingNormal(mat) => mat.scattering(r_in, rec, attenuation, scattered),
+            MaterialObject::AlphaMask(mat) => mat.scattering(r_in, rec, attenuation, scattered),
+            MaterialObject::Principled(mat) => mat.scattering(r_in, rec, attenuation, scattered),
+            MaterialObject::Coated(mat) => mat.scattering(r_in, rec, attenuation, scattered),
+            MaterialObject::Subsurface(mat) => mat.scattering(r_in, rec, attenuation, scattered),
+            MaterialObject::EmptyMaterial(mat) => mat.scattering(r_in, rec, attenuation, scattered),
+        }
+    }
+
+    pub fn is_opaque(&self, rec: &HitRecord) -> bool {
+        match self {
+            MaterialObject::Lambertian(mat) => mat.is_opaque(rec),
+            MaterialObject::Metal(mat) => mat.is_opaque(rec),
+            MaterialObject::Dielectric(mat) => mat.is_opaque(rec),
+            MaterialObject::DiffuseLight(mat) => mat.is_opaque(rec),
+            MaterialObject::Isotropic(mat) => mat.is_opaque(rec),
+            MaterialObject::ShadingNormal(mat) => mat.is_opaque(rec),
+            MaterialObject::AlphaMask(mat) => mat.is_opaque(rec),
+            MaterialObject::Principled(mat) => mat.is_opaque(rec),
+            MaterialObject::Coated(mat) => mat.is_opaque(rec),
+            MaterialObject::Subsurface(mat) => mat.is_opaque(rec),
+            MaterialObject::EmptyMaterial(mat) => mat.is_opaque(rec),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeInclusive;
+
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::hittable::{make_ref, Hittable, HittableRef};
+    use crate::books::the_rest_of_your_life::hittable_list::HittableList;
+    use crate::books::the_rest_of_your_life::interval::Interval;
+    use crate::books::the_rest_of_your_life::procedural::GradientTexture;
+    use crate::books::the_rest_of_your_life::projection::{TransformedTexture, TriplanarTexture};
+    use crate::books::the_rest_of_your_life::quad::Quad;
+    use crate::books::the_rest_of_your_life::rtweekend::seed_random;
+    use crate::books::the_rest_of_your_life::sphere::Sphere;
+    use crate::books::the_rest_of_your_life::texture::CheckerTexture;
+    use crate::books::the_rest_of_your_life::test_support::{
+        assert_sampler_matches_pdf, incoming_from_both_sides, sample_weight, surface_hit,
+    };
+    use crate::books::the_rest_of_your_life::transform::Transform;
+
+    const WHITE: Color = Color { e: [1.0, 1.0, 1.0] };
+
+    // Every material, made as reflective as it can be, with the fraction of light it should
+    // send back into a white furnace, or the range it should fall in for those that lose some.
+    fn materials() -> Vec<(&'static str, MaterialRef, RangeInclusive<f64>)> {
+        vec![
+            ("lambertian", make_mat(Lambertian::new(WHITE)), 1.0..=1.0),
+            ("mirror", make_mat(Metal::new(WHITE, 0.0)), 1.0..=1.0),
+            ("fuzzy metal", make_mat(Metal::new(WHITE, 0.4)), 1.0..=1.0),
+            ("glass", make_mat(Dielectric::new(1.5)), 1.0..=1.0),
+            ("soap bubble", make_mat(Dielectric::new(1.0).with_thin_film(ThinFilm::new(400.0, 1.33))), 1.0..=1.0),
+            ("oiled glass", make_mat(Dielectric::new(1.5).with_thin_film(ThinFilm::new(250.0, 1.45))), 1.0..=1.0),
+            ("isotropic", make_mat(Isotropic::new(WHITE)), 1.0..=1.0),
+            ("normal mapped lambertian", make_mat(ShadingNormal::normal_map(make_mat(Lambertian::new(WHITE)), tilted_normal())), 1.0..=1.0),
+            ("principled glass", make_mat(Principled::new(WHITE).with_roughness(0.0).with_transmission(1.0)), 1.0..=1.0),
+            // Coatings lose what they reflect back into the base, and what leaves the base
+            // at angles they reflect back in.
+            ("varnished lambertian", make_mat(Coated::new(make_mat(Lambertian::new(WHITE)), 1.5)), 0.85..=1.0),
+            ("lacquered mirror", make_mat(Coated::new(make_mat(Metal::new(WHITE, 0.0)), 1.5)), 0.7..=1.0),
+            // A white ball under the surface gives back all the light, less the few walks
+            // cut off at the event limit.
+            ("subsurface", make_mat(Subsurface::from_mean_free_path(ball_under_surface(), 0.25 * WHITE, WHITE)), 0.99..=1.0),
+            // A mask decides whether a ray hits at all; what it hits reflects as the base does.
+            ("masked lambertian", make_mat(AlphaMask::new(make_mat(Lambertian::new(WHITE)), make_tex(SolidColor::from_value(0.5)))), 1.0..=1.0),
+            ("diffuse light", make_mat(DiffuseLight::new(WHITE)), 0.0..=0.0),
+            ("empty", make_mat(EmptyMaterial), 0.0..=0.0),
+        ]
+    }
+
+    // A unit ball just under the surface `surface_hit` makes, touching it at the origin.
+    fn ball_under_surface() -> HittableRef {
+        make_ref(Sphere::new(Point3::new(0.0, 0.0, -1.0), 1.0, make_mat(EmptyMaterial)))
+    }
+
+    // A normal map color for a normal leaning towards +u: (0.6, 0, 0.8) in tangent space.
+    fn tilted_normal() -> TextureRef {
+        make_tex(SolidColor::new(Color::new(0.8, 0.5, 0.9)))
+    }
+
+    #[test]
+    fn normal_maps_bend_the_normal_in_the_tangent_frame() {
+        let base = make_mat(Lambertian::new(WHITE));
+        let mapped = ShadingNormal::normal_map(base.clone(), tilted_normal());
+        for incoming in incoming_from_both_sides() {
+            let (_, rec) = surface_hit(&base, incoming);
+            let normal = mapped.shade(&rec).normal;
+            let expected = if rec.front_face { Vec3::new(0.6, 0.0, 0.8) } else { Vec3::new(-0.6, 0.0, -0.8) };
+            assert!((normal - expected).length() < 1e-9, "lit from {:?}: normal {:?}", incoming, normal);
+        }
+    }
+
+    #[test]
+    fn flat_maps_keep_the_normal() {
+        let base = make_mat(Lambertian::new(WHITE));
+        let flat_normals = ShadingNormal::normal_map(base.clone(), make_tex(SolidColor::new(Color::new(0.5, 0.5, 1.0))));
+        let flat_heights = ShadingNormal::bump_map(base.clone(), make_tex(SolidColor::from_value(0.7)), 3.0);
+        for incoming in incoming_from_both_sides() {
+            let (_, rec) = surface_hit(&base, incoming);
+            for shading in [&flat_normals, &flat_heights] {
+                let normal = shading.shade(&rec).normal;
+                assert!((normal - rec.normal).length() < 1e-9, "lit from {:?}: normal {:?}", incoming, normal);
+            }
+        }
+    }
+
+    #[test]
+    fn bump_maps_read_textures_placed_on_the_object() {
+        // Heights rising by one per unit of u, measured in object space. The hit is where an
+        // instance moved it, out past the end of both ramps in world space.
+        let ramp = make_tex(GradientTexture::new(Point3::new(-1.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0)));
+        let heights = [
+            ("transformed", make_tex(TransformedTexture::new(ramp, Transform::identity())), 1.0),
+            ("triplanar", make_tex(TriplanarTexture::new(make_tex(GradientTexture::along_u()), 2.0)), 0.25),
+        ];
+        let base = make_mat(Lambertian::new(WHITE));
+        for (name, height, scale) in heights {
+            let bumped = ShadingNormal::bump_map(base.clone(), height, scale);
+            let (_, mut rec) = surface_hit(&base, Vec3::new(0.0, 0.0, 1.0));
+            rec.object_p = Point3::new(0.2, 0.3, 0.0);
+            rec.p = rec.object_p + Vec3::new(10.0, 0.0, 0.0);
+            let normal = bumped.shade(&rec).normal;
+            let expected = unit_vector(Vec3::new(-2.0, 0.0, 4.0));
+            assert!((normal - expected).length() < 1e-6, "{}: normal {:?}", name, normal);
+        }
+    }
+
+    #[test]
+    fn scalar_parameters_follow_their_textures() {
+        // A mirror where the fuzz texture is zero reflects exactly; where it is one it doesn't.
+        let fuzz = make_tex(CheckerTexture::from_colors(1.0, Color::new(0.0, 0.0, 0.0), WHITE));
+        let metal = make_mat(Metal::from_textures(make_tex(SolidColor::new(WHITE)), fuzz));
+        let incoming = unit_vector(Vec3::new(1.0, 0.0, 1.0));
+        let r_in = Ray::new(incoming, -incoming);
+        let mirrored = Vec3::new(-incoming.x(), incoming.y(), incoming.z());
+        for (p, exact) in [(Point3::new(0.5, 0.5, 0.0), true), (Point3::new(1.5, 0.5, 0.0), false)] {
+            let rec = HitRecord::new(p, 1.0, &r_in, Vec3::new(0.0, 0.0, 1.0), metal.clone(), 0.5, 0.5);
+            let direction = unit_vector(metal.scatter(&r_in, &rec).unwrap().skip_pdf_ray.direction());
+            assert_eq!((direction - mirrored).length() < 1e-9, exact, "at {:?}: reflected towards {:?}", p, direction);
+        }
+    }
+
+    #[test]
+    fn scatter_pdfs_match_their_samples() {
+        seed_random(golden::SEED);
+        for (name, mat, _) in materials() {
+            for incoming in incoming_from_both_sides() {
+                let (r_in, rec) = surface_hit(&mat, incoming);
+                let Some(ScatterRecord { pdf_ptr: Some(pdf), .. }) = mat.scatter(&r_in, &rec) else {
+                    continue;
+                };
+                let name = format!("{} lit from {:?}", name, incoming);
+                assert_sampler_matches_pdf(&name, || pdf.generate(), |d| pdf.value(d));
+            }
+        }
+    }
+
+    #[test]
+    fn white_furnace() {
+        seed_random(golden::SEED);
+        // The average weight the renderer gives a scattered ray is the fraction of light a
+        // material reflects. Nothing may reflect more than it receives.
+        const SAMPLES: usize = 100_000;
+        for (name, mat, expected) in materials() {
+            for incoming in incoming_from_both_sides() {
+                let (r_in, rec) = surface_hit(&mat, incoming);
+                let mut sum = 0.0;
+                let mut sum_squared = 0.0;
+                for _ in 0..SAMPLES {
+                    let weight = sample_weight(&mat, &r_in, &rec);
+                    let weight = (weight.x() + weight.y() + weight.z()) / 3.0;
+                    sum += weight;
+                    sum_squared += weight * weight;
+                }
+
+                let mean = sum / SAMPLES as f64;
+                let error = ((sum_squared / SAMPLES as f64 - mean * mean).max(0.0) / SAMPLES as f64).sqrt();
+                let outside = (expected.start() - mean).max(mean - expected.end());
+                assert!(
+                    outside <= 5.0 * error + 1e-3,
+                    "{} lit from {:?}: reflects {} +/- {}, expected {:?}",
+                    name,
+                    incoming,
+                    mean,
+                    error,
+                    expected
+                );
+            }
+        }
+    }
+
+    // A square at z = 1 in front of a wall twice its size at z = 2, filling the same view
+    // from the origin.
+    fn screen_in_front_of_wall(screen: MaterialRef) -> HittableList {
+        let mut world = HittableList::new();
+        let square = |z: f64, mat: MaterialRef| {
+            make_ref(Quad::new(Point3::new(-z, -z, z), Vec3::new(2.0 * z, 0.0, 0.0), Vec3::new(0.0, 2.0 * z, 0.0), mat))
+        };
+        world.add(square(1.0, screen));
+        world.add(square(2.0, make_mat(Lambertian::new(WHITE))));
+        world
+    }
+
+    #[test]
+    fn alpha_masks_cut_holes() {
+        // Checkers half a unit wide, opaque where even.
+        let opacity = make_tex(CheckerTexture::from_colors(0.5, WHITE, Color::new(0.0, 0.0, 0.0)));
+        let screen = make_mat(AlphaMask::new(make_mat(Lambertian::new(WHITE)), opacity));
+        let world = screen_in_front_of_wall(screen);
+
+        // Towards (0.25, 0.25, 1), an even square, and (0.75, 0.25, 1), an odd one.
+        for (x, expected_t) in [(0.25, 1.0), (0.75, 2.0)] {
+            let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(x, 0.25, 1.0));
+            let rec = world.hit(&r, Interval::new(0.001, f64::INFINITY)).expect("the wall is behind the screen");
+            assert!((rec.t - expected_t).abs() < 1e-9, "towards x = {}: hit at t = {}", x, rec.t);
+        }
+    }
+
+    #[test]
+    fn stochastic_masks_cover_by_opacity() {
+        seed_random(golden::SEED);
+        const RAYS: usize = 20_000;
+        let opacity = make_tex(SolidColor::from_value(0.3));
+        let screen = make_mat(AlphaMask::new(make_mat(Lambertian::new(WHITE)), opacity).with_mode(MaskMode::Stochastic));
+        let world = screen_in_front_of_wall(screen);
+
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.1, 0.2, 1.0));
+        let blocked = (0..RAYS)
+            .filter(|_| world.hit(&r, Interval::new(0.001, f64::INFINITY)).is_some_and(|rec| rec.t < 1.5))
+            .count();
+        let coverage = blocked as f64 / RAYS as f64;
+        // Five standard errors of a binomial proportion.
+        assert!((coverage - 0.3).abs() < 5.0 * (0.3 * 0.7 / RAYS as f64).sqrt(), "covered {} of rays", coverage);
+    }
 }
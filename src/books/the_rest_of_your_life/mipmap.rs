@@ -0,0 +1,226 @@
+use super::rtw_image::RtwImage;
+use super::vec3::Color;
+
+// What happens to texture coordinates outside [0, 1].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WrapMode {
+    // Repeat the edge texels.
+    #[default]
+    Clamp,
+    // Tile the image.
+    Repeat,
+    // Tile the image, flipping every other copy so the seams match.
+    Mirror,
+}
+
+impl WrapMode {
+    fn apply(self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        let i = match self {
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                if i < n { i } else { 2 * n - 1 - i }
+            }
+        };
+        i as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FilterMode {
+    // The texel under the point, from the full-size image.
+    Nearest,
+    // A blend of the four texels around the point, from the full-size image.
+    Bilinear,
+    // Bilinear lookups in the two levels of the pyramid closest to the footprint's size,
+    // blended.
+    #[default]
+    Trilinear,
+}
+
+struct MipLevel {
+    width: usize,
+    height: usize,
+    texels: Vec<[f32; 3]>,
+}
+
+impl MipLevel {
+    fn texel(&self, x: i64, y: i64, wrap: WrapMode) -> Color {
+        let [r, g, b] = self.texels[wrap.apply(y, self.height) * self.width + wrap.apply(x, self.width)];
+        Color::new(r as f64, g as f64, b as f64)
+    }
+
+    // Halves the level in each direction, averaging blocks of 2x2 texels. An odd last row
+    // or column is averaged with itself.
+    fn downsample(&self) -> Self {
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0; 3];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (2 * x + dx).min(self.width - 1);
+                    let sy = (2 * y + dy).min(self.height - 1);
+                    let texel = self.texels[sy * self.width + sx];
+                    for c in 0..3 {
+                        sum[c] += texel[c];
+                    }
+                }
+                texels.push(sum.map(|c| c / 4.0));
+            }
+        }
+        Self { width, height, texels }
+    }
+
+    fn nearest(&self, u: f64, v: f64, wrap: WrapMode) -> Color {
+        let x = (u * self.width as f64).floor() as i64;
+        let y = ((1.0 - v) * self.height as f64).floor() as i64;
+        self.texel(x, y, wrap)
+    }
+
+    fn bilinear(&self, u: f64, v: f64, wrap: WrapMode) -> Color {
+        // Texel centers sit at half-integer coordinates.
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        (1.0 - fy) * ((1.0 - fx) * self.texel(x0, y0, wrap) + fx * self.texel(x0 + 1, y0, wrap))
+            + fy * ((1.0 - fx) * self.texel(x0, y0 + 1, wrap) + fx * self.texel(x0 + 1, y0 + 1, wrap))
+    }
+}
+
+// An image with a pyramid of prefiltered copies, each half the size of the one before, so
+// a lookup covering many texels can read one from a smaller copy instead.
+pub struct MipMap {
+    levels: Vec<MipLevel>,
+}
+
+impl MipMap {
+    // `texels` holds `width` by `height` colors, row by row from the top.
+    pub fn new(width: usize, height: usize, texels: Vec<[f32; 3]>) -> Self {
+        assert!(width > 0 && height > 0, "a mipmap needs at least one texel");
+        assert_eq!(texels.len(), width * height);
+
+        let mut levels = vec![MipLevel { width, height, texels }];
+        while let Some(last) = levels.last()
+            && (last.width > 1 || last.height > 1)
+        {
+            let next = last.downsample();
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    // The image decoded to linear values, so levels average light rather than encoded values.
+    pub fn from_image(image: &RtwImage) -> Self {
+        let (width, height) = (image.width().max(1) as usize, image.height().max(1) as usize);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                texels.push(image.linear_pixel(x, y));
+            }
+        }
+        Self::new(width, height, texels)
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    // The color at (u, v), averaged over about `width` texels of the full-size image.
+    pub fn lookup(&self, u: f64, v: f64, width: f64, filter: FilterMode, wrap: WrapMode) -> Color {
+        match filter {
+            FilterMode::Nearest => self.levels[0].nearest(u, v, wrap),
+            FilterMode::Bilinear => self.levels[0].bilinear(u, v, wrap),
+            FilterMode::Trilinear => {
+                let level = width.max(1.0).log2().min((self.levels.len() - 1) as f64);
+                let lower = level.floor() as usize;
+                let blend = level - lower as f64;
+                let color = self.levels[lower].bilinear(u, v, wrap);
+                if blend <= 0.0 {
+                    return color;
+                }
+                (1.0 - blend) * color + blend * self.levels[lower + 1].bilinear(u, v, wrap)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(value: f32) -> [f32; 3] {
+        [value; 3]
+    }
+
+    // Single texel checks, white where x + y is even.
+    fn checkerboard(width: usize, height: usize) -> MipMap {
+        let texels = (0..width * height).map(|k| gray(((k % width + k / width + 1) % 2) as f32)).collect();
+        MipMap::new(width, height, texels)
+    }
+
+    #[test]
+    fn wrap_modes_fold_indices_back_into_the_image() {
+        let fold = |wrap: WrapMode| [-5, -4, -2, -1, 0, 3, 4, 5, 8].map(|i| wrap.apply(i, 4));
+        assert_eq!(fold(WrapMode::Clamp), [0, 0, 0, 0, 0, 3, 3, 3, 3]);
+        assert_eq!(fold(WrapMode::Repeat), [3, 0, 2, 3, 0, 3, 0, 1, 0]);
+        assert_eq!(fold(WrapMode::Mirror), [3, 3, 1, 0, 0, 3, 3, 2, 0]);
+        // A single texel wide image has nothing to fold.
+        for wrap in [WrapMode::Clamp, WrapMode::Repeat, WrapMode::Mirror] {
+            assert_eq!([-3, -1, 0, 1, 7].map(|i| wrap.apply(i, 1)), [0; 5]);
+        }
+    }
+
+    #[test]
+    fn odd_sizes_downsample_with_their_last_texels() {
+        // Columns valued 0, 1 and 2, plus 10 on the bottom row.
+        let texels = (0..9).map(|k| gray((k % 3) as f32 + if k / 3 == 2 { 10.0 } else { 0.0 })).collect();
+        let level = MipLevel { width: 3, height: 3, texels };
+        let half = level.downsample();
+        assert_eq!((half.width, half.height), (2, 2));
+        let values: Vec<f32> = half.texels.iter().map(|t| t[0]).collect();
+        assert_eq!(values, [0.5, 2.0, 10.5, 12.0]);
+
+        let sizes: Vec<(usize, usize)> = MipMap::new(5, 3, vec![gray(0.0); 15]).levels.iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, [(5, 3), (3, 2), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn trilinear_lookups_pick_levels_by_footprint() {
+        let mipmap = checkerboard(8, 8);
+        // The center of the white top left texel, where every coarser level is gray.
+        let (u, v) = (1.0 / 16.0, 1.0 - 1.0 / 16.0);
+        let at = |width: f64| mipmap.lookup(u, v, width, FilterMode::Trilinear, WrapMode::Repeat).x();
+
+        assert_eq!(at(0.25), 1.0);
+        assert_eq!(at(1.0), 1.0);
+        assert!((at(2.0_f64.sqrt()) - 0.75).abs() < 1e-6, "halfway to the first level: {}", at(2.0_f64.sqrt()));
+        assert!((at(2.0) - 0.5).abs() < 1e-6);
+        assert!((at(1e6) - 0.5).abs() < 1e-6, "past the top of the pyramid: {}", at(1e6));
+
+        // The full-size filters ignore the footprint.
+        for width in [1.0, 4.0, 1e6] {
+            assert_eq!(mipmap.lookup(u, v, width, FilterMode::Nearest, WrapMode::Repeat).x(), 1.0);
+            assert_eq!(mipmap.lookup(u, v, width, FilterMode::Bilinear, WrapMode::Repeat).x(), 1.0);
+        }
+    }
+
+    #[test]
+    fn bilinear_lookups_blend_across_the_wrapped_edge() {
+        // Between the last column and, wrapped, the first.
+        let mipmap = MipMap::new(4, 1, vec![gray(1.0), gray(0.0), gray(0.0), gray(0.0)]);
+        let edge = |wrap: WrapMode| mipmap.lookup(0.0, 0.5, 1.0, FilterMode::Bilinear, wrap).x();
+        assert!((edge(WrapMode::Repeat) - 0.5).abs() < 1e-6);
+        assert!((edge(WrapMode::Mirror) - 1.0).abs() < 1e-6);
+        assert!((edge(WrapMode::Clamp) - 1.0).abs() < 1e-6);
+    }
+}
@@ -1,32 +1,85 @@
 #![allow(dead_code)]
 
 mod aabb;
+mod animation;
+mod aperture;
+mod bench;
 mod bvh;
 mod camera;
 mod color;
 mod constant_medium;
+mod csg;
+mod flat_bvh;
+mod heightfield;
 mod hittable;
 mod hittable_list;
+mod instance;
 mod interval;
+mod layered;
+mod lens;
 mod material;
+mod mipmap;
 mod onb;
 mod pdf;
 mod perlin;
+mod principled;
+mod procedural;
+mod projection;
+mod qbvh;
 mod quad;
+mod quadric;
 mod ray;
 mod rtw_image;
 mod rtweekend;
+mod sdf;
 mod sphere;
+mod spherical;
+mod subsurface;
+#[cfg(test)]
+mod test_support;
 mod texture;
+mod thin_film;
+mod torus;
+mod transform;
 mod vec3;
+mod worley;
 
-use bvh::BvhNode;
+use std::ops::Range;
+
+use aabb::Aabb;
+use animation::{Animated, CameraAnimation, Interpolation, Track};
+use aperture::Aperture;
+use flat_bvh::FlatBvh;
 use camera::Camera;
-use hittable::{make_ref, RotateY, Translate};
+use csg::Csg;
+use heightfield::Heightfield;
+use hittable::{make_ref, Hittable, HittableRef, RotateY, Translate};
 use hittable_list::HittableList;
-use material::{make_mat, Dielectric, DiffuseLight, EmptyMaterial, Lambertian};
+use instance::{build_blas, build_tlas, Instance};
+use layered::Coated;
+use lens::RealisticLens;
+use material::{
+    make_mat, AlphaMask, Dielectric, DiffuseLight, EmptyMaterial, Lambertian, MaskMode, Metal, ShadingNormal,
+};
+use mipmap::{FilterMode, WrapMode};
+use perlin::Perlin;
+use principled::Principled;
+use projection::{TransformedTexture, TriplanarTexture};
+use procedural::{
+    ColorRamp, FbmTexture, GradientTexture, MarbleTexture, MixTexture, RemapTexture, WoodTexture, WorleyFeature,
+    WorleyTexture,
+};
 use quad::{make_box, Quad};
+use quadric::{Cone, Cylinder, Disk};
+use rtw_image::RtwImage;
+use rtweekend::{degrees_to_radians, random_double_range, INFINITY, PI};
+use sdf::{DistanceField, Sdf};
 use sphere::Sphere;
+use subsurface::Subsurface;
+use texture::{make_tex, CheckerTexture, ImageTexture, NoiseTexture, SolidColor};
+use thin_film::ThinFilm;
+use torus::Torus;
+use transform::{MotionTransform, Quat, Transform};
 use vec3::{Color, Point3, Vec3};
 
 fn apply_overrides(cam: &mut Camera) {
@@ -61,14 +114,70 @@ fn apply_overrides(cam: &mut Camera) {
     if let Some(value) = o.focus_dist {
         cam.focus_dist = value;
     }
+    if let Some(value) = crate::config::projection_override() {
+        cam.projection = value;
+    }
     if let Some(value) = o.background {
         cam.background = Color::new(value[0], value[1], value[2]);
     }
+    if let Some(shape) = crate::config::aperture_override() {
+        cam.aperture = Aperture::from_name(shape);
+    }
+    if let Some(filename) = crate::config::lens_override() {
+        // Lens files are in millimetres, as the Cornell box scenes are, on 35mm film.
+        match RealisticLens::load(filename, INFINITY, FILM_DIAGONAL_35MM) {
+            Ok(lens) => cam.lens = Some(lens),
+            Err(e) => eprintln!("WARNING: {}; keeping the scene's camera.", e),
+        }
+    }
 }
 
-pub fn run(_scene: Option<i32>) {
-    let mut world = HittableList::new();
+const FILM_DIAGONAL_35MM: f64 = 43.27;
 
+pub fn run(scene: Option<i32>, frames: Option<Range<i32>>) {
+    match scene.unwrap_or(0) {
+        2 => animated_cornell_box(frames),
+        3 => motion_blur_cornell_box(frames),
+        4 => instanced_cornell_box(frames),
+        5 => marching_cornell_box(frames),
+        6 => csg_cornell_box(frames),
+        7 => quadric_cornell_box(frames),
+        8 => sdf_cornell_box(frames),
+        9 => terrain(frames),
+        10 => texture_filtering(frames),
+        11 => surface_detail(frames),
+        12 => cut_outs(frames),
+        13 => procedural_textures(frames),
+        14 => texture_projection(frames),
+        15 => principled_materials(frames),
+        16 => coatings(frames),
+        17 => subsurface_scattering(frames),
+        18 => bokeh(frames),
+        _ => cornell_box(frames),
+    }
+}
+
+pub fn bench() {
+    bench::run();
+}
+
+fn render<H: Hittable>(
+    cam: &mut Camera,
+    world: &H,
+    lights: HittableRef,
+    animation: &CameraAnimation,
+    frames: Option<Range<i32>>,
+) {
+    match frames {
+        Some(frames) => cam.render_sequence(world, lights, animation, frames),
+        None => {
+            animation.apply(cam, 0);
+            cam.render(world, lights);
+        }
+    }
+}
+
+fn add_cornell_room(world: &mut HittableList) {
     let red = make_mat(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
     let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
     let green = make_mat(Lambertian::new(Color::new(0.12, 0.45, 0.15)));
@@ -103,7 +212,7 @@ pub fn run(_scene: Option<i32>) {
         Point3::new(555.0, 0.0, 555.0),
         Vec3::new(-555.0, 0.0, 0.0),
         Vec3::new(0.0, 555.0, 0.0),
-        white.clone(),
+        white,
     )));
 
     // Light
@@ -113,9 +222,43 @@ pub fn run(_scene: Option<i32>) {
         Vec3::new(0.0, 0.0, 105.0),
         light,
     )));
+}
+
+fn cornell_camera() -> Camera {
+    let mut cam = Camera::default();
+
+    cam.aspect_ratio = 1.0;
+    cam.image_width = 600;
+    cam.samples_per_pixel = 100;
+    cam.max_depth = 50;
+    cam.background = Color::new(0.0, 0.0, 0.0);
+
+    cam.vfov = 40.0;
+    cam.lookfrom = Point3::new(278.0, 278.0, -800.0);
+    cam.lookat = Point3::new(278.0, 278.0, 0.0);
+    cam.vup = Vec3::new(0.0, 1.0, 0.0);
+
+    cam.defocus_angle = 0.0;
+
+    cam
+}
+
+fn cornell_box(frames: Option<Range<i32>>) {
+    let (mut cam, world, lights) = cornell_box_scene();
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, make_ref(lights), &CameraAnimation::default(), frames);
+}
+
+fn cornell_box_scene() -> (Camera, HittableList, HittableList) {
+    let mut world = HittableList::new();
+    add_cornell_room(&mut world);
+
+    let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
 
     // Box
-    let box1 = make_box(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 330.0, 165.0), white.clone());
+    let box1 = make_box(Point3::new(0.0, 0.0, 0.0), Point3::new(165.0, 330.0, 165.0), white);
     let box1 = make_ref(RotateY::new(box1, 15.0));
     let box1 = make_ref(Translate::new(box1, Vec3::new(265.0, 0.0, 295.0)));
     world.add(box1);
@@ -139,23 +282,955 @@ pub fn run(_scene: Option<i32>) {
         empty_material,
     )));
 
-    let mut cam = Camera::default();
+    (cornell_camera(), world, lights)
+}
 
-    cam.aspect_ratio = 1.0;
-    cam.image_width = 600;
-    cam.samples_per_pixel = 100;
-    cam.max_depth = 50;
-    cam.background = Color::new(0.0, 0.0, 0.0);
+fn animated_cornell_box(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+    add_cornell_room(&mut world);
 
-    cam.vfov = 40.0;
-    cam.lookfrom = Point3::new(278.0, 278.0, -800.0);
-    cam.lookat = Point3::new(278.0, 278.0, 0.0);
-    cam.vup = Vec3::new(0.0, 1.0, 0.0);
+    let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
 
-    cam.defocus_angle = 0.0;
+    // A spinning box that slides towards the back wall, pivoting about its own center.
+    let box1 = make_box(Point3::new(-82.5, 0.0, -82.5), Point3::new(82.5, 330.0, 82.5), white);
+    let spin = Track::new(Interpolation::Linear).key(0.0, 15.0).key(48.0, 195.0);
+    let slide = Track::new(Interpolation::CatmullRom)
+        .key(0.0, Vec3::new(347.5, 0.0, 377.5))
+        .key(24.0, Vec3::new(380.0, 0.0, 300.0))
+        .key(48.0, Vec3::new(347.5, 0.0, 377.5));
+    world.add(make_ref(Animated::new(box1, spin, slide)));
+
+    // A glass sphere bouncing along a spline.
+    let glass = make_mat(Dielectric::new(1.5));
+    let sphere = make_ref(Sphere::new(Point3::new(0.0, 0.0, 0.0), 90.0, glass));
+    let bounce = Track::new(Interpolation::CatmullRom)
+        .key(0.0, Vec3::new(190.0, 90.0, 190.0))
+        .key(12.0, Vec3::new(160.0, 300.0, 190.0))
+        .key(24.0, Vec3::new(130.0, 90.0, 190.0))
+        .key(36.0, Vec3::new(160.0, 300.0, 190.0))
+        .key(48.0, Vec3::new(190.0, 90.0, 190.0));
+    world.add(make_ref(Animated::new(sphere, Track::constant(0.0), bounce)));
+
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Quad::new(
+        Point3::new(343.0, 554.0, 332.0),
+        Vec3::new(-130.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -105.0),
+        empty_material,
+    ));
+
+    let mut cam = cornell_camera();
+    apply_overrides(&mut cam);
+
+    // Slow dolly in with a half-frame (180 degree) shutter.
+    let animation = CameraAnimation {
+        lookfrom: Some(
+            Track::new(Interpolation::CatmullRom)
+                .key(0.0, Point3::new(278.0, 278.0, -800.0))
+                .key(48.0, Point3::new(278.0, 278.0, -600.0)),
+        ),
+        shutter_close: Track::constant(0.5),
+        ..CameraAnimation::default()
+    };
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &animation, frames);
+}
+
+fn motion_blur_cornell_box(frames: Option<Range<i32>>) {
+    let mut room = HittableList::new();
+    add_cornell_room(&mut room);
+
+    let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let up = Vec3::new(0.0, 1.0, 0.0);
+
+    // The tall box spins a quarter turn while the shutter is open.
+    let box1 = make_box(Point3::new(-82.5, 0.0, -82.5), Point3::new(82.5, 330.0, 82.5), white.clone());
+    let start1 = Transform::new(Vec3::new(347.5, 0.0, 377.5), Quat::from_axis_angle(up, 15.0), Vec3::new(1.0, 1.0, 1.0));
+    let end1 = Transform::new(Vec3::new(347.5, 0.0, 377.5), Quat::from_axis_angle(up, 105.0), Vec3::new(1.0, 1.0, 1.0));
+
+    // The short box tumbles forward and grows.
+    let box2 = make_box(Point3::new(-82.5, -82.5, -82.5), Point3::new(82.5, 82.5, 82.5), white);
+    let start2 = Transform::new(Vec3::new(180.0, 82.5, 200.0), Quat::from_axis_angle(up, -18.0), Vec3::new(1.0, 1.0, 1.0));
+    let end2 = Transform::new(
+        Vec3::new(180.0, 110.0, 140.0),
+        Quat::from_axis_angle(Vec3::new(1.0, 0.0, 0.0), -60.0) * Quat::from_axis_angle(up, -18.0),
+        Vec3::new(1.3, 1.3, 1.3),
+    );
+
+    // The top level holds the room at index 0 and the two boxes after it. In a sequence,
+    // every frame gives the boxes that frame's shutter interval, so each frame shows the
+    // same blur rather than the end pose of frame 0.
+    let boxes = [(box1, start1, end1), (box2, start2, end2)];
+    let place = |k: usize, cam: &Camera| {
+        let (object, start, end) = &boxes[k];
+        make_ref(MotionTransform::new_with_times(object.clone(), *start, *end, cam.shutter_open, cam.shutter_close))
+    };
+
+    let mut cam = cornell_camera();
+    apply_overrides(&mut cam);
+    let animation = CameraAnimation::default();
+    animation.apply(&mut cam, 0);
+
+    let mut objects = HittableList::new();
+    objects.add(make_ref(FlatBvh::new(room)));
+    for k in 0..boxes.len() {
+        objects.add(place(k, &cam));
+    }
+    let mut world = FlatBvh::new(objects);
+
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Quad::new(
+        Point3::new(343.0, 554.0, 332.0),
+        Vec3::new(-130.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -105.0),
+        empty_material,
+    ));
 
+    match frames {
+        Some(frames) => cam.render_sequence_with(&mut world, lights, &animation, frames, |world, cam, _| {
+            world.update(|index, object| {
+                if index > 0 {
+                    *object = place(index - 1, cam);
+                }
+            });
+        }),
+        None => cam.render(&world, lights),
+    }
+}
+
+fn snowman_model() -> HittableList {
+    let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let red = make_mat(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
+    let mut model = HittableList::new();
+    model.add(make_ref(Sphere::new(Point3::new(0.0, 10.0, 0.0), 10.0, white.clone())));
+    model.add(make_ref(Sphere::new(Point3::new(0.0, 24.0, 0.0), 7.0, white.clone())));
+    model.add(make_ref(Sphere::new(Point3::new(0.0, 35.0, 0.0), 5.0, white)));
+    model.add(make_box(Point3::new(-4.0, 39.0, -4.0), Point3::new(4.0, 46.0, 4.0), red));
+    model
+}
+
+fn instanced_cornell_box(frames: Option<Range<i32>>) {
+    let mut room = HittableList::new();
+    add_cornell_room(&mut room);
+
+    // A small snowman, built once and placed hundreds of times across the floor.
+    let snowman = build_blas(snowman_model());
+
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    let mut instances = Vec::new();
+    for i in 0..20 {
+        for j in 0..20 {
+            let scale = random_double_range(0.8, 1.3);
+            let position = Vec3::new(20.0 + 26.5 * i as f64, 0.0, 20.0 + 26.5 * j as f64);
+            let rotation = Quat::from_axis_angle(up, random_double_range(0.0, 360.0));
+            instances.push(Instance::new(snowman.clone(), Transform::new(position, rotation, Vec3::new(scale, scale, scale))));
+        }
+    }
+
+    let mut world = HittableList::new();
+    world.add(make_ref(FlatBvh::new(room)));
+    world.add(make_ref(build_tlas(instances)));
+
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Quad::new(
+        Point3::new(343.0, 554.0, 332.0),
+        Vec3::new(-130.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -105.0),
+        empty_material,
+    ));
+
+    let mut cam = cornell_camera();
+    apply_overrides(&mut cam);
+
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+// Snowman k marches around a ring, hopping as it goes. Time is in frames.
+fn marching_snowman(k: usize, time: f64) -> Transform {
+    let ring = k % 4;
+    let radius = 60.0 + 55.0 * ring as f64;
+    let direction = if ring.is_multiple_of(2) { 1.0 } else { -1.0 };
+    let angle = 360.0 * (k as f64 / 24.0) + direction * 7.5 * time;
+    let (sin_a, cos_a) = degrees_to_radians(angle).sin_cos();
+    let hop = 25.0 * (PI * (time / 6.0 + k as f64 / 7.0)).sin().abs();
+
+    Transform::new(
+        Vec3::new(278.0 + radius * cos_a, hop, 278.0 + radius * sin_a),
+        Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), -angle),
+        Vec3::new(1.5, 1.5, 1.5),
+    )
+}
+
+fn marching_cornell_box(frames: Option<Range<i32>>) {
+    const SNOWMEN: usize = 96;
+
+    let mut room = HittableList::new();
+    add_cornell_room(&mut room);
+
+    // The top level holds the room at index 0 and one motion transform per snowman, all
+    // sharing one snowman BVH. Every frame swaps in transforms spanning that frame's shutter
+    // interval and refits the top level around them.
+    let snowman = make_ref(FlatBvh::new(snowman_model()));
+    let place = |k: usize, cam: &Camera| {
+        let start = marching_snowman(k, cam.shutter_open);
+        let end = marching_snowman(k, cam.shutter_close);
+        make_ref(MotionTransform::new_with_times(snowman.clone(), start, end, cam.shutter_open, cam.shutter_close))
+    };
+
+    let mut cam = cornell_camera();
     apply_overrides(&mut cam);
+    let animation = CameraAnimation::default();
+    animation.apply(&mut cam, 0);
+
+    let mut objects = HittableList::new();
+    objects.add(make_ref(FlatBvh::new(room)));
+    for k in 0..SNOWMEN {
+        objects.add(place(k, &cam));
+    }
+    let mut world = FlatBvh::new(objects);
+
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Quad::new(
+        Point3::new(343.0, 554.0, 332.0),
+        Vec3::new(-130.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -105.0),
+        empty_material,
+    ));
+
+    match frames {
+        Some(frames) => cam.render_sequence_with(&mut world, lights, &animation, frames, |world, cam, frame| {
+            let rebuilt = world.update(|index, object| {
+                if index > 0 {
+                    *object = place(index - 1, cam);
+                }
+            });
+            if rebuilt {
+                eprintln!("Frame {}: rebuilt the top-level BVH", frame);
+            }
+        }),
+        None => cam.render(&world, lights),
+    }
+}
+
+fn csg_cornell_box(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+    add_cornell_room(&mut world);
 
-    let world = BvhNode::new(world);
-    cam.render(&world, make_ref(lights));
+    let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let dark = make_mat(Lambertian::new(Color::new(0.05, 0.05, 0.05)));
+
+    // A die: a cube with its corners rounded off by a sphere, and pips carved out of three
+    // faces. The pits take the pips' dark material.
+    let cube = make_box(Point3::new(-60.0, -60.0, -60.0), Point3::new(60.0, 60.0, 60.0), white.clone());
+    let rounding = make_ref(Sphere::new(Point3::new(0.0, 0.0, 0.0), 84.0, white.clone()));
+    let mut die = make_ref(Csg::intersection(cube, rounding));
+    let pips = [
+        Point3::new(0.0, 60.0, 0.0),
+        Point3::new(-25.0, 25.0, -60.0),
+        Point3::new(25.0, -25.0, -60.0),
+        Point3::new(60.0, 30.0, -30.0),
+        Point3::new(60.0, 0.0, 0.0),
+        Point3::new(60.0, -30.0, 30.0),
+    ];
+    for center in pips {
+        let pip = make_ref(Sphere::new(center, 11.0, dark.clone()));
+        die = make_ref(Csg::difference(die, pip));
+    }
+    let die = make_ref(RotateY::new(die, -30.0));
+    world.add(make_ref(Translate::new(die, Vec3::new(390.0, 60.0, 200.0))));
+
+    // A block drilled through along all three axes.
+    let block = make_box(Point3::new(-75.0, 0.0, -75.0), Point3::new(75.0, 150.0, 75.0), white.clone());
+    let drills = [
+        make_box(Point3::new(-100.0, 50.0, -25.0), Point3::new(100.0, 100.0, 25.0), white.clone()),
+        make_box(Point3::new(-25.0, -25.0, -25.0), Point3::new(25.0, 175.0, 25.0), white.clone()),
+        make_box(Point3::new(-25.0, 50.0, -100.0), Point3::new(25.0, 100.0, 100.0), white),
+    ];
+    let mut block = make_ref(Csg::difference(block, drills[0].clone()));
+    for drill in &drills[1..] {
+        block = make_ref(Csg::difference(block, drill.clone()));
+    }
+    let block = make_ref(RotateY::new(block, 25.0));
+    world.add(make_ref(Translate::new(block, Vec3::new(160.0, 0.0, 380.0))));
+
+    // A biconvex glass lens: the overlap of two large spheres.
+    let glass = make_mat(Dielectric::new(1.5));
+    let front = make_ref(Sphere::new(Point3::new(270.0, 300.0, 60.0), 200.0, glass.clone()));
+    let back = make_ref(Sphere::new(Point3::new(270.0, 300.0, 400.0), 200.0, glass));
+    world.add(make_ref(Csg::intersection(front, back)));
+
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Quad::new(
+        Point3::new(343.0, 554.0, 332.0),
+        Vec3::new(-130.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -105.0),
+        empty_material,
+    ));
+
+    let mut cam = cornell_camera();
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+fn quadric_cornell_box(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+    add_cornell_room(&mut world);
+
+    let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let up = Vec3::new(0.0, 1.0, 0.0);
+
+    // A pillar standing on a washer, with a glass ring leaning against it.
+    world.add(make_ref(Disk::new_annulus(Point3::new(380.0, 0.5, 350.0), up, 50.0, 110.0, white.clone())));
+    world.add(make_ref(Cylinder::new(Point3::new(380.0, 0.0, 350.0), 280.0 * up, 50.0, true, white.clone())));
+    let glass = make_mat(Dielectric::new(1.5));
+    world.add(make_ref(Torus::new(Point3::new(300.0, 78.0, 270.0), Vec3::new(0.9, 0.15, -0.4), 70.0, 18.0, glass)));
+
+    // A lamp: a shade open at both ends hanging over a glowing tube, plus a traffic cone.
+    world.add(make_ref(Cone::new(Point3::new(150.0, 0.0, 220.0), 150.0 * up, 70.0, 8.0, true, white.clone())));
+    world.add(make_ref(Cone::new(Point3::new(170.0, 330.0, 400.0), 80.0 * up, 90.0, 40.0, false, white)));
+    let glow = make_mat(DiffuseLight::new(Color::new(4.0, 3.0, 1.5)));
+    let tube = (Point3::new(130.0, 340.0, 400.0), Vec3::new(80.0, 0.0, 0.0), 10.0);
+    world.add(make_ref(Cylinder::new(tube.0, tube.1, tube.2, true, glow)));
+
+    let empty_material = make_mat(EmptyMaterial);
+    let mut lights = HittableList::new();
+    lights.add(make_ref(Quad::new(
+        Point3::new(343.0, 554.0, 332.0),
+        Vec3::new(-130.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -105.0),
+        empty_material.clone(),
+    )));
+    lights.add(make_ref(Cylinder::new(tube.0, tube.1, tube.2, true, empty_material)));
+
+    let mut cam = cornell_camera();
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, make_ref(lights), &CameraAnimation::default(), frames);
+}
+
+fn sdf_cornell_box(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+    add_cornell_room(&mut world);
+
+    let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let bounds = |min: Point3, max: Point3| Aabb::from_points(min, max);
+
+    // A Mandelbulb in brass.
+    let brass = make_mat(Metal::new(Color::new(0.8, 0.6, 0.3), 0.2));
+    let center = Point3::new(380.0, 140.0, 360.0);
+    let size = Vec3::new(140.0, 140.0, 140.0);
+    let bulb = Sdf::mandelbulb(8.0, 10).scale(110.0).translate(center);
+    world.add(make_ref(DistanceField::new(bulb, bounds(center - size, center + size), brass)));
+
+    // A twisted column with rounded edges.
+    let column = Sdf::rounded_box(Vec3::new(45.0, 140.0, 45.0), 10.0).twist(0.01).translate(Vec3::new(150.0, 140.0, 380.0));
+    world.add(make_ref(DistanceField::new(column, bounds(Point3::new(85.0, 0.0, 315.0), Point3::new(215.0, 280.0, 445.0)), white.clone())));
+
+    // Two drops of glass melting into each other.
+    let glass = make_mat(Dielectric::new(1.5));
+    let drops = Sdf::sphere(55.0)
+        .translate(Vec3::new(190.0, 55.0, 150.0))
+        .smooth_union(Sdf::sphere(35.0).translate(Vec3::new(270.0, 40.0, 130.0)), 40.0);
+    world.add(make_ref(DistanceField::new(drops, bounds(Point3::new(130.0, 0.0, 90.0), Point3::new(310.0, 115.0, 210.0)), glass)));
+
+    // A five by five grid of small rings.
+    let rings = Sdf::torus(12.0, 4.0)
+        .repeat_limited(Vec3::new(36.0, 0.0, 36.0), Vec3::new(2.0, 0.0, 2.0))
+        .translate(Vec3::new(400.0, 4.0, 120.0));
+    world.add(make_ref(DistanceField::new(rings, bounds(Point3::new(310.0, 0.0, 30.0), Point3::new(490.0, 8.0, 210.0)), white)));
+
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Quad::new(
+        Point3::new(343.0, 554.0, 332.0),
+        Vec3::new(-130.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -105.0),
+        empty_material,
+    ));
+
+    let mut cam = cornell_camera();
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+fn terrain(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+
+    // Hills from Perlin turbulence around a lake.
+    let grass = make_mat(Lambertian::new(Color::new(0.35, 0.45, 0.2)));
+    let hills = Heightfield::from_perlin(
+        &Perlin::new(),
+        6.0,
+        512,
+        512,
+        Point3::new(-2000.0, 0.0, -3000.0),
+        Vec3::new(4000.0, 500.0, 4000.0),
+        grass,
+    );
+    world.add(make_ref(hills));
+    let water = make_mat(Metal::new(Color::new(0.3, 0.45, 0.6), 0.05));
+    world.add(make_ref(Quad::new(
+        Point3::new(-2000.0, 110.0, -3000.0),
+        Vec3::new(4000.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 4000.0),
+        water,
+    )));
+
+    // A relief map of the earth on a slab, its heights taken from the brightness of the same
+    // image that textures it.
+    let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    world.add(make_box(Point3::new(-210.0, 0.0, 390.0), Point3::new(210.0, 300.0, 610.0), white));
+    let earth = make_mat(Lambertian::from_texture(make_tex(ImageTexture::new("earthmap.jpg"))));
+    let relief = Heightfield::from_image(
+        &RtwImage::new("earthmap.jpg"),
+        Point3::new(-200.0, 300.0, 400.0),
+        Vec3::new(400.0, 12.0, 200.0),
+        earth,
+    );
+    world.add(make_ref(relief));
+
+    let sun = make_mat(DiffuseLight::new(Color::new(40.0, 36.0, 30.0)));
+    world.add(make_ref(Sphere::new(Point3::new(3000.0, 4000.0, 5000.0), 500.0, sun)));
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Sphere::new(Point3::new(3000.0, 4000.0, 5000.0), 500.0, empty_material));
+
+    let mut cam = Camera {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 800,
+        samples_per_pixel: 100,
+        max_depth: 50,
+        background: Color::new(0.7, 0.8, 1.0),
+        vfov: 40.0,
+        lookfrom: Point3::new(0.0, 650.0, 1200.0),
+        lookat: Point3::new(0.0, 250.0, 300.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        ..Camera::default()
+    };
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+// Three strips of floor running off into the distance under the same image, looked up
+// without filtering, with bilinear filtering and through the mip pyramid, left to right.
+fn texture_filtering(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+
+    let image = RtwImage::new("earthmap.jpg");
+    let filters = [FilterMode::Nearest, FilterMode::Bilinear, FilterMode::Trilinear];
+    for (strip, filter) in filters.into_iter().enumerate() {
+        let texture = ImageTexture::from_image(&image).with_filter(filter).with_wrap(WrapMode::Mirror);
+        // Longitude runs away from the camera, so the continents shrink into the distance.
+        world.add(make_ref(Quad::new(
+            Point3::new(-5.5 + 10.0 * strip as f64, 0.0, 10.0),
+            Vec3::new(0.0, 0.0, -400.0),
+            Vec3::new(-9.5, 0.0, 0.0),
+            make_mat(Lambertian::from_texture(make_tex(texture))),
+        )));
+    }
+
+    // A globe up close, where filtering smooths the texels instead of blurring them away.
+    let globe = make_mat(Lambertian::from_texture(make_tex(ImageTexture::from_image(&image))));
+    world.add(make_ref(Sphere::new(Point3::new(6.0, 2.0, -4.0), 2.0, globe)));
+
+    let sun = make_mat(DiffuseLight::new(Color::new(40.0, 36.0, 30.0)));
+    world.add(make_ref(Sphere::new(Point3::new(300.0, 400.0, 500.0), 50.0, sun)));
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Sphere::new(Point3::new(300.0, 400.0, 500.0), 50.0, empty_material));
+
+    let mut cam = Camera {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 800,
+        samples_per_pixel: 16,
+        max_depth: 20,
+        background: Color::new(0.7, 0.8, 1.0),
+        vfov: 40.0,
+        lookfrom: Point3::new(0.0, 4.0, 16.0),
+        lookat: Point3::new(0.0, 0.0, -40.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        ..Camera::default()
+    };
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+// Detail from maps rather than geometry: a floor bumped by the brightness of the earth
+// image, a stone ball bumped by noise, a metal ball with checkered roughness and a glass
+// ball whose index of refraction alternates in a checkerboard.
+fn surface_detail(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+
+    let earth = make_tex(ImageTexture::new("earthmap.jpg"));
+    let floor = make_mat(Lambertian::from_texture(earth.clone()));
+    world.add(make_ref(Quad::new(
+        Point3::new(-8.0, 0.0, 4.0),
+        Vec3::new(16.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -8.0),
+        make_mat(ShadingNormal::bump_map(floor, earth, 0.3)),
+    )));
+
+    let stone = make_mat(Lambertian::new(Color::new(0.6, 0.55, 0.5)));
+    let bumps = make_tex(NoiseTexture::new(1.0));
+    let stone = make_mat(ShadingNormal::bump_map(stone, bumps, 0.05));
+    world.add(make_ref(Sphere::new(Point3::new(-2.5, 1.0, 0.0), 1.0, stone)));
+
+    let roughness = make_tex(CheckerTexture::from_colors(0.5, Color::new(0.0, 0.0, 0.0), Color::new(0.5, 0.5, 0.5)));
+    let metal = make_mat(Metal::from_textures(make_tex(SolidColor::new(Color::new(0.8, 0.6, 0.3))), roughness));
+    world.add(make_ref(Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, metal)));
+
+    let bands = make_tex(CheckerTexture::from_colors(0.5, Color::new(1.3, 1.5, 1.5), Color::new(1.9, 1.9, 1.9)));
+    world.add(make_ref(Sphere::new(Point3::new(2.5, 1.0, 0.0), 1.0, make_mat(Dielectric::from_texture(bands)))));
+
+    let sun = make_mat(DiffuseLight::new(Color::new(40.0, 36.0, 30.0)));
+    world.add(make_ref(Sphere::new(Point3::new(30.0, 40.0, 50.0), 5.0, sun)));
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Sphere::new(Point3::new(30.0, 40.0, 50.0), 5.0, empty_material));
+
+    let mut cam = Camera {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 800,
+        samples_per_pixel: 100,
+        max_depth: 50,
+        background: Color::new(0.7, 0.8, 1.0),
+        vfov: 30.0,
+        lookfrom: Point3::new(0.0, 3.0, 10.0),
+        lookat: Point3::new(0.0, 0.8, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        ..Camera::default()
+    };
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+// A fence in front of the box, cut from a quad by a checkered mask, and behind it a ball
+// whose noise mask leaves it partly see-through.
+fn cut_outs(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+    add_cornell_room(&mut world);
+
+    let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let holes = make_tex(CheckerTexture::from_colors(30.0, Color::new(1.0, 1.0, 1.0), Color::new(0.0, 0.0, 0.0)));
+    world.add(make_ref(Quad::new(
+        Point3::new(80.0, 0.0, 100.0),
+        Vec3::new(395.0, 0.0, 0.0),
+        Vec3::new(0.0, 240.0, 0.0),
+        make_mat(AlphaMask::new(white, holes)),
+    )));
+
+    let blue = make_mat(Lambertian::new(Color::new(0.2, 0.3, 0.7)));
+    let patches = make_tex(NoiseTexture::new(0.05));
+    let ball = AlphaMask::new(blue, patches).with_mode(MaskMode::Stochastic);
+    world.add(make_ref(Sphere::new(Point3::new(278.0, 120.0, 278.0), 120.0, make_mat(ball))));
+
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Quad::new(
+        Point3::new(343.0, 554.0, 332.0),
+        Vec3::new(-130.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -105.0),
+        empty_material,
+    ));
+
+    let mut cam = cornell_camera();
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+// Six balls of procedural texture: clouds from fBm, cells from Worley noise, marble, wood,
+// a gradient from pole to pole and marble mixed into the cells by fBm. The floor is metal
+// with its roughness remapped from fBm.
+fn procedural_textures(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+
+    let fbm = make_tex(FbmTexture::new(2.0).with_octaves(8));
+    let clouds = ColorRamp::new(fbm.clone(), vec![(0.35, Color::new(0.1, 0.3, 0.8)), (0.65, Color::new(1.0, 1.0, 1.0))]);
+
+    let cells = make_tex(WorleyTexture::new(3.0).with_feature(WorleyFeature::Edges));
+    let cells = make_tex(ColorRamp::new(cells, vec![(0.0, Color::new(0.05, 0.05, 0.05)), (0.15, Color::new(0.9, 0.6, 0.2))]));
+
+    let veins = make_tex(MarbleTexture::new(10.0, 4.0));
+    let marble = make_tex(ColorRamp::new(
+        veins,
+        vec![(0.0, Color::new(0.2, 0.25, 0.3)), (0.4, Color::new(0.85, 0.85, 0.8)), (1.0, Color::new(0.95, 0.95, 0.95))],
+    ));
+
+    let rings = make_tex(WoodTexture::new(3.0, 0.8));
+    let wood = ColorRamp::new(rings, vec![(0.0, Color::new(0.55, 0.35, 0.17)), (0.8, Color::new(0.35, 0.2, 0.08))]);
+
+    let gradient = ColorRamp::new(
+        make_tex(GradientTexture::along_v()),
+        vec![(0.0, Color::new(0.8, 0.1, 0.1)), (0.5, Color::new(0.9, 0.8, 0.1)), (1.0, Color::new(0.1, 0.3, 0.8))],
+    );
+
+    let mixed = MixTexture::new(marble.clone(), cells.clone(), fbm.clone());
+
+    let balls = [
+        make_tex(clouds),
+        cells,
+        marble,
+        make_tex(wood),
+        make_tex(gradient),
+        make_tex(mixed),
+    ];
+    for (i, texture) in balls.into_iter().enumerate() {
+        let center = Point3::new(-2.4 + 2.4 * (i % 3) as f64, 1.0, -2.4 * (i / 3) as f64);
+        world.add(make_ref(Sphere::new(center, 1.0, make_mat(Lambertian::from_texture(texture)))));
+    }
+
+    let roughness = make_tex(RemapTexture::new(fbm, (0.4, 0.6), (0.0, 0.4)));
+    let floor = Metal::from_textures(make_tex(SolidColor::new(Color::new(0.7, 0.7, 0.7))), roughness);
+    world.add(make_ref(Quad::new(
+        Point3::new(-10.0, 0.0, 6.0),
+        Vec3::new(20.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -16.0),
+        make_mat(floor),
+    )));
+
+    let sun = make_mat(DiffuseLight::new(Color::new(40.0, 36.0, 30.0)));
+    world.add(make_ref(Sphere::new(Point3::new(30.0, 40.0, 50.0), 5.0, sun)));
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Sphere::new(Point3::new(30.0, 40.0, 50.0), 5.0, empty_material));
+
+    let mut cam = Camera {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 800,
+        samples_per_pixel: 100,
+        max_depth: 50,
+        background: Color::new(0.7, 0.8, 1.0),
+        vfov: 35.0,
+        lookfrom: Point3::new(0.0, 5.0, 9.0),
+        lookat: Point3::new(0.0, 0.8, -1.2),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        ..Camera::default()
+    };
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+// On the left, a cube wrapped in the earth by its faces' own coordinates, stretched to each
+// face; next to it the same cube projected triplanar, the continents the same size on every
+// side. On the right, two turned and moved copies of a marble ball: the one with its
+// texture pinned in object space shows the same veins as the original would, the other
+// takes whatever veins run through where it now stands.
+fn texture_projection(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+
+    let earth = make_tex(ImageTexture::new("earthmap.jpg").with_wrap(WrapMode::Repeat));
+    let stretched = make_box(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(1.5, 1.5, 1.5),
+        make_mat(Lambertian::from_texture(earth.clone())),
+    );
+    let stretched = make_ref(RotateY::new(stretched, 30.0));
+    world.add(make_ref(Translate::new(stretched, Vec3::new(-4.0, 0.0, 0.0))));
+
+    let projected = make_tex(TriplanarTexture::new(earth, 0.4));
+    let projected = make_box(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(1.5, 1.5, 1.5),
+        make_mat(Lambertian::from_texture(projected)),
+    );
+    let projected = make_ref(RotateY::new(projected, 30.0));
+    world.add(make_ref(Translate::new(projected, Vec3::new(-1.4, 0.0, 0.0))));
+
+    let veins = make_tex(MarbleTexture::new(10.0, 4.0));
+    let marble = |texture| {
+        make_tex(ColorRamp::new(texture, vec![(0.0, Color::new(0.2, 0.25, 0.3)), (0.4, Color::new(0.9, 0.9, 0.85))]))
+    };
+    let pinned = marble(make_tex(TransformedTexture::new(veins.clone(), Transform::identity())));
+    let loose = marble(veins);
+    for (texture, x) in [(pinned, 1.8), (loose, 4.2)] {
+        let ball = make_ref(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, make_mat(Lambertian::from_texture(texture))));
+        let ball = make_ref(RotateY::new(ball, 70.0));
+        world.add(make_ref(Translate::new(ball, Vec3::new(x, 1.0, 0.5))));
+    }
+
+    let ground = make_mat(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    world.add(make_ref(Quad::new(
+        Point3::new(-10.0, 0.0, 6.0),
+        Vec3::new(20.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -16.0),
+        ground,
+    )));
+
+    let sun = make_mat(DiffuseLight::new(Color::new(40.0, 36.0, 30.0)));
+    world.add(make_ref(Sphere::new(Point3::new(30.0, 40.0, 50.0), 5.0, sun)));
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Sphere::new(Point3::new(30.0, 40.0, 50.0), 5.0, empty_material));
+
+    let mut cam = Camera {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 800,
+        samples_per_pixel: 100,
+        max_depth: 50,
+        background: Color::new(0.7, 0.8, 1.0),
+        vfov: 35.0,
+        lookfrom: Point3::new(0.0, 4.0, 10.0),
+        lookat: Point3::new(0.0, 0.7, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        ..Camera::default()
+    };
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+fn principled_materials(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+
+    // Back to front: metallic from 0 to 1, roughness from 0 to 1, then sheen, clearcoat,
+    // frosted glass, clear glass and half-transmissive plastic.
+    let gold = Color::new(0.95, 0.7, 0.3);
+    let red = Color::new(0.7, 0.08, 0.05);
+    let blue = Color::new(0.1, 0.25, 0.6);
+    let steps = [0.0, 0.25, 0.5, 0.75, 1.0];
+    let rows: [Vec<Principled>; 3] = [
+        steps.map(|t| Principled::new(gold).with_metallic(t).with_roughness(0.25)).into(),
+        steps.map(|t| Principled::new(red).with_roughness(t)).into(),
+        vec![
+            Principled::new(blue).with_roughness(1.0).with_sheen(1.0),
+            Principled::new(red).with_roughness(0.5).with_clearcoat(1.0),
+            Principled::new(Color::new(0.9, 0.95, 1.0)).with_roughness(0.3).with_transmission(1.0),
+            Principled::new(Color::new(1.0, 1.0, 1.0)).with_roughness(0.0).with_transmission(1.0),
+            Principled::new(blue).with_roughness(0.2).with_transmission(0.5),
+        ],
+    ];
+    for (row, materials) in rows.into_iter().enumerate() {
+        for (column, material) in materials.into_iter().enumerate() {
+            let center = Point3::new(-2.4 + 1.2 * column as f64, 0.5, -1.2 + 1.2 * row as f64);
+            world.add(make_ref(Sphere::new(center, 0.5, make_mat(material))));
+        }
+    }
+
+    let checker = make_tex(CheckerTexture::from_colors(1.2, Color::new(0.2, 0.2, 0.2), Color::new(0.8, 0.8, 0.8)));
+    world.add(make_ref(Quad::new(
+        Point3::new(-10.0, 0.0, 6.0),
+        Vec3::new(20.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -16.0),
+        make_mat(Lambertian::from_texture(checker)),
+    )));
+
+    let sun = make_mat(DiffuseLight::new(Color::new(40.0, 36.0, 30.0)));
+    world.add(make_ref(Sphere::new(Point3::new(-20.0, 40.0, 30.0), 5.0, sun)));
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Sphere::new(Point3::new(-20.0, 40.0, 30.0), 5.0, empty_material));
+
+    let mut cam = Camera {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 800,
+        samples_per_pixel: 100,
+        max_depth: 50,
+        background: Color::new(0.7, 0.8, 1.0),
+        vfov: 30.0,
+        lookfrom: Point3::new(0.0, 5.0, 9.0),
+        lookat: Point3::new(0.0, 0.3, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        ..Camera::default()
+    };
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+// Left to right: car paint, clear coat over a metallic base; varnished wood; a soap bubble
+// whose film thins and thickens in swirls; and heat-tinted steel, a metal under an oxide film.
+fn coatings(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+
+    let paint = Principled::new(Color::new(0.6, 0.02, 0.05)).with_metallic(0.6).with_roughness(0.4);
+    let paint = Coated::new(make_mat(paint), 1.5);
+
+    let rings = make_tex(WoodTexture::new(3.0, 0.8));
+    let wood = make_tex(ColorRamp::new(rings, vec![(0.0, Color::new(0.55, 0.35, 0.17)), (0.8, Color::new(0.35, 0.2, 0.08))]));
+    let varnish = Coated::new(make_mat(Lambertian::from_texture(wood)), 1.5).with_tint(Color::new(0.95, 0.85, 0.6));
+
+    let swirls = make_tex(FbmTexture::new(1.5).with_octaves(5));
+    let thickness = make_tex(RemapTexture::new(swirls, (0.3, 0.7), (250.0, 900.0)));
+    let bubble = Dielectric::new(1.0).with_thin_film(ThinFilm::from_texture(thickness, 1.33));
+
+    let steel = Metal::new(Color::new(0.6, 0.6, 0.62), 0.05).with_thin_film(ThinFilm::new(320.0, 2.4));
+
+    let balls = [make_mat(paint), make_mat(varnish), make_mat(bubble), make_mat(steel)];
+    for (i, material) in balls.into_iter().enumerate() {
+        let center = Point3::new(-3.3 + 2.2 * i as f64, 1.0, 0.0);
+        world.add(make_ref(Sphere::new(center, 1.0, material)));
+    }
+
+    let checker = make_tex(CheckerTexture::from_colors(1.2, Color::new(0.2, 0.2, 0.2), Color::new(0.8, 0.8, 0.8)));
+    world.add(make_ref(Quad::new(
+        Point3::new(-10.0, 0.0, 6.0),
+        Vec3::new(20.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -16.0),
+        make_mat(Lambertian::from_texture(checker)),
+    )));
+
+    let sun = make_mat(DiffuseLight::new(Color::new(40.0, 36.0, 30.0)));
+    world.add(make_ref(Sphere::new(Point3::new(-20.0, 40.0, 30.0), 5.0, sun)));
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Sphere::new(Point3::new(-20.0, 40.0, 30.0), 5.0, empty_material));
+
+    let mut cam = Camera {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 800,
+        samples_per_pixel: 100,
+        max_depth: 50,
+        background: Color::new(0.7, 0.8, 1.0),
+        vfov: 35.0,
+        lookfrom: Point3::new(0.0, 3.0, 10.0),
+        lookat: Point3::new(0.0, 0.8, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        ..Camera::default()
+    };
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+// Marble, skin and green wax, each a ball scattering light inside, lit from behind and to
+// the side so the light shows through the edges.
+fn subsurface_scattering(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+
+    let balls = [
+        (Color::new(0.3, 0.3, 0.3), Color::new(0.99, 0.98, 0.96)),
+        (Color::new(0.6, 0.25, 0.12), Color::new(0.98, 0.85, 0.7)),
+        (Color::new(0.15, 0.4, 0.2), Color::new(0.8, 0.97, 0.75)),
+    ];
+    for (i, (mean_free_path, albedo)) in balls.into_iter().enumerate() {
+        let center = Point3::new(-2.4 + 2.4 * i as f64, 1.0, 0.0);
+        let boundary = make_ref(Sphere::new(center, 1.0, make_mat(EmptyMaterial)));
+        let material = make_mat(Subsurface::from_mean_free_path(boundary, mean_free_path, albedo));
+        world.add(make_ref(Sphere::new(center, 1.0, material)));
+    }
+
+    let ground = make_mat(Lambertian::new(Color::new(0.4, 0.4, 0.4)));
+    world.add(make_ref(Quad::new(
+        Point3::new(-10.0, 0.0, 6.0),
+        Vec3::new(20.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -16.0),
+        ground,
+    )));
+
+    let sun = make_mat(DiffuseLight::new(Color::new(10.0, 9.0, 7.5)));
+    world.add(make_ref(Sphere::new(Point3::new(20.0, 30.0, -30.0), 10.0, sun)));
+    let empty_material = make_mat(EmptyMaterial);
+    let lights = make_ref(Sphere::new(Point3::new(20.0, 30.0, -30.0), 10.0, empty_material));
+
+    let mut cam = Camera {
+        aspect_ratio: 16.0 / 9.0,
+        image_width: 800,
+        samples_per_pixel: 100,
+        max_depth: 50,
+        background: Color::new(0.7, 0.8, 1.0),
+        vfov: 35.0,
+        lookfrom: Point3::new(0.0, 2.5, 9.0),
+        lookat: Point3::new(0.0, 0.9, 0.0),
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        ..Camera::default()
+    };
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+// Out of focus lights take the shape of the aperture. The scene is in millimetres like the
+// Cornell box, so the camera stands in for a 50mm lens at f/2 on 35mm film: try
+// --aperture 5 for other blades or --lens dgauss.50mm.dat for the real lens.
+fn bokeh(frames: Option<Range<i32>>) {
+    let mut world = HittableList::new();
+
+    let ground = make_mat(Lambertian::new(Color::new(0.35, 0.35, 0.35)));
+    world.add(make_ref(Quad::new(
+        Point3::new(-2000.0, 0.0, -1000.0),
+        Vec3::new(4000.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 5000.0),
+        ground,
+    )));
+
+    // In focus.
+    let subjects = [
+        make_mat(Lambertian::new(Color::new(0.8, 0.3, 0.2))),
+        make_mat(Dielectric::new(1.5)),
+        make_mat(Metal::new(Color::new(0.8, 0.8, 0.8), 0.05)),
+    ];
+    for (i, material) in subjects.into_iter().enumerate() {
+        world.add(make_ref(Sphere::new(Point3::new(-120.0 + 120.0 * i as f64, 50.0, 0.0), 50.0, material)));
+    }
+
+    // Far behind, small lights in a row of colors.
+    for i in 0..24 {
+        let x = -1200.0 + 2400.0 * (i as f64 + 0.5) / 24.0;
+        let y = 60.0 + 180.0 * ((i as f64) * 1.7).sin().abs();
+        let z = 2500.0 + 400.0 * ((i as f64) * 2.3).cos();
+        let hue = i as f64 / 24.0 * 2.0 * PI;
+        let color = Color::new(1.0 + hue.cos(), 1.0 + (hue - 2.1).cos(), 1.0 + (hue + 2.1).cos());
+        world.add(make_ref(Sphere::new(Point3::new(x, y, z), 8.0, make_mat(DiffuseLight::new(8.0 * color)))));
+    }
+
+    let light = make_mat(DiffuseLight::new(Color::new(6.0, 6.0, 6.0)));
+    let lamp = |material| Quad::new(Point3::new(-300.0, 800.0, -400.0), Vec3::new(600.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 400.0), material);
+    world.add(make_ref(lamp(light)));
+    let lights = make_ref(lamp(make_mat(EmptyMaterial)));
+
+    let lookfrom = Point3::new(0.0, 120.0, -600.0);
+    let lookat = Point3::new(0.0, 50.0, 0.0);
+    let focus_dist = (lookat - lookfrom).length();
+    let mut cam = Camera {
+        aspect_ratio: 3.0 / 2.0,
+        image_width: 600,
+        samples_per_pixel: 200,
+        max_depth: 50,
+        background: Color::new(0.0, 0.0, 0.0),
+        // The 24mm height of 35mm film behind a 50mm lens.
+        vfov: 2.0 * (12.0_f64 / 50.0).atan().to_degrees(),
+        lookfrom,
+        lookat,
+        vup: Vec3::new(0.0, 1.0, 0.0),
+        // A 25mm opening, f/2 for a 50mm lens.
+        defocus_angle: 2.0 * (12.5 / focus_dist).atan().to_degrees(),
+        focus_dist,
+        aperture: Aperture::polygon(6, PI / 6.0),
+        ..Camera::default()
+    };
+    apply_overrides(&mut cam);
+
+    let world = FlatBvh::new(world);
+    render(&mut cam, &world, lights, &CameraAnimation::default(), frames);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden::{self, Tolerance};
+
+    #[test]
+    fn cornell_box_matches_reference() {
+        rtweekend::seed_random(golden::SEED);
+        let (mut cam, world, lights) = cornell_box_scene();
+        cam.image_width = 64;
+        cam.samples_per_pixel = 64;
+        cam.max_depth = 8;
+        cam.seed = Some(golden::SEED);
+
+        let mut image = Vec::new();
+        cam.render_to(&FlatBvh::new(world), make_ref(lights), &mut image);
+        golden::assert_matches_reference("the_rest_of_your_life_cornell_box", &image, Tolerance::default());
+    }
 }
@@ -1,5 +1,6 @@
-use super::vec3::{cross, unit_vector, Vec3};
+use super::vec3::{cross, dot, unit_vector, Vec3};
 
+#[derive(Clone, Copy)]
 pub struct Onb {
     axis: [Vec3; 3],
 }
@@ -32,4 +33,9 @@ impl Onb {
     pub fn transform(&self, v: Vec3) -> Vec3 {
         v[0] * self.axis[0] + v[1] * self.axis[1] + v[2] * self.axis[2]
     }
+
+    // Inverse of `transform`: the coordinates of v in this basis.
+    pub fn to_local(self, v: Vec3) -> Vec3 {
+        Vec3::new(dot(v, self.axis[0]), dot(v, self.axis[1]), dot(v, self.axis[2]))
+    }
 }
@@ -2,6 +2,7 @@ use std::sync::Arc;
 
 use super::hittable::{Hittable, HittableRef};
 use super::onb::Onb;
+use super::principled::PrincipledPdf;
 use super::rtweekend::{random_double, PI};
 use super::vec3::{dot, random_cosine_direction, random_unit_vector, unit_vector, Point3, Vec3};
 
@@ -56,21 +57,28 @@ impl Pdf for CosinePdf {
 pub struct HittablePdf {
     objects: HittableRef,
     origin: Point3,
+    time: f64,
 }
 
 impl HittablePdf {
     pub fn new(objects: HittableRef, origin: Point3) -> Self {
-        Self { objects, origin }
+        Self { objects, origin, time: 0.0 }
+    }
+
+    // Moving lights are sampled where they are at the time of the ray being scattered.
+    pub fn with_time(mut self, time: f64) -> Self {
+        self.time = time;
+        self
     }
 }
 
 impl Pdf for HittablePdf {
     fn value(&self, direction: Vec3) -> f64 {
-        self.objects.pdf_value(self.origin, direction)
+        self.objects.pdf_value(self.origin, direction, self.time)
     }
 
     fn generate(&self) -> Vec3 {
-        self.objects.random(self.origin)
+        self.objects.random(self.origin, self.time)
     }
 }
 
@@ -104,6 +112,7 @@ pub enum PdfObject {
     CosinePdf(CosinePdf),
     HittablePdf(HittablePdf),
     MixturePdf(MixturePdf),
+    PrincipledPdf(PrincipledPdf),
 }
 
 impl From<SpherePdf> for PdfObject {
@@ -130,6 +139,12 @@ impl From<MixturePdf> for PdfObject {
     }
 }
 
+impl From<PrincipledPdf> for PdfObject {
+    fn from(value: PrincipledPdf) -> Self {
+        Self::PrincipledPdf(value)
+    }
+}
+
 impl PdfObject {
     pub fn value(&self, direction: Vec3) -> f64 {
         match self {
@@ -137,6 +152,7 @@ impl PdfObject {
             PdfObject::CosinePdf(pdf) => pdf.value(direction),
             PdfObject::HittablePdf(pdf) => pdf.value(direction),
             PdfObject::MixturePdf(pdf) => pdf.value(direction),
+            PdfObject::PrincipledPdf(pdf) => pdf.value(direction),
         }
     }
 
@@ -146,6 +162,112 @@ impl PdfObject {
             PdfObject::CosinePdf(pdf) => pdf.generate(),
             PdfObject::HittablePdf(pdf) => pdf.generate(),
             PdfObject::MixturePdf(pdf) => pdf.generate(),
+            PdfObject::PrincipledPdf(pdf) => pdf.generate(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::the_rest_of_your_life::hittable::make_ref;
+    use crate::books::the_rest_of_your_life::hittable_list::HittableList;
+    use crate::books::the_rest_of_your_life::interval::Interval;
+    use crate::books::the_rest_of_your_life::material::{make_mat, AlphaMask, EmptyMaterial, Lambertian};
+    use crate::books::the_rest_of_your_life::texture::{make_tex, CheckerTexture};
+    use crate::books::the_rest_of_your_life::vec3::Color;
+    use crate::books::the_rest_of_your_life::quad::Quad;
+    use crate::books::the_rest_of_your_life::ray::Ray;
+    use crate::books::the_rest_of_your_life::sphere::Sphere;
+    use crate::books::the_rest_of_your_life::test_support::assert_sampler_matches_pdf;
+
+    fn assert_pdf(name: &str, pdf: PdfObject) {
+        assert_sampler_matches_pdf(name, || pdf.generate(), |d| pdf.value(d));
+    }
+
+    // A sphere and a quad close enough to the origin to cover a good part of the sky.
+    fn sphere_light() -> HittableRef {
+        make_ref(Sphere::new(Point3::new(0.5, 3.0, 1.0), 2.0, make_mat(EmptyMaterial)))
+    }
+
+    fn quad_light() -> HittableRef {
+        make_ref(Quad::new(
+            Point3::new(-2.0, -1.0, 1.5),
+            Vec3::new(3.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 2.0),
+            make_mat(EmptyMaterial),
+        ))
+    }
+
+    #[test]
+    fn sphere_pdf() {
+        assert_pdf("sphere", SpherePdf.into());
+    }
+
+    #[test]
+    fn cosine_pdf() {
+        assert_pdf("cosine about +z", CosinePdf::new(Vec3::new(0.0, 0.0, 1.0)).into());
+        assert_pdf("cosine about a tilted normal", CosinePdf::new(Vec3::new(0.3, -0.8, 0.5)).into());
+    }
+
+    #[test]
+    fn hittable_pdf() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        assert_pdf("sphere light", HittablePdf::new(sphere_light(), origin).into());
+        assert_pdf("quad light", HittablePdf::new(quad_light(), origin).into());
+
+        let mut lights = HittableList::new();
+        lights.add(sphere_light());
+        lights.add(quad_light());
+        assert_pdf("list of lights", HittablePdf::new(make_ref(lights), origin).into());
+    }
+
+    #[test]
+    fn mixture_pdf() {
+        let light = make_pdf(HittablePdf::new(quad_light(), Point3::new(0.0, 0.0, 0.0)));
+        let surface = make_pdf(CosinePdf::new(Vec3::new(0.0, 1.0, 0.0)));
+        assert_pdf("light and cosine mixture", MixturePdf::new(light, surface).into());
+    }
+
+    #[test]
+    fn light_sampling_sees_through_cut_outs() {
+        // A light behind a screen cut into half-unit checkers. Rays aimed at the light reach
+        // it through the holes and stop at the screen elsewhere; the light's own density
+        // doesn't change.
+        let opacity = make_tex(CheckerTexture::from_colors(0.5, Color::new(1.0, 1.0, 1.0), Color::new(0.0, 0.0, 0.0)));
+        let screen = make_mat(AlphaMask::new(make_mat(Lambertian::new(Color::new(0.5, 0.5, 0.5))), opacity));
+        let light = quad_light();
+        let mut world = HittableList::new();
+        world.add(make_ref(Quad::new(
+            Point3::new(-3.0, -3.0, 0.5),
+            Vec3::new(6.0, 0.0, 0.0),
+            Vec3::new(0.0, 6.0, 0.0),
+            screen.clone(),
+        )));
+        world.add(light.clone());
+
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let pdf = HittablePdf::new(light.clone(), origin);
+        let (mut through, mut blocked) = (0, 0);
+        for _ in 0..2000 {
+            let r = Ray::new(origin, pdf.generate());
+            let rec = world.hit(&r, Interval::new(0.001, f64::INFINITY)).expect("the ray was aimed at the light");
+            let on_screen = (rec.t * r.direction().z() - 0.5).abs() < 1e-9;
+            if on_screen {
+                assert!(screen.is_opaque(&rec), "stopped by a hole at {:?}", rec.p);
+                blocked += 1;
+            } else {
+                through += 1;
+            }
+        }
+        assert!(through > 0 && blocked > 0, "{} rays reached the light and {} were blocked", through, blocked);
+
+        let cut_out_light = make_ref(Quad::new(
+            Point3::new(-2.0, -1.0, 1.5),
+            Vec3::new(3.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 2.0),
+            screen,
+        ));
+        assert_pdf("cut-out quad light", HittablePdf::new(cut_out_light, origin).into());
+    }
+}
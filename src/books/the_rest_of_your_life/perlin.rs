@@ -61,6 +61,25 @@ impl Perlin {
         accum.abs()
     }
 
+    // Fractal Brownian motion: `octaves` layers of noise, each `lacunarity` times the
+    // frequency and `gain` times the amplitude of the one before. The sum is divided by the
+    // total amplitude, so it stays in the range of a single layer, about -1 to 1.
+    pub fn fbm(&self, p: Point3, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+        let mut accum = 0.0;
+        let mut total = 0.0;
+        let mut temp_p = p;
+        let mut weight = 1.0;
+
+        for _ in 0..octaves {
+            accum += weight * self.noise(temp_p);
+            total += weight;
+            weight *= gain;
+            temp_p *= lacunarity;
+        }
+
+        if total > 0.0 { accum / total } else { 0.0 }
+    }
+
     const POINT_COUNT: usize = 256;
 
     fn perlin_generate_perm() -> Vec<usize> {
@@ -0,0 +1,517 @@
+// The principled BSDF from Burley's "Physically Based Shading at Disney", with specular
+// transmission from its 2015 follow-up: one material whose parameters cover the looks of
+// Lambertian, Metal and Dielectric and the range between them.
+//
+// Reflection is a sum of lobes: Burley's diffuse with a sheen at grazing angles, GGX
+// specular and a GTR1 clearcoat. Transmission is a rough dielectric, chosen at random in
+// proportion to its weight and traced like Dielectric. The reflected lobes have a pdf for
+// the renderer to mix with light sampling.
+
+use super::hittable::HitRecord;
+use super::material::{Material, ScatterRecord};
+use super::onb::Onb;
+use super::pdf::{make_pdf, Pdf};
+use super::ray::Ray;
+use super::rtweekend::{random_double, PI};
+use super::texture::{make_tex, SolidColor, TextureRef};
+use super::vec3::{cross, dot, random_cosine_direction, refract, unit_vector, Color, Vec3};
+
+const WHITE: Color = Color { e: [1.0, 1.0, 1.0] };
+
+pub struct Principled {
+    base_color: TextureRef,
+    metallic: TextureRef,
+    roughness: TextureRef,
+    specular: f64,
+    specular_tint: f64,
+    sheen: f64,
+    sheen_tint: f64,
+    clearcoat: f64,
+    clearcoat_gloss: f64,
+    transmission: f64,
+    ior: f64,
+}
+
+impl Principled {
+    pub fn new(base_color: Color) -> Self {
+        Self::from_texture(make_tex(SolidColor::new(base_color)))
+    }
+
+    pub fn from_texture(base_color: TextureRef) -> Self {
+        Self {
+            base_color,
+            metallic: make_tex(SolidColor::from_value(0.0)),
+            roughness: make_tex(SolidColor::from_value(0.5)),
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            ior: 1.5,
+        }
+    }
+
+    // From 0 for a dielectric to 1 for a metal, whose specular takes the base color.
+    pub fn with_metallic(self, metallic: f64) -> Self {
+        self.with_metallic_map(make_tex(SolidColor::from_value(metallic)))
+    }
+
+    pub fn with_metallic_map(mut self, metallic: TextureRef) -> Self {
+        self.metallic = metallic;
+        self
+    }
+
+    // From 0 for a mirror finish to 1 for a rough one.
+    pub fn with_roughness(self, roughness: f64) -> Self {
+        self.with_roughness_map(make_tex(SolidColor::from_value(roughness)))
+    }
+
+    pub fn with_roughness_map(mut self, roughness: TextureRef) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    // The dielectric specular reflectance, 0.5 giving the 4% of common materials.
+    pub fn with_specular(mut self, specular: f64) -> Self {
+        self.specular = specular;
+        self
+    }
+
+    // How far the dielectric specular turns from white towards the base color.
+    pub fn with_specular_tint(mut self, specular_tint: f64) -> Self {
+        self.specular_tint = specular_tint;
+        self
+    }
+
+    // A soft rim at grazing angles, as on cloth.
+    pub fn with_sheen(mut self, sheen: f64) -> Self {
+        self.sheen = sheen;
+        self
+    }
+
+    pub fn with_sheen_tint(mut self, sheen_tint: f64) -> Self {
+        self.sheen_tint = sheen_tint;
+        self
+    }
+
+    // A second, clear specular layer on top, as on car paint or varnish.
+    pub fn with_clearcoat(mut self, clearcoat: f64) -> Self {
+        self.clearcoat = clearcoat;
+        self
+    }
+
+    // From 0 for a satin clearcoat to 1 for a gloss one.
+    pub fn with_clearcoat_gloss(mut self, clearcoat_gloss: f64) -> Self {
+        self.clearcoat_gloss = clearcoat_gloss;
+        self
+    }
+
+    // How much of the non-metallic part lets light through, tinted by the base color.
+    pub fn with_transmission(mut self, transmission: f64) -> Self {
+        self.transmission = transmission;
+        self
+    }
+
+    // The index of refraction that transmitted light bends by.
+    pub fn with_ior(mut self, ior: f64) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    fn lobes(&self, r_in: &Ray, rec: &HitRecord) -> Lobes {
+        let base = self.base_color.sample(rec);
+        let metallic = self.metallic.sample_scalar(rec).clamp(0.0, 1.0);
+        let roughness = self.roughness.sample_scalar(rec).clamp(0.0, 1.0);
+
+        let luminance = luminance(base);
+        let tint = if luminance > 0.0 { base / luminance } else { WHITE };
+        let dielectric_f0 = 0.08 * self.specular * lerp(WHITE, tint, self.specular_tint);
+        let transmission = (1.0 - metallic) * self.transmission.clamp(0.0, 1.0);
+
+        // Shading normals can leave the viewer just behind the surface; treat that as
+        // grazing.
+        let frame = Onb::new(rec.normal);
+        let v = frame.to_local(-unit_vector(r_in.direction()));
+        let v = unit_vector(Vec3::new(v.x(), v.y(), v.z().max(1e-4)));
+
+        Lobes {
+            frame,
+            v,
+            base,
+            roughness,
+            alpha: (roughness * roughness).max(1e-3),
+            diffuse: (1.0 - metallic) * (1.0 - self.transmission.clamp(0.0, 1.0)),
+            sheen: self.sheen * lerp(WHITE, tint, self.sheen_tint),
+            specular: 1.0 - transmission,
+            f0: lerp(dielectric_f0, base, metallic),
+            clearcoat: 0.25 * self.clearcoat,
+            clearcoat_alpha: 0.1 + (0.001 - 0.1) * self.clearcoat_gloss,
+            transmission,
+            ior: self.ior,
+        }
+    }
+
+    // Rough refraction or reflection through a microfacet drawn from the visible normals,
+    // chosen by Fresnel the way Dielectric chooses.
+    fn transmit(lobes: &Lobes, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let v = lobes.v;
+        let h = sample_ggx_visible_normal(v, lobes.alpha);
+        let ri = if rec.front_face { 1.0 / lobes.ior } else { lobes.ior };
+
+        let cos_theta = dot(v, h).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let reflected = ri * sin_theta > 1.0 || reflectance(cos_theta, ri) > random_double();
+        let (direction, tint) = if reflected {
+            (2.0 * dot(v, h) * h - v, WHITE)
+        } else {
+            (refract(-v, h, ri), lobes.base)
+        };
+
+        // Directions the microfacet sent to the wrong side of the surface are lost.
+        if (direction.z() > 0.0) != reflected {
+            return None;
+        }
+
+        Some(ScatterRecord {
+            attenuation: smith_g1(direction.z().abs(), lobes.alpha) * tint,
+            pdf_ptr: None,
+            skip_pdf: true,
+            skip_pdf_ray: Ray::new_with_time(rec.p, lobes.frame.transform(direction), r_in.time()),
+        })
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let lobes = self.lobes(r_in, rec);
+        if random_double() < lobes.transmission {
+            return Self::transmit(&lobes, r_in, rec);
+        }
+
+        Some(ScatterRecord {
+            attenuation: WHITE / (1.0 - lobes.transmission),
+            pdf_ptr: Some(make_pdf(PrincipledPdf::new(lobes))),
+            skip_pdf: false,
+            skip_pdf_ray: Ray::new_with_time(rec.p, rec.normal, r_in.time()),
+        })
+    }
+
+    // How often `scatter` sends light along `scattered` through the reflected lobes, which
+    // it picks when it doesn't pick transmission.
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let lobes = self.lobes(r_in, rec);
+        (1.0 - lobes.transmission) * PrincipledPdf::new(lobes).value(scattered.direction())
+    }
+
+    fn scattering(&self, r_in: &Ray, rec: &HitRecord, attenuation: Color, scattered: &Ray) -> Color {
+        let lobes = self.lobes(r_in, rec);
+        let l = unit_vector(lobes.frame.to_local(scattered.direction()));
+        attenuation * lobes.reflection(l)
+    }
+}
+
+// The parameters at a hit, in the shading frame where the normal is +z, with the weight
+// of each reflected lobe.
+#[derive(Clone, Copy)]
+struct Lobes {
+    frame: Onb,
+    // Towards the viewer.
+    v: Vec3,
+    base: Color,
+    roughness: f64,
+    alpha: f64,
+    diffuse: f64,
+    sheen: Color,
+    specular: f64,
+    f0: Color,
+    clearcoat: f64,
+    clearcoat_alpha: f64,
+    transmission: f64,
+    ior: f64,
+}
+
+impl Lobes {
+    // The reflected BSDF times the cosine, for light leaving along `l`.
+    fn reflection(&self, l: Vec3) -> Color {
+        let v = self.v;
+        if l.z() <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let h = unit_vector(v + l);
+        let cos_d = dot(l, h);
+        let fresnel = schlick_weight(cos_d);
+
+        // Burley's diffuse, renormalized as in Frostbite so its rough retro-reflection
+        // doesn't give back more light than arrives.
+        let energy_bias = 0.5 * self.roughness;
+        let energy_factor = 1.0 + (1.0 / 1.51 - 1.0) * self.roughness;
+        let fd90 = energy_bias + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = energy_factor
+            * (1.0 + (fd90 - 1.0) * schlick_weight(l.z()))
+            * (1.0 + (fd90 - 1.0) * schlick_weight(v.z()));
+        let diffuse = self.diffuse * (fd / PI * self.base + fresnel * self.sheen);
+
+        let masking = smith_g1(l.z(), self.alpha) * smith_g1(v.z(), self.alpha) / (4.0 * l.z() * v.z());
+        let specular = self.specular * ggx(h.z(), self.alpha) * masking * lerp(self.f0, WHITE, fresnel);
+
+        let masking = smith_g1(l.z(), 0.25) * smith_g1(v.z(), 0.25) / (4.0 * l.z() * v.z());
+        let clearcoat = self.clearcoat * gtr1(h.z(), self.clearcoat_alpha) * masking * (0.04 + 0.96 * fresnel);
+
+        (diffuse + specular + clearcoat * WHITE) * l.z()
+    }
+
+    // How often PrincipledPdf draws from the diffuse, specular and clearcoat lobes, by
+    // roughly how much light each reflects towards the viewer.
+    fn probabilities(&self) -> [f64; 3] {
+        let fresnel = schlick_weight(self.v.z());
+        let weights = [
+            self.diffuse * (luminance(self.base) + luminance(self.sheen)),
+            self.specular * luminance(lerp(self.f0, WHITE, fresnel)),
+            self.clearcoat * (0.04 + 0.96 * fresnel),
+        ];
+        let total: f64 = weights.iter().sum();
+        if total > 0.0 { weights.map(|w| w / total) } else { [1.0, 0.0, 0.0] }
+    }
+}
+
+// Draws directions for the reflected lobes: cosine weighted for the diffuse, from the GGX
+// visible normals for the specular and from GTR1 half vectors for the clearcoat.
+pub struct PrincipledPdf {
+    lobes: Lobes,
+    probabilities: [f64; 3],
+}
+
+impl PrincipledPdf {
+    fn new(lobes: Lobes) -> Self {
+        Self { probabilities: lobes.probabilities(), lobes }
+    }
+}
+
+impl Pdf for PrincipledPdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        let lobes = &self.lobes;
+        let (v, l) = (lobes.v, unit_vector(lobes.frame.to_local(direction)));
+        let [diffuse, specular, clearcoat] = self.probabilities;
+
+        let mut pdf = diffuse * l.z().max(0.0) / PI;
+        let h = unit_vector(v + l);
+        let v_dot_h = dot(v, h);
+        if h.z() > 0.0 && v_dot_h > 0.0 {
+            pdf += specular * smith_g1(v.z(), lobes.alpha) * ggx(h.z(), lobes.alpha) / (4.0 * v.z());
+        }
+        // GTR1 draws half vectors facing away from the viewer too. Those reflect it about
+        // the opposite half vector, which points below the surface.
+        if v_dot_h > 0.0 {
+            pdf += clearcoat * gtr1(h.z(), lobes.clearcoat_alpha) * h.z().abs() / (4.0 * v_dot_h);
+        }
+        pdf
+    }
+
+    fn generate(&self) -> Vec3 {
+        let lobes = &self.lobes;
+        let v = lobes.v;
+        let [diffuse, specular, _] = self.probabilities;
+
+        let pick = random_double();
+        let l = if pick < diffuse {
+            random_cosine_direction()
+        } else {
+            let h = if pick < diffuse + specular {
+                sample_ggx_visible_normal(v, lobes.alpha)
+            } else {
+                sample_gtr1(lobes.clearcoat_alpha)
+            };
+            2.0 * dot(v, h) * h - v
+        };
+        lobes.frame.transform(l)
+    }
+}
+
+fn lerp(a: Color, b: Color, t: f64) -> Color {
+    (1.0 - t) * a + t * b
+}
+
+fn luminance(c: Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+fn schlick_weight(cosine: f64) -> f64 {
+    (1.0 - cosine).clamp(0.0, 1.0).powi(5)
+}
+
+// Schlick's approximation for a dielectric, as in Dielectric.
+fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
+    let r0 = ((1.0 - refraction_index) / (1.0 + refraction_index)).powi(2);
+    r0 + (1.0 - r0) * schlick_weight(cosine)
+}
+
+// The GGX distribution of microfacet normals at cos_h from the surface normal.
+fn ggx(cos_h: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    let t = cos_h * cos_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * t * t)
+}
+
+// Smith's masking for GGX, seen from cos_theta off the normal.
+fn smith_g1(cos_theta: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    2.0 * cos_theta / (cos_theta + (a2 + (1.0 - a2) * cos_theta * cos_theta).sqrt())
+}
+
+// Burley's GTR1 distribution, with its longer tail, for the clearcoat.
+fn gtr1(cos_h: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_h * cos_h))
+}
+
+fn sample_gtr1(alpha: f64) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos_h = ((1.0 - a2.powf(1.0 - random_double())) / (1.0 - a2)).sqrt();
+    let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+    let phi = 2.0 * PI * random_double();
+    Vec3::new(sin_h * phi.cos(), sin_h * phi.sin(), cos_h)
+}
+
+// A GGX microfacet normal drawn in proportion to how much of it faces `v`, from Heitz's
+// "Sampling the GGX Distribution of Visible Normals".
+fn sample_ggx_visible_normal(v: Vec3, alpha: f64) -> Vec3 {
+    // Stretch the view so the distribution becomes a hemisphere.
+    let vh = unit_vector(Vec3::new(alpha * v.x(), alpha * v.y(), v.z()));
+    let length_squared = vh.x() * vh.x() + vh.y() * vh.y();
+    let t1 = if length_squared > 0.0 {
+        Vec3::new(-vh.y(), vh.x(), 0.0) / length_squared.sqrt()
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let t2 = cross(vh, t1);
+
+    // A point on the disk, squeezed onto the part of the hemisphere the view sees.
+    let r = random_double().sqrt();
+    let phi = 2.0 * PI * random_double();
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z());
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+    // Unstretch.
+    unit_vector(Vec3::new(alpha * nh.x(), alpha * nh.y(), nh.z().max(0.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::material::make_mat;
+    use crate::books::the_rest_of_your_life::rtweekend::seed_random;
+    use crate::books::the_rest_of_your_life::test_support::{
+        assert_sampler_matches_pdf, incoming_directions, integrate_directions, mean_weight, sample_weight, surface_hit,
+    };
+    use crate::books::the_rest_of_your_life::vec3::random_unit_vector;
+
+    fn materials() -> Vec<(&'static str, Principled)> {
+        let orange = Color::new(0.9, 0.5, 0.2);
+        vec![
+            ("rough plastic", Principled::new(orange).with_roughness(0.8)),
+            ("glossy plastic", Principled::new(orange).with_roughness(0.3).with_sheen(1.0)),
+            ("metal", Principled::new(orange).with_metallic(1.0).with_roughness(0.4)),
+            ("clearcoated paint", Principled::new(orange).with_roughness(0.6).with_clearcoat(1.0).with_clearcoat_gloss(0.3)),
+            ("frosted glass", Principled::new(orange).with_roughness(0.3).with_transmission(1.0)),
+            ("half clear", Principled::new(orange).with_roughness(0.5).with_transmission(0.5).with_clearcoat(1.0)),
+        ]
+    }
+
+    #[test]
+    fn sampling_matches_the_pdf() {
+        seed_random(golden::SEED);
+        for (name, mat) in materials().into_iter().filter(|(_, m)| m.transmission == 0.0) {
+            let mat = make_mat(mat);
+            for incoming in [Vec3::new(0.0, 0.0, 1.0), unit_vector(Vec3::new(1.0, 0.0, 0.5))] {
+                let (r_in, rec) = surface_hit(&mat, incoming);
+                let pdf = mat.scatter(&r_in, &rec).and_then(|srec| srec.pdf_ptr).expect("reflection has a pdf");
+                let name = format!("{} lit from {:?}", name, incoming);
+                assert_sampler_matches_pdf(&name, || pdf.generate(), |d| pdf.value(d));
+            }
+        }
+    }
+
+    #[test]
+    fn scattering_pdf_covers_the_reflected_share() {
+        seed_random(golden::SEED);
+        for (name, principled) in materials() {
+            let reflected = 1.0 - principled.transmission;
+            let mat = make_mat(principled);
+            for incoming in incoming_directions() {
+                let (r_in, rec) = surface_hit(&mat, incoming);
+                let total = integrate_directions(400, |d| mat.scattering_pdf(&r_in, &rec, &Ray::new(rec.p, d)));
+                assert!((total - reflected).abs() < 0.02, "{} lit from {:?}: pdf integrates to {}", name, incoming, total);
+            }
+        }
+    }
+
+    #[test]
+    fn reflects_no_more_than_it_receives() {
+        seed_random(golden::SEED);
+        const SAMPLES: usize = 100_000;
+        for (name, mat) in materials() {
+            let mat = make_mat(mat);
+            for incoming in incoming_directions() {
+                let (r_in, rec) = surface_hit(&mat, incoming);
+                let (mut sum, mut sum_squared) = (0.0, 0.0);
+                for _ in 0..SAMPLES {
+                    let weight = sample_weight(&mat, &r_in, &rec);
+                    let weight = weight.x().max(weight.y()).max(weight.z());
+                    sum += weight;
+                    sum_squared += weight * weight;
+                }
+
+                let mean = sum / SAMPLES as f64;
+                let error = ((sum_squared / SAMPLES as f64 - mean * mean).max(0.0) / SAMPLES as f64).sqrt();
+                assert!(mean <= 1.0 + 5.0 * error + 1e-3, "{} lit from {:?}: reflects {} +/- {}", name, incoming, mean, error);
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_clear_glass_loses_nothing() {
+        seed_random(golden::SEED);
+        let mat = make_mat(Principled::new(Color::new(1.0, 1.0, 1.0)).with_roughness(0.0).with_transmission(1.0));
+        for incoming in incoming_directions() {
+            let (r_in, rec) = surface_hit(&mat, incoming);
+            let mean = mean_weight(&mat, &r_in, &rec, 10_000).y();
+            assert!((mean - 1.0).abs() < 1e-3, "lit from {:?}: passes on {}", incoming, mean);
+        }
+    }
+
+    #[test]
+    fn reflection_is_reciprocal() {
+        seed_random(golden::SEED);
+        // The BSDF, without the cosine at the outgoing side, is the same both ways round.
+        for (name, mat) in materials() {
+            let mat = make_mat(mat);
+            let bsdf = |incoming: Vec3, outgoing: Vec3| {
+                let (r_in, rec) = surface_hit(&mat, incoming);
+                mat.scattering(&r_in, &rec, WHITE, &Ray::new(rec.p, outgoing)) / outgoing.z()
+            };
+            for _ in 0..1000 {
+                let (a, b) = (random_unit_vector(), random_unit_vector());
+                let (a, b) = (Vec3::new(a.x(), a.y(), a.z().abs()), Vec3::new(b.x(), b.y(), b.z().abs()));
+                if a.z() < 1e-3 || b.z() < 1e-3 {
+                    // The material treats views at the horizon as slightly above it.
+                    continue;
+                }
+                let (forward, backward) = (bsdf(a, b), bsdf(b, a));
+                assert!(
+                    (forward - backward).length() <= 1e-9 * forward.length().max(1.0),
+                    "{}: f({:?}, {:?}) = {:?} but the reverse is {:?}",
+                    name,
+                    a,
+                    b,
+                    forward,
+                    backward
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,397 @@
+// Textures computed rather than looked up: noise patterns that give a gray level, and nodes
+// that turn gray levels into colors or combine other textures. Gray inputs are read as the
+// mean of their channels, the same as scalar material parameters.
+
+use super::perlin::Perlin;
+use super::hittable::HitRecord;
+use super::texture::{Texture, TextureRef, UvFootprint};
+use super::vec3::{dot, Color, Point3, Vec3};
+use super::worley::Worley;
+
+fn gray(value: f64) -> Color {
+    Color::new(value, value, value)
+}
+
+fn mean(color: Color) -> f64 {
+    (color.x() + color.y() + color.z()) / 3.0
+}
+
+// Perlin fBm of the hit point scaled by `scale`, mapped to run from 0 to 1.
+pub struct FbmTexture {
+    noise: Perlin,
+    scale: f64,
+    octaves: u32,
+    lacunarity: f64,
+    gain: f64,
+}
+
+impl FbmTexture {
+    pub fn new(scale: f64) -> Self {
+        Self { noise: Perlin::new(), scale, octaves: 6, lacunarity: 2.0, gain: 0.5 }
+    }
+
+    pub fn with_octaves(mut self, octaves: u32) -> Self {
+        self.octaves = octaves;
+        self
+    }
+
+    pub fn with_lacunarity(mut self, lacunarity: f64) -> Self {
+        self.lacunarity = lacunarity;
+        self
+    }
+
+    pub fn with_gain(mut self, gain: f64) -> Self {
+        self.gain = gain;
+        self
+    }
+}
+
+impl Texture for FbmTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let n = self.noise.fbm(self.scale * p, self.octaves, self.lacunarity, self.gain);
+        gray((0.5 + 0.5 * n).clamp(0.0, 1.0))
+    }
+}
+
+// Which distance a WorleyTexture shows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WorleyFeature {
+    // Distance to the nearest feature point: dark spots in bright cells.
+    #[default]
+    Nearest,
+    // Distance to the second nearest: rounded cells that meet in creases.
+    SecondNearest,
+    // The difference of the two: dark lines along the cell walls.
+    Edges,
+}
+
+// Cellular noise with cells about 1 / `scale` across. Values run from 0 to around 1.
+pub struct WorleyTexture {
+    noise: Worley,
+    scale: f64,
+    feature: WorleyFeature,
+}
+
+impl WorleyTexture {
+    pub fn new(scale: f64) -> Self {
+        Self { noise: Worley::new(), scale, feature: WorleyFeature::default() }
+    }
+
+    pub fn with_feature(mut self, feature: WorleyFeature) -> Self {
+        self.feature = feature;
+        self
+    }
+}
+
+impl Texture for WorleyTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let (f1, f2) = self.noise.distances(self.scale * p);
+        gray(match self.feature {
+            WorleyFeature::Nearest => f1,
+            WorleyFeature::SecondNearest => f2,
+            WorleyFeature::Edges => f2 - f1,
+        })
+    }
+}
+
+// Veins of a sine wave along z, `frequency` radians per unit, pushed about by `turbulence`
+// times fBm. From 0 to 1.
+pub struct MarbleTexture {
+    noise: Perlin,
+    frequency: f64,
+    turbulence: f64,
+    octaves: u32,
+}
+
+impl MarbleTexture {
+    pub fn new(frequency: f64, turbulence: f64) -> Self {
+        Self { noise: Perlin::new(), frequency, turbulence, octaves: 7 }
+    }
+
+    pub fn with_octaves(mut self, octaves: u32) -> Self {
+        self.octaves = octaves;
+        self
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let distortion = self.turbulence * self.noise.fbm(p, self.octaves, 2.0, 0.5);
+        gray(0.5 * (1.0 + (self.frequency * p.z() + distortion).sin()))
+    }
+}
+
+// Growth rings around the y axis, `rings` to a unit, wobbled by `turbulence` rings of fBm.
+// Each ring ramps from 0 at its inside to 1 at its outside; a ColorRamp gives it color.
+pub struct WoodTexture {
+    noise: Perlin,
+    rings: f64,
+    turbulence: f64,
+}
+
+impl WoodTexture {
+    pub fn new(rings: f64, turbulence: f64) -> Self {
+        Self { noise: Perlin::new(), rings, turbulence }
+    }
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let radius = (p.x() * p.x() + p.z() * p.z()).sqrt();
+        // The grain runs along the trunk, so the wobble varies slowly in y.
+        let grain = Point3::new(p.x(), 0.1 * p.y(), p.z()) * self.rings;
+        let ring = self.rings * radius + self.turbulence * self.noise.fbm(grain, 4, 2.0, 0.5);
+        gray(ring.rem_euclid(1.0))
+    }
+}
+
+enum GradientAxis {
+    U,
+    V,
+    Between(Point3, Vec3),
+}
+
+// A ramp from 0 to 1, across the texture coordinates or between two points in space, and
+// clamped beyond its ends.
+pub struct GradientTexture {
+    axis: GradientAxis,
+}
+
+impl GradientTexture {
+    // From 0 at `start` to 1 at `end`, constant across planes at right angles to the line.
+    pub fn new(start: Point3, end: Point3) -> Self {
+        let direction = end - start;
+        Self { axis: GradientAxis::Between(start, direction / direction.length_squared()) }
+    }
+
+    pub fn along_u() -> Self {
+        Self { axis: GradientAxis::U }
+    }
+
+    pub fn along_v() -> Self {
+        Self { axis: GradientAxis::V }
+    }
+}
+
+impl Texture for GradientTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let t = match self.axis {
+            GradientAxis::U => u,
+            GradientAxis::V => v,
+            GradientAxis::Between(start, direction) => dot(p - start, direction),
+        };
+        gray(t.clamp(0.0, 1.0))
+    }
+}
+
+// Colors a gray input through stops at positions from 0 to 1, blending linearly between
+// neighbors and holding the end colors beyond the first and last stops.
+pub struct ColorRamp {
+    input: TextureRef,
+    stops: Vec<(f64, Color)>,
+}
+
+impl ColorRamp {
+    pub fn new(input: TextureRef, mut stops: Vec<(f64, Color)>) -> Self {
+        assert!(!stops.is_empty(), "a color ramp needs at least one stop");
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { input, stops }
+    }
+
+    fn color_at(&self, t: f64) -> Color {
+        let next = self.stops.partition_point(|&(position, _)| position <= t);
+        if next == 0 {
+            return self.stops[0].1;
+        }
+        if next == self.stops.len() {
+            return self.stops[next - 1].1;
+        }
+        let ((p0, c0), (p1, c1)) = (self.stops[next - 1], self.stops[next]);
+        let blend = (t - p0) / (p1 - p0);
+        (1.0 - blend) * c0 + blend * c1
+    }
+}
+
+impl Texture for ColorRamp {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.filtered_value(u, v, p, UvFootprint::default())
+    }
+
+    fn filtered_value(&self, u: f64, v: f64, p: Point3, footprint: UvFootprint) -> Color {
+        self.color_at(mean(self.input.filtered_value(u, v, p, footprint)))
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.color_at(mean(self.input.sample(rec)))
+    }
+}
+
+// `a` where the gray `factor` is 0, `b` where it is 1, and a blend in between.
+pub struct MixTexture {
+    a: TextureRef,
+    b: TextureRef,
+    factor: TextureRef,
+}
+
+impl MixTexture {
+    pub fn new(a: TextureRef, b: TextureRef, factor: TextureRef) -> Self {
+        Self { a, b, factor }
+    }
+
+    fn mix(&self, lookup: impl Fn(&TextureRef) -> Color) -> Color {
+        let factor = mean(lookup(&self.factor)).clamp(0.0, 1.0);
+        (1.0 - factor) * lookup(&self.a) + factor * lookup(&self.b)
+    }
+}
+
+impl Texture for MixTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.filtered_value(u, v, p, UvFootprint::default())
+    }
+
+    fn filtered_value(&self, u: f64, v: f64, p: Point3, footprint: UvFootprint) -> Color {
+        self.mix(|texture| texture.filtered_value(u, v, p, footprint))
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.mix(|texture| texture.sample(rec))
+    }
+}
+
+// The product of two textures, channel by channel.
+pub struct MultiplyTexture {
+    a: TextureRef,
+    b: TextureRef,
+}
+
+impl MultiplyTexture {
+    pub fn new(a: TextureRef, b: TextureRef) -> Self {
+        Self { a, b }
+    }
+}
+
+impl Texture for MultiplyTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.filtered_value(u, v, p, UvFootprint::default())
+    }
+
+    fn filtered_value(&self, u: f64, v: f64, p: Point3, footprint: UvFootprint) -> Color {
+        self.a.filtered_value(u, v, p, footprint) * self.b.filtered_value(u, v, p, footprint)
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.a.sample(rec) * self.b.sample(rec)
+    }
+}
+
+// Maps each channel of the input linearly from the range `from` to the range `to`,
+// clamping to `to`. Handy for turning noise into roughness or a mask.
+pub struct RemapTexture {
+    input: TextureRef,
+    from: (f64, f64),
+    to: (f64, f64),
+}
+
+impl RemapTexture {
+    pub fn new(input: TextureRef, from: (f64, f64), to: (f64, f64)) -> Self {
+        Self { input, from, to }
+    }
+
+    fn remap(&self, color: Color) -> Color {
+        let remap = |x: f64| {
+            let t = ((x - self.from.0) / (self.from.1 - self.from.0)).clamp(0.0, 1.0);
+            self.to.0 + t * (self.to.1 - self.to.0)
+        };
+        Color::new(remap(color.x()), remap(color.y()), remap(color.z()))
+    }
+}
+
+impl Texture for RemapTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.filtered_value(u, v, p, UvFootprint::default())
+    }
+
+    fn filtered_value(&self, u: f64, v: f64, p: Point3, footprint: UvFootprint) -> Color {
+        self.remap(self.input.filtered_value(u, v, p, footprint))
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.remap(self.input.sample(rec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::the_rest_of_your_life::texture::{make_tex, SolidColor};
+    use crate::books::the_rest_of_your_life::vec3::random_unit_vector;
+
+    fn solid(value: f64) -> TextureRef {
+        make_tex(SolidColor::from_value(value))
+    }
+
+    fn at_origin(texture: &impl Texture) -> Color {
+        texture.value(0.0, 0.0, Point3::new(0.0, 0.0, 0.0))
+    }
+
+    fn assert_close(actual: Color, expected: Color) {
+        assert!((actual - expected).length() < 1e-9, "got {:?}, expected {:?}", actual, expected);
+    }
+
+    #[test]
+    fn color_ramps_blend_between_stops_and_hold_their_ends() {
+        let (red, blue) = (Color::new(1.0, 0.0, 0.0), Color::new(0.0, 0.0, 1.0));
+        // Stops given out of order are sorted.
+        let ramp = |input| ColorRamp::new(solid(input), vec![(0.8, blue), (0.2, red)]);
+        assert_close(at_origin(&ramp(0.0)), red);
+        assert_close(at_origin(&ramp(0.2)), red);
+        assert_close(at_origin(&ramp(0.35)), Color::new(0.75, 0.0, 0.25));
+        assert_close(at_origin(&ramp(0.8)), blue);
+        assert_close(at_origin(&ramp(1.5)), blue);
+    }
+
+    #[test]
+    fn math_nodes() {
+        let (a, b) = (make_tex(SolidColor::from_rgb(1.0, 0.5, 0.0)), make_tex(SolidColor::from_rgb(0.0, 0.5, 1.0)));
+        assert_close(at_origin(&MixTexture::new(a.clone(), b.clone(), solid(0.25))), Color::new(0.75, 0.5, 0.25));
+        assert_close(at_origin(&MixTexture::new(a.clone(), b.clone(), solid(2.0))), Color::new(0.0, 0.5, 1.0));
+        assert_close(at_origin(&MultiplyTexture::new(a.clone(), b)), Color::new(0.0, 0.25, 0.0));
+        assert_close(at_origin(&RemapTexture::new(a, (0.25, 0.75), (2.0, 4.0))), Color::new(4.0, 3.0, 2.0));
+    }
+
+    #[test]
+    fn gradients() {
+        let along_x = GradientTexture::new(Point3::new(1.0, 0.0, 0.0), Point3::new(3.0, 0.0, 0.0));
+        assert_close(along_x.value(0.0, 0.0, Point3::new(2.5, 7.0, -4.0)), gray(0.75));
+        assert_close(along_x.value(0.0, 0.0, Point3::new(-2.0, 0.0, 0.0)), gray(0.0));
+        assert_close(GradientTexture::along_v().value(0.1, 0.6, Point3::new(0.0, 0.0, 0.0)), gray(0.6));
+    }
+
+    #[test]
+    fn worley_finds_the_two_nearest_feature_points() {
+        let worley = Worley::new();
+        for _ in 0..200 {
+            let p = 20.0 * random_unit_vector();
+            let (f1, f2) = worley.distances(p);
+            assert!(0.0 <= f1 && f1 <= f2, "distances {} and {} at {:?}", f1, f2, p);
+            // The nearest point is never more than a cell's diagonal away.
+            assert!(f1 <= 3.0_f64.sqrt(), "nearest feature point {} away from {:?}", f1, p);
+        }
+    }
+
+    #[test]
+    fn noise_textures_stay_in_range() {
+        let textures: Vec<(&str, TextureRef)> = vec![
+            ("fbm", make_tex(FbmTexture::new(3.0).with_octaves(8).with_gain(0.7))),
+            ("marble", make_tex(MarbleTexture::new(5.0, 6.0))),
+            ("wood", make_tex(WoodTexture::new(4.0, 2.0))),
+        ];
+        for (name, texture) in textures {
+            for _ in 0..1000 {
+                let p = 10.0 * random_unit_vector();
+                let value = texture.value(0.0, 0.0, p);
+                assert!((0.0..=1.0).contains(&value.x()), "{} at {:?}: {:?}", name, p, value);
+            }
+        }
+    }
+}
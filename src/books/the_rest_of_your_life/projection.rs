@@ -0,0 +1,167 @@
+// Textures that decide where another texture is looked up: across three planes by the
+// surface's facing, or in a space moved and turned with the object.
+
+use super::hittable::HitRecord;
+use super::texture::{Texture, TextureRef, UvFootprint};
+use super::transform::Transform;
+use super::vec3::{unit_vector, Color, Point3, Vec3};
+
+// Looks a texture up in three planar projections of object space, along x, y and z, and
+// blends them by how squarely the surface faces each axis. Surfaces without useful texture
+// coordinates, such as the faces of a box, get the texture without stretching or seams.
+// Image textures should wrap with WrapMode::Repeat. Lookups without a hit to give the
+// normal fall back to the texture's own coordinates.
+pub struct TriplanarTexture {
+    texture: TextureRef,
+    scale: f64,
+    sharpness: f64,
+}
+
+impl TriplanarTexture {
+    // `scale` repeats of the texture to a unit of object space.
+    pub fn new(texture: TextureRef, scale: f64) -> Self {
+        Self { texture, scale, sharpness: 4.0 }
+    }
+
+    // Higher values narrow the band where neighboring projections blend.
+    pub fn with_sharpness(mut self, sharpness: f64) -> Self {
+        self.sharpness = sharpness;
+        self
+    }
+
+    fn blend(&self, p: Point3, normal: Vec3, footprint: f64) -> Color {
+        let weights = [normal.x(), normal.y(), normal.z()].map(|n| n.abs().powf(self.sharpness));
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let footprint = UvFootprint { du: self.scale * footprint, dv: self.scale * footprint };
+        let planes = [(p.z(), p.y()), (p.x(), p.z()), (p.x(), p.y())];
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        for (weight, (u, v)) in weights.into_iter().zip(planes) {
+            if weight > 0.0 {
+                color += (weight / total) * self.texture.filtered_value(self.scale * u, self.scale * v, p, footprint);
+            }
+        }
+        color
+    }
+}
+
+impl Texture for TriplanarTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.texture.value(u, v, p)
+    }
+
+    fn filtered_value(&self, u: f64, v: f64, p: Point3, footprint: UvFootprint) -> Color {
+        self.texture.filtered_value(u, v, p, footprint)
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.blend(rec.object_p, rec.object_normal, rec.footprint)
+    }
+}
+
+// Places another texture in object space, scaled, turned and moved there by `transform`.
+// Solid and triplanar textures then stick to the object however it is instanced; the
+// identity transform just pins them to the object. Lookups without a hit transform the
+// point they are given.
+pub struct TransformedTexture {
+    texture: TextureRef,
+    transform: Transform,
+}
+
+impl TransformedTexture {
+    pub fn new(texture: TextureRef, transform: Transform) -> Self {
+        Self { texture, transform }
+    }
+
+    // The hit as the texture sees it, with its point and normal in the texture's space.
+    fn to_texture_space(&self, rec: &HitRecord) -> HitRecord {
+        let scale = self.transform.scale;
+        let mean_scale = (scale.x() * scale.y() * scale.z()).cbrt();
+        // Normals go through the inverse transpose of the inverse transform.
+        let normal = scale * self.transform.rotation.conjugate().rotate(rec.object_normal);
+
+        let mut local = rec.clone();
+        local.p = self.transform.inverse_point(rec.object_p);
+        local.object_p = local.p;
+        local.object_normal = unit_vector(normal);
+        local.dpdu = self.transform.inverse_vector(rec.dpdu);
+        local.dpdv = self.transform.inverse_vector(rec.dpdv);
+        local.object_dpdu = self.transform.inverse_vector(rec.object_dpdu);
+        local.object_dpdv = self.transform.inverse_vector(rec.object_dpdv);
+        local.footprint = rec.footprint / mean_scale;
+        local
+    }
+}
+
+impl Texture for TransformedTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.texture.value(u, v, self.transform.inverse_point(p))
+    }
+
+    fn filtered_value(&self, u: f64, v: f64, p: Point3, footprint: UvFootprint) -> Color {
+        self.texture.filtered_value(u, v, self.transform.inverse_point(p), footprint)
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.texture.sample(&self.to_texture_space(rec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::the_rest_of_your_life::hittable::{make_ref, Hittable, RotateY, Translate};
+    use crate::books::the_rest_of_your_life::interval::Interval;
+    use crate::books::the_rest_of_your_life::material::{make_mat, Lambertian};
+    use crate::books::the_rest_of_your_life::procedural::GradientTexture;
+    use crate::books::the_rest_of_your_life::ray::Ray;
+    use crate::books::the_rest_of_your_life::sphere::Sphere;
+    use crate::books::the_rest_of_your_life::texture::make_tex;
+    use crate::books::the_rest_of_your_life::transform::Quat;
+
+    fn hit_at(p: Point3, normal: Vec3) -> HitRecord {
+        let r = Ray::new(p + normal, -normal);
+        HitRecord::new(p, 1.0, &r, normal, make_mat(Lambertian::new(Color::new(0.5, 0.5, 0.5))), 0.0, 0.0)
+    }
+
+    fn assert_gray(actual: Color, expected: f64) {
+        assert!((actual - Color::new(expected, expected, expected)).length() < 1e-9, "got {:?}, expected {}", actual, expected);
+    }
+
+    #[test]
+    fn triplanar_blends_projections_by_the_normal() {
+        // The texture's u is the first coordinate of each plane: z, x and x.
+        let triplanar = TriplanarTexture::new(make_tex(GradientTexture::along_u()), 2.0).with_sharpness(1.0);
+        let p = Point3::new(0.1, 0.3, 0.4);
+        assert_gray(triplanar.sample(&hit_at(p, Vec3::new(1.0, 0.0, 0.0))), 0.8);
+        assert_gray(triplanar.sample(&hit_at(p, Vec3::new(0.0, -1.0, 0.0))), 0.2);
+        assert_gray(triplanar.sample(&hit_at(p, unit_vector(Vec3::new(1.0, 0.0, 1.0)))), 0.5);
+    }
+
+    #[test]
+    fn transformed_textures_stay_on_instanced_objects() {
+        // A gradient across x placed one unit along x, on a sphere that is turned and moved.
+        let gradient = make_tex(GradientTexture::new(Point3::new(-1.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0)));
+        let placed = TransformedTexture::new(
+            gradient,
+            Transform::new(Vec3::new(1.0, 0.0, 0.0), Quat::IDENTITY, Vec3::new(1.0, 1.0, 1.0)),
+        );
+        let ball = make_ref(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, make_mat(Lambertian::new(Color::new(0.5, 0.5, 0.5)))));
+        let ball = Translate::new(make_ref(RotateY::new(ball, 90.0)), Vec3::new(10.0, 0.0, 0.0));
+
+        // Straight down onto the top of the ball, whose object space is unchanged there.
+        let r = Ray::new(Point3::new(10.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let rec = ball.hit(&r, Interval::new(0.001, f64::INFINITY)).expect("the ray is aimed at the ball");
+        assert!((rec.object_p - Point3::new(0.0, 1.0, 0.0)).length() < 1e-9, "object point {:?}", rec.object_p);
+        assert_gray(placed.sample(&rec), 0.0);
+
+        // Object-space x points along world z after the quarter turn.
+        let r = Ray::new(Point3::new(10.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let rec = ball.hit(&r, Interval::new(0.001, f64::INFINITY)).expect("the ray is aimed at the ball");
+        assert!((rec.object_p - Point3::new(1.0, 0.0, 0.0)).length() < 1e-9, "object point {:?}", rec.object_p);
+        assert_gray(placed.sample(&rec), 0.5);
+    }
+}
@@ -0,0 +1,707 @@
+use std::mem::MaybeUninit;
+
+use super::aabb::Aabb;
+use super::flat_bvh::{BvhOptions, FlatBvh, LinearNode};
+use super::hittable::{HitRecord, Hittable, HittableRef};
+use super::hittable_list::HittableList;
+use super::interval::Interval;
+use super::ray::Ray;
+
+pub const WIDTH: usize = 4;
+// Every node pops one entry and pushes at most four, and the collapsed tree is no deeper
+// than the binary one, whose depth the builder keeps well under 64.
+const STACK_SIZE: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Child {
+    Empty,
+    Node(u32),
+    Leaf { offset: u32, count: u32 },
+}
+
+// The boxes of four children stored axis by axis, so that one SIMD register holds the same
+// slab of every child.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, align(32))]
+struct Bounds4 {
+    min: [[f64; WIDTH]; 3],
+    max: [[f64; WIDTH]; 3],
+}
+
+impl Bounds4 {
+    const EMPTY: Bounds4 = Bounds4 { min: [[0.0; WIDTH]; 3], max: [[0.0; WIDTH]; 3] };
+
+    fn set(&mut self, lane: usize, bbox: &Aabb) {
+        for axis in 0..3 {
+            let interval = bbox.axis_interval(axis);
+            self.min[axis][lane] = interval.min;
+            self.max[axis][lane] = interval.max;
+        }
+    }
+
+    fn lane_min(&self, lane: usize) -> [f64; 3] {
+        [self.min[0][lane], self.min[1][lane], self.min[2][lane]]
+    }
+
+    fn lane_max(&self, lane: usize) -> [f64; 3] {
+        [self.max[0][lane], self.max[1][lane], self.max[2][lane]]
+    }
+}
+
+struct QbvhNode {
+    bounds: Bounds4,
+    children: [Child; WIDTH],
+}
+
+#[derive(Clone, Copy)]
+struct RayInv {
+    origin: [f64; 3],
+    inv_dir: [f64; 3],
+}
+
+impl RayInv {
+    fn new(r: &Ray) -> Self {
+        let o = r.origin();
+        let d = r.direction();
+        Self { origin: [o.x(), o.y(), o.z()], inv_dir: [1.0 / d.x(), 1.0 / d.y(), 1.0 / d.z()] }
+    }
+}
+
+// Four rays stored axis by axis, for testing one box against all of them at once.
+#[derive(Clone, Copy)]
+#[repr(C, align(32))]
+struct Packet {
+    origin: [[f64; WIDTH]; 3],
+    inv_dir: [[f64; WIDTH]; 3],
+}
+
+impl Packet {
+    fn new(rays: &[Ray; WIDTH]) -> Self {
+        let mut packet = Packet { origin: [[0.0; WIDTH]; 3], inv_dir: [[0.0; WIDTH]; 3] };
+        for (lane, r) in rays.iter().enumerate() {
+            let ray = RayInv::new(r);
+            for axis in 0..3 {
+                packet.origin[axis][lane] = ray.origin[axis];
+                packet.inv_dir[axis][lane] = ray.inv_dir[axis];
+            }
+        }
+        packet
+    }
+
+    fn lane(&self, lane: usize) -> RayInv {
+        RayInv {
+            origin: [self.origin[0][lane], self.origin[1][lane], self.origin[2][lane]],
+            inv_dir: [self.inv_dir[0][lane], self.inv_dir[1][lane], self.inv_dir[2][lane]],
+        }
+    }
+}
+
+// A 4-wide BVH collapsed from the binary SAH tree of FlatBvh. Each node tests all of its
+// children's boxes in one go, with AVX where the CPU has it and a scalar loop otherwise.
+// Both paths perform exactly the operations of `Aabb::hit_inv` in the same order, so they
+// accept the same boxes and report the same hits as the binary tree, bit for bit.
+pub struct Qbvh {
+    nodes: Vec<QbvhNode>,
+    primitives: Vec<HittableRef>,
+    bbox: Aabb,
+    simd: bool,
+}
+
+impl Qbvh {
+    pub fn new(list: HittableList) -> Self {
+        Self::with_options(list, BvhOptions::default())
+    }
+
+    pub fn with_options(list: HittableList, options: BvhOptions) -> Self {
+        let (binary, primitives) = FlatBvh::with_options(list, options).into_parts();
+        let bbox = binary.first().map_or(Aabb::EMPTY, |node| node.bbox);
+
+        let mut nodes = Vec::with_capacity(binary.len() / 3 + 1);
+        if let Some(root) = binary.first() {
+            if root.count > 0 {
+                let mut bounds = Bounds4::EMPTY;
+                bounds.set(0, &root.bbox);
+                let mut children = [Child::Empty; WIDTH];
+                children[0] = Child::Leaf { offset: root.offset, count: root.count as u32 };
+                nodes.push(QbvhNode { bounds, children });
+            } else {
+                Self::collapse(&binary, 0, &mut nodes);
+            }
+        }
+
+        Self { nodes, primitives, bbox, simd: simd_available() }
+    }
+
+    // Forces the scalar node test, for comparing against the SIMD one.
+    pub fn without_simd(mut self) -> Self {
+        self.simd = false;
+        self
+    }
+
+    pub fn uses_simd(&self) -> bool {
+        self.simd
+    }
+
+    // Turns the binary interior node `index` into a 4-wide node by repeatedly opening its
+    // largest interior child, and returns the new node's index.
+    fn collapse(binary: &[LinearNode], index: usize, nodes: &mut Vec<QbvhNode>) -> u32 {
+        let children_of = |i: usize| [i + 1, binary[i].offset as usize];
+
+        let mut open = children_of(index).to_vec();
+        while open.len() < WIDTH {
+            let largest = open
+                .iter()
+                .enumerate()
+                .filter(|&(_, &i)| binary[i].count == 0)
+                .max_by(|&(_, &a), &(_, &b)| binary[a].bbox.surface_area().total_cmp(&binary[b].bbox.surface_area()))
+                .map(|(slot, _)| slot);
+            let Some(slot) = largest else { break };
+            let i = open.remove(slot);
+            open.extend(children_of(i));
+        }
+
+        let position = nodes.len();
+        nodes.push(QbvhNode { bounds: Bounds4::EMPTY, children: [Child::Empty; WIDTH] });
+
+        let mut bounds = Bounds4::EMPTY;
+        let mut children = [Child::Empty; WIDTH];
+        for (lane, &i) in open.iter().enumerate() {
+            let node = &binary[i];
+            bounds.set(lane, &node.bbox);
+            children[lane] = if node.count > 0 {
+                Child::Leaf { offset: node.offset, count: node.count as u32 }
+            } else {
+                Child::Node(Self::collapse(binary, i, nodes))
+            };
+        }
+
+        nodes[position] = QbvhNode { bounds, children };
+        position as u32
+    }
+
+    // Traces four rays together, typically neighbouring primary rays. A child is visited
+    // when any ray of the packet reaches its box, and only those rays test its primitives,
+    // so every ray gets the same hit as when traced on its own.
+    pub fn hit_packet(&self, rays: &[Ray; WIDTH], ray_t: Interval) -> [Option<HitRecord>; WIDTH] {
+        #[cfg(target_arch = "x86_64")]
+        if self.simd {
+            // SAFETY: `simd` is only set when the CPU reports AVX support.
+            return unsafe { self.hit_packet_avx(rays, ray_t) };
+        }
+        self.traverse_packet(rays, ray_t, intersect_packet_scalar)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx")]
+    fn hit_avx(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        self.traverse(r, ray_t, |bounds, ray, t_min, t_max| intersect4_avx(bounds, ray, t_min, t_max))
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx")]
+    fn hit_packet_avx(&self, rays: &[Ray; WIDTH], ray_t: Interval) -> [Option<HitRecord>; WIDTH] {
+        self.traverse_packet(rays, ray_t, |min, max, packet, t_min, t_max| {
+            intersect_packet_avx(min, max, packet, t_min, t_max)
+        })
+    }
+
+    // The node test is a parameter so that the AVX entry points get a copy of the whole
+    // loop compiled with AVX enabled, with the node test inlined into it.
+    #[inline(always)]
+    fn traverse<F>(&self, r: &Ray, ray_t: Interval, intersect4: F) -> Option<HitRecord>
+    where
+        F: Fn(&Bounds4, &RayInv, f64, f64) -> (u32, [f64; WIDTH]),
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let ray = RayInv::new(r);
+        let mut closest = None;
+        let mut closest_t = ray_t.max;
+        let mut stack = TraversalStack::new((Child::Node(0), ray_t.min));
+
+        while let Some((child, entry)) = stack.pop() {
+            // A box entered at or beyond the closest hit would fail its slab test now.
+            if entry >= closest_t {
+                continue;
+            }
+
+            match child {
+                Child::Empty => {}
+                Child::Leaf { offset, count } => {
+                    let start = offset as usize;
+                    for object in &self.primitives[start..start + count as usize] {
+                        if let Some(rec) = object.hit(r, Interval::new(ray_t.min, closest_t)) {
+                            closest_t = rec.t;
+                            closest = Some(rec);
+                        }
+                    }
+                }
+                Child::Node(index) => {
+                    let node = &self.nodes[index as usize];
+                    let (mask, entries) = intersect4(&node.bounds, &ray, ray_t.min, closest_t);
+
+                    // Insert the hit children so the farthest is pushed first and the
+                    // nearest is visited next.
+                    let mut hits = [(Child::Empty, 0.0); WIDTH];
+                    let mut hit_count = 0;
+                    for (lane, &child) in node.children.iter().enumerate() {
+                        if mask & (1 << lane) != 0 && child != Child::Empty {
+                            let mut i = hit_count;
+                            while i > 0 && hits[i - 1].1 < entries[lane] {
+                                hits[i] = hits[i - 1];
+                                i -= 1;
+                            }
+                            hits[i] = (child, entries[lane]);
+                            hit_count += 1;
+                        }
+                    }
+                    for &hit in &hits[..hit_count] {
+                        stack.push(hit);
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+
+    #[inline(always)]
+    fn traverse_packet<F>(&self, rays: &[Ray; WIDTH], ray_t: Interval, intersect_packet: F) -> [Option<HitRecord>; WIDTH]
+    where
+        F: Fn([f64; 3], [f64; 3], &Packet, f64, &[f64; WIDTH]) -> (u32, [f64; WIDTH]),
+    {
+        let mut closest: [Option<HitRecord>; WIDTH] = Default::default();
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let packet = Packet::new(rays);
+        let mut closest_t = [ray_t.max; WIDTH];
+        let mut stack = TraversalStack::new((Child::Node(0), (1u32 << WIDTH) - 1, [ray_t.min; WIDTH]));
+
+        while let Some((child, mut active, entries)) = stack.pop() {
+            // Drop the rays that have found a hit closer than where they enter this box.
+            for lane in 0..WIDTH {
+                if entries[lane] >= closest_t[lane] {
+                    active &= !(1 << lane);
+                }
+            }
+            if active == 0 {
+                continue;
+            }
+
+            match child {
+                Child::Empty => {}
+                Child::Leaf { offset, count } => {
+                    let start = offset as usize;
+                    for lane in (0..WIDTH).filter(|lane| active & (1 << lane) != 0) {
+                        for object in &self.primitives[start..start + count as usize] {
+                            if let Some(rec) = object.hit(&rays[lane], Interval::new(ray_t.min, closest_t[lane])) {
+                                closest_t[lane] = rec.t;
+                                closest[lane] = Some(rec);
+                            }
+                        }
+                    }
+                }
+                Child::Node(index) => {
+                    let node = &self.nodes[index as usize];
+
+                    // Order the children by the nearest entry of any ray that reaches them,
+                    // pushing the farthest first.
+                    let mut hits = [(Child::Empty, 0u32, [0.0; WIDTH], 0.0); WIDTH];
+                    let mut hit_count = 0;
+                    for (lane, &child) in node.children.iter().enumerate() {
+                        if child == Child::Empty {
+                            continue;
+                        }
+                        let min = node.bounds.lane_min(lane);
+                        let max = node.bounds.lane_max(lane);
+                        let (mask, child_entries) = intersect_packet(min, max, &packet, ray_t.min, &closest_t);
+                        let mask = mask & active;
+                        if mask == 0 {
+                            continue;
+                        }
+
+                        let nearest = (0..WIDTH)
+                            .filter(|ray| mask & (1 << ray) != 0)
+                            .map(|ray| child_entries[ray])
+                            .fold(f64::INFINITY, f64::min);
+                        let mut i = hit_count;
+                        while i > 0 && hits[i - 1].3 < nearest {
+                            hits[i] = hits[i - 1];
+                            i -= 1;
+                        }
+                        hits[i] = (child, mask, child_entries, nearest);
+                        hit_count += 1;
+                    }
+                    for &(child, mask, child_entries, _) in &hits[..hit_count] {
+                        stack.push((child, mask, child_entries));
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+// A fixed-size stack that leaves its unused slots uninitialized, since clearing the whole
+// array would cost more than traversing a small tree.
+struct TraversalStack<T: Copy> {
+    entries: [MaybeUninit<T>; STACK_SIZE],
+    len: usize,
+}
+
+impl<T: Copy> TraversalStack<T> {
+    #[inline(always)]
+    fn new(first: T) -> Self {
+        let mut stack = Self { entries: [const { MaybeUninit::uninit() }; STACK_SIZE], len: 0 };
+        stack.push(first);
+        stack
+    }
+
+    #[inline(always)]
+    fn push(&mut self, value: T) {
+        self.entries[self.len].write(value);
+        self.len += 1;
+    }
+
+    #[inline(always)]
+    fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        // SAFETY: every slot below `len` has been written by `push`.
+        Some(unsafe { self.entries[self.len].assume_init() })
+    }
+}
+
+impl Hittable for Qbvh {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        #[cfg(target_arch = "x86_64")]
+        if self.simd {
+            // SAFETY: `simd` is only set when the CPU reports AVX support.
+            return unsafe { self.hit_avx(r, ray_t) };
+        }
+        self.traverse(r, ray_t, intersect4_scalar)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+fn simd_available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("avx")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+// The slab test of `Aabb::hit_inv`, also returning the distance at which the box is entered.
+fn slab_test(min: [f64; 3], max: [f64; 3], ray: &RayInv, t_min: f64, t_max: f64) -> Option<f64> {
+    let mut lo = t_min;
+    let mut hi = t_max;
+    for axis in 0..3 {
+        let t0 = (min[axis] - ray.origin[axis]) * ray.inv_dir[axis];
+        let t1 = (max[axis] - ray.origin[axis]) * ray.inv_dir[axis];
+
+        let (t_near, t_far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+        if t_near > lo {
+            lo = t_near;
+        }
+        if t_far < hi {
+            hi = t_far;
+        }
+
+        if hi <= lo {
+            return None;
+        }
+    }
+    Some(lo)
+}
+
+fn intersect4_scalar(bounds: &Bounds4, ray: &RayInv, t_min: f64, t_max: f64) -> (u32, [f64; WIDTH]) {
+    let mut mask = 0;
+    let mut entries = [0.0; WIDTH];
+    for (lane, entry) in entries.iter_mut().enumerate() {
+        if let Some(t) = slab_test(bounds.lane_min(lane), bounds.lane_max(lane), ray, t_min, t_max) {
+            mask |= 1 << lane;
+            *entry = t;
+        }
+    }
+    (mask, entries)
+}
+
+fn intersect_packet_scalar(
+    min: [f64; 3],
+    max: [f64; 3],
+    packet: &Packet,
+    t_min: f64,
+    t_max: &[f64; WIDTH],
+) -> (u32, [f64; WIDTH]) {
+    let mut mask = 0;
+    let mut entries = [0.0; WIDTH];
+    for (lane, entry) in entries.iter_mut().enumerate() {
+        if let Some(t) = slab_test(min, max, &packet.lane(lane), t_min, t_max[lane]) {
+            mask |= 1 << lane;
+            *entry = t;
+        }
+    }
+    (mask, entries)
+}
+
+// One step of the slab test on four lanes. The ordered comparisons are false for NaN just
+// like the scalar `<` and `>`, and the selects mirror its branches, so every lane ends up
+// with the same bits the scalar code would compute.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+#[inline]
+fn slab_step_avx(
+    t0: std::arch::x86_64::__m256d,
+    t1: std::arch::x86_64::__m256d,
+    lo: &mut std::arch::x86_64::__m256d,
+    hi: &mut std::arch::x86_64::__m256d,
+) {
+    use std::arch::x86_64::*;
+
+    let ordered = _mm256_cmp_pd::<_CMP_LT_OQ>(t0, t1);
+    let t_near = _mm256_blendv_pd(t1, t0, ordered);
+    let t_far = _mm256_blendv_pd(t0, t1, ordered);
+    *lo = _mm256_blendv_pd(*lo, t_near, _mm256_cmp_pd::<_CMP_GT_OQ>(t_near, *lo));
+    *hi = _mm256_blendv_pd(*hi, t_far, _mm256_cmp_pd::<_CMP_LT_OQ>(t_far, *hi));
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+#[inline]
+fn intersect4_avx(bounds: &Bounds4, ray: &RayInv, t_min: f64, t_max: f64) -> (u32, [f64; WIDTH]) {
+    use std::arch::x86_64::*;
+
+    let mut lo = _mm256_set1_pd(t_min);
+    let mut hi = _mm256_set1_pd(t_max);
+    for axis in 0..3 {
+        let origin = _mm256_set1_pd(ray.origin[axis]);
+        let inv_dir = _mm256_set1_pd(ray.inv_dir[axis]);
+        // SAFETY: each row is WIDTH contiguous f64s.
+        let (min, max) = unsafe { (_mm256_loadu_pd(bounds.min[axis].as_ptr()), _mm256_loadu_pd(bounds.max[axis].as_ptr())) };
+        let t0 = _mm256_mul_pd(_mm256_sub_pd(min, origin), inv_dir);
+        let t1 = _mm256_mul_pd(_mm256_sub_pd(max, origin), inv_dir);
+        slab_step_avx(t0, t1, &mut lo, &mut hi);
+    }
+
+    // Once hi <= lo a lane can never recover, so checking at the end matches the scalar
+    // early exit.
+    let mask = _mm256_movemask_pd(_mm256_cmp_pd::<_CMP_GT_OQ>(hi, lo)) as u32;
+    let mut entries = [0.0; WIDTH];
+    // SAFETY: `entries` holds WIDTH f64s.
+    unsafe { _mm256_storeu_pd(entries.as_mut_ptr(), lo) };
+    (mask, entries)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+#[inline]
+fn intersect_packet_avx(
+    min: [f64; 3],
+    max: [f64; 3],
+    packet: &Packet,
+    t_min: f64,
+    t_max: &[f64; WIDTH],
+) -> (u32, [f64; WIDTH]) {
+    use std::arch::x86_64::*;
+
+    let mut lo = _mm256_set1_pd(t_min);
+    // SAFETY: `t_max` holds WIDTH f64s.
+    let mut hi = unsafe { _mm256_loadu_pd(t_max.as_ptr()) };
+    for axis in 0..3 {
+        // SAFETY: each row is WIDTH contiguous f64s.
+        let (origin, inv_dir) =
+            unsafe { (_mm256_loadu_pd(packet.origin[axis].as_ptr()), _mm256_loadu_pd(packet.inv_dir[axis].as_ptr())) };
+        let t0 = _mm256_mul_pd(_mm256_sub_pd(_mm256_set1_pd(min[axis]), origin), inv_dir);
+        let t1 = _mm256_mul_pd(_mm256_sub_pd(_mm256_set1_pd(max[axis]), origin), inv_dir);
+        slab_step_avx(t0, t1, &mut lo, &mut hi);
+    }
+
+    let mask = _mm256_movemask_pd(_mm256_cmp_pd::<_CMP_GT_OQ>(hi, lo)) as u32;
+    let mut entries = [0.0; WIDTH];
+    // SAFETY: `entries` holds WIDTH f64s.
+    unsafe { _mm256_storeu_pd(entries.as_mut_ptr(), lo) };
+    (mask, entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::the_rest_of_your_life::flat_bvh::FlatBvh;
+    use crate::books::the_rest_of_your_life::hittable::make_ref;
+    use crate::books::the_rest_of_your_life::material::{make_mat, Lambertian};
+    use crate::books::the_rest_of_your_life::rtweekend::random_double;
+    use crate::books::the_rest_of_your_life::sphere::Sphere;
+    use crate::books::the_rest_of_your_life::vec3::{random_unit_vector, Color, Point3, Vec3};
+
+    fn sphere_cluster(count: usize) -> HittableList {
+        let mut list = HittableList::new();
+        let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+        for _ in 0..count {
+            list.add(make_ref(Sphere::new(Vec3::random_range(0.0, 100.0), 5.0, white.clone())));
+        }
+        list
+    }
+
+    fn copy_list(list: &HittableList) -> HittableList {
+        let mut copy = HittableList::new();
+        for object in &list.objects {
+            copy.add(object.clone());
+        }
+        copy
+    }
+
+    fn assert_same_hit(a: &Option<HitRecord>, b: &Option<HitRecord>) {
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                assert_eq!(a.t.to_bits(), b.t.to_bits());
+                for axis in 0..3 {
+                    assert_eq!(a.p[axis].to_bits(), b.p[axis].to_bits());
+                    assert_eq!(a.normal[axis].to_bits(), b.normal[axis].to_bits());
+                }
+                assert_eq!(a.front_face, b.front_face);
+            }
+            (None, None) => {}
+            _ => panic!("one traversal hit and the other missed"),
+        }
+    }
+
+    // Rays that stress the slab test: axis-parallel directions (infinite reciprocals) and
+    // origins lying exactly on box faces (0 * inf = NaN).
+    fn awkward_ray(boxes: &[Aabb; WIDTH]) -> Ray {
+        let b = boxes[(random_double() * WIDTH as f64) as usize];
+        let mut origin = Vec3::random_range(-20.0, 120.0);
+        let mut direction = random_unit_vector();
+        for axis in 0..3 {
+            let choice = random_double();
+            if choice < 0.3 {
+                direction[axis] = 0.0;
+            }
+            if choice < 0.15 {
+                origin[axis] = b.axis_interval(axis).min;
+            } else if choice > 0.85 {
+                origin[axis] = b.axis_interval(axis).max;
+            }
+        }
+        if direction.length_squared() == 0.0 {
+            direction[0] = -1.0;
+        }
+        Ray::new(origin, direction)
+    }
+
+    #[test]
+    fn node_test_matches_aabb_hit_inv() {
+        for _ in 0..20_000 {
+            let boxes: [Aabb; WIDTH] =
+                std::array::from_fn(|_| Aabb::from_points(Vec3::random_range(0.0, 100.0), Vec3::random_range(0.0, 100.0)));
+            let mut bounds = Bounds4::EMPTY;
+            for (lane, b) in boxes.iter().enumerate() {
+                bounds.set(lane, b);
+            }
+
+            let r = awkward_ray(&boxes);
+            let ray = RayInv::new(&r);
+            let t_max = if random_double() < 0.5 { f64::INFINITY } else { 50.0 + 100.0 * random_double() };
+            let inv_dir = Vec3::new(ray.inv_dir[0], ray.inv_dir[1], ray.inv_dir[2]);
+
+            let (scalar_mask, scalar_entries) = intersect4_scalar(&bounds, &ray, 0.001, t_max);
+            for (lane, b) in boxes.iter().enumerate() {
+                let expected = b.hit_inv(r.origin(), inv_dir, Interval::new(0.001, t_max));
+                assert_eq!(scalar_mask & (1 << lane) != 0, expected);
+            }
+
+            #[cfg(target_arch = "x86_64")]
+            if simd_available() {
+                let (simd_mask, simd_entries) = unsafe { intersect4_avx(&bounds, &ray, 0.001, t_max) };
+                assert_eq!(simd_mask, scalar_mask);
+                for lane in (0..WIDTH).filter(|lane| scalar_mask & (1 << lane) != 0) {
+                    assert_eq!(simd_entries[lane].to_bits(), scalar_entries[lane].to_bits());
+                }
+
+                let rays: [Ray; WIDTH] = std::array::from_fn(|_| awkward_ray(&boxes));
+                let packet = Packet::new(&rays);
+                let t_maxes = [t_max, 10.0, f64::INFINITY, 75.0];
+                for b in &boxes {
+                    let (min, max) = ([b.x.min, b.y.min, b.z.min], [b.x.max, b.y.max, b.z.max]);
+                    let (scalar_mask, scalar_entries) = intersect_packet_scalar(min, max, &packet, 0.001, &t_maxes);
+                    let (simd_mask, simd_entries) = unsafe { intersect_packet_avx(min, max, &packet, 0.001, &t_maxes) };
+                    assert_eq!(simd_mask, scalar_mask);
+                    for lane in (0..WIDTH).filter(|lane| scalar_mask & (1 << lane) != 0) {
+                        assert_eq!(simd_entries[lane].to_bits(), scalar_entries[lane].to_bits());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn traversal_matches_flat_bvh() {
+        let spheres = sphere_cluster(500);
+        let flat = FlatBvh::new(copy_list(&spheres));
+        let qbvh = Qbvh::new(copy_list(&spheres));
+        let scalar = Qbvh::new(copy_list(&spheres)).without_simd();
+
+        let center = Point3::new(50.0, 50.0, 50.0);
+        for _ in 0..20_000 {
+            let origin = center + 200.0 * random_unit_vector();
+            let r = Ray::new(origin, Vec3::random_range(0.0, 100.0) - origin);
+            let ray_t = Interval::new(0.001, f64::INFINITY);
+
+            let expected = flat.hit(&r, ray_t);
+            assert_same_hit(&qbvh.hit(&r, ray_t), &expected);
+            assert_same_hit(&scalar.hit(&r, ray_t), &expected);
+        }
+    }
+
+    #[test]
+    fn packets_match_single_rays() {
+        let qbvh = Qbvh::new(sphere_cluster(500));
+        let scalar_qbvh = Qbvh::new(sphere_cluster(500)).without_simd();
+        let origin = Point3::new(50.0, 50.0, -150.0);
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+
+        // Neighbouring rays of a pinhole camera looking into the cluster.
+        for y in 0..64 {
+            for x in (0..64).step_by(WIDTH) {
+                let rays: [Ray; WIDTH] = std::array::from_fn(|i| {
+                    let target = Point3::new((x + i) as f64 * 100.0 / 64.0, y as f64 * 100.0 / 64.0, 50.0);
+                    Ray::new(origin, target - origin)
+                });
+
+                for bvh in [&qbvh, &scalar_qbvh] {
+                    let hits = bvh.hit_packet(&rays, ray_t);
+                    for (r, hit) in rays.iter().zip(&hits) {
+                        assert_same_hit(hit, &bvh.hit(r, ray_t));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_and_single_leaf_trees() {
+        let empty = Qbvh::new(HittableList::new());
+        let r = Ray::new(Point3::new(0.0, 0.0, -10.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(empty.hit(&r, Interval::new(0.001, f64::INFINITY)).is_none());
+
+        let white = make_mat(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+        let mut list = HittableList::new();
+        list.add(make_ref(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, white)));
+        let single = Qbvh::new(list);
+        let rec = single.hit(&r, Interval::new(0.001, f64::INFINITY)).expect("ray should hit the sphere");
+        assert_eq!(rec.t, 9.0);
+    }
+}
@@ -4,9 +4,30 @@ use super::hittable_list::HittableList;
 use super::interval::Interval;
 use super::material::MaterialRef;
 use super::ray::Ray;
-use super::rtweekend::INFINITY;
+use super::rtweekend::{random_double, INFINITY};
+use super::spherical::{SphericalRectangle, SphericalTriangle};
 use super::vec3::{cross, dot, unit_vector, Point3, Vec3};
 
+// Below this solid angle the quad is sampled by area instead, which is nearly uniform in
+// solid angle by then and avoids the precision loss of the spherical constructions.
+const MIN_SOLID_ANGLE: f64 = 1e-4;
+
+// The quad as seen from a point, for sampling directions towards it uniformly by solid
+// angle. Rectangles are sampled directly and other parallelograms as two triangles.
+enum Projection {
+    Rectangle(SphericalRectangle),
+    Triangles(SphericalTriangle, SphericalTriangle),
+}
+
+impl Projection {
+    fn solid_angle(&self) -> f64 {
+        match self {
+            Projection::Rectangle(rectangle) => rectangle.solid_angle(),
+            Projection::Triangles(t0, t1) => t0.solid_angle() + t1.solid_angle(),
+        }
+    }
+}
+
 pub struct Quad {
     q: Point3,
     u: Vec3,
@@ -17,6 +38,7 @@ pub struct Quad {
     normal: Vec3,
     d: f64,
     area: f64,
+    rectangular: bool,
 }
 
 impl Quad {
@@ -26,6 +48,7 @@ impl Quad {
         let d = dot(normal, q);
         let w = n / dot(n, n);
         let area = n.length();
+        let rectangular = dot(u, v).abs() <= 1e-9 * u.length() * v.length();
 
         let mut quad = Self {
             q,
@@ -37,6 +60,7 @@ impl Quad {
             normal,
             d,
             area,
+            rectangular,
         };
         quad.set_bounding_box();
         quad
@@ -58,6 +82,19 @@ impl Quad {
         rec.v = b;
         true
     }
+
+    fn projection(&self, origin: Point3) -> Option<Projection> {
+        let projection = if self.rectangular {
+            Projection::Rectangle(SphericalRectangle::new(origin, self.q, self.u, self.v))
+        } else {
+            let (q, u, v) = (self.q, self.u, self.v);
+            Projection::Triangles(
+                SphericalTriangle::new(origin, [q, q + u, q + u + v]),
+                SphericalTriangle::new(origin, [q, q + u + v, q + v]),
+            )
+        };
+        (projection.solid_angle() >= MIN_SOLID_ANGLE).then_some(projection)
+    }
 }
 
 impl Hittable for Quad {
@@ -78,7 +115,8 @@ impl Hittable for Quad {
         let alpha = dot(self.w, cross(planar_hitpt_vector, self.v));
         let beta = dot(self.w, cross(self.u, planar_hitpt_vector));
 
-        let mut rec = HitRecord::new(intersection, t, r, self.normal, self.mat.clone(), 0.0, 0.0);
+        let mut rec =
+            HitRecord::new(intersection, t, r, self.normal, self.mat.clone(), 0.0, 0.0).with_derivatives(self.u, self.v);
         if !self.is_interior(alpha, beta, &mut rec) {
             return None;
         }
@@ -90,25 +128,41 @@ impl Hittable for Quad {
         self.bbox
     }
 
-    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    fn pdf_value(&self, origin: Point3, direction: Vec3, _time: f64) -> f64 {
         let Some(rec) = self.hit(&Ray::new(origin, direction), Interval::new(0.001, INFINITY)) else {
             return 0.0;
         };
 
+        if let Some(projection) = self.projection(origin) {
+            return 1.0 / projection.solid_angle();
+        }
+
         let distance_squared = rec.t * rec.t * direction.length_squared();
         let cosine = dot(direction, rec.normal).abs() / direction.length();
 
         distance_squared / (cosine * self.area)
     }
 
-    fn random(&self, origin: Point3) -> Vec3 {
-        let p = self.q + (random_double() * self.u) + (random_double() * self.v);
-        p - origin
+    fn random(&self, origin: Point3, _time: f64) -> Vec3 {
+        match self.projection(origin) {
+            Some(Projection::Rectangle(rectangle)) => rectangle.sample(random_double(), random_double()),
+            Some(Projection::Triangles(t0, t1)) => {
+                let total = t0.solid_angle() + t1.solid_angle();
+                let pick = random_double() * total;
+                if pick < t0.solid_angle() {
+                    t0.sample(pick / t0.solid_angle(), random_double())
+                } else {
+                    t1.sample((pick - t0.solid_angle()) / t1.solid_angle(), random_double())
+                }
+            }
+            None => {
+                let p = self.q + (random_double() * self.u) + (random_double() * self.v);
+                p - origin
+            }
+        }
     }
 }
 
-use super::rtweekend::random_double;
-
 pub fn make_box(
     a: Point3,
     b: Point3,
@@ -132,3 +186,125 @@ pub fn make_box(
 
     make_ref(sides)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::material::{make_mat, EmptyMaterial};
+    use crate::books::the_rest_of_your_life::rtweekend::seed_random;
+    use crate::books::the_rest_of_your_life::test_support::{assert_chi_square, integrate_directions};
+
+    // Lights and the points they are seen from, from easy to awkward.
+    fn lights() -> Vec<(&'static str, Quad, Point3)> {
+        let mat = make_mat(EmptyMaterial);
+        let cornell = |mat| Quad::new(Point3::new(343.0, 554.0, 332.0), Vec3::new(-130.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -105.0), mat);
+        vec![
+            ("cornell light from the floor", cornell(mat.clone()), Point3::new(278.0, 0.0, 278.0)),
+            ("cornell light from near the ceiling", cornell(mat.clone()), Point3::new(20.0, 540.0, 500.0)),
+            (
+                "large rectangle at a grazing angle",
+                Quad::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(10.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 10.0), mat.clone()),
+                Point3::new(-1.0, 0.3, 5.0),
+            ),
+            (
+                "rectangle seen from behind",
+                Quad::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0), Vec3::new(3.0, 0.0, 0.0), mat.clone()),
+                Point3::new(1.0, 1.5, 1.0),
+            ),
+            (
+                "skewed parallelogram",
+                Quad::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(4.0, 0.0, 0.0), Vec3::new(3.0, 0.0, 2.0), mat.clone()),
+                Point3::new(1.0, 1.0, 3.5),
+            ),
+            (
+                "distant rectangle",
+                Quad::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), mat),
+                Point3::new(0.3, 0.2, 300.0),
+            ),
+        ]
+    }
+
+    // Integrates `f` over the part of the quad with (alpha, beta) in the given ranges, by
+    // solid angle as seen from `origin`.
+    fn integrate_over_quad(
+        quad: &Quad,
+        origin: Point3,
+        alphas: (f64, f64),
+        betas: (f64, f64),
+        mut f: impl FnMut(Vec3) -> f64,
+    ) -> f64 {
+        let n = 200;
+        let cell_area = quad.area * (alphas.1 - alphas.0) * (betas.1 - betas.0) / (n * n) as f64;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let a = alphas.0 + (alphas.1 - alphas.0) * (i as f64 + 0.5) / n as f64;
+                let b = betas.0 + (betas.1 - betas.0) * (j as f64 + 0.5) / n as f64;
+                let direction = quad.q + a * quad.u + b * quad.v - origin;
+                let cosine = dot(unit_vector(direction), quad.normal).abs();
+                sum += f(direction) * cosine / direction.length_squared() * cell_area;
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn pdf_integrates_to_one_over_the_quad() {
+        for (name, quad, origin) in lights() {
+            let total = integrate_over_quad(&quad, origin, (0.0, 1.0), (0.0, 1.0), |d| quad.pdf_value(origin, d, 0.0));
+            assert!((total - 1.0).abs() < 1e-3, "{}: pdf integrates to {}", name, total);
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_one_over_all_directions() {
+        seed_random(golden::SEED);
+        // The pdf must also vanish away from the quad. The grid only resolves lights that
+        // cover a reasonable solid angle.
+        for (name, quad, origin) in lights() {
+            if quad.projection(origin).is_none_or(|p| p.solid_angle() < 0.01) {
+                continue;
+            }
+            let total = integrate_directions(600, |d| quad.pdf_value(origin, d, 0.0));
+            assert!((total - 1.0).abs() < 0.01, "{}: pdf integrates to {}", name, total);
+        }
+    }
+
+    #[test]
+    fn samples_follow_pdf() {
+        seed_random(golden::SEED);
+        const BINS: usize = 4;
+        const SAMPLES: usize = 100_000;
+
+        for (name, quad, origin) in lights() {
+            // Bin the samples by where they land on the quad, and expect each bin to get the
+            // pdf's integral over it.
+            let mut expected = Vec::new();
+            for i in 0..BINS {
+                for j in 0..BINS {
+                    let alphas = (i as f64 / BINS as f64, (i + 1) as f64 / BINS as f64);
+                    let betas = (j as f64 / BINS as f64, (j + 1) as f64 / BINS as f64);
+                    expected.push(integrate_over_quad(&quad, origin, alphas, betas, |d| quad.pdf_value(origin, d, 0.0)));
+                }
+            }
+
+            let mut counts = vec![0; BINS * BINS];
+            let mut misses = 0;
+            for _ in 0..SAMPLES {
+                let direction = quad.random(origin, 0.0);
+                match quad.hit(&Ray::new(origin, direction), Interval::new(0.001, INFINITY)) {
+                    Some(rec) => {
+                        let i = ((rec.u * BINS as f64) as usize).min(BINS - 1);
+                        let j = ((rec.v * BINS as f64) as usize).min(BINS - 1);
+                        counts[i * BINS + j] += 1;
+                    }
+                    None => misses += 1,
+                }
+            }
+
+            assert!(misses <= SAMPLES / 10_000, "{}: {} samples missed the quad", name, misses);
+            assert_chi_square(name, &counts, &expected);
+        }
+    }
+}
@@ -0,0 +1,635 @@
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::MaterialRef;
+use super::onb::Onb;
+use super::ray::Ray;
+use super::rtweekend::{random_double, INFINITY, PI};
+use super::vec3::{dot, Point3, Vec3};
+
+// The quadrics below are built in a local frame with their axis along +z, placed in the
+// world by a base point and an axis vector whose length is the height.
+#[derive(Clone, Copy)]
+pub(super) struct Frame {
+    origin: Point3,
+    onb: Onb,
+}
+
+impl Frame {
+    pub(super) fn new(origin: Point3, axis: Vec3) -> Self {
+        Self { origin, onb: Onb::new(axis) }
+    }
+
+    pub(super) fn axis(&self) -> Vec3 {
+        self.onb.w()
+    }
+
+    pub(super) fn to_local_point(self, p: Point3) -> Point3 {
+        self.onb.to_local(p - self.origin)
+    }
+
+    pub(super) fn to_local(self, v: Vec3) -> Vec3 {
+        self.onb.to_local(v)
+    }
+
+    pub(super) fn to_world(self, v: Vec3) -> Vec3 {
+        self.onb.transform(v)
+    }
+
+    pub(super) fn to_world_point(self, p: Point3) -> Point3 {
+        self.origin + self.onb.transform(p)
+    }
+}
+
+// Bounds of a disk around `center` facing `normal`: along each world axis it reaches
+// radius * sin of the angle between that axis and the normal.
+pub(super) fn disk_bounds(center: Point3, normal: Vec3, radius: f64) -> Aabb {
+    let extent = |n: f64| radius * (1.0 - n * n).max(0.0).sqrt();
+    let half = Vec3::new(extent(normal.x()), extent(normal.y()), extent(normal.z()));
+    Aabb::from_points(center - half, center + half)
+}
+
+// Angle around the local z axis, as a texture coordinate in [0, 1).
+pub(super) fn azimuth(p: Point3) -> f64 {
+    let phi = p.y().atan2(p.x());
+    (if phi < 0.0 { phi + 2.0 * PI } else { phi }) / (2.0 * PI)
+}
+
+// How a local point moves as its azimuth goes once around the axis, and the unit
+// direction straight out from the axis through it (along x on the axis itself).
+pub(super) fn around_axis(p: Point3) -> Vec3 {
+    2.0 * PI * Vec3::new(-p.y(), p.x(), 0.0)
+}
+
+pub(super) fn away_from_axis(p: Point3) -> Vec3 {
+    let rho = (p.x() * p.x() + p.y() * p.y()).sqrt();
+    if rho > 0.0 { Vec3::new(p.x() / rho, p.y() / rho, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) }
+}
+
+// Where a ray meets a surface along its whole line, with the part of the surface each
+// parameter belongs to. No shape here has more than four crossings.
+#[derive(Clone, Copy)]
+pub(super) struct Roots {
+    roots: [(f64, u8); 4],
+    len: usize,
+}
+
+impl Roots {
+    pub(super) fn new() -> Self {
+        Self { roots: [(0.0, 0); 4], len: 0 }
+    }
+
+    pub(super) fn push(&mut self, t: f64, part: u8) {
+        if self.len < self.roots.len() {
+            self.roots[self.len] = (t, part);
+            self.len += 1;
+        }
+    }
+
+    pub(super) fn nearest(&self, ray_t: Interval) -> Option<(f64, u8)> {
+        self.roots[..self.len]
+            .iter()
+            .filter(|(t, _)| ray_t.surrounds(*t))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .copied()
+    }
+
+    pub(super) fn sorted(&self, ray_t: Interval) -> Vec<(f64, u8)> {
+        let mut roots: Vec<(f64, u8)> = self.roots[..self.len].iter().filter(|(t, _)| ray_t.surrounds(*t)).copied().collect();
+        roots.sort_by(|a, b| a.0.total_cmp(&b.0));
+        roots
+    }
+}
+
+// Solid angle density of sampling points uniformly over an object's area, seen from
+// `origin`. Every crossing along the direction counts, because uniform area sampling also
+// picks points that are hidden behind the object's own front surface.
+fn area_pdf<H: Hittable>(object: &H, area: f64, origin: Point3, direction: Vec3) -> f64 {
+    let r = Ray::new(origin, direction);
+    object
+        .crossings(&r, Interval::new(0.001, INFINITY))
+        .iter()
+        .map(|rec| {
+            let distance_squared = rec.t * rec.t * direction.length_squared();
+            let cosine = dot(direction, rec.normal).abs() / direction.length();
+            distance_squared / (cosine * area)
+        })
+        .sum()
+}
+
+// Point in an annulus between `inner` and `outer`, uniform by area.
+fn sample_annulus(inner: f64, outer: f64) -> (f64, f64) {
+    let radius = (inner * inner + random_double() * (outer * outer - inner * inner)).sqrt();
+    let phi = 2.0 * PI * random_double();
+    (radius * phi.cos(), radius * phi.sin())
+}
+
+const SIDE: u8 = 0;
+const BOTTOM: u8 = 1;
+const TOP: u8 = 2;
+
+// Hits on the disk of `radius` at local height z, for a capped cylinder or cone.
+fn cap_root(roots: &mut Roots, o: Point3, d: Vec3, z: f64, radius: f64, part: u8) {
+    if d.z() == 0.0 || radius <= 0.0 {
+        return;
+    }
+    let t = (z - o.z()) / d.z();
+    let x = o.x() + t * d.x();
+    let y = o.y() + t * d.y();
+    if x * x + y * y <= radius * radius {
+        roots.push(t, part);
+    }
+}
+
+fn cap_record(
+    r: &Ray,
+    t: f64,
+    frame: &Frame,
+    local: Point3,
+    outward: f64,
+    radius: f64,
+    mat: &MaterialRef,
+) -> HitRecord {
+    let outward_normal = outward * frame.axis();
+    let v = (local.x() * local.x() + local.y() * local.y()).sqrt() / radius;
+    let dpdu = frame.to_world(around_axis(local));
+    let dpdv = frame.to_world(radius * away_from_axis(local));
+    HitRecord::new(r.at(t), t, r, outward_normal, mat.clone(), azimuth(local), v).with_derivatives(dpdu, dpdv)
+}
+
+// A flat disk, or an annulus when `inner_radius` is above zero. The v texture coordinate
+// runs from the inner edge out.
+pub struct Disk {
+    frame: Frame,
+    inner_radius: f64,
+    radius: f64,
+    mat: MaterialRef,
+    bbox: Aabb,
+    area: f64,
+}
+
+impl Disk {
+    pub fn new(center: Point3, normal: Vec3, radius: f64, mat: MaterialRef) -> Self {
+        Self::new_annulus(center, normal, 0.0, radius, mat)
+    }
+
+    pub fn new_annulus(center: Point3, normal: Vec3, inner_radius: f64, radius: f64, mat: MaterialRef) -> Self {
+        let frame = Frame::new(center, normal);
+        let inner_radius = inner_radius.clamp(0.0, radius);
+        Self {
+            frame,
+            inner_radius,
+            radius,
+            mat,
+            bbox: disk_bounds(center, frame.axis(), radius),
+            area: PI * (radius * radius - inner_radius * inner_radius),
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let o = self.frame.to_local_point(r.origin());
+        let d = self.frame.to_local(r.direction());
+        if d.z().abs() < 1e-8 {
+            return None;
+        }
+
+        let t = -o.z() / d.z();
+        if !ray_t.contains(t) {
+            return None;
+        }
+
+        let x = o.x() + t * d.x();
+        let y = o.y() + t * d.y();
+        let dist = (x * x + y * y).sqrt();
+        if dist > self.radius || dist < self.inner_radius {
+            return None;
+        }
+
+        let v = (dist - self.inner_radius) / (self.radius - self.inner_radius);
+        let local = Point3::new(x, y, 0.0);
+        let dpdu = self.frame.to_world(around_axis(local));
+        let dpdv = self.frame.to_world((self.radius - self.inner_radius) * away_from_axis(local));
+        let rec = HitRecord::new(r.at(t), t, r, self.frame.axis(), self.mat.clone(), azimuth(local), v);
+        Some(rec.with_derivatives(dpdu, dpdv))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3, _time: f64) -> f64 {
+        let Some(rec) = self.hit(&Ray::new(origin, direction), Interval::new(0.001, INFINITY)) else {
+            return 0.0;
+        };
+
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = dot(direction, rec.normal).abs() / direction.length();
+
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: Point3, _time: f64) -> Vec3 {
+        let (x, y) = sample_annulus(self.inner_radius, self.radius);
+        self.frame.to_world_point(Point3::new(x, y, 0.0)) - origin
+    }
+}
+
+// A cylinder from `base` to `base + axis`, open or closed with caps. Around the side, u
+// follows the angle and v the height; on the caps v is the distance from the axis.
+pub struct Cylinder {
+    frame: Frame,
+    radius: f64,
+    height: f64,
+    capped: bool,
+    mat: MaterialRef,
+    bbox: Aabb,
+    side_area: f64,
+    area: f64,
+}
+
+impl Cylinder {
+    pub fn new(base: Point3, axis: Vec3, radius: f64, capped: bool, mat: MaterialRef) -> Self {
+        let frame = Frame::new(base, axis);
+        let height = axis.length();
+        let bbox = Aabb::from_boxes(
+            disk_bounds(base, frame.axis(), radius),
+            disk_bounds(base + axis, frame.axis(), radius),
+        );
+        let side_area = 2.0 * PI * radius * height;
+        let area = side_area + if capped { 2.0 * PI * radius * radius } else { 0.0 };
+        Self { frame, radius, height, capped, mat, bbox, side_area, area }
+    }
+
+    fn roots(&self, r: &Ray) -> Roots {
+        let o = self.frame.to_local_point(r.origin());
+        let d = self.frame.to_local(r.direction());
+        let mut roots = Roots::new();
+
+        let a = d.x() * d.x() + d.y() * d.y();
+        if a > 0.0 {
+            let h = o.x() * d.x() + o.y() * d.y();
+            let c = o.x() * o.x() + o.y() * o.y() - self.radius * self.radius;
+            let discriminant = h * h - a * c;
+            if discriminant >= 0.0 {
+                let sqrtd = discriminant.sqrt();
+                for t in [(-h - sqrtd) / a, (-h + sqrtd) / a] {
+                    let z = o.z() + t * d.z();
+                    if (0.0..=self.height).contains(&z) {
+                        roots.push(t, SIDE);
+                    }
+                }
+            }
+        }
+
+        if self.capped {
+            cap_root(&mut roots, o, d, 0.0, self.radius, BOTTOM);
+            cap_root(&mut roots, o, d, self.height, self.radius, TOP);
+        }
+        roots
+    }
+
+    fn record(&self, r: &Ray, t: f64, part: u8) -> HitRecord {
+        let local = self.frame.to_local_point(r.at(t));
+        match part {
+            BOTTOM => cap_record(r, t, &self.frame, local, -1.0, self.radius, &self.mat),
+            TOP => cap_record(r, t, &self.frame, local, 1.0, self.radius, &self.mat),
+            _ => {
+                let outward_normal = self.frame.to_world(Vec3::new(local.x(), local.y(), 0.0) / self.radius);
+                let v = local.z() / self.height;
+                let dpdu = self.frame.to_world(around_axis(local));
+                let dpdv = self.frame.to_world(Vec3::new(0.0, 0.0, self.height));
+                HitRecord::new(r.at(t), t, r, outward_normal, self.mat.clone(), azimuth(local), v)
+                    .with_derivatives(dpdu, dpdv)
+            }
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let (t, part) = self.roots(r).nearest(ray_t)?;
+        Some(self.record(r, t, part))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        self.roots(r).sorted(ray_t).into_iter().map(|(t, part)| self.record(r, t, part)).collect()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3, _time: f64) -> f64 {
+        area_pdf(self, self.area, origin, direction)
+    }
+
+    fn random(&self, origin: Point3, _time: f64) -> Vec3 {
+        let pick = random_double() * self.area;
+        let local = if pick < self.side_area {
+            let phi = 2.0 * PI * random_double();
+            Point3::new(self.radius * phi.cos(), self.radius * phi.sin(), self.height * random_double())
+        } else {
+            let (x, y) = sample_annulus(0.0, self.radius);
+            let z = if pick < self.side_area + 0.5 * (self.area - self.side_area) { 0.0 } else { self.height };
+            Point3::new(x, y, z)
+        };
+        self.frame.to_world_point(local) - origin
+    }
+}
+
+// A cone frustum from a circle of `base_radius` at `base` to one of `top_radius` at
+// `base + axis`; a top radius of zero gives a pointed cone. Caps close any end with a
+// non-zero radius. Texture coordinates follow the cylinder's.
+pub struct Cone {
+    frame: Frame,
+    base_radius: f64,
+    top_radius: f64,
+    height: f64,
+    capped: bool,
+    mat: MaterialRef,
+    bbox: Aabb,
+}
+
+impl Cone {
+    pub fn new(base: Point3, axis: Vec3, base_radius: f64, top_radius: f64, capped: bool, mat: MaterialRef) -> Self {
+        let frame = Frame::new(base, axis);
+        let bbox = Aabb::from_boxes(
+            disk_bounds(base, frame.axis(), base_radius),
+            disk_bounds(base + axis, frame.axis(), top_radius),
+        );
+        Self { frame, base_radius, top_radius, height: axis.length(), capped, mat, bbox }
+    }
+
+    // Rate at which the radius changes with height.
+    fn slope(&self) -> f64 {
+        (self.top_radius - self.base_radius) / self.height
+    }
+
+    fn roots(&self, r: &Ray) -> Roots {
+        let o = self.frame.to_local_point(r.origin());
+        let d = self.frame.to_local(r.direction());
+        let mut roots = Roots::new();
+
+        // x^2 + y^2 = (r0 + s z)^2 along the ray.
+        let s = self.slope();
+        let radius_at_origin = self.base_radius + s * o.z();
+        let a = d.x() * d.x() + d.y() * d.y() - s * s * d.z() * d.z();
+        let h = o.x() * d.x() + o.y() * d.y() - s * radius_at_origin * d.z();
+        let c = o.x() * o.x() + o.y() * o.y() - radius_at_origin * radius_at_origin;
+
+        let mut side = |t: f64| {
+            let z = o.z() + t * d.z();
+            if (0.0..=self.height).contains(&z) {
+                roots.push(t, SIDE);
+            }
+        };
+        if a.abs() < 1e-12 {
+            if h != 0.0 {
+                side(-c / (2.0 * h));
+            }
+        } else {
+            let discriminant = h * h - a * c;
+            if discriminant >= 0.0 {
+                let sqrtd = discriminant.sqrt();
+                side((-h - sqrtd) / a);
+                side((-h + sqrtd) / a);
+            }
+        }
+
+        if self.capped {
+            cap_root(&mut roots, o, d, 0.0, self.base_radius, BOTTOM);
+            cap_root(&mut roots, o, d, self.height, self.top_radius, TOP);
+        }
+        roots
+    }
+
+    fn record(&self, r: &Ray, t: f64, part: u8) -> HitRecord {
+        let local = self.frame.to_local_point(r.at(t));
+        match part {
+            BOTTOM => cap_record(r, t, &self.frame, local, -1.0, self.base_radius, &self.mat),
+            TOP => cap_record(r, t, &self.frame, local, 1.0, self.top_radius, &self.mat),
+            _ => {
+                let s = self.slope();
+                let gradient = Vec3::new(local.x(), local.y(), -s * (self.base_radius + s * local.z()));
+                let outward_normal = self.frame.to_world(gradient / gradient.length());
+                let v = local.z() / self.height;
+                let dpdu = self.frame.to_world(around_axis(local));
+                let dpdv = self.frame.to_world(self.height * (s * away_from_axis(local) + Vec3::new(0.0, 0.0, 1.0)));
+                HitRecord::new(r.at(t), t, r, outward_normal, self.mat.clone(), azimuth(local), v)
+                    .with_derivatives(dpdu, dpdv)
+            }
+        }
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let (t, part) = self.roots(r).nearest(ray_t)?;
+        Some(self.record(r, t, part))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        self.roots(r).sorted(ray_t).into_iter().map(|(t, part)| self.record(r, t, part)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::material::{make_mat, EmptyMaterial, Metal, ShadingNormal};
+    use crate::books::the_rest_of_your_life::rtweekend::seed_random;
+    use crate::books::the_rest_of_your_life::test_support::{
+        assert_chi_square, assert_derivatives_follow_uvs, assert_sampler_matches_pdf, integrate_directions,
+    };
+    use crate::books::the_rest_of_your_life::texture::{make_tex, SolidColor};
+    use crate::books::the_rest_of_your_life::vec3::{reflect, unit_vector, Color};
+
+    fn disk() -> Disk {
+        Disk::new(Point3::new(1.0, 2.0, 0.5), Vec3::new(0.3, 1.0, -0.2), 1.5, make_mat(EmptyMaterial))
+    }
+
+    fn annulus() -> Disk {
+        Disk::new_annulus(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 0.5, 2.0, make_mat(EmptyMaterial))
+    }
+
+    fn cylinder(capped: bool) -> Cylinder {
+        Cylinder::new(Point3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 2.0, 1.0), 0.75, capped, make_mat(EmptyMaterial))
+    }
+
+    // Each shape with a point it is seen from.
+    fn views() -> Vec<(&'static str, Box<dyn Hittable>, Point3)> {
+        vec![
+            ("disk", Box::new(disk()), Point3::new(-2.0, 6.0, 3.0)),
+            ("disk seen nearly edge on", Box::new(disk()), Point3::new(8.0, 1.9, -6.0)),
+            ("annulus", Box::new(annulus()), Point3::new(0.5, -0.3, 1.0)),
+            ("capped cylinder", Box::new(cylinder(true)), Point3::new(3.0, 0.5, -2.0)),
+            ("open cylinder", Box::new(cylinder(false)), Point3::new(3.0, 0.5, -2.0)),
+            ("inside a capped cylinder", Box::new(cylinder(true)), Point3::new(0.1, 0.0, 0.5)),
+        ]
+    }
+
+    #[test]
+    fn pdfs_integrate_to_one() {
+        seed_random(golden::SEED);
+        for (name, shape, origin) in views() {
+            let total = integrate_directions(600, |d| shape.pdf_value(origin, d, 0.0));
+            assert!((total - 1.0).abs() < 0.01, "{}: pdf integrates to {}", name, total);
+        }
+    }
+
+    #[test]
+    fn disk_samples_follow_pdf() {
+        seed_random(golden::SEED);
+        for (name, shape, origin) in views().into_iter().take(3) {
+            assert_sampler_matches_pdf(name, || shape.random(origin, 0.0), |d| shape.pdf_value(origin, d, 0.0));
+        }
+    }
+
+    // Seen from outside, the cylinder's density grows without bound towards its silhouette,
+    // which binning by direction can't integrate accurately. The points it samples should
+    // instead be spread evenly over its surface.
+    #[test]
+    fn cylinder_samples_cover_the_surface_evenly() {
+        const BANDS: usize = 8;
+        const SECTORS: usize = 8;
+        const SAMPLES: usize = 100_000;
+        seed_random(golden::SEED);
+
+        let cylinder = cylinder(true);
+        let origin = Point3::new(3.0, 0.5, -2.0);
+        // Bins over the side by height and angle, then each cap by angle.
+        let mut counts = vec![0; (BANDS + 2) * SECTORS];
+        for _ in 0..SAMPLES {
+            let local = cylinder.frame.to_local_point(origin + cylinder.random(origin, 0.0));
+            let sector = ((azimuth(local) * SECTORS as f64) as usize).min(SECTORS - 1);
+            let on_cap = |z: f64| (local.z() - z).abs() < 1e-9 && local.x().hypot(local.y()) <= cylinder.radius + 1e-9;
+            let row = if on_cap(0.0) {
+                BANDS
+            } else if on_cap(cylinder.height) {
+                BANDS + 1
+            } else {
+                let distance = local.x().hypot(local.y());
+                assert!((distance - cylinder.radius).abs() < 1e-9, "sampled {:?} off the surface", local);
+                ((local.z() / cylinder.height * BANDS as f64) as usize).min(BANDS - 1)
+            };
+            counts[row * SECTORS + sector] += 1;
+        }
+
+        let cap_area = PI * cylinder.radius * cylinder.radius;
+        let mut expected = vec![cylinder.side_area / (BANDS * SECTORS) as f64 / cylinder.area; BANDS * SECTORS];
+        expected.extend(vec![cap_area / SECTORS as f64 / cylinder.area; 2 * SECTORS]);
+        assert_chi_square("capped cylinder", &counts, &expected);
+    }
+
+    // A ray from straight above `p` on a shape, along `normal`.
+    fn hit_at<H: Hittable>(shape: &H, p: Point3, normal: Vec3) -> HitRecord {
+        let r = Ray::new(p + normal, -normal);
+        let rec = shape.hit(&r, Interval::new(0.001, INFINITY)).expect("hits the shape");
+        assert!((rec.p - p).length() < 1e-9, "hits {:?} instead of {:?}", rec.p, p);
+        rec
+    }
+
+    // u runs once around the axis, clockwise seen from its tip, following the frame's basis.
+    fn assert_quarter_turn(name: &str, u0: f64, u1: f64) {
+        let step = (u0 - u1).rem_euclid(1.0);
+        assert!((step - 0.25).abs() < 1e-9, "{}: a quarter turn moves u from {} to {}", name, u0, u1);
+    }
+
+    #[test]
+    fn disk_uvs() {
+        let annulus = annulus();
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        for (radius, v) in [(0.5, 0.0), (1.25, 0.5), (2.0 - 1e-9, 1.0)] {
+            let rec = hit_at(&annulus, Point3::new(radius, 0.0, 0.0), up);
+            assert!((rec.v - v).abs() < 1e-6, "v {} at radius {}", rec.v, radius);
+        }
+        let u0 = hit_at(&annulus, Point3::new(1.0, 0.0, 0.0), up).u;
+        let u1 = hit_at(&annulus, Point3::new(0.0, 1.0, 0.0), up).u;
+        assert_quarter_turn("annulus", u0, u1);
+        assert!(annulus.hit(&Ray::new(Point3::new(0.2, 0.0, 1.0), -up), Interval::new(0.001, INFINITY)).is_none());
+    }
+
+    #[test]
+    fn cylinder_uvs() {
+        let cylinder = Cylinder::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 4.0), 2.0, true, make_mat(EmptyMaterial));
+        let side = |x: f64, y: f64, z: f64| hit_at(&cylinder, Point3::new(x, y, z), unit_vector(Vec3::new(x, y, 0.0)));
+        for (z, v) in [(0.5, 0.125), (2.0, 0.5), (3.0, 0.75)] {
+            let rec = side(2.0, 0.0, z);
+            assert!((rec.v - v).abs() < 1e-9, "side v {} at height {}", rec.v, z);
+        }
+        assert_quarter_turn("side", side(2.0, 0.0, 1.0).u, side(0.0, 2.0, 1.0).u);
+
+        let up = Vec3::new(0.0, 0.0, 1.0);
+        let top = hit_at(&cylinder, Point3::new(1.0, 0.0, 4.0), up);
+        assert!((top.v - 0.5).abs() < 1e-9 && top.front_face, "top cap {:?}", (top.v, top.front_face));
+        assert_quarter_turn("top", top.u, hit_at(&cylinder, Point3::new(0.0, 1.0, 4.0), up).u);
+        let bottom = hit_at(&cylinder, Point3::new(0.0, -1.5, 0.0), -up);
+        assert!((bottom.v - 0.75).abs() < 1e-9 && bottom.front_face, "bottom cap {:?}", (bottom.v, bottom.front_face));
+    }
+
+    #[test]
+    fn rays_along_the_axis_cross_both_caps() {
+        let cylinder = cylinder(true);
+        let axis = Vec3::new(0.0, 2.0, 1.0);
+        let base = Point3::new(0.0, -1.0, 0.0);
+        let r = Ray::new(base - axis, axis);
+        let crossings = cylinder.crossings(&r, Interval::new(0.001, INFINITY));
+        assert_eq!(crossings.len(), 2);
+        assert!((crossings[0].t - 1.0).abs() < 1e-9 && crossings[0].front_face);
+        assert!((crossings[1].t - 2.0).abs() < 1e-9 && !crossings[1].front_face);
+
+        // Without caps the same ray goes straight through.
+        assert!(self::cylinder(false).hit(&r, Interval::new(0.001, INFINITY)).is_none());
+    }
+
+    #[test]
+    fn derivatives_follow_the_uvs() {
+        let cone = Cone::new(Point3::new(0.5, 0.0, -1.0), Vec3::new(0.0, 1.0, 2.0), 1.0, 0.4, true, make_mat(EmptyMaterial));
+        let shapes: Vec<(&str, Box<dyn Hittable>)> = vec![
+            ("disk", Box::new(disk())),
+            ("annulus", Box::new(annulus())),
+            ("capped cylinder", Box::new(cylinder(true))),
+            ("capped cone", Box::new(cone)),
+        ];
+        let origins = [Point3::new(4.0, 3.0, 2.5), Point3::new(-3.0, -4.0, 1.0), Point3::new(0.5, 1.0, -5.0)];
+        for (name, shape) in &shapes {
+            let target = shape.bounding_box();
+            let middle = |axis| 0.5 * (target.axis_interval(axis).min + target.axis_interval(axis).max);
+            let center = Point3::new(middle(0), middle(1), middle(2));
+            for origin in origins {
+                for offset in [Vec3::new(0.07, 0.05, -0.11), Vec3::new(0.21, -0.13, 0.17), Vec3::new(-0.3, 0.25, -0.1)] {
+                    let r = Ray::new(origin, center + offset - origin);
+                    if let Some(rec) = shape.hit(&r, Interval::new(0.001, INFINITY)) {
+                        assert_derivatives_follow_uvs(name, shape.as_ref(), &rec);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn normal_maps_bend_the_normal_on_a_cylinder() {
+        // A mirror whose normal map leans every normal towards +u: (0.6, 0, 0.8) in tangent space.
+        let map = make_tex(SolidColor::new(Color::new(0.8, 0.5, 0.9)));
+        let mirror = make_mat(ShadingNormal::normal_map(make_mat(Metal::new(Color::new(1.0, 1.0, 1.0), 0.0)), map));
+        let cylinder = Cylinder::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 4.0), 2.0, false, mirror.clone());
+
+        // Head on at (2, 0, 1), where u runs around the axis along y one way or the other.
+        let r = Ray::new(Point3::new(3.0, 0.0, 1.0), Vec3::new(-1.0, 0.0, 0.0));
+        let rec = cylinder.hit(&r, Interval::new(0.001, INFINITY)).expect("hits the side");
+        let around = unit_vector(rec.dpdu);
+        assert!((around.y().abs() - 1.0).abs() < 1e-9, "u runs along {:?}", around);
+
+        let srec = mirror.scatter(&r, &rec).expect("the mirror reflects");
+        let expected = reflect(r.direction(), 0.6 * around + 0.8 * rec.normal);
+        let reflected = unit_vector(srec.skip_pdf_ray.direction());
+        assert!((reflected - expected).length() < 1e-9, "reflects along {:?}, expected {:?}", reflected, expected);
+    }
+}
@@ -1,19 +1,40 @@
 use super::vec3::{Point3, Vec3};
 
+// The cone of rays a single ray stands in for, so a hit can tell how much of the surface
+// it covers. `width` is the cone's width at the ray origin and `spread` how fast it grows
+// per unit of distance travelled. Camera rays cover a pixel.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RayCone {
+    pub width: f64,
+    pub spread: f64,
+}
+
+impl RayCone {
+    pub fn width_at(self, distance: f64) -> f64 {
+        self.width + self.spread * distance
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Ray {
     orig: Point3,
     dir: Vec3,
     tm: f64,
+    cone: RayCone,
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Self {
-        Self { orig: origin, dir: direction, tm: 0.0 }
+        Self { orig: origin, dir: direction, tm: 0.0, cone: RayCone::default() }
     }
 
     pub fn new_with_time(origin: Point3, direction: Vec3, time: f64) -> Self {
-        Self { orig: origin, dir: direction, tm: time }
+        Self { orig: origin, dir: direction, tm: time, cone: RayCone::default() }
+    }
+
+    pub fn with_cone(mut self, cone: RayCone) -> Self {
+        self.cone = cone;
+        self
     }
 
     pub fn origin(self) -> Point3 {
@@ -28,6 +49,10 @@ impl Ray {
         self.tm
     }
 
+    pub fn cone(self) -> RayCone {
+        self.cone
+    }
+
     pub fn at(self, t: f64) -> Point3 {
         self.orig + t * self.dir
     }
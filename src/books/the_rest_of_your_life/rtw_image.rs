@@ -1,15 +1,39 @@
 use std::env;
 use std::path::{Path, PathBuf};
 
+use image::DynamicImage;
+
+// How the values stored in an image relate to light.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    // Gamma encoded, as 8 and 16-bit photographs and paintings usually are.
+    Srgb,
+    // Proportional to light, as in HDR images, or plain data such as normals or roughness.
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn to_linear(self, value: f32) -> f32 {
+        match self {
+            ColorSpace::Srgb if value <= 0.04045 => value / 12.92,
+            ColorSpace::Srgb => ((value + 0.055) / 1.055).powf(2.4),
+            ColorSpace::Linear => value,
+        }
+    }
+}
+
+// An image kept at full precision, with channels from 0 to 1 for 8 and 16-bit files and
+// unbounded for HDR ones.
 pub struct RtwImage {
-    data: Vec<u8>,
+    data: Vec<f32>,
     width: i32,
     height: i32,
+    color_space: ColorSpace,
 }
 
 impl RtwImage {
     pub fn new(image_filename: &str) -> Self {
-        let mut image = Self { data: Vec::new(), width: 0, height: 0 };
+        let mut image = Self { data: Vec::new(), width: 0, height: 0, color_space: ColorSpace::Srgb };
 
         if let Ok(imagedir) = env::var("RTW_IMAGES") {
             let candidate = Path::new(&imagedir).join(image_filename);
@@ -43,9 +67,21 @@ impl RtwImage {
         self.height
     }
 
-    pub fn pixel_data(&self, x: i32, y: i32) -> [u8; 3] {
+    // Floating point files are taken to be linear and the rest sRGB.
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    // Overrides the guess from the file, as for data maps saved as ordinary images.
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    // The values stored for the pixel, still in the image's color space.
+    pub fn pixel(&self, x: i32, y: i32) -> [f32; 3] {
         if self.data.is_empty() {
-            return [255, 0, 255];
+            return [1.0, 0.0, 1.0];
         }
 
         let x = clamp(x, 0, self.width);
@@ -54,12 +90,20 @@ impl RtwImage {
         [self.data[idx], self.data[idx + 1], self.data[idx + 2]]
     }
 
+    pub fn linear_pixel(&self, x: i32, y: i32) -> [f32; 3] {
+        self.pixel(x, y).map(|c| self.color_space.to_linear(c))
+    }
+
     fn load(&mut self, filename: &Path) -> bool {
         let Ok(img) = image::open(filename) else {
             return false;
         };
 
-        let rgb = img.to_rgb8();
+        self.color_space = match img {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => ColorSpace::Linear,
+            _ => ColorSpace::Srgb,
+        };
+        let rgb = img.to_rgb32f();
         self.width = rgb.width() as i32;
         self.height = rgb.height() as i32;
         self.data = rgb.into_raw();
@@ -76,3 +120,67 @@ fn clamp(x: i32, low: i32, high: i32) -> i32 {
         high - 1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    // Somewhere to write a test image, unique to this run.
+    fn scratch_file(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rtw_image_{}_{}", std::process::id(), name))
+    }
+
+    fn load(path: &Path) -> RtwImage {
+        let image = RtwImage::new(path.to_str().expect("temporary paths are valid unicode"));
+        std::fs::remove_file(path).ok();
+        image
+    }
+
+    #[test]
+    fn srgb_decoding_follows_the_standard_curve() {
+        assert_eq!(ColorSpace::Srgb.to_linear(0.0), 0.0);
+        assert!((ColorSpace::Srgb.to_linear(0.04045) - 0.04045 / 12.92).abs() < 1e-7);
+        assert!((ColorSpace::Srgb.to_linear(1.0) - 1.0).abs() < 1e-6);
+        // Both pieces of the curve meet at the threshold.
+        assert!((ColorSpace::Srgb.to_linear(0.04046) - ColorSpace::Srgb.to_linear(0.04045)).abs() < 1e-6);
+        assert_eq!(ColorSpace::Linear.to_linear(0.5), 0.5);
+    }
+
+    #[test]
+    fn sixteen_bit_pngs_keep_their_fine_steps() {
+        let path = scratch_file("sixteen_bit.png");
+        let values = [100u16, 101, 65535, 0, 32768, 32769];
+        ImageBuffer::<Rgb<u16>, _>::from_raw(2, 1, values.to_vec()).unwrap().save(&path).unwrap();
+
+        let image = load(&path);
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.color_space(), ColorSpace::Srgb);
+        let pixels = [image.pixel(0, 0), image.pixel(1, 0)].concat();
+        for (&value, &stored) in values.iter().zip(&pixels) {
+            assert!((stored - value as f32 / 65535.0).abs() < 1e-7, "{} came back as {}", value, stored);
+        }
+        // Neighboring 16-bit values are far closer together than 8-bit steps.
+        assert!(pixels[1] > pixels[0] && pixels[1] - pixels[0] < 1.0 / 255.0);
+    }
+
+    #[test]
+    fn hdr_files_keep_values_above_one() {
+        let path = scratch_file("bright.hdr");
+        let pixels = [[12.5f32, 1.5, 0.25], [0.003, 1.0, 200.0]];
+        ImageBuffer::<Rgb<f32>, _>::from_raw(2, 1, pixels.concat()).unwrap().save(&path).unwrap();
+
+        let image = load(&path);
+        assert_eq!(image.color_space(), ColorSpace::Linear);
+        for (x, expected) in pixels.iter().enumerate() {
+            // RGBE keeps about 8 bits of mantissa, shared by the three channels of a pixel.
+            let brightest = expected.iter().copied().fold(0.0, f32::max);
+            let stored = image.pixel(x as i32, 0);
+            for c in 0..3 {
+                assert!((stored[c] - expected[c]).abs() <= brightest / 128.0, "{:?} came back as {:?}", expected, stored);
+            }
+        }
+        assert!(image.pixel(0, 0)[0] > 12.0 && image.pixel(1, 0)[2] > 190.0);
+        assert_eq!(image.linear_pixel(1, 0), image.pixel(1, 0));
+    }
+}
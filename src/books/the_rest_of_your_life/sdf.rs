@@ -0,0 +1,427 @@
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::MaterialRef;
+use super::ray::Ray;
+use super::rtweekend::{INFINITY, PI};
+use super::vec3::{unit_vector, Point3, Vec3};
+
+// A signed distance function built as a tree of primitives and operators. Distances are
+// negative inside. Operators that bend space (twist) or blend shapes only estimate the
+// distance, which the tracer makes up for by taking shorter steps.
+pub enum Sdf {
+    Sphere { radius: f64 },
+    RoundedBox { half_size: Vec3, rounding: f64 },
+    // A ring around the y axis.
+    Torus { major_radius: f64, minor_radius: f64 },
+    // The power-8 Mandelbulb and its relatives, about 1.2 across. A distance estimate
+    // only, positive everywhere.
+    Mandelbulb { power: f64, iterations: u32 },
+    Translate { sdf: Box<Sdf>, offset: Vec3 },
+    Scale { sdf: Box<Sdf>, factor: f64 },
+    Union(Box<Sdf>, Box<Sdf>),
+    Intersection(Box<Sdf>, Box<Sdf>),
+    Difference(Box<Sdf>, Box<Sdf>),
+    SmoothUnion { a: Box<Sdf>, b: Box<Sdf>, k: f64 },
+    // Copies of the shape every `period` along each axis with a nonzero period, at most
+    // `limit` cells either side of the origin. The shape should fit inside one cell.
+    Repeat { sdf: Box<Sdf>, period: Vec3, limit: Vec3 },
+    // Rotates each slice of the shape about the y axis by `rate` radians per unit height.
+    Twist { sdf: Box<Sdf>, rate: f64 },
+}
+
+impl Sdf {
+    pub fn sphere(radius: f64) -> Self {
+        Sdf::Sphere { radius }
+    }
+
+    pub fn rounded_box(half_size: Vec3, rounding: f64) -> Self {
+        Sdf::RoundedBox { half_size, rounding }
+    }
+
+    pub fn torus(major_radius: f64, minor_radius: f64) -> Self {
+        Sdf::Torus { major_radius, minor_radius }
+    }
+
+    pub fn mandelbulb(power: f64, iterations: u32) -> Self {
+        Sdf::Mandelbulb { power, iterations }
+    }
+
+    pub fn translate(self, offset: Vec3) -> Self {
+        Sdf::Translate { sdf: Box::new(self), offset }
+    }
+
+    pub fn scale(self, factor: f64) -> Self {
+        Sdf::Scale { sdf: Box::new(self), factor }
+    }
+
+    pub fn union(self, other: Sdf) -> Self {
+        Sdf::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersection(self, other: Sdf) -> Self {
+        Sdf::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn difference(self, other: Sdf) -> Self {
+        Sdf::Difference(Box::new(self), Box::new(other))
+    }
+
+    // Blends the two shapes over a seam about `k` wide.
+    pub fn smooth_union(self, other: Sdf, k: f64) -> Self {
+        Sdf::SmoothUnion { a: Box::new(self), b: Box::new(other), k }
+    }
+
+    pub fn repeat(self, period: Vec3) -> Self {
+        self.repeat_limited(period, Vec3::new(INFINITY, INFINITY, INFINITY))
+    }
+
+    pub fn repeat_limited(self, period: Vec3, limit: Vec3) -> Self {
+        Sdf::Repeat { sdf: Box::new(self), period, limit }
+    }
+
+    pub fn twist(self, rate: f64) -> Self {
+        Sdf::Twist { sdf: Box::new(self), rate }
+    }
+
+    pub fn distance(&self, p: Point3) -> f64 {
+        match self {
+            Sdf::Sphere { radius } => p.length() - radius,
+            Sdf::RoundedBox { half_size, rounding } => {
+                let q = Vec3::new(p.x().abs(), p.y().abs(), p.z().abs()) - *half_size
+                    + Vec3::new(*rounding, *rounding, *rounding);
+                let outside = Vec3::new(q.x().max(0.0), q.y().max(0.0), q.z().max(0.0));
+                outside.length() + q.x().max(q.y()).max(q.z()).min(0.0) - rounding
+            }
+            Sdf::Torus { major_radius, minor_radius } => {
+                let ring = (p.x() * p.x() + p.z() * p.z()).sqrt() - major_radius;
+                (ring * ring + p.y() * p.y()).sqrt() - minor_radius
+            }
+            Sdf::Mandelbulb { power, iterations } => mandelbulb(p, *power, *iterations),
+            Sdf::Translate { sdf, offset } => sdf.distance(p - *offset),
+            Sdf::Scale { sdf, factor } => sdf.distance(p / *factor) * factor,
+            Sdf::Union(a, b) => a.distance(p).min(b.distance(p)),
+            Sdf::Intersection(a, b) => a.distance(p).max(b.distance(p)),
+            Sdf::Difference(a, b) => a.distance(p).max(-b.distance(p)),
+            Sdf::SmoothUnion { a, b, k } => {
+                let (da, db) = (a.distance(p), b.distance(p));
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+                db + (da - db) * h - k * h * (1.0 - h)
+            }
+            Sdf::Repeat { sdf, period, limit } => {
+                let mut q = p;
+                for axis in 0..3 {
+                    if period[axis] > 0.0 {
+                        let cell = (p[axis] / period[axis]).round().clamp(-limit[axis], limit[axis]);
+                        q[axis] = p[axis] - period[axis] * cell;
+                    }
+                }
+                sdf.distance(q)
+            }
+            Sdf::Twist { sdf, rate } => {
+                let (sin_a, cos_a) = (rate * p.y()).sin_cos();
+                sdf.distance(Point3::new(cos_a * p.x() + sin_a * p.z(), p.y(), -sin_a * p.x() + cos_a * p.z()))
+            }
+        }
+    }
+
+    // An upper bound on how fast the distance can change for points within `bounds`. An
+    // exact distance has a bound of one.
+    fn lipschitz(&self, bounds: Aabb) -> f64 {
+        let axes = [bounds.axis_interval(0), bounds.axis_interval(1), bounds.axis_interval(2)];
+        match self {
+            Sdf::Sphere { .. } | Sdf::RoundedBox { .. } | Sdf::Torus { .. } | Sdf::Mandelbulb { .. } => 1.0,
+            Sdf::Translate { sdf, offset } => {
+                let shift = |axis: usize| Interval::new(axes[axis].min - offset[axis], axes[axis].max - offset[axis]);
+                sdf.lipschitz(Aabb::new(shift(0), shift(1), shift(2)))
+            }
+            Sdf::Scale { sdf, factor } => {
+                let [x, y, z] = axes.map(|a| Interval::new(a.min / factor, a.max / factor));
+                sdf.lipschitz(Aabb::new(x, y, z))
+            }
+            Sdf::Union(a, b)
+            | Sdf::Intersection(a, b)
+            | Sdf::Difference(a, b)
+            | Sdf::SmoothUnion { a, b, .. } => a.lipschitz(bounds).max(b.lipschitz(bounds)),
+            Sdf::Repeat { sdf, period, .. } => {
+                let mut cell = axes;
+                for (axis, interval) in cell.iter_mut().enumerate() {
+                    if period[axis] > 0.0 {
+                        *interval = Interval::new(-period[axis] / 2.0, period[axis] / 2.0);
+                    }
+                }
+                sdf.lipschitz(Aabb::new(cell[0], cell[1], cell[2]))
+            }
+            // A twist shears each slice sideways by up to `rate` times its distance from
+            // the axis per unit height.
+            Sdf::Twist { sdf, rate } => {
+                let far = |a: Interval| a.min.abs().max(a.max.abs());
+                let radius = (far(axes[0]).powi(2) + far(axes[2]).powi(2)).sqrt();
+                let slice = Interval::new(-radius, radius);
+                sdf.lipschitz(Aabb::new(slice, axes[1], slice)) * (1.0 + rate.abs() * radius)
+            }
+        }
+    }
+}
+
+fn mandelbulb(p: Point3, power: f64, iterations: u32) -> f64 {
+    let mut z = p;
+    let mut dr = 1.0;
+    let mut r = z.length();
+    for _ in 0..iterations {
+        if r > 2.0 || r == 0.0 {
+            break;
+        }
+        let theta = (z.z() / r).acos() * power;
+        let phi = z.y().atan2(z.x()) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        z = r.powf(power) * Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()) + p;
+        r = z.length();
+    }
+    if r == 0.0 {
+        return 0.0;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+// Renders an `Sdf` by sphere tracing: stepping along the ray by the distance to the
+// nearest surface until it gets within `epsilon` of one. The box bounds the search and
+// must contain the whole shape.
+pub struct DistanceField {
+    sdf: Sdf,
+    mat: MaterialRef,
+    bbox: Aabb,
+    step_scale: f64,
+    epsilon: f64,
+    max_steps: u32,
+}
+
+impl DistanceField {
+    pub fn new(sdf: Sdf, bbox: Aabb, mat: MaterialRef) -> Self {
+        let (x, y, z) = (bbox.axis_interval(0), bbox.axis_interval(1), bbox.axis_interval(2));
+        let diagonal = Vec3::new(x.size(), y.size(), z.size()).length();
+
+        Self {
+            step_scale: 1.0 / sdf.lipschitz(bbox),
+            sdf,
+            mat,
+            bbox,
+            epsilon: 1e-5 * diagonal,
+            max_steps: 512,
+        }
+    }
+
+    // Tighter tolerances bring out fine detail at the cost of more steps per ray.
+    pub fn with_precision(mut self, epsilon: f64, max_steps: u32) -> Self {
+        self.epsilon = epsilon;
+        self.max_steps = max_steps;
+        self
+    }
+
+    fn normal(&self, p: Point3) -> Vec3 {
+        // The gradient from four samples at the corners of a tetrahedron.
+        let h = self.epsilon;
+        let corners = [
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ];
+        corners
+            .iter()
+            .fold(Vec3::new(0.0, 0.0, 0.0), |sum, &k| sum + k * self.sdf.distance(p + h * k))
+    }
+
+    // The texture coordinates map the normal like a sphere's, so the derivatives are a
+    // sphere's through `p` around the middle of the box. They are exact for a round
+    // shape and keep a consistent tangent frame elsewhere.
+    fn derivatives(&self, p: Point3, n: Vec3) -> (Vec3, Vec3) {
+        let middle = |axis| {
+            let interval = self.bbox.axis_interval(axis);
+            0.5 * (interval.min + interval.max)
+        };
+        let center = Point3::new(middle(0), middle(1), middle(2));
+        let radius = (p - center).length().max(self.epsilon);
+        let sin_theta = (n.x() * n.x() + n.z() * n.z()).sqrt();
+        let (cos_phi, sin_phi) = if sin_theta > 0.0 { (n.x() / sin_theta, n.z() / sin_theta) } else { (1.0, 0.0) };
+
+        let dpdu = 2.0 * PI * radius * Vec3::new(-n.z(), 0.0, n.x());
+        let dpdv = PI * radius * Vec3::new(n.y() * cos_phi, -sin_theta, n.y() * sin_phi);
+        (dpdu, dpdv)
+    }
+}
+
+impl Hittable for DistanceField {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let span = self.bbox.clip(r, ray_t)?;
+
+        // March in units of distance along a unit direction.
+        let length = r.direction().length();
+        let direction = r.direction() / length;
+        let origin = r.origin();
+        let t_max = span.max * length;
+        let mut t = span.min * length;
+
+        // Rays that start inside the shape, like those refracted into it, march out to the
+        // surface the same way. A ray leaving the surface it just hit must first get clear
+        // of it before a near miss counts as a hit.
+        let start = self.sdf.distance(origin + t * direction);
+        let side = if start < 0.0 { -1.0 } else { 1.0 };
+        let mut clear = start.abs() >= self.epsilon;
+
+        for _ in 0..self.max_steps {
+            let d = side * self.sdf.distance(origin + t * direction);
+            if d < 0.0 || (clear && d < self.epsilon) {
+                let p = origin + t * direction;
+                let gradient = self.normal(p);
+                let outward_normal = if gradient.near_zero() { -direction } else { unit_vector(gradient) };
+                let u = 0.5 + outward_normal.z().atan2(outward_normal.x()) / (2.0 * PI);
+                let v = outward_normal.y().clamp(-1.0, 1.0).acos() / PI;
+                let (dpdu, dpdv) = self.derivatives(p, outward_normal);
+                let rec = HitRecord::new(p, t / length, r, outward_normal, self.mat.clone(), u, v);
+                return Some(rec.with_derivatives(dpdu, dpdv));
+            }
+            clear |= d >= self.epsilon;
+
+            t += (d * self.step_scale).max(self.epsilon);
+            if t > t_max {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::material::{make_mat, EmptyMaterial};
+    use crate::books::the_rest_of_your_life::rtweekend::seed_random;
+    use crate::books::the_rest_of_your_life::sphere::Sphere;
+    use crate::books::the_rest_of_your_life::test_support::assert_derivatives_follow_uvs;
+    use crate::books::the_rest_of_your_life::vec3::{dot, random_unit_vector};
+
+    const CENTER: Point3 = Point3 { e: [0.5, -0.25, 0.3] };
+
+    fn field(sdf: Sdf, half_size: f64) -> DistanceField {
+        let corner = Vec3::new(half_size, half_size, half_size);
+        DistanceField::new(sdf.translate(CENTER), Aabb::from_points(CENTER - corner, CENTER + corner), make_mat(EmptyMaterial))
+    }
+
+    // Rays from all around aimed at points scattered over the shape and a little past it.
+    fn rays(n: usize) -> Vec<Ray> {
+        seed_random(golden::SEED);
+        (0..n)
+            .map(|_| {
+                let origin = CENTER + 4.0 * random_unit_vector();
+                let target = CENTER + Vec3::random_range(-1.3, 1.3);
+                Ray::new(origin, target - origin)
+            })
+            .collect()
+    }
+
+    const FROM_OUTSIDE: Interval = Interval { min: 0.001, max: INFINITY };
+
+    #[test]
+    fn sphere_tracing_matches_an_analytic_sphere() {
+        let traced = field(Sdf::sphere(1.0), 1.1);
+        let sphere = Sphere::new(CENTER, 1.0, make_mat(EmptyMaterial));
+        let mut hits = 0;
+        for r in rays(2000) {
+            let expected = sphere.hit(&r, FROM_OUTSIDE);
+            let actual = traced.hit(&r, FROM_OUTSIDE);
+            // Rays that only graze the surface may pass within the tolerance of it.
+            let slope = expected.as_ref().map_or(1.0, |rec| -dot(unit_vector(r.direction()), rec.normal));
+            if slope < 0.05 {
+                continue;
+            }
+            match (expected, actual) {
+                (None, None) => {}
+                (Some(expected), Some(actual)) => {
+                    hits += 1;
+                    assert!((actual.t - expected.t).abs() * r.direction().length() < 1e-3, "hits at {} for {}", actual.t, expected.t);
+                    assert!(actual.front_face);
+                    assert!(dot(actual.normal, expected.normal) > 0.9999, "normal {:?} for {:?}", actual.normal, expected.normal);
+                }
+                (expected, actual) => panic!("{:?} hits {}, traced {}", r.direction(), expected.is_some(), actual.is_some()),
+            }
+        }
+        assert!(hits > 500, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn sphere_tracing_matches_an_analytic_box() {
+        let half_size = Vec3::new(0.6, 0.4, 0.8);
+        let traced = field(Sdf::rounded_box(half_size, 0.0), 1.0);
+        let solid = Aabb::from_points(CENTER - half_size, CENTER + half_size);
+        let mut hits = 0;
+        for r in rays(2000) {
+            let expected = solid.clip(&r, FROM_OUTSIDE).map(|span| span.min);
+            let actual = traced.hit(&r, FROM_OUTSIDE);
+            let Some(t) = expected else {
+                assert!(actual.is_none(), "{:?} misses the box, traced a hit", r.direction());
+                continue;
+            };
+
+            // The face the ray enters through, unless it comes in near an edge.
+            let local = r.at(t) - CENTER;
+            let gaps = [0, 1, 2].map(|axis| half_size[axis] - local[axis].abs());
+            let axis = (0..3).min_by(|&a, &b| gaps[a].total_cmp(&gaps[b])).unwrap();
+            let mut face = Vec3::new(0.0, 0.0, 0.0);
+            face[axis] = local[axis].signum();
+            let slope = -dot(unit_vector(r.direction()), face);
+            if slope < 0.05 || (0..3).any(|other| other != axis && gaps[other] < 1e-3) {
+                continue;
+            }
+
+            hits += 1;
+            let actual = actual.unwrap_or_else(|| panic!("{:?} hits the box, traced a miss", r.direction()));
+            assert!((actual.t - t).abs() * r.direction().length() < 1e-3, "hits at {} for {}", actual.t, t);
+            assert!(dot(actual.normal, face) > 0.9999, "normal {:?} on face {:?}", actual.normal, face);
+        }
+        assert!(hits > 500, "only {} rays hit", hits);
+    }
+
+    #[test]
+    fn rays_from_inside_find_the_way_out() {
+        let traced = field(Sdf::sphere(1.0), 1.1);
+        seed_random(golden::SEED);
+        for _ in 0..100 {
+            let direction = random_unit_vector();
+            let rec = traced.hit(&Ray::new(CENTER, direction), FROM_OUTSIDE).expect("leaves the sphere");
+            assert!((rec.t - 1.0).abs() < 1e-4 && !rec.front_face, "leaves at t {}", rec.t);
+            assert!(dot(rec.normal, -direction) > 0.9999);
+        }
+    }
+
+    #[test]
+    fn bent_distances_still_find_the_surface() {
+        // Twisting a sphere about its axis leaves it unchanged, but the tracer can only
+        // trust the distance for a fraction of each step.
+        let twisted = field(Sdf::sphere(1.0).twist(3.0), 1.1);
+        for offset in [-0.9, -0.5, 0.0, 0.3, 0.7] {
+            let r = Ray::new(CENTER + Vec3::new(-4.0, offset, 0.0), Vec3::new(1.0, 0.0, 0.0));
+            let rec = twisted.hit(&r, FROM_OUTSIDE).expect("hits the twisted sphere");
+            let expected = 4.0 - (1.0 - offset * offset).sqrt();
+            assert!((rec.t - expected).abs() < 1e-4, "hits at t {}, expected {}", rec.t, expected);
+        }
+    }
+
+    #[test]
+    fn derivatives_follow_the_uvs() {
+        let traced = field(Sdf::sphere(1.0), 1.1);
+        let mut hits = 0;
+        for r in rays(50) {
+            if let Some(rec) = traced.hit(&r, FROM_OUTSIDE) {
+                hits += 1;
+                assert_derivatives_follow_uvs("sphere", &traced, &rec);
+            }
+        }
+        assert!(hits > 10, "only {} rays hit", hits);
+    }
+}
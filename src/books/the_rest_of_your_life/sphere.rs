@@ -5,7 +5,7 @@ use super::material::MaterialRef;
 use super::onb::Onb;
 use super::ray::Ray;
 use super::rtweekend::{random_double, INFINITY, PI};
-use super::vec3::{dot, Point3, Vec3};
+use super::vec3::{dot, random_unit_vector, Point3, Vec3};
 
 pub struct Sphere {
     center: Ray,
@@ -44,6 +44,12 @@ impl Sphere {
         }
     }
 
+    // A moving sphere goes from center1 to center2 over [0, 1] and stays put outside it,
+    // so it never leaves its bounding box.
+    fn center_at(&self, time: f64) -> Point3 {
+        self.center.at(time.clamp(0.0, 1.0))
+    }
+
     fn get_sphere_uv(p: Point3) -> (f64, f64) {
         let theta = (-p.y()).acos();
         let phi = (-p.z()).atan2(p.x()) + PI;
@@ -52,7 +58,33 @@ impl Sphere {
         (u, v)
     }
 
+    fn hit_record(&self, r: &Ray, root: f64, current_center: Point3) -> HitRecord {
+        let p = r.at(root);
+        let outward_normal = (p - current_center) / self.radius;
+        let (u, v) = Sphere::get_sphere_uv(outward_normal);
+        let (dpdu, dpdv) = self.sphere_derivatives(outward_normal);
+
+        HitRecord::new(p, root, r, outward_normal, self.mat.clone(), u, v).with_derivatives(dpdu, dpdv)
+    }
+
+    // How the point with unit normal `n` moves with u and v, from the mapping above.
+    fn sphere_derivatives(&self, n: Vec3) -> (Vec3, Vec3) {
+        let cos_theta = -n.y();
+        let sin_theta = (n.x() * n.x() + n.z() * n.z()).sqrt();
+        let (cos_phi, sin_phi) = if sin_theta > 0.0 { (-n.x() / sin_theta, n.z() / sin_theta) } else { (1.0, 0.0) };
+
+        let dpdu = 2.0 * PI * self.radius * Vec3::new(n.z(), 0.0, -n.x());
+        let dpdv = PI * self.radius * Vec3::new(-cos_theta * cos_phi, sin_theta, cos_theta * sin_phi);
+        (dpdu, dpdv)
+    }
+
+    // A direction towards a sphere at the given squared distance along +z, uniform over the
+    // cone it subtends. From inside the sphere every direction reaches it.
     fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
+        if distance_squared <= radius * radius {
+            return random_unit_vector();
+        }
+
         let r1 = random_double();
         let r2 = random_double();
         let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
@@ -67,7 +99,7 @@ impl Sphere {
 
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let current_center = self.center.at(r.time());
+        let current_center = self.center_at(r.time());
         let oc = current_center - r.origin();
         let a = r.direction().length_squared();
         let h = dot(r.direction(), oc);
@@ -88,36 +120,133 @@ impl Hittable for Sphere {
             }
         }
 
-        let p = r.at(root);
-        let outward_normal = (p - current_center) / self.radius;
-        let (u, v) = Sphere::get_sphere_uv(outward_normal);
+        Some(self.hit_record(r, root, current_center))
+    }
 
-        Some(HitRecord::new(p, root, r, outward_normal, self.mat.clone(), u, v))
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        let current_center = self.center_at(r.time());
+        let oc = current_center - r.origin();
+        let a = r.direction().length_squared();
+        let h = dot(r.direction(), oc);
+        let c = oc.length_squared() - self.radius * self.radius;
+
+        let discriminant = h * h - a * c;
+        if discriminant < 0.0 {
+            return Vec::new();
+        }
+
+        let sqrtd = discriminant.sqrt();
+        [(h - sqrtd) / a, (h + sqrtd) / a]
+            .into_iter()
+            .filter(|&root| ray_t.surrounds(root))
+            .map(|root| self.hit_record(r, root, current_center))
+            .collect()
     }
 
     fn bounding_box(&self) -> Aabb {
         self.bbox
     }
 
-    fn pdf_value(&self, origin: Point3, direction: Vec3) -> f64 {
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
         if self
-            .hit(&Ray::new(origin, direction), Interval::new(0.001, INFINITY))
+            .hit(&Ray::new_with_time(origin, direction, time), Interval::new(0.001, INFINITY))
             .is_none()
         {
             return 0.0;
         }
 
-        let dist_squared = (self.center.at(0.0) - origin).length_squared();
+        let dist_squared = (self.center_at(time) - origin).length_squared();
+        if dist_squared <= self.radius * self.radius {
+            return 1.0 / (4.0 * PI);
+        }
         let cos_theta_max = (1.0 - self.radius * self.radius / dist_squared).sqrt();
         let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
 
         1.0 / solid_angle
     }
 
-    fn random(&self, origin: Point3) -> Vec3 {
-        let direction = self.center.at(0.0) - origin;
+    fn random(&self, origin: Point3, time: f64) -> Vec3 {
+        let direction = self.center_at(time) - origin;
         let distance_squared = direction.length_squared();
         let uvw = Onb::new(direction);
         uvw.transform(Sphere::random_to_sphere(self.radius, distance_squared))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::material::{make_mat, EmptyMaterial};
+    use crate::books::the_rest_of_your_life::rtweekend::seed_random;
+    use crate::books::the_rest_of_your_life::test_support::{assert_chi_square, integrate_directions};
+    use crate::books::the_rest_of_your_life::vec3::unit_vector;
+
+    // Spheres of radius 2 at (1, 2, 3), and the points they are seen from.
+    fn views() -> Vec<(&'static str, Sphere, Point3)> {
+        let sphere = || Sphere::new(Point3::new(1.0, 2.0, 3.0), 2.0, make_mat(EmptyMaterial));
+        vec![
+            ("far outside", sphere(), Point3::new(-20.0, 5.0, 10.0)),
+            ("just outside", sphere(), Point3::new(1.0, 2.0, 5.05)),
+            ("inside", sphere(), Point3::new(1.5, 1.0, 3.2)),
+        ]
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        seed_random(golden::SEED);
+        for (name, sphere, origin) in views() {
+            let total = integrate_directions(600, |d| sphere.pdf_value(origin, d, 0.0));
+            assert!((total - 1.0).abs() < 0.01, "{}: pdf integrates to {}", name, total);
+        }
+    }
+
+    #[test]
+    fn samples_follow_pdf() {
+        seed_random(golden::SEED);
+        const BINS: usize = 16;
+        const SAMPLES: usize = 100_000;
+
+        for (name, sphere, origin) in views() {
+            // Uniform sampling by solid angle makes the cosine of the angle from the center
+            // uniform over the cone, or over all directions from inside.
+            let axis = unit_vector(sphere.center.at(0.0) - origin);
+            let distance_squared = (sphere.center.at(0.0) - origin).length_squared();
+            let cos_max = if distance_squared > 4.0 { (1.0 - 4.0 / distance_squared).sqrt() } else { -1.0 };
+
+            let mut counts = vec![0; BINS];
+            let mut misses = 0;
+            for _ in 0..SAMPLES {
+                let direction = sphere.random(origin, 0.0);
+                if sphere.hit(&Ray::new(origin, direction), Interval::new(0.001, INFINITY)).is_none() {
+                    misses += 1;
+                    continue;
+                }
+                let cosine = dot(unit_vector(direction), axis);
+                let bin = ((cosine - cos_max) / (1.0 - cos_max) * BINS as f64) as usize;
+                counts[bin.min(BINS - 1)] += 1;
+            }
+
+            assert!(misses <= SAMPLES / 10_000, "{}: {} samples missed the sphere", name, misses);
+            assert_chi_square(name, &counts, &[1.0 / BINS as f64; BINS]);
+        }
+    }
+
+    #[test]
+    fn moving_spheres_stay_in_their_bounds_after_the_shutter() {
+        let sphere = Sphere::new_moving(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 2.0, 0.0),
+            1.0,
+            make_mat(EmptyMaterial),
+        );
+        // At frame 3 the sphere rests where it ended, at the top of its bounding box.
+        let r = Ray::new_with_time(Point3::new(0.0, 2.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 3.0);
+        let rec = sphere.hit(&r, Interval::new(0.001, INFINITY)).expect("the sphere should hold its last position");
+        assert!((rec.p - Point3::new(0.0, 2.0, 1.0)).length() < 1e-9);
+        assert!(sphere.bounding_box().y.contains(rec.p.y() + 1.0));
+
+        let below = Ray::new_with_time(Point3::new(0.0, -1.5, 5.0), Vec3::new(0.0, 0.0, -1.0), -1.0);
+        assert!(sphere.hit(&below, Interval::new(0.001, INFINITY)).is_none());
+    }
+}
@@ -0,0 +1,132 @@
+use super::rtweekend::PI;
+use super::vec3::{cross, dot, unit_vector, Point3, Vec3};
+
+// The directions from an origin towards a rectangle, sampled uniformly by solid angle, after
+// Urena, Fajardo and King, "An Area-Preserving Parametrization for Spherical Rectangles".
+pub(super) struct SphericalRectangle {
+    x: Vec3,
+    y: Vec3,
+    z: Vec3,
+    z0: f64,
+    x0: f64,
+    y0: f64,
+    x1: f64,
+    y1: f64,
+    b0: f64,
+    b1: f64,
+    k: f64,
+    solid_angle: f64,
+}
+
+impl SphericalRectangle {
+    // The rectangle with a corner at `corner` and perpendicular edges `ex` and `ey`.
+    pub(super) fn new(origin: Point3, corner: Point3, ex: Vec3, ey: Vec3) -> Self {
+        let (width, height) = (ex.length(), ey.length());
+        let x = ex / width;
+        let y = ey / height;
+        let mut z = cross(x, y);
+
+        // Work in a frame where the rectangle lies below the origin, at z = z0 < 0.
+        let d = corner - origin;
+        let mut z0 = dot(d, z);
+        if z0 > 0.0 {
+            z = -z;
+            z0 = -z0;
+        }
+        let x0 = dot(d, x);
+        let y0 = dot(d, y);
+        let (x1, y1) = (x0 + width, y0 + height);
+
+        // Normals of the planes through the origin and each edge, and the angles between them.
+        let n0 = unit_vector(Vec3::new(0.0, z0, -y0));
+        let n1 = unit_vector(Vec3::new(-z0, 0.0, x1));
+        let n2 = unit_vector(Vec3::new(0.0, -z0, y1));
+        let n3 = unit_vector(Vec3::new(z0, 0.0, -x0));
+        let g0 = (-dot(n0, n1)).clamp(-1.0, 1.0).acos();
+        let g1 = (-dot(n1, n2)).clamp(-1.0, 1.0).acos();
+        let g2 = (-dot(n2, n3)).clamp(-1.0, 1.0).acos();
+        let g3 = (-dot(n3, n0)).clamp(-1.0, 1.0).acos();
+
+        let k = 2.0 * PI - g2 - g3;
+        Self { x, y, z, z0, x0, y0, x1, y1, b0: n0.z(), b1: n2.z(), k, solid_angle: g0 + g1 - k }
+    }
+
+    pub(super) fn solid_angle(&self) -> f64 {
+        self.solid_angle
+    }
+
+    // The point on the rectangle for the sample (u, v) in [0, 1)^2, as an offset from the
+    // origin.
+    pub(super) fn sample(&self, u: f64, v: f64) -> Vec3 {
+        // Pick the x coordinate so the slab to its left holds a fraction u of the solid angle.
+        let au = u * self.solid_angle + self.k;
+        let fu = (au.cos() * self.b0 - self.b1) / au.sin();
+        let cu = (fu.signum() / (fu * fu + self.b0 * self.b0).sqrt()).clamp(-1.0, 1.0);
+        let xu = (-(cu * self.z0) / (1.0 - cu * cu).sqrt()).clamp(self.x0, self.x1);
+
+        // Then y, uniformly in the sine of the elevation along that slice.
+        let d = (xu * xu + self.z0 * self.z0).sqrt();
+        let h0 = self.y0 / (d * d + self.y0 * self.y0).sqrt();
+        let h1 = self.y1 / (d * d + self.y1 * self.y1).sqrt();
+        let hv = h0 + v * (h1 - h0);
+        let yv = if hv * hv < 1.0 - 1e-12 { hv * d / (1.0 - hv * hv).sqrt() } else { self.y1 };
+
+        xu * self.x + yv * self.y + self.z0 * self.z
+    }
+}
+
+// The directions from an origin towards a triangle, sampled uniformly by solid angle, after
+// Arvo, "Stratified Sampling of Spherical Triangles".
+pub(super) struct SphericalTriangle {
+    a: Vec3,
+    b: Vec3,
+    c: Vec3,
+    alpha: f64,
+    cos_c: f64,
+    solid_angle: f64,
+}
+
+impl SphericalTriangle {
+    pub(super) fn new(origin: Point3, [p0, p1, p2]: [Point3; 3]) -> Self {
+        let a = unit_vector(p0 - origin);
+        let b = unit_vector(p1 - origin);
+        let c = unit_vector(p2 - origin);
+
+        // Van Oosterom and Strackee's formula, which holds up for small triangles.
+        let solid_angle = 2.0 * dot(a, cross(b, c)).abs().atan2(1.0 + dot(a, b) + dot(b, c) + dot(c, a));
+        let alpha = angle_between(cross(a, b), cross(a, c));
+
+        Self { a, b, c, alpha, cos_c: dot(a, b), solid_angle }
+    }
+
+    pub(super) fn solid_angle(&self) -> f64 {
+        self.solid_angle
+    }
+
+    // The unit direction for the sample (u, v) in [0, 1)^2.
+    pub(super) fn sample(&self, u: f64, v: f64) -> Vec3 {
+        // Find the vertex c' that cuts off a sub-triangle (a, b, c') with a fraction u of the
+        // solid angle...
+        let (sin_alpha, cos_alpha) = self.alpha.sin_cos();
+        let (s, t) = (u * self.solid_angle - self.alpha).sin_cos();
+        let uu = t - cos_alpha;
+        let vv = s + sin_alpha * self.cos_c;
+        let q = (((vv * t - uu * s) * cos_alpha - vv) / ((vv * s + uu * t) * sin_alpha)).clamp(-1.0, 1.0);
+        let c_hat = q * self.a + (1.0 - q * q).sqrt() * orthogonal(self.c, self.a);
+
+        // ...then a point on the arc from b to c'.
+        let z = (1.0 - v * (1.0 - dot(c_hat, self.b))).clamp(-1.0, 1.0);
+        z * self.b + (1.0 - z * z).sqrt() * orthogonal(c_hat, self.b)
+    }
+}
+
+// The unit vector along the part of `v` perpendicular to the unit vector `axis`.
+fn orthogonal(v: Vec3, axis: Vec3) -> Vec3 {
+    let w = v - dot(v, axis) * axis;
+    let length = w.length();
+    if length > 0.0 { w / length } else { Vec3::new(0.0, 0.0, 0.0) }
+}
+
+fn angle_between(u: Vec3, v: Vec3) -> f64 {
+    cross(u, v).length().atan2(dot(u, v))
+}
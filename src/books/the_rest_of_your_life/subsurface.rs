@@ -0,0 +1,276 @@
+// Subsurface scattering by a random walk, for skin, wax, marble and milk: light refracts
+// into the object, scatters around inside it like in a ConstantMedium and leaves somewhere
+// else, softened and colored by how far each wavelength got.
+//
+// The walk happens within `scatter`, so the material needs the shape it is on. Like the
+// lights list the scenes pass to the renderer, that is a second copy of the shape, and it
+// must be closed. The walk runs in world space, so the copy has to sit exactly where the
+// shape ends up: a shape placed by a transform needs its boundary wrapped in the same one.
+// Debug builds catch boundaries that don't pass through the hits on their shape. The ray the walk hands back
+// starts where light leaves the object and, like a ray out of glass, finds lights only by
+// hitting them, so this is at its best under large lights or a bright sky.
+
+use super::hittable::{HitRecord, Hittable, HittableRef};
+use super::interval::Interval;
+use super::material::{Dielectric, Material, ScatterRecord};
+use super::ray::Ray;
+use super::rtweekend::{random_double, INFINITY};
+use super::vec3::{dot, random_unit_vector, reflect, refract, unit_vector, Color, Point3, Vec3};
+
+// Walks that scatter more often than this are taken as absorbed. Light rarely gets this far
+// in anything but a large object of very white material.
+const MAX_EVENTS: usize = 256;
+
+pub struct Subsurface {
+    boundary: HittableRef,
+    // Per unit length, per channel.
+    scattering: Color,
+    absorption: Color,
+    ior: f64,
+}
+
+impl Subsurface {
+    pub fn new(boundary: HittableRef, scattering: Color, absorption: Color) -> Self {
+        Self { boundary, scattering, absorption, ior: 1.4 }
+    }
+
+    // From the mean distance light travels between events and the share that survives each
+    // one, which are easier to pick than coefficients.
+    pub fn from_mean_free_path(boundary: HittableRef, mean_free_path: Color, albedo: Color) -> Self {
+        let extinction = Color::new(1.0 / mean_free_path.x(), 1.0 / mean_free_path.y(), 1.0 / mean_free_path.z());
+        let scattering = albedo * extinction;
+        Self::new(boundary, scattering, extinction - scattering)
+    }
+
+    pub fn with_ior(mut self, ior: f64) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    // Where the walk's ray leaves through the surface at `hit`, or None if the surface
+    // reflects it back inside.
+    fn leave(&self, direction: Vec3, hit: &HitRecord) -> Option<Vec3> {
+        let ri = if hit.front_face { 1.0 / self.ior } else { self.ior };
+        let cos_theta = (-dot(direction, hit.normal)).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        if ri * sin_theta > 1.0 || Dielectric::reflectance(cos_theta, ri) > random_double() {
+            return None;
+        }
+        Some(refract(direction, hit.normal, ri))
+    }
+
+    // Whether the boundary's surface passes through the hit: a short ray back down the
+    // normal from just outside should land on it there. A point merely inside the boundary's
+    // box, or on a copy that was scaled or moved, fails this.
+    fn on_boundary(&self, rec: &HitRecord, time: f64) -> bool {
+        let scale = 1.0 + rec.p.length();
+        let lift = 1e-4 * scale;
+        let probe = Ray::new_with_time(rec.p + lift * rec.normal, -rec.normal, time);
+        self.boundary
+            .hit(&probe, Interval::new(0.0, 2.0 * lift))
+            .is_some_and(|hit| (hit.p - rec.p).length() < 1e-6 * scale)
+    }
+
+    // Follows light that has entered at `p` going along `direction` until it leaves, and
+    // returns the ray it leaves on with the share of each channel that made it.
+    fn walk(&self, mut p: Point3, mut direction: Vec3, time: f64) -> Option<(Ray, Color)> {
+        let extinction = self.scattering + self.absorption;
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        // Steps from the surface skip a sliver so they don't hit it again; steps from inside
+        // must not, or they could skip past it.
+        let mut t_min = 0.001;
+
+        for _ in 0..MAX_EVENTS {
+            // Channels thin out at different rates. Draw the distance for one of them and
+            // weight by the average chance over all three of drawing it.
+            let channel = ((3.0 * random_double()) as usize).min(2);
+            let distance = if extinction[channel] > 0.0 {
+                -(1.0 - random_double()).ln() / extinction[channel]
+            } else {
+                INFINITY
+            };
+            let transmittance = |d: f64| {
+                let t = |sigma: f64| if sigma > 0.0 { (-sigma * d).exp() } else { 1.0 };
+                Color::new(t(extinction.x()), t(extinction.y()), t(extinction.z()))
+            };
+
+            let ray = Ray::new_with_time(p, direction, time);
+            if let Some(hit) = self.boundary.hit(&ray, Interval::new(t_min, distance)) {
+                // Reached the surface before the next event.
+                let tr = transmittance(hit.t);
+                throughput = throughput * tr / ((tr.x() + tr.y() + tr.z()) / 3.0);
+                p = hit.p;
+                t_min = 0.001;
+                match self.leave(direction, &hit) {
+                    Some(out) => return Some((Ray::new_with_time(p, out, time), throughput)),
+                    None => direction = unit_vector(reflect(direction, hit.normal)),
+                }
+            } else {
+                // Scattered inside, in a new direction taken at random as Isotropic does.
+                let tr = transmittance(distance);
+                let density = extinction * tr;
+                throughput = throughput * self.scattering * tr / ((density.x() + density.y() + density.z()) / 3.0);
+                p = ray.at(distance);
+                t_min = 0.0;
+                direction = random_unit_vector();
+            }
+        }
+        None
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let unit_direction = unit_vector(r_in.direction());
+        let skip = |direction: Vec3, attenuation: Color| ScatterRecord {
+            attenuation,
+            pdf_ptr: None,
+            skip_pdf: true,
+            skip_pdf_ray: Ray::new_with_time(rec.p, direction, r_in.time()),
+        };
+
+        // Rays that start inside, say from a camera there, see only the surface on the way
+        // out.
+        if !rec.front_face {
+            return Some(skip(unit_direction, Color::new(1.0, 1.0, 1.0)));
+        }
+
+        debug_assert!(
+            self.on_boundary(rec, r_in.time()),
+            "subsurface hit at {:?} is off its boundary; transform the boundary like the shape",
+            rec.p
+        );
+        let cos_theta = (-dot(unit_direction, rec.normal)).min(1.0);
+        if Dielectric::reflectance(cos_theta, 1.0 / self.ior) > random_double() {
+            return Some(skip(reflect(unit_direction, rec.normal), Color::new(1.0, 1.0, 1.0)));
+        }
+
+        let entering = refract(unit_direction, rec.normal, 1.0 / self.ior);
+        let (exit, throughput) = self.walk(rec.p, unit_vector(entering), r_in.time())?;
+        Some(ScatterRecord { attenuation: throughput, pdf_ptr: None, skip_pdf: true, skip_pdf_ray: exit })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::hittable::make_ref;
+    use crate::books::the_rest_of_your_life::material::{make_mat, EmptyMaterial, MaterialRef};
+    use crate::books::the_rest_of_your_life::rtweekend::seed_random;
+    use crate::books::the_rest_of_your_life::sphere::Sphere;
+    use crate::books::the_rest_of_your_life::test_support::mean_weight;
+    use crate::books::the_rest_of_your_life::transform::{MotionTransform, Quat, Transform};
+
+    const SAMPLES: usize = 5_000;
+
+    fn unit_ball() -> HittableRef {
+        make_ref(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, make_mat(EmptyMaterial)))
+    }
+
+    // Light arriving at the unit ball from `incoming`, where the ball's surface is.
+    fn hit(mat: &MaterialRef, incoming: Vec3) -> (Ray, HitRecord) {
+        let r_in = Ray::new(3.0 * incoming, -incoming);
+        let rec = HitRecord::new(incoming, 2.0, &r_in, incoming, mat.clone(), 0.5, 0.5);
+        (r_in, rec)
+    }
+
+    fn scattered(mat: &MaterialRef, incoming: Vec3) -> Vec<ScatterRecord> {
+        let (r_in, rec) = hit(mat, incoming);
+        (0..SAMPLES).filter_map(|_| mat.scatter(&r_in, &rec)).collect()
+    }
+
+    // The fraction of the light arriving from `incoming` that comes back out of the ball.
+    fn albedo(mat: &MaterialRef, incoming: Vec3) -> Color {
+        let (r_in, rec) = hit(mat, incoming);
+        mean_weight(mat, &r_in, &rec, SAMPLES)
+    }
+
+    #[test]
+    fn clear_material_passes_light_straight_through() {
+        seed_random(golden::SEED);
+        let zero = Color::new(0.0, 0.0, 0.0);
+        let mat = make_mat(Subsurface::new(unit_ball(), zero, zero).with_ior(1.0));
+        let (r_in, rec) = hit(&mat, Vec3::new(0.0, 0.0, 1.0));
+        let srec = mat.scatter(&r_in, &rec).expect("light gets through");
+        assert!((srec.skip_pdf_ray.origin() - Point3::new(0.0, 0.0, -1.0)).length() < 1e-6);
+        assert!((unit_vector(srec.skip_pdf_ray.direction()) - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-6);
+        assert!((srec.attenuation - Color::new(1.0, 1.0, 1.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn light_leaves_through_the_surface() {
+        seed_random(golden::SEED);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let mat = make_mat(Subsurface::from_mean_free_path(unit_ball(), 0.3 * white, white));
+        for srec in scattered(&mat, unit_vector(Vec3::new(1.0, 0.0, 1.0))) {
+            let (p, direction) = (srec.skip_pdf_ray.origin(), srec.skip_pdf_ray.direction());
+            assert!((p.length() - 1.0).abs() < 1e-6, "leaves from {:?}", p);
+            assert!(dot(direction, p) > 0.0, "leaves {:?} going {:?}", p, direction);
+        }
+    }
+
+    #[test]
+    fn white_material_loses_nothing() {
+        seed_random(golden::SEED);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let mat = make_mat(Subsurface::from_mean_free_path(unit_ball(), 0.25 * white, white));
+        let mean = albedo(&mat, Vec3::new(0.0, 0.0, 1.0));
+        for c in 0..3 {
+            assert!(mean[c] > 0.99 && mean[c] <= 1.0 + 1e-9, "returns {:?}", mean);
+        }
+    }
+
+    #[test]
+    fn absorbed_colors_come_out_darker() {
+        seed_random(golden::SEED);
+        let mat = make_mat(Subsurface::from_mean_free_path(
+            unit_ball(),
+            Color::new(0.2, 0.2, 0.2),
+            Color::new(0.99, 0.9, 0.6),
+        ));
+        let mean = albedo(&mat, Vec3::new(0.0, 0.0, 1.0));
+        assert!(1.0 > mean.x() && mean.x() > mean.y() && mean.y() > mean.z() && mean.z() > 0.0, "returns {:?}", mean);
+    }
+
+    #[test]
+    fn boundaries_move_with_their_shape() {
+        seed_random(golden::SEED);
+        // A unit ball moved and grown to radius 2 by a transform, with its boundary placed
+        // by the same transform.
+        let center = Point3::new(4.0, -2.0, 3.0);
+        let transform = || Transform::new(center, Quat::from_axis_angle(Vec3::new(1.0, 1.0, 0.0), 30.0), Vec3::new(2.0, 2.0, 2.0));
+        let place = |mat: MaterialRef| {
+            let ball = make_ref(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, mat));
+            make_ref(MotionTransform::new(ball, transform(), transform()))
+        };
+        let white = Color::new(1.0, 1.0, 1.0);
+        let ball = place(make_mat(Subsurface::from_mean_free_path(place(make_mat(EmptyMaterial)), 0.5 * white, white)));
+
+        let r_in = Ray::new(center + Vec3::new(1.0, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = ball.hit(&r_in, Interval::new(0.001, INFINITY)).expect("hits the ball");
+        let mut returned = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..SAMPLES {
+            let Some(srec) = rec.mat.scatter(&r_in, &rec) else { continue };
+            let (p, direction) = (srec.skip_pdf_ray.origin(), srec.skip_pdf_ray.direction());
+            assert!(((p - center).length() - 2.0).abs() < 1e-6, "leaves from {:?}", p);
+            assert!(dot(direction, p - center) > 0.0, "leaves {:?} going {:?}", p, direction);
+            returned += srec.attenuation;
+        }
+        let mean = returned / SAMPLES as f64;
+        assert!(mean.x() > 0.99 && mean.x() <= 1.0 + 1e-9, "returns {:?}", mean);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "off its boundary")]
+    fn boundaries_that_miss_the_shape_are_caught() {
+        // The hit is inside the boundary's box, but on a smaller ball than the boundary.
+        let white = Color::new(1.0, 1.0, 1.0);
+        let mat = make_mat(Subsurface::from_mean_free_path(unit_ball(), 0.5 * white, white).with_ior(1.0));
+        let ball = Sphere::new(Point3::new(0.0, 0.0, 0.0), 0.9, mat.clone());
+        let r_in = Ray::new(Point3::new(0.2, 0.1, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = ball.hit(&r_in, Interval::new(0.001, INFINITY)).expect("hits the ball");
+        mat.scatter(&r_in, &rec);
+    }
+}
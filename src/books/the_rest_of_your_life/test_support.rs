@@ -0,0 +1,193 @@
+// Numerical helpers and fixtures for the sampling and material tests.
+
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::{MaterialRef, ScatterRecord};
+use super::ray::Ray;
+use super::rtweekend::{random_double, seed_random, PI};
+use super::vec3::{dot, unit_vector, Color, Point3, Vec3};
+use crate::books::golden;
+
+// Light arriving at `mat` on a surface at the origin facing +z, with u and v running along
+// x and y. `incoming` points back along the ray.
+pub(super) fn surface_hit(mat: &MaterialRef, incoming: Vec3) -> (Ray, HitRecord) {
+    let r_in = Ray::new(incoming, -incoming);
+    let rec = HitRecord::new(Point3::new(0.0, 0.0, 0.0), 1.0, &r_in, Vec3::new(0.0, 0.0, 1.0), mat.clone(), 0.5, 0.5)
+        .with_derivatives(Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0));
+    (r_in, rec)
+}
+
+// Checks a shape's dpdu and dpdv at `rec` against its texture coordinates: a small step
+// along either, hit again from just above the surface, should move that coordinate by the
+// step and leave the other alone. Coordinates that wrap around are compared across the seam.
+pub(super) fn assert_derivatives_follow_uvs(name: &str, shape: &dyn Hittable, rec: &HitRecord) {
+    const STEP: f64 = 1e-5;
+    let n = rec.outward_normal();
+    let change = |a: f64, b: f64| ((b - a + 0.5).rem_euclid(1.0) - 0.5) / STEP;
+    for (along, dp, expected) in [("u", rec.dpdu, (1.0, 0.0)), ("v", rec.dpdv, (0.0, 1.0))] {
+        assert!(dot(dp, n).abs() < 1e-9 * dp.length(), "{}: dp/d{} {:?} leaves the surface", name, along, dp);
+        let stepped = rec.p + STEP * dp + 1e-3 * n;
+        let moved = shape
+            .hit(&Ray::new(stepped, -n), Interval::new(0.0, 2e-3))
+            .unwrap_or_else(|| panic!("{}: stepping along dp/d{} falls off the shape", name, along));
+        let (du, dv) = (change(rec.u, moved.u), change(rec.v, moved.v));
+        assert!(
+            (du - expected.0).abs() < 1e-3 && (dv - expected.1).abs() < 1e-3,
+            "{}: a step along dp/d{} moves (u, v) by ({}, {}) steps",
+            name,
+            along,
+            du,
+            dv
+        );
+    }
+}
+
+// Light arriving from straight above, at 45 degrees and at a grazing angle.
+pub(super) fn incoming_directions() -> [Vec3; 3] {
+    [Vec3::new(0.0, 0.0, 1.0), unit_vector(Vec3::new(1.0, 0.0, 1.0)), unit_vector(Vec3::new(0.3, 1.0, 0.18))]
+}
+
+// The same, and from below the surface.
+pub(super) fn incoming_from_both_sides() -> [Vec3; 4] {
+    let [above, oblique, grazing] = incoming_directions();
+    [above, oblique, grazing, unit_vector(Vec3::new(0.2, -0.3, -1.0))]
+}
+
+// The weight the renderer gives one scattered ray when it samples the material alone.
+pub(super) fn sample_weight(mat: &MaterialRef, r_in: &Ray, rec: &HitRecord) -> Color {
+    match mat.scatter(r_in, rec) {
+        None => Color::new(0.0, 0.0, 0.0),
+        Some(srec) if srec.skip_pdf => srec.attenuation,
+        Some(ScatterRecord { pdf_ptr: None, .. }) => Color::new(0.0, 0.0, 0.0),
+        Some(ScatterRecord { attenuation, pdf_ptr: Some(pdf), .. }) => {
+            let scattered = Ray::new(rec.p, pdf.generate());
+            let pdf_value = pdf.value(scattered.direction());
+            if pdf_value > 0.0 {
+                mat.scattering(r_in, rec, attenuation, &scattered) / pdf_value
+            } else {
+                Color::new(0.0, 0.0, 0.0)
+            }
+        }
+    }
+}
+
+// The fraction of the light arriving at the hit that the material sends back out, as the
+// average of `samples` sample weights.
+pub(super) fn mean_weight(mat: &MaterialRef, r_in: &Ray, rec: &HitRecord, samples: usize) -> Color {
+    let sum = (0..samples).fold(Color::new(0.0, 0.0, 0.0), |sum, _| sum + sample_weight(mat, r_in, rec));
+    sum / samples as f64
+}
+
+// Integrates `f` over the sphere of directions, sampling a random point in each cell of an
+// equal-area grid with `n` bands in cos(theta) and 2n steps around. The jitter keeps the
+// grid from aliasing with the edges of whatever `f` is measuring.
+pub(super) fn integrate_directions(n: usize, mut f: impl FnMut(Vec3) -> f64) -> f64 {
+    let mut sum = 0.0;
+    for i in 0..n {
+        for j in 0..2 * n {
+            let z = 1.0 - 2.0 * (i as f64 + random_double()) / n as f64;
+            let ring = (1.0 - z * z).sqrt();
+            let phi = PI * (j as f64 + random_double()) / n as f64;
+            sum += f(Vec3::new(ring * phi.cos(), ring * phi.sin(), z));
+        }
+    }
+    sum * 4.0 * PI / (2 * n * n) as f64
+}
+
+// Pearson's chi-square test of `observed` counts against the `expected` probabilities of
+// each bin. Bins expected to hold fewer than five samples are pooled so the statistic stays
+// well behaved. Panics when the counts are less likely than one in a million to have come
+// from the expected distribution.
+pub(super) fn assert_chi_square(name: &str, observed: &[usize], expected: &[f64]) {
+    assert_eq!(observed.len(), expected.len());
+    let total: usize = observed.iter().sum();
+    let total = total as f64;
+
+    let mut statistic = 0.0;
+    let mut bins = 0;
+    let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+    for (&count, &probability) in observed.iter().zip(expected) {
+        let expected_count = probability * total;
+        if expected_count < 5.0 {
+            pooled_observed += count as f64;
+            pooled_expected += expected_count;
+            continue;
+        }
+        statistic += (count as f64 - expected_count).powi(2) / expected_count;
+        bins += 1;
+    }
+    if pooled_expected > 0.0 {
+        statistic += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+        bins += 1;
+    }
+    assert!(bins > 1, "{}: too few samples for a chi-square test", name);
+
+    // The critical value from the Wilson-Hilferty approximation of the chi-square
+    // distribution, at the standard normal quantile for one in a million.
+    let dof = (bins - 1) as f64;
+    let z = 4.753;
+    let critical = dof * (1.0 - 2.0 / (9.0 * dof) + z * (2.0 / (9.0 * dof)).sqrt()).powi(3);
+    assert!(
+        statistic < critical,
+        "{}: chi-square statistic {:.1} exceeds {:.1} with {} degrees of freedom",
+        name,
+        statistic,
+        critical,
+        dof
+    );
+}
+
+// Checks that the directions drawn from `generate` are distributed as the density `value`
+// says: bins the samples on an equal-area grid over the sphere, compares the histogram with
+// the density integrated over each bin, and checks the density integrates to one. The
+// samples come from a fixed seed, so a failing case fails on every run.
+pub(super) fn assert_sampler_matches_pdf(
+    name: &str,
+    mut generate: impl FnMut() -> Vec3,
+    value: impl Fn(Vec3) -> f64,
+) {
+    const BANDS: usize = 16;
+    const STEPS: usize = 2 * BANDS;
+    // Fine enough that the integral is accurate in bins cut by the edge of a light.
+    const SUBDIVISIONS: usize = 64;
+    const SAMPLES: usize = 200_000;
+
+    let bin_of = |d: Vec3| {
+        let d = d / d.length();
+        let band = (((1.0 - d.z()) / 2.0 * BANDS as f64) as usize).min(BANDS - 1);
+        let phi = d.y().atan2(d.x()).rem_euclid(2.0 * PI);
+        let step = ((phi / (2.0 * PI) * STEPS as f64) as usize).min(STEPS - 1);
+        band * STEPS + step
+    };
+
+    // Integrate the density over each bin at the centers of a finer grid.
+    let fine_bands = BANDS * SUBDIVISIONS;
+    let fine_steps = STEPS * SUBDIVISIONS;
+    let cell = 4.0 * PI / (fine_bands * fine_steps) as f64;
+    let mut expected = vec![0.0; BANDS * STEPS];
+    for i in 0..fine_bands {
+        let z = 1.0 - 2.0 * (i as f64 + 0.5) / fine_bands as f64;
+        let ring = (1.0 - z * z).sqrt();
+        for j in 0..fine_steps {
+            let phi = 2.0 * PI * (j as f64 + 0.5) / fine_steps as f64;
+            let direction = Vec3::new(ring * phi.cos(), ring * phi.sin(), z);
+            expected[(i / SUBDIVISIONS) * STEPS + j / SUBDIVISIONS] += value(direction) * cell;
+        }
+    }
+    let total: f64 = expected.iter().sum();
+    assert!((total - 1.0).abs() < 0.01, "{}: pdf integrates to {}", name, total);
+
+    seed_random(golden::SEED);
+    let mut counts = vec![0; BANDS * STEPS];
+    for _ in 0..SAMPLES {
+        let direction = generate();
+        assert!(
+            direction.length_squared() > 0.0 && direction.length_squared().is_finite(),
+            "{}: generated direction {:?}",
+            name,
+            direction
+        );
+        counts[bin_of(direction)] += 1;
+    }
+    assert_chi_square(name, &counts, &expected);
+}
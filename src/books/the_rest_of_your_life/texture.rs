@@ -1,12 +1,38 @@
 use std::sync::Arc;
 
-use super::interval::Interval;
+use super::hittable::HitRecord;
+use super::mipmap::{FilterMode, MipMap, WrapMode};
 use super::perlin::Perlin;
-use super::rtw_image::RtwImage;
+use super::projection::{TransformedTexture, TriplanarTexture};
+use super::procedural::{
+    ColorRamp, FbmTexture, GradientTexture, MarbleTexture, MixTexture, MultiplyTexture, RemapTexture, WoodTexture,
+    WorleyTexture,
+};
+use super::rtw_image::{ColorSpace, RtwImage};
 use super::vec3::{Color, Point3};
 
+// How far a lookup's footprint reaches in u and in v.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UvFootprint {
+    pub du: f64,
+    pub dv: f64,
+}
+
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+
+    // The texture averaged over `footprint` around (u, v). Only textures with detail that
+    // can alias need to override this.
+    fn filtered_value(&self, u: f64, v: f64, p: Point3, _footprint: UvFootprint) -> Color {
+        self.value(u, v, p)
+    }
+
+    // The texture at a hit. Textures that need more of the hit than (u, v, p), such as the
+    // normal or the point in object space, override this, and textures built from others
+    // pass the hit on.
+    fn sample(&self, rec: &HitRecord) -> Color {
+        self.filtered_value(rec.u, rec.v, rec.p, rec.uv_footprint())
+    }
 }
 
 pub type TextureRef = Arc<TextureObject>;
@@ -24,6 +50,11 @@ impl SolidColor {
         Self { albedo }
     }
 
+    // A gray level, for textures that drive scalar parameters.
+    pub fn from_value(value: f64) -> Self {
+        Self::new(Color::new(value, value, value))
+    }
+
     #[allow(dead_code)]
     pub fn from_rgb(red: f64, green: f64, blue: f64) -> Self {
         Self::new(Color::new(red, green, blue))
@@ -60,51 +91,88 @@ impl CheckerTexture {
     }
 }
 
-impl Texture for CheckerTexture {
-    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+impl CheckerTexture {
+    fn is_even(&self, p: Point3) -> bool {
         let x_integer = (self.inv_scale * p.x()).floor() as i32;
         let y_integer = (self.inv_scale * p.y()).floor() as i32;
         let z_integer = (self.inv_scale * p.z()).floor() as i32;
 
-        let is_even = (x_integer + y_integer + z_integer) % 2 == 0;
+        (x_integer + y_integer + z_integer) % 2 == 0
+    }
+}
 
-        if is_even {
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        if self.is_even(p) {
             self.even.value(u, v, p)
         } else {
             self.odd.value(u, v, p)
         }
     }
+
+    fn filtered_value(&self, u: f64, v: f64, p: Point3, footprint: UvFootprint) -> Color {
+        if self.is_even(p) {
+            self.even.filtered_value(u, v, p, footprint)
+        } else {
+            self.odd.filtered_value(u, v, p, footprint)
+        }
+    }
+
+    fn sample(&self, rec: &HitRecord) -> Color {
+        if self.is_even(rec.p) { self.even.sample(rec) } else { self.odd.sample(rec) }
+    }
 }
 
+// An image mapped over u and v, filtered through a mip pyramid by default so distant
+// surfaces don't alias. Values come out linear, decoded from the image's color space.
 pub struct ImageTexture {
-    image: RtwImage,
+    mipmap: Option<MipMap>,
+    filter: FilterMode,
+    wrap: WrapMode,
 }
 
 impl ImageTexture {
+    // Decodes the file from the color space RtwImage guesses for it. Ordinary 8 and 16-bit
+    // images are taken as sRGB, so their midtones come out darker than in scenes rendered
+    // before textures were decoded, which used the stored values as they were. Ask for
+    // ColorSpace::Linear to get that look back.
     pub fn new(filename: &str) -> Self {
-        Self { image: RtwImage::new(filename) }
+        Self::from_image(&RtwImage::new(filename))
+    }
+
+    pub fn new_with_color_space(filename: &str, color_space: ColorSpace) -> Self {
+        Self::from_image(&RtwImage::new(filename).with_color_space(color_space))
+    }
+
+    pub fn from_image(image: &RtwImage) -> Self {
+        let mipmap = (image.height() > 0).then(|| MipMap::from_image(image));
+        Self { mipmap, filter: FilterMode::default(), wrap: WrapMode::default() }
+    }
+
+    pub fn with_filter(mut self, filter: FilterMode) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        self
     }
 }
 
 impl Texture for ImageTexture {
-    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
-        if self.image.height() <= 0 {
-            return Color::new(0.0, 1.0, 1.0);
-        }
-
-        let u = Interval::new(0.0, 1.0).clamp(u);
-        let v = 1.0 - Interval::new(0.0, 1.0).clamp(v);
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        self.filtered_value(u, v, p, UvFootprint::default())
+    }
 
-        let i = (u * self.image.width() as f64) as i32;
-        let j = (v * self.image.height() as f64) as i32;
-        let pixel = self.image.pixel_data(i, j);
+    fn filtered_value(&self, u: f64, v: f64, _p: Point3, footprint: UvFootprint) -> Color {
+        let Some(mipmap) = &self.mipmap else {
+            return Color::new(0.0, 1.0, 1.0);
+        };
 
-        let color_scale = 1.0 / 255.0;
-        Color::new(
-            color_scale * pixel[0] as f64,
-            color_scale * pixel[1] as f64,
-            color_scale * pixel[2] as f64,
-        )
+        // The footprint's longer side, in texels of the full-size image.
+        let width = (footprint.du * mipmap.width() as f64).max(footprint.dv * mipmap.height() as f64);
+        mipmap.lookup(u, v, width, self.filter, self.wrap)
     }
 }
 
@@ -130,6 +198,17 @@ pub enum TextureObject {
     CheckerTexture(CheckerTexture),
     ImageTexture(ImageTexture),
     NoiseTexture(NoiseTexture),
+    FbmTexture(FbmTexture),
+    WorleyTexture(WorleyTexture),
+    MarbleTexture(MarbleTexture),
+    WoodTexture(WoodTexture),
+    GradientTexture(GradientTexture),
+    ColorRamp(ColorRamp),
+    MixTexture(MixTexture),
+    MultiplyTexture(MultiplyTexture),
+    RemapTexture(RemapTexture),
+    TriplanarTexture(TriplanarTexture),
+    TransformedTexture(TransformedTexture),
 }
 
 impl From<SolidColor> for TextureObject {
@@ -156,6 +235,72 @@ impl From<NoiseTexture> for TextureObject {
     }
 }
 
+impl From<FbmTexture> for TextureObject {
+    fn from(value: FbmTexture) -> Self {
+        Self::FbmTexture(value)
+    }
+}
+
+impl From<WorleyTexture> for TextureObject {
+    fn from(value: WorleyTexture) -> Self {
+        Self::WorleyTexture(value)
+    }
+}
+
+impl From<MarbleTexture> for TextureObject {
+    fn from(value: MarbleTexture) -> Self {
+        Self::MarbleTexture(value)
+    }
+}
+
+impl From<WoodTexture> for TextureObject {
+    fn from(value: WoodTexture) -> Self {
+        Self::WoodTexture(value)
+    }
+}
+
+impl From<GradientTexture> for TextureObject {
+    fn from(value: GradientTexture) -> Self {
+        Self::GradientTexture(value)
+    }
+}
+
+impl From<ColorRamp> for TextureObject {
+    fn from(value: ColorRamp) -> Self {
+        Self::ColorRamp(value)
+    }
+}
+
+impl From<MixTexture> for TextureObject {
+    fn from(value: MixTexture) -> Self {
+        Self::MixTexture(value)
+    }
+}
+
+impl From<MultiplyTexture> for TextureObject {
+    fn from(value: MultiplyTexture) -> Self {
+        Self::MultiplyTexture(value)
+    }
+}
+
+impl From<RemapTexture> for TextureObject {
+    fn from(value: RemapTexture) -> Self {
+        Self::RemapTexture(value)
+    }
+}
+
+impl From<TriplanarTexture> for TextureObject {
+    fn from(value: TriplanarTexture) -> Self {
+        Self::TriplanarTexture(value)
+    }
+}
+
+impl From<TransformedTexture> for TextureObject {
+    fn from(value: TransformedTexture) -> Self {
+        Self::TransformedTexture(value)
+    }
+}
+
 impl TextureObject {
     pub fn value(&self, u: f64, v: f64, p: Point3) -> Color {
         match self {
@@ -163,6 +308,123 @@ impl TextureObject {
             TextureObject::CheckerTexture(texture) => texture.value(u, v, p),
             TextureObject::ImageTexture(texture) => texture.value(u, v, p),
             TextureObject::NoiseTexture(texture) => texture.value(u, v, p),
+            TextureObject::FbmTexture(texture) => texture.value(u, v, p),
+            TextureObject::WorleyTexture(texture) => texture.value(u, v, p),
+            TextureObject::MarbleTexture(texture) => texture.value(u, v, p),
+            TextureObject::WoodTexture(texture) => texture.value(u, v, p),
+            TextureObject::GradientTexture(texture) => texture.value(u, v, p),
+            TextureObject::ColorRamp(texture) => texture.value(u, v, p),
+            TextureObject::MixTexture(texture) => texture.value(u, v, p),
+            TextureObject::MultiplyTexture(texture) => texture.value(u, v, p),
+            TextureObject::RemapTexture(texture) => texture.value(u, v, p),
+            TextureObject::TriplanarTexture(texture) => texture.value(u, v, p),
+            TextureObject::TransformedTexture(texture) => texture.value(u, v, p),
+        }
+    }
+
+    pub fn filtered_value(&self, u: f64, v: f64, p: Point3, footprint: UvFootprint) -> Color {
+        match self {
+            TextureObject::SolidColor(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::CheckerTexture(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::ImageTexture(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::NoiseTexture(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::FbmTexture(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::WorleyTexture(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::MarbleTexture(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::WoodTexture(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::GradientTexture(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::ColorRamp(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::MixTexture(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::MultiplyTexture(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::RemapTexture(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::TriplanarTexture(texture) => texture.filtered_value(u, v, p, footprint),
+            TextureObject::TransformedTexture(texture) => texture.filtered_value(u, v, p, footprint),
+        }
+    }
+
+    // The texture at a hit, filtered over the area the ray covers.
+    pub fn sample(&self, rec: &HitRecord) -> Color {
+        match self {
+            TextureObject::SolidColor(texture) => texture.sample(rec),
+            TextureObject::CheckerTexture(texture) => texture.sample(rec),
+            TextureObject::ImageTexture(texture) => texture.sample(rec),
+            TextureObject::NoiseTexture(texture) => texture.sample(rec),
+            TextureObject::FbmTexture(texture) => texture.sample(rec),
+            TextureObject::WorleyTexture(texture) => texture.sample(rec),
+            TextureObject::MarbleTexture(texture) => texture.sample(rec),
+            TextureObject::WoodTexture(texture) => texture.sample(rec),
+            TextureObject::GradientTexture(texture) => texture.sample(rec),
+            TextureObject::ColorRamp(texture) => texture.sample(rec),
+            TextureObject::MixTexture(texture) => texture.sample(rec),
+            TextureObject::MultiplyTexture(texture) => texture.sample(rec),
+            TextureObject::RemapTexture(texture) => texture.sample(rec),
+            TextureObject::TriplanarTexture(texture) => texture.sample(rec),
+            TextureObject::TransformedTexture(texture) => texture.sample(rec),
         }
     }
+
+    // A scalar parameter such as roughness or an index of refraction: the mean of the
+    // channels, so gray images and solid values both work.
+    pub fn filtered_scalar(&self, u: f64, v: f64, p: Point3, footprint: UvFootprint) -> f64 {
+        let color = self.filtered_value(u, v, p, footprint);
+        (color.x() + color.y() + color.z()) / 3.0
+    }
+
+    pub fn sample_scalar(&self, rec: &HitRecord) -> f64 {
+        let color = self.sample(rec);
+        (color.x() + color.y() + color.z()) / 3.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An 8 by 2 image of single texel checks, white at the top left. Every level past the
+    // first is gray.
+    fn checks() -> ImageTexture {
+        let texels = (0..16).map(|k| [((k % 8 + k / 8 + 1) % 2) as f32; 3]).collect();
+        ImageTexture { mipmap: Some(MipMap::new(8, 2, texels)), filter: FilterMode::Trilinear, wrap: WrapMode::Repeat }
+    }
+
+    #[test]
+    fn image_footprints_are_measured_in_texels() {
+        let texture = checks();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        // The center of the top left texel.
+        let (u, v) = (1.0 / 16.0, 0.75);
+        let at = |du: f64, dv: f64| texture.filtered_value(u, v, origin, UvFootprint { du, dv }).x();
+
+        assert_eq!(texture.value(u, v, origin).x(), 1.0);
+        assert_eq!(at(1.0 / 8.0, 0.0), 1.0);
+        assert!((at(2.0 / 8.0, 0.0) - 0.5).abs() < 1e-6);
+        // The same footprint in v covers a quarter as many texels across the short side.
+        assert_eq!(at(0.0, 2.0 / 8.0), 1.0);
+        assert_eq!(at(0.0, 1.0 / 2.0), 1.0);
+        assert!((at(0.0, 1.0) - 0.5).abs() < 1e-6);
+        assert!((at(1.0 / 8.0, 1.0) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn filtered_lookups_pass_through_other_textures() {
+        let image = make_tex(checks());
+        let checker = CheckerTexture::new(1.0, image.clone(), make_tex(SolidColor::from_value(0.25)));
+        let (u, v) = (1.0 / 16.0, 0.75);
+        let wide = UvFootprint { du: 0.5, dv: 0.5 };
+
+        // The even checks show the image, filtered as if looked up directly.
+        let even = Point3::new(0.5, 0.5, 0.5);
+        assert_eq!(checker.filtered_value(u, v, even, wide).x(), image.filtered_value(u, v, even, wide).x());
+        assert!((checker.filtered_value(u, v, even, wide).x() - 0.5).abs() < 1e-6);
+        assert_eq!(checker.value(u, v, even).x(), 1.0);
+        assert_eq!(checker.filtered_value(u, v, Point3::new(1.5, 0.5, 0.5), wide).x(), 0.25);
+        assert!((image.filtered_scalar(u, v, even, wide) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn missing_images_show_cyan() {
+        let texture = ImageTexture { mipmap: None, filter: FilterMode::default(), wrap: WrapMode::default() };
+        let color = texture.filtered_value(0.5, 0.5, Point3::new(0.0, 0.0, 0.0), UvFootprint { du: 0.1, dv: 0.1 });
+        assert_eq!((color.x(), color.y(), color.z()), (0.0, 1.0, 1.0));
+    }
 }
@@ -0,0 +1,152 @@
+// Interference in a thin transparent film on a surface, like oil on water or the wall of a
+// soap bubble. Light reflected from the top of the film and light reflected from the
+// surface below it add up or cancel depending on the extra distance the second travels,
+// which depends on wavelength and so on color.
+
+use super::hittable::HitRecord;
+use super::rtweekend::PI;
+use super::texture::{make_tex, SolidColor, TextureRef};
+use super::vec3::Color;
+
+// The wavelength, in nanometres, standing in for each of the red, green and blue channels.
+const WAVELENGTHS: [f64; 3] = [650.0, 532.0, 450.0];
+
+pub struct ThinFilm {
+    // In nanometres.
+    thickness: TextureRef,
+    ior: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, ior: f64) -> Self {
+        Self::from_texture(make_tex(SolidColor::from_value(thickness)), ior)
+    }
+
+    // A thickness texture in nanometres, for films that swirl and thin out like a bubble's.
+    pub fn from_texture(thickness: TextureRef, ior: f64) -> Self {
+        Self { thickness, ior }
+    }
+
+    // The reflectance of the film on a dielectric, for light arriving at cos_theta from the
+    // normal out of a medium with index `outside` and passing into one with index `inside`.
+    pub fn over_dielectric(&self, rec: &HitRecord, cos_theta: f64, outside: f64, inside: f64) -> Color {
+        let sin2_theta = 1.0 - cos_theta * cos_theta;
+        let cos_inside_squared = 1.0 - sin2_theta * (outside / inside).powi(2);
+        if cos_inside_squared <= 0.0 {
+            // Light that can't leave the film into the far side all comes back.
+            return Color::new(1.0, 1.0, 1.0);
+        }
+        let cos_inside = cos_inside_squared.sqrt();
+        let n_film = self.ior;
+        self.reflectance(rec, cos_theta, outside, |_, cos_film| {
+            (
+                fresnel_s(n_film, cos_film, inside, cos_inside),
+                fresnel_p(n_film, cos_film, inside, cos_inside),
+            )
+        })
+    }
+
+    // The reflectance of the film on a metal with the given color at normal incidence. Metals
+    // here have colors rather than complex indices, so the metal reflects with Schlick's
+    // strength and the half-wave phase shift of a much denser medium.
+    pub fn over_conductor(&self, rec: &HitRecord, cos_theta: f64, f0: Color) -> Color {
+        self.reflectance(rec, cos_theta, 1.0, |channel, cos_film| {
+            let f0 = f0[channel].clamp(0.0, 1.0);
+            let amplitude = -(f0 + (1.0 - f0) * (1.0 - cos_film).powi(5)).sqrt();
+            (amplitude, amplitude)
+        })
+    }
+
+    // Airy's sum over the light bouncing back and forth in the film, averaged over both
+    // polarizations. `substrate` gives the amplitude the film's lower face reflects for s
+    // and p polarized light, for a color channel and the cosine inside the film.
+    fn reflectance(
+        &self,
+        rec: &HitRecord,
+        cos_theta: f64,
+        outside: f64,
+        substrate: impl Fn(usize, f64) -> (f64, f64),
+    ) -> Color {
+        let thickness = self.thickness.sample_scalar(rec).max(0.0);
+        let n_film = self.ior;
+        let cos_film_squared = 1.0 - (1.0 - cos_theta * cos_theta) * (outside / n_film).powi(2);
+        if cos_film_squared <= 0.0 {
+            // Nothing gets into the film.
+            return Color::new(1.0, 1.0, 1.0);
+        }
+        let cos_film = cos_film_squared.sqrt();
+
+        let top = (
+            fresnel_s(outside, cos_theta, n_film, cos_film),
+            fresnel_p(outside, cos_theta, n_film, cos_film),
+        );
+
+        let airy = |r12: f64, r23: f64, phase: f64| {
+            let cross = 2.0 * r12 * r23 * phase.cos();
+            ((r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)).clamp(0.0, 1.0)
+        };
+        let channel = |channel: usize| {
+            let bottom = substrate(channel, cos_film);
+            let phase = 4.0 * PI * n_film * thickness * cos_film / WAVELENGTHS[channel];
+            0.5 * (airy(top.0, bottom.0, phase) + airy(top.1, bottom.1, phase))
+        };
+        Color::new(channel(0), channel(1), channel(2))
+    }
+}
+
+// The Fresnel amplitude coefficients for light going from index n1 to n2.
+fn fresnel_s(n1: f64, cos1: f64, n2: f64, cos2: f64) -> f64 {
+    (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2)
+}
+
+fn fresnel_p(n1: f64, cos1: f64, n2: f64, cos2: f64) -> f64 {
+    (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::the_rest_of_your_life::material::{make_mat, EmptyMaterial};
+    use crate::books::the_rest_of_your_life::ray::Ray;
+    use crate::books::the_rest_of_your_life::vec3::{Point3, Vec3};
+
+    fn rec() -> HitRecord {
+        let r_in = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        HitRecord::new(Point3::new(0.0, 0.0, 0.0), 1.0, &r_in, Vec3::new(0.0, 0.0, 1.0), make_mat(EmptyMaterial), 0.5, 0.5)
+    }
+
+    fn assert_close(actual: Color, expected: Color) {
+        assert!((actual - expected).length() < 1e-9, "got {:?}, expected {:?}", actual, expected);
+    }
+
+    #[test]
+    fn a_film_like_the_air_above_changes_nothing() {
+        let film = ThinFilm::new(300.0, 1.0);
+        assert_close(film.over_dielectric(&rec(), 1.0, 1.0, 1.5), Color::new(0.04, 0.04, 0.04));
+        let gold = Color::new(1.0, 0.78, 0.34);
+        assert_close(film.over_conductor(&rec(), 1.0, gold), gold);
+    }
+
+    #[test]
+    fn a_quarter_wave_film_cancels_its_color() {
+        // The coating on camera lenses: the two reflections meet half a wave apart and with
+        // equal strength.
+        let n = 1.5_f64.sqrt();
+        let film = ThinFilm::new(WAVELENGTHS[1] / (4.0 * n), n);
+        let reflectance = film.over_dielectric(&rec(), 1.0, 1.0, 1.5);
+        assert!(reflectance.y() < 1e-9, "green reflects {}", reflectance.y());
+        assert!(reflectance.x() > 1e-3 && reflectance.z() > 1e-3, "reflects {:?}", reflectance);
+    }
+
+    #[test]
+    fn films_stay_within_what_arrives() {
+        for thickness in [0.0, 120.0, 350.0, 800.0] {
+            for cos_theta in [1.0, 0.7, 0.2, 0.01] {
+                let film = ThinFilm::new(thickness, 1.33);
+                for r in [film.over_dielectric(&rec(), cos_theta, 1.0, 1.0), film.over_conductor(&rec(), cos_theta, Color::new(0.9, 0.6, 0.3))] {
+                    assert!((0..3).all(|c| (0.0..=1.0).contains(&r[c])), "{} nm at {}: {:?}", thickness, cos_theta, r);
+                }
+            }
+        }
+    }
+}
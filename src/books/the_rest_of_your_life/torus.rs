@@ -0,0 +1,362 @@
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::MaterialRef;
+use super::quadric::{around_axis, away_from_axis, azimuth, disk_bounds, Frame, Roots};
+use super::ray::Ray;
+use super::rtweekend::PI;
+use super::vec3::{dot, Point3, Vec3};
+
+// A ring of tube radius `minor_radius` swept around `axis` at `major_radius` from the
+// center. u runs around the axis and v around the tube.
+pub struct Torus {
+    frame: Frame,
+    major_radius: f64,
+    minor_radius: f64,
+    mat: MaterialRef,
+    bbox: Aabb,
+}
+
+impl Torus {
+    pub fn new(center: Point3, axis: Vec3, major_radius: f64, minor_radius: f64, mat: MaterialRef) -> Self {
+        let frame = Frame::new(center, axis);
+        // The torus is the core circle grown by a ball of the tube radius.
+        let core = disk_bounds(center, frame.axis(), major_radius);
+        let tube = 2.0 * minor_radius;
+        let bbox = Aabb::new(core.x.expand(tube), core.y.expand(tube), core.z.expand(tube));
+        Self { frame, major_radius, minor_radius, mat, bbox }
+    }
+
+    fn roots(&self, r: &Ray) -> Roots {
+        let mut roots = Roots::new();
+        let d = self.frame.to_local(r.direction());
+        let length = d.length();
+        if length == 0.0 {
+            return roots;
+        }
+        let d = d / length;
+
+        // Solve around the point of the line closest to the center, so the quartic's
+        // coefficients stay small however far away the ray starts.
+        let o = self.frame.to_local_point(r.origin());
+        let shift = -dot(o, d);
+        let o = o + shift * d;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (px^2 + py^2) with p = o + t d and |d| = 1.
+        let big_r2 = self.major_radius * self.major_radius;
+        let b = 2.0 * dot(o, d);
+        let g = o.length_squared() + big_r2 - self.minor_radius * self.minor_radius;
+        let a2 = d.x() * d.x() + d.y() * d.y();
+        let a1 = 2.0 * (o.x() * d.x() + o.y() * d.y());
+        let a0 = o.x() * o.x() + o.y() * o.y();
+        let coeffs = [
+            g * g - 4.0 * big_r2 * a0,
+            2.0 * b * g - 4.0 * big_r2 * a1,
+            b * b + 2.0 * g - 4.0 * big_r2 * a2,
+            2.0 * b,
+            1.0,
+        ];
+
+        for t in solve_quartic(coeffs) {
+            let t = polish_root(&coeffs, t);
+            roots.push((t + shift) / length, 0);
+        }
+        roots
+    }
+
+    fn record(&self, r: &Ray, t: f64) -> HitRecord {
+        let local = self.frame.to_local_point(r.at(t));
+        let ring_distance = (local.x() * local.x() + local.y() * local.y()).sqrt();
+        let core = if ring_distance > 0.0 {
+            Point3::new(local.x(), local.y(), 0.0) * (self.major_radius / ring_distance)
+        } else {
+            Point3::new(self.major_radius, 0.0, 0.0)
+        };
+        let outward_normal = self.frame.to_world((local - core) / self.minor_radius);
+
+        let tube_angle = local.z().atan2(ring_distance - self.major_radius);
+        let v = (if tube_angle < 0.0 { tube_angle + 2.0 * PI } else { tube_angle }) / (2.0 * PI);
+
+        // u turns the point around the axis; v turns it around the tube's core circle.
+        let dpdu = self.frame.to_world(around_axis(local));
+        let (sin_tube, cos_tube) = tube_angle.sin_cos();
+        let around_tube = -sin_tube * away_from_axis(local) + Vec3::new(0.0, 0.0, cos_tube);
+        let dpdv = self.frame.to_world(2.0 * PI * self.minor_radius * around_tube);
+        HitRecord::new(r.at(t), t, r, outward_normal, self.mat.clone(), azimuth(local), v)
+            .with_derivatives(dpdu, dpdv)
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        if !self.bbox.hit(r, ray_t) {
+            return None;
+        }
+        let (t, _) = self.roots(r).nearest(ray_t)?;
+        Some(self.record(r, t))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        self.roots(r).sorted(ray_t).into_iter().map(|(t, _)| self.record(r, t)).collect()
+    }
+}
+
+const EPSILON: f64 = 1e-9;
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EPSILON
+}
+
+fn evaluate(coeffs: &[f64], x: f64) -> f64 {
+    coeffs.iter().rev().fold(0.0, |acc, &c| acc * x + c)
+}
+
+// A couple of Newton steps tighten the closed-form roots, which lose precision when
+// the quartic's roots are close together.
+fn polish_root(coeffs: &[f64; 5], mut x: f64) -> f64 {
+    let derivative = [coeffs[1], 2.0 * coeffs[2], 3.0 * coeffs[3], 4.0 * coeffs[4]];
+    for _ in 0..2 {
+        let slope = evaluate(&derivative, x);
+        if slope == 0.0 {
+            break;
+        }
+        x -= evaluate(coeffs, x) / slope;
+    }
+    x
+}
+
+// Real roots of c[0] + c[1] x + c[2] x^2.
+fn solve_quadratic(c: [f64; 3]) -> Vec<f64> {
+    let p = c[1] / (2.0 * c[2]);
+    let q = c[0] / c[2];
+    let discriminant = p * p - q;
+
+    if is_zero(discriminant) {
+        vec![-p]
+    } else if discriminant < 0.0 {
+        Vec::new()
+    } else {
+        let sqrt_d = discriminant.sqrt();
+        vec![sqrt_d - p, -sqrt_d - p]
+    }
+}
+
+// Real roots of c[0] + c[1] x + c[2] x^2 + c[3] x^3, by Cardano's method.
+fn solve_cubic(c: [f64; 4]) -> Vec<f64> {
+    let a = c[2] / c[3];
+    let b = c[1] / c[3];
+    let cc = c[0] / c[3];
+
+    // Substitute x = y - a/3 to get y^3 + 3p y + 2q = 0.
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + cc) / 2.0;
+    let cb_p = p * p * p;
+    let discriminant = q * q + cb_p;
+
+    let mut roots = if is_zero(discriminant) {
+        if is_zero(q) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 {
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![t * phi.cos(), -t * (phi + PI / 3.0).cos(), -t * (phi - PI / 3.0).cos()]
+    } else {
+        let sqrt_d = discriminant.sqrt();
+        vec![(sqrt_d - q).cbrt() - (sqrt_d + q).cbrt()]
+    };
+
+    for root in &mut roots {
+        *root -= a / 3.0;
+    }
+    roots
+}
+
+// Real roots of c[0] + c[1] x + ... + c[4] x^4, by Ferrari's method.
+fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    let a = c[3] / c[4];
+    let b = c[2] / c[4];
+    let cc = c[1] / c[4];
+    let d = c[0] / c[4];
+
+    // Substitute x = y - a/4 to get y^4 + p y^2 + q y + r = 0.
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = sq_a * a / 8.0 - a * b / 2.0 + cc;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * cc / 4.0 + d;
+
+    let mut roots = if is_zero(r) {
+        // y (y^3 + p y + q) = 0
+        let mut roots = solve_cubic([q, p, 0.0, 1.0]);
+        roots.push(0.0);
+        roots
+    } else {
+        // Take one root of the resolvent cubic and split into two quadratics.
+        let z = solve_cubic([r * p / 2.0 - q * q / 8.0, -r, -p / 2.0, 1.0])[0];
+
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if is_zero(u) {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return Vec::new();
+        };
+        let v = if is_zero(v) {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return Vec::new();
+        };
+
+        let mut roots = solve_quadratic([z - u, if q < 0.0 { -v } else { v }, 1.0]);
+        roots.extend(solve_quadratic([z + u, if q < 0.0 { v } else { -v }, 1.0]));
+        roots
+    };
+
+    for root in &mut roots {
+        *root -= a / 4.0;
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::the_rest_of_your_life::material::{make_mat, EmptyMaterial};
+    use crate::books::the_rest_of_your_life::rtweekend::INFINITY;
+    use crate::books::the_rest_of_your_life::test_support::assert_derivatives_follow_uvs;
+
+    // The coefficients of the polynomial with these four roots, lowest order first.
+    fn with_roots(roots: [f64; 4]) -> [f64; 5] {
+        let mut coeffs = [1.0, 0.0, 0.0, 0.0, 0.0];
+        for root in roots {
+            for k in (1..5).rev() {
+                coeffs[k] = coeffs[k - 1] - root * coeffs[k];
+            }
+            coeffs[0] *= -root;
+        }
+        coeffs
+    }
+
+    fn assert_roots(coeffs: [f64; 5], expected: &[f64]) {
+        let mut roots: Vec<f64> = solve_quartic(coeffs).into_iter().map(|x| polish_root(&coeffs, x)).collect();
+        roots.sort_by(f64::total_cmp);
+        roots.dedup_by(|a, b| (*a - *b).abs() < 1e-6);
+        assert_eq!(roots.len(), expected.len(), "{:?} has roots {:?}, expected {:?}", coeffs, roots, expected);
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-6, "{:?} has roots {:?}, expected {:?}", coeffs, roots, expected);
+        }
+    }
+
+    #[test]
+    fn quartic_solver_finds_known_roots() {
+        assert_roots(with_roots([-3.0, -1.0, 2.0, 5.0]), &[-3.0, -1.0, 2.0, 5.0]);
+        assert_roots(with_roots([0.5, 0.5001, 7.0, 7.25]), &[0.5, 0.5001, 7.0, 7.25]);
+        assert_roots(with_roots([0.0, 1.0, 2.0, 3.0]), &[0.0, 1.0, 2.0, 3.0]);
+        // Double roots, as tangent rays give.
+        assert_roots(with_roots([-2.0, 1.0, 1.0, 4.0]), &[-2.0, 1.0, 4.0]);
+        // (x^2 + 1)(x - 1)(x - 2) and (x^2 + 1)(x^2 + 4) have two real roots and none.
+        assert_roots([2.0, -3.0, 3.0, -3.0, 1.0], &[1.0, 2.0]);
+        assert_roots([4.0, 0.0, 5.0, 0.0, 1.0], &[]);
+    }
+
+    // A ring around the z axis, 2 from the center, with a tube of radius 0.5.
+    fn ring() -> Torus {
+        Torus::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), 2.0, 0.5, make_mat(EmptyMaterial))
+    }
+
+    fn crossings(origin: Point3, direction: Vec3) -> Vec<HitRecord> {
+        ring().crossings(&Ray::new(origin, direction), Interval::new(0.001, INFINITY))
+    }
+
+    #[test]
+    fn rays_through_the_hole_cross_both_sides() {
+        let hits = crossings(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let expected = [(2.5, true), (3.5, false), (6.5, true), (7.5, false)];
+        assert_eq!(hits.len(), expected.len());
+        for (rec, (t, entering)) in hits.iter().zip(expected) {
+            assert!((rec.t - t).abs() < 1e-9 && rec.front_face == entering, "crosses at t {} {}", rec.t, rec.front_face);
+            // On the tube's surface, with the normal pointing away from its core.
+            let ring_distance = rec.p.x().hypot(rec.p.y());
+            assert!(((ring_distance - 2.0).hypot(rec.p.z()) - 0.5).abs() < 1e-9);
+            assert!((rec.normal.length() - 1.0).abs() < 1e-9);
+        }
+
+        // Far away rays solve as accurately.
+        let far = crossings(Point3::new(-1e5, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(far.len(), 4);
+        assert!((far[0].p.x() + 2.5).abs() < 1e-6 && (far[3].p.x() - 2.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn grazing_rays_just_hit_or_miss_the_top() {
+        let across = Vec3::new(1.0, 0.0, 0.0);
+        let below = crossings(Point3::new(-5.0, 0.0, 0.5 - 1e-4), across);
+        assert_eq!(below.len(), 4, "just below the top crosses at {:?}", below.iter().map(|rec| rec.p).collect::<Vec<_>>());
+        for rec in &below {
+            assert!((rec.p.x().abs() - 2.0).abs() < 0.02, "crosses at {:?}", rec.p);
+        }
+        assert!(crossings(Point3::new(-5.0, 0.0, 0.5 + 1e-4), across).is_empty());
+    }
+
+    #[test]
+    fn tangent_rays_touch_the_inside_of_the_ring() {
+        // Along the inner edge of the tube, touching it at (1.5, 0, 0) on the way through.
+        let hits = crossings(Point3::new(1.5, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let first = hits.first().expect("hits the ring");
+        let last = hits.last().expect("hits the ring");
+        assert!((first.p.y() + 2.0).abs() < 1e-9 && first.front_face, "first crossing {:?}", first.p);
+        assert!((last.p.y() - 2.0).abs() < 1e-9 && !last.front_face, "last crossing {:?}", last.p);
+        for rec in &hits[1..hits.len() - 1] {
+            assert!(rec.p.y().abs() < 1e-3, "crosses at {:?}", rec.p);
+        }
+    }
+
+    #[test]
+    fn uvs_go_around_the_axis_and_the_tube() {
+        let ring = ring();
+        let hit = |origin: Point3, direction: Vec3| {
+            ring.hit(&Ray::new(origin, direction), Interval::new(0.001, INFINITY)).expect("hits the ring")
+        };
+        let down = Vec3::new(0.0, 0.0, -1.0);
+
+        // The outer equator is where v starts, and the top of the tube a quarter round.
+        let outside = hit(Point3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let top = hit(Point3::new(2.0, 0.0, 3.0), down);
+        let inside = hit(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(outside.v.abs() < 1e-9 || (outside.v - 1.0).abs() < 1e-9, "outer v {}", outside.v);
+        assert!((top.v - 0.25).abs() < 1e-9, "top v {}", top.v);
+        assert!((inside.v - 0.5).abs() < 1e-9, "inner v {}", inside.v);
+
+        // u is the same all around the tube and turns with the ring.
+        assert!((outside.u - top.u).abs() < 1e-9 && (top.u - inside.u).abs() < 1e-9);
+        let turned = hit(Point3::new(0.0, 2.0, 3.0), down);
+        assert!(((outside.u - turned.u).rem_euclid(1.0) - 0.25).abs() < 1e-9, "u {} and {}", outside.u, turned.u);
+    }
+
+    #[test]
+    fn derivatives_follow_the_uvs() {
+        let ring = Torus::new(Point3::new(0.5, -1.0, 0.2), Vec3::new(0.3, 1.0, 0.4), 2.0, 0.5, make_mat(EmptyMaterial));
+        let mut hits = 0;
+        for origin in [Point3::new(5.0, 2.0, 1.0), Point3::new(-1.0, 4.0, -3.0), Point3::new(0.5, -1.0, 0.2)] {
+            for target in [Point3::new(2.5, -1.5, 0.0), Point3::new(-1.2, -0.4, 0.9), Point3::new(0.4, -0.8, -1.8)] {
+                if let Some(rec) = ring.hit(&Ray::new(origin, target - origin), Interval::new(0.001, INFINITY)) {
+                    hits += 1;
+                    assert_derivatives_follow_uvs("torus", &ring, &rec);
+                }
+            }
+        }
+        assert!(hits > 3, "only {} rays hit", hits);
+    }
+}
@@ -0,0 +1,353 @@
+use std::ops::Mul;
+
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable, HittableRef};
+use super::interval::Interval;
+use super::ray::Ray;
+use super::rtweekend::{degrees_to_radians, INFINITY};
+use super::vec3::{cross, dot, unit_vector, Point3, Vec3};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Quat {
+    pub w: f64,
+    pub v: Vec3,
+}
+
+impl Quat {
+    pub const IDENTITY: Quat = Quat { w: 1.0, v: Vec3 { e: [0.0, 0.0, 0.0] } };
+
+    pub fn from_axis_angle(axis: Vec3, degrees: f64) -> Self {
+        let half = 0.5 * degrees_to_radians(degrees);
+        Self { w: half.cos(), v: half.sin() * unit_vector(axis) }
+    }
+
+    pub fn dot(self, other: Quat) -> f64 {
+        self.w * other.w + dot(self.v, other.v)
+    }
+
+    pub fn conjugate(self) -> Self {
+        Self { w: self.w, v: -self.v }
+    }
+
+    pub fn normalized(self) -> Self {
+        let len = self.dot(self).sqrt();
+        Self { w: self.w / len, v: self.v / len }
+    }
+
+    pub fn rotate(self, p: Vec3) -> Vec3 {
+        // v' = p + 2w(v x p) + 2v x (v x p)
+        let t = 2.0 * cross(self.v, p);
+        p + self.w * t + cross(self.v, t)
+    }
+
+    // Rotation angle in radians between two orientations.
+    pub fn angle_between(self, other: Quat) -> f64 {
+        2.0 * self.dot(other).abs().min(1.0).acos()
+    }
+
+    pub fn slerp(self, other: Quat, t: f64) -> Self {
+        // Take the short way around; q and -q are the same rotation.
+        let mut cos_theta = self.dot(other);
+        let other = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            Quat { w: -other.w, v: -other.v }
+        } else {
+            other
+        };
+
+        if cos_theta > 0.9995 {
+            return Quat {
+                w: self.w + t * (other.w - self.w),
+                v: self.v + t * (other.v - self.v),
+            }
+            .normalized();
+        }
+
+        let theta = cos_theta.acos();
+        let sin_theta = theta.sin();
+        let a = ((1.0 - t) * theta).sin() / sin_theta;
+        let b = (t * theta).sin() / sin_theta;
+        Quat { w: a * self.w + b * other.w, v: a * self.v + b * other.v }
+    }
+}
+
+impl Mul for Quat {
+    type Output = Quat;
+
+    fn mul(self, rhs: Quat) -> Self::Output {
+        Quat {
+            w: self.w * rhs.w - dot(self.v, rhs.v),
+            v: self.w * rhs.v + rhs.w * self.v + cross(self.v, rhs.v),
+        }
+    }
+}
+
+// Scale, then rotate, then translate. Scale factors must be positive.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub fn new(translation: Vec3, rotation: Quat, scale: Vec3) -> Self {
+        Self { translation, rotation: rotation.normalized(), scale }
+    }
+
+    pub fn identity() -> Self {
+        Self::new(Vec3::new(0.0, 0.0, 0.0), Quat::IDENTITY, Vec3::new(1.0, 1.0, 1.0))
+    }
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self::new(translation, Quat::IDENTITY, Vec3::new(1.0, 1.0, 1.0))
+    }
+
+    pub fn interpolate(a: &Transform, b: &Transform, t: f64) -> Self {
+        Self {
+            translation: a.translation + t * (b.translation - a.translation),
+            rotation: a.rotation.slerp(b.rotation, t),
+            scale: a.scale + t * (b.scale - a.scale),
+        }
+    }
+
+    pub fn apply_point(&self, p: Point3) -> Point3 {
+        self.rotation.rotate(self.scale * p) + self.translation
+    }
+
+    pub fn apply_vector(&self, v: Vec3) -> Vec3 {
+        self.rotation.rotate(self.scale * v)
+    }
+
+    pub fn apply_normal(&self, n: Vec3) -> Vec3 {
+        let inv_scaled = Vec3::new(n.x() / self.scale.x(), n.y() / self.scale.y(), n.z() / self.scale.z());
+        unit_vector(self.rotation.rotate(inv_scaled))
+    }
+
+    pub fn inverse_point(&self, p: Point3) -> Point3 {
+        self.inverse_vector(p - self.translation)
+    }
+
+    pub fn inverse_vector(&self, v: Vec3) -> Vec3 {
+        let r = self.rotation.conjugate().rotate(v);
+        Vec3::new(r.x() / self.scale.x(), r.y() / self.scale.y(), r.z() / self.scale.z())
+    }
+
+    // The ray in the object's space. The direction is not renormalized, so t is the same in
+    // both spaces.
+    pub fn inverse_ray(&self, r: &Ray) -> Ray {
+        Ray::new_with_time(self.inverse_point(r.origin()), self.inverse_vector(r.direction()), r.time())
+    }
+
+    // Moves a hit found with inverse_ray back out to world space.
+    pub fn apply_hit(&self, mut rec: HitRecord) -> HitRecord {
+        rec.p = self.apply_point(rec.p);
+        rec.normal = self.apply_normal(rec.normal);
+        rec.dpdu = self.apply_vector(rec.dpdu);
+        rec.dpdv = self.apply_vector(rec.dpdv);
+        rec
+    }
+
+    pub fn apply_box(&self, bbox: &Aabb) -> Aabb {
+        let mut min = Point3::new(INFINITY, INFINITY, INFINITY);
+        let mut max = Point3::new(-INFINITY, -INFINITY, -INFINITY);
+        for c in box_corners(bbox) {
+            let p = self.apply_point(c);
+            for axis in 0..3 {
+                min[axis] = min[axis].min(p[axis]);
+                max[axis] = max[axis].max(p[axis]);
+            }
+        }
+        Aabb::from_points(min, max)
+    }
+}
+
+fn box_corners(bbox: &Aabb) -> [Point3; 8] {
+    let mut corners = [Point3::new(0.0, 0.0, 0.0); 8];
+    for (n, corner) in corners.iter_mut().enumerate() {
+        *corner = Point3::new(
+            if n & 1 != 0 { bbox.x.max } else { bbox.x.min },
+            if n & 2 != 0 { bbox.y.max } else { bbox.y.min },
+            if n & 4 != 0 { bbox.z.max } else { bbox.z.min },
+        );
+    }
+    corners
+}
+
+// Instances an object under a transform that moves from `start` at time0 to `end` at
+// time1, interpolating translation and scale linearly and rotation by slerp. Rays outside
+// the interval see the nearest end transform.
+pub struct MotionTransform {
+    object: HittableRef,
+    start: Transform,
+    end: Transform,
+    time0: f64,
+    time1: f64,
+    bbox: Aabb,
+}
+
+impl MotionTransform {
+    pub fn new(object: HittableRef, start: Transform, end: Transform) -> Self {
+        Self::new_with_times(object, start, end, 0.0, 1.0)
+    }
+
+    pub fn new_with_times(object: HittableRef, start: Transform, end: Transform, time0: f64, time1: f64) -> Self {
+        let bbox = Self::motion_bounds(object.bounding_box(), &start, &end);
+        Self { object, start, end, time0, time1, bbox }
+    }
+
+    pub fn transform_at(&self, time: f64) -> Transform {
+        let span = self.time1 - self.time0;
+        let t = if span > 0.0 { ((time - self.time0) / span).clamp(0.0, 1.0) } else { 0.0 };
+        Transform::interpolate(&self.start, &self.end, t)
+    }
+
+    fn motion_bounds(bbox: Aabb, start: &Transform, end: &Transform) -> Aabb {
+        const SEGMENTS: usize = 32;
+
+        let corners = box_corners(&bbox);
+
+        // Bound every segment by its midpoint transform plus the furthest any corner can
+        // drift from it: half the segment's rotation arc, scale change and translation.
+        let step = 1.0 / SEGMENTS as f64;
+        let half_angle = 0.5 * step * start.rotation.angle_between(end.rotation);
+        let half_scale_step = 0.5 * step * (end.scale - start.scale);
+        let half_translation = 0.5 * step * (end.translation - start.translation).length();
+
+        let mut max_extent: f64 = 0.0;
+        let mut max_scale_drift: f64 = 0.0;
+        for &c in &corners {
+            max_extent = max_extent.max((start.scale * c).length()).max((end.scale * c).length());
+            max_scale_drift = max_scale_drift.max((half_scale_step * c).length());
+        }
+        let padding = max_extent * half_angle + max_scale_drift + half_translation;
+
+        let mut min = Point3::new(INFINITY, INFINITY, INFINITY);
+        let mut max = Point3::new(-INFINITY, -INFINITY, -INFINITY);
+        for s in 0..SEGMENTS {
+            let mid = Transform::interpolate(start, end, (s as f64 + 0.5) * step);
+            for &c in &corners {
+                let p = mid.apply_point(c);
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(p[axis] - padding);
+                    max[axis] = max[axis].max(p[axis] + padding);
+                }
+            }
+        }
+
+        Aabb::from_points(min, max)
+    }
+}
+
+impl Hittable for MotionTransform {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        let transform = self.transform_at(r.time());
+        let rec = self.object.hit(&transform.inverse_ray(r), ray_t)?;
+        Some(transform.apply_hit(rec))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn crossings(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        let transform = self.transform_at(r.time());
+        self.object.crossings(&transform.inverse_ray(r), ray_t).into_iter().map(|rec| transform.apply_hit(rec)).collect()
+    }
+
+    fn pdf_value(&self, origin: Point3, direction: Vec3, time: f64) -> f64 {
+        let transform = self.transform_at(time);
+        let local_direction = transform.inverse_vector(direction / direction.length());
+        let pdf = self.object.pdf_value(transform.inverse_point(origin), local_direction, time);
+
+        // Scaling squeezes and stretches solid angle; rotating and moving leave it be.
+        let s = transform.scale;
+        pdf / (s.x() * s.y() * s.z() * local_direction.length().powi(3))
+    }
+
+    fn random(&self, origin: Point3, time: f64) -> Vec3 {
+        let transform = self.transform_at(time);
+        transform.apply_vector(self.object.random(transform.inverse_point(origin), time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::the_rest_of_your_life::hittable::make_ref;
+    use crate::books::the_rest_of_your_life::material::{make_mat, EmptyMaterial};
+    use crate::books::the_rest_of_your_life::quad::make_box;
+    use crate::books::the_rest_of_your_life::rtweekend::PI;
+    use crate::books::the_rest_of_your_life::sphere::Sphere;
+    use crate::books::the_rest_of_your_life::test_support::assert_sampler_matches_pdf;
+
+    fn unit_ball() -> HittableRef {
+        make_ref(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, make_mat(EmptyMaterial)))
+    }
+
+    fn contains(outer: &Aabb, inner: &Aabb) -> bool {
+        (0..3).all(|axis| {
+            let (o, i) = (outer.axis_interval(axis), inner.axis_interval(axis));
+            o.min <= i.min && i.max <= o.max
+        })
+    }
+
+    #[test]
+    fn bounds_cover_the_whole_sweep() {
+        let object = make_box(Point3::new(1.0, 0.0, -0.5), Point3::new(3.0, 1.0, 0.5), make_mat(EmptyMaterial));
+        let start = Transform::new(Vec3::new(0.0, 0.0, 0.0), Quat::IDENTITY, Vec3::new(1.0, 1.0, 1.0));
+        let end = Transform::new(
+            Vec3::new(4.0, 2.0, 0.0),
+            Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 170.0),
+            Vec3::new(2.0, 0.5, 1.0),
+        );
+        let motion = MotionTransform::new_with_times(object.clone(), start, end, 2.0, 3.0);
+
+        for i in 0..=200 {
+            let time = 2.0 + i as f64 / 200.0;
+            let swept = motion.transform_at(time).apply_box(&object.bounding_box());
+            assert!(contains(&motion.bounding_box(), &swept), "object at time {} is out of bounds", time);
+        }
+    }
+
+    #[test]
+    fn pdf_follows_the_object_in_time() {
+        let end = Transform::from_translation(Vec3::new(0.0, 0.0, 10.0));
+        let motion = MotionTransform::new(unit_ball(), Transform::identity(), end);
+        let origin = Point3::new(0.0, 0.0, -5.0);
+        let ahead = Vec3::new(0.0, 0.0, 1.0);
+        let aside = Vec3::new(0.0, 0.15, 1.0);
+
+        // A unit ball 5 and 10 away from the origin.
+        let near = 1.0 / (2.0 * PI * (1.0 - (1.0 - 1.0 / 25.0_f64).sqrt()));
+        let far = 1.0 / (2.0 * PI * (1.0 - (1.0 - 1.0 / 100.0_f64).sqrt()));
+        assert!((motion.pdf_value(origin, ahead, 0.0) - near).abs() < 1e-9 * near);
+        assert!((motion.pdf_value(origin, ahead, 0.5) - far).abs() < 1e-9 * far);
+        // Just off the ball at its start, and off it once it has moved away.
+        assert!(motion.pdf_value(origin, aside, 0.0) > 0.0);
+        assert_eq!(motion.pdf_value(origin, aside, 0.5), 0.0);
+    }
+
+    #[test]
+    fn samples_follow_pdf_under_scale_and_rotation() {
+        let start = Transform::new(
+            Vec3::new(2.0, 0.0, 0.0),
+            Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        );
+        let end = Transform::new(
+            Vec3::new(0.0, 3.0, 1.0),
+            Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), 60.0),
+            Vec3::new(3.0, 0.5, 1.5),
+        );
+        let motion = MotionTransform::new(unit_ball(), start, end);
+        let origin = Point3::new(-1.0, -1.0, 0.5);
+        for time in [0.0, 0.7] {
+            assert_sampler_matches_pdf(
+                &format!("moving ellipsoid at time {}", time),
+                || motion.random(origin, time),
+                |d| motion.pdf_value(origin, d, time),
+            );
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use super::rtweekend::{random_double, random_int};
+use super::vec3::{Point3, Vec3};
+
+// Cellular noise: the distances from a point to the nearest of a set of feature points
+// scattered one to each unit cell of space.
+pub struct Worley {
+    perm: Vec<usize>,
+    offsets: Vec<Vec3>,
+}
+
+impl Worley {
+    pub fn new() -> Self {
+        let mut perm: Vec<usize> = (0..Self::POINT_COUNT).collect();
+        for i in (1..perm.len()).rev() {
+            let target = random_int(0, i as i32) as usize;
+            perm.swap(i, target);
+        }
+        let offsets = (0..Self::POINT_COUNT)
+            .map(|_| Vec3::new(random_double(), random_double(), random_double()))
+            .collect();
+
+        Self { perm, offsets }
+    }
+
+    // The distances to the nearest and the second nearest feature points.
+    pub fn distances(&self, p: Point3) -> (f64, f64) {
+        let cell = [p.x().floor() as i32, p.y().floor() as i32, p.z().floor() as i32];
+        let mut nearest = (f64::INFINITY, f64::INFINITY);
+
+        // The point in p's own cell is at most a cell's diagonal, sqrt(3), away, so the
+        // nearest two are within two cells on every axis. Cells that can't hold anything
+        // closer than the second nearest so far are skipped.
+        for di in -2..=2 {
+            for dj in -2..=2 {
+                for dk in -2..=2 {
+                    let (i, j, k) = (cell[0] + di, cell[1] + dj, cell[2] + dk);
+                    let corner = Point3::new(i as f64, j as f64, k as f64);
+                    if Self::gap(corner, p) >= nearest.1 {
+                        continue;
+                    }
+                    let distance = (corner + self.offsets[self.hash(i, j, k)] - p).length();
+                    if distance < nearest.0 {
+                        nearest = (distance, nearest.0);
+                    } else if distance < nearest.1 {
+                        nearest.1 = distance;
+                    }
+                }
+            }
+        }
+
+        nearest
+    }
+
+    // The distance from p to the nearest point of the unit cell at `corner`.
+    fn gap(corner: Point3, p: Point3) -> f64 {
+        let outside = |low: f64, x: f64| (low - x).max(x - (low + 1.0)).max(0.0);
+        Vec3::new(outside(corner.x(), p.x()), outside(corner.y(), p.y()), outside(corner.z(), p.z())).length()
+    }
+
+    const POINT_COUNT: usize = 256;
+
+    fn hash(&self, i: i32, j: i32, k: i32) -> usize {
+        let n = Self::POINT_COUNT as i32;
+        let h = self.perm[i.rem_euclid(n) as usize];
+        let h = self.perm[(h as i32 + j).rem_euclid(n) as usize];
+        self.perm[(h as i32 + k).rem_euclid(n) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::golden;
+    use crate::books::the_rest_of_your_life::rtweekend::seed_random;
+
+    #[test]
+    fn searching_nearby_cells_matches_a_wide_search() {
+        seed_random(golden::SEED);
+        let worley = Worley::new();
+        for _ in 0..10000 {
+            let p = Vec3::random_range(-20.0, 20.0);
+            let cell = [p.x().floor() as i32, p.y().floor() as i32, p.z().floor() as i32];
+            let mut all = Vec::new();
+            for di in -4..=4 {
+                for dj in -4..=4 {
+                    for dk in -4..=4 {
+                        let (i, j, k) = (cell[0] + di, cell[1] + dj, cell[2] + dk);
+                        let corner = Point3::new(i as f64, j as f64, k as f64);
+                        all.push((corner + worley.offsets[worley.hash(i, j, k)] - p).length());
+                    }
+                }
+            }
+            all.sort_by(f64::total_cmp);
+
+            let (f1, f2) = worley.distances(p);
+            assert!(f1 == all[0] && f2 == all[1], "found {} and {} at {:?}, expected {} and {}", f1, f2, p, all[0], all[1]);
+        }
+    }
+}
@@ -1,3 +1,62 @@
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Projection {
+    // Pinhole or thin-lens perspective built from `vfov`.
+    Perspective,
+    // Parallel rays; the view volume matches the perspective viewport at `focus_dist`.
+    Orthographic,
+    // Equidistant fisheye; `vfov` is the angle spanned by the image height.
+    Fisheye,
+    // Full 360x180 latitude/longitude panorama; `vfov` is ignored.
+    Equirectangular,
+}
+
+impl Projection {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "perspective" => Some(Projection::Perspective),
+            "orthographic" | "ortho" => Some(Projection::Orthographic),
+            "fisheye" => Some(Projection::Fisheye),
+            "equirectangular" | "panorama" => Some(Projection::Equirectangular),
+            _ => None,
+        }
+    }
+}
+
+// Set from the command line with --projection; takes precedence over OVERRIDES.projection.
+static PROJECTION: OnceLock<Projection> = OnceLock::new();
+
+pub fn set_projection(projection: Projection) {
+    let _ = PROJECTION.set(projection);
+}
+
+// The projection every scene should use instead of its own, if any.
+pub fn projection_override() -> Option<Projection> {
+    PROJECTION.get().copied().or(OVERRIDES.projection)
+}
+
+// Set from the command line with --lens and --aperture: a lens file to film the scene
+// through, and the shape of the thin lens's aperture.
+static LENS: OnceLock<String> = OnceLock::new();
+static APERTURE: OnceLock<String> = OnceLock::new();
+
+pub fn set_lens(filename: String) {
+    let _ = LENS.set(filename);
+}
+
+pub fn lens_override() -> Option<&'static str> {
+    LENS.get().map(String::as_str)
+}
+
+pub fn set_aperture(shape: String) {
+    let _ = APERTURE.set(shape);
+}
+
+pub fn aperture_override() -> Option<&'static str> {
+    APERTURE.get().map(String::as_str)
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct RenderOverrides {
     pub aspect_ratio: Option<f64>,
@@ -11,6 +70,7 @@ pub struct RenderOverrides {
     pub defocus_angle: Option<f64>,
     pub focus_dist: Option<f64>,
     pub background: Option<[f64; 3]>,
+    pub projection: Option<Projection>,
 }
 
 impl RenderOverrides {
@@ -28,6 +88,7 @@ impl RenderOverrides {
             defocus_angle: None,
             focus_dist: None,
             background: None,
+            projection: None,
         }
     }
 }
@@ -46,6 +107,7 @@ impl RenderOverrides {
 //     defocus_angle: Some(0.6),
 //     focus_dist: Some(10.0),
 //     background: Some([0.0, 0.0, 0.0]),
+//     projection: Some(Projection::Fisheye),
 // };
 pub const OVERRIDES: RenderOverrides = RenderOverrides {
     aspect_ratio: None,
@@ -59,4 +121,5 @@ pub const OVERRIDES: RenderOverrides = RenderOverrides {
     defocus_angle: None,
     focus_dist: None,
     background: None,
+    projection: None,
 };
@@ -11,10 +11,22 @@ fn normalize_book_name(name: &str) -> String {
         .collect::<String>()
 }
 
+fn parse_frames(value: &str) -> Option<std::ops::Range<i32>> {
+    if let Some((start, end)) = value.split_once("..=") {
+        let start = start.trim().parse::<i32>().ok()?;
+        let end = end.trim().parse::<i32>().ok()?;
+        return Some(start..end + 1);
+    }
+    let (start, end) = value.split_once("..")?;
+    Some(start.trim().parse::<i32>().ok()?..end.trim().parse::<i32>().ok()?)
+}
+
 fn main() {
     eprintln!("Rayon threads: {}", rayon::current_num_threads());
 
     let mut backend = "cpu".to_string();
+    let mut frames = None;
+    let mut projection = None;
     let mut positional_args = Vec::new();
     let mut args = std::env::args().skip(1).peekable();
 
@@ -44,6 +56,62 @@ fn main() {
             backend = value.to_string();
             continue;
         }
+        if arg == "--frames" || arg.starts_with("--frames=") {
+            let value = match arg.strip_prefix("--frames=") {
+                Some(value) => Some(value.to_string()),
+                None => args.next(),
+            };
+            match value.as_deref().and_then(parse_frames) {
+                Some(range) => frames = Some(range),
+                None => {
+                    eprintln!("--frames expects a range such as 0..24 or 1..=24");
+                    return;
+                }
+            }
+            continue;
+        }
+        if arg == "--projection" || arg.starts_with("--projection=") {
+            let value = match arg.strip_prefix("--projection=") {
+                Some(value) => Some(value.to_string()),
+                None => args.next(),
+            };
+            match value.as_deref().and_then(config::Projection::from_name) {
+                Some(value) => projection = Some(value),
+                None => {
+                    eprintln!("--projection expects perspective, orthographic, fisheye or equirectangular");
+                    return;
+                }
+            }
+            continue;
+        }
+        if arg == "--lens" || arg.starts_with("--lens=") {
+            let value = match arg.strip_prefix("--lens=") {
+                Some(value) => Some(value.to_string()),
+                None => args.next(),
+            };
+            match value {
+                Some(filename) => config::set_lens(filename),
+                None => {
+                    eprintln!("--lens expects a lens file such as dgauss.50mm.dat");
+                    return;
+                }
+            }
+            continue;
+        }
+        if arg == "--aperture" || arg.starts_with("--aperture=") {
+            let value = match arg.strip_prefix("--aperture=") {
+                Some(value) => Some(value.to_string()),
+                None => args.next(),
+            };
+            match value {
+                Some(shape) => config::set_aperture(shape),
+                None => {
+                    eprintln!("--aperture expects circle, a number of blades or a mask image");
+                    return;
+                }
+            }
+            continue;
+        }
         positional_args.push(arg);
     }
 
@@ -54,6 +122,28 @@ fn main() {
         .unwrap_or_else(|| "in_one_weekend".to_string());
     let scene = positional_args.get(1).and_then(|arg| arg.parse::<i32>().ok());
     let book_key = normalize_book_name(&book_arg);
+    let is_rest = matches!(
+        book_key.as_str(),
+        "therestofyourlife" | "restofyourlife" | "rest" | "restoflife"
+    );
+
+    if frames.is_some() && (backend != "cpu" || !is_rest) {
+        eprintln!("--frames is only supported by the_rest_of_your_life on the CPU backend.");
+        return;
+    }
+
+    if projection.is_some() && backend != "cpu" {
+        eprintln!("--projection is only supported on the CPU backend.");
+        return;
+    }
+    if let Some(projection) = projection {
+        config::set_projection(projection);
+    }
+
+    if (config::lens_override().is_some() || config::aperture_override().is_some()) && (backend != "cpu" || !is_rest) {
+        eprintln!("--lens and --aperture are only supported by the_rest_of_your_life on the CPU backend.");
+        return;
+    }
 
     if backend == "cuda" {
         if matches!(book_key.as_str(), "inoneweekend" | "oneweekend" | "weekend") {
@@ -84,16 +174,27 @@ fn main() {
         }
     }
 
+    if book_key == "bench" {
+        books::the_rest_of_your_life::bench();
+        return;
+    }
+
     match book_key.as_str() {
         "inoneweekend" | "oneweekend" | "weekend" => books::in_one_weekend::run(None),
         "thenextweek" | "nextweek" | "next" => books::the_next_week::run(scene),
         "therestofyourlife" | "restofyourlife" | "rest" | "restoflife" => {
-            books::the_rest_of_your_life::run(None)
+            books::the_rest_of_your_life::run(scene, frames)
         }
         _ => {
-            eprintln!("Usage: cargo run -- [--backend cpu|gpu|cuda] <book> [scene]");
+            eprintln!(
+                "Usage: cargo run -- [--backend cpu|gpu|cuda] [--frames start..end] [--projection name] [--lens file] [--aperture shape] <book> [scene]"
+            );
             eprintln!("books: in_one_weekend, the_next_week, the_rest_of_your_life");
             eprintln!("example: cargo run -- the_next_week 3");
+            eprintln!("example: cargo run -- --frames 0..48 the_rest_of_your_life 2");
+            eprintln!("example: cargo run -- --projection fisheye the_next_week 1");
+            eprintln!("example: cargo run -- --lens dgauss.50mm.dat --aperture 6 the_rest_of_your_life 18");
+            eprintln!("benchmark: cargo run --release -- bench");
         }
     }
 }